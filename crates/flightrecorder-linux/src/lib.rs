@@ -6,6 +6,10 @@
 #![warn(missing_debug_implementations)]
 #![deny(unsafe_code)]
 
+pub mod systemd;
+
+pub use systemd::{ServiceUnit, Systemctl, SystemdError};
+
 /// Initialize Linux-specific components
 ///
 /// # Errors
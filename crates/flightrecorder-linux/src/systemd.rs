@@ -0,0 +1,337 @@
+//! systemd user service installation.
+//!
+//! The daemon is installed as a `Type=notify` user unit, so systemd considers
+//! it started only once it reports `READY=1`, and restarts it if it stops
+//! sending watchdog keep-alives. [`Systemctl`] drives `systemctl --user` to
+//! enable, start, stop and disable the unit.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use thiserror::Error;
+
+/// Name of the daemon's unit file.
+pub const UNIT_NAME: &str = "fliterec.service";
+
+/// Default watchdog timeout written to the unit, in seconds.
+pub const DEFAULT_WATCHDOG_SEC: u32 = 30;
+
+/// Errors that can occur while managing the systemd unit.
+#[derive(Debug, Error)]
+pub enum SystemdError {
+    /// The user's systemd unit directory could not be determined.
+    #[error("could not determine the systemd user unit directory")]
+    NoUnitDirectory,
+
+    /// Reading or writing a unit file failed.
+    #[error("failed to access {path}: {source}")]
+    Io {
+        /// Path of the unit file or directory.
+        path: PathBuf,
+        /// The underlying error.
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// A `systemctl` invocation failed.
+    #[error("`{command}` failed: {message}")]
+    Systemctl {
+        /// The command line that was run.
+        command: String,
+        /// Error output or exit status.
+        message: String,
+    },
+}
+
+/// Get the directory for systemd user units (`~/.config/systemd/user`).
+///
+/// # Errors
+///
+/// Returns an error if the user's configuration directory is unknown.
+pub fn user_unit_dir() -> Result<PathBuf, SystemdError> {
+    dirs::config_dir()
+        .map(|dir| dir.join("systemd").join("user"))
+        .ok_or(SystemdError::NoUnitDirectory)
+}
+
+/// The daemon's systemd service unit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceUnit {
+    /// Absolute path of the `fliterec` binary.
+    pub exec_path: PathBuf,
+    /// Configuration file passed to the daemon with `--config`, if any.
+    pub config_path: Option<PathBuf>,
+    /// Watchdog timeout in seconds, or 0 to disable the watchdog.
+    pub watchdog_sec: u32,
+}
+
+impl ServiceUnit {
+    /// Create a unit that runs the given binary with the default settings.
+    #[must_use]
+    pub fn new(exec_path: impl Into<PathBuf>) -> Self {
+        Self {
+            exec_path: exec_path.into(),
+            config_path: None,
+            watchdog_sec: DEFAULT_WATCHDOG_SEC,
+        }
+    }
+
+    /// Pass a configuration file to the daemon.
+    #[must_use]
+    pub fn with_config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    /// Get the unit's `ExecStart=` command line.
+    #[must_use]
+    pub fn exec_start(&self) -> String {
+        let mut command = quote_arg(&self.exec_path);
+        if let Some(config) = &self.config_path {
+            command.push_str(" --config ");
+            command.push_str(&quote_arg(config));
+        }
+        command.push_str(" daemon start --foreground");
+        command
+    }
+
+    /// Render the unit file.
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut unit = String::from(
+            "[Unit]\n\
+             Description=flightrecorder text capture daemon\n\
+             Documentation=https://github.com/oxur/flightrecorder\n\
+             \n\
+             [Service]\n\
+             Type=notify\n\
+             NotifyAccess=main\n",
+        );
+        let _ = writeln!(unit, "ExecStart={}", self.exec_start());
+        if self.watchdog_sec > 0 {
+            let _ = writeln!(unit, "WatchdogSec={}", self.watchdog_sec);
+        }
+        unit.push_str(
+            "Restart=on-failure\n\
+             RestartSec=5\n\
+             \n\
+             [Install]\n\
+             WantedBy=default.target\n",
+        );
+        unit
+    }
+
+    /// Write the unit file into `dir`, creating it if needed, and return its path.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory or file cannot be written.
+    pub fn install(&self, dir: &Path) -> Result<PathBuf, SystemdError> {
+        std::fs::create_dir_all(dir).map_err(|source| SystemdError::Io {
+            path: dir.to_path_buf(),
+            source,
+        })?;
+        let path = dir.join(UNIT_NAME);
+        std::fs::write(&path, self.render()).map_err(|source| SystemdError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        Ok(path)
+    }
+}
+
+/// Remove the unit file from `dir`. Returns `false` if it was not installed.
+///
+/// # Errors
+///
+/// Returns an error if the file exists but cannot be removed.
+pub fn uninstall(dir: &Path) -> Result<bool, SystemdError> {
+    let path = dir.join(UNIT_NAME);
+    match std::fs::remove_file(&path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(source) => Err(SystemdError::Io { path, source }),
+    }
+}
+
+/// Quote an `ExecStart=` argument following systemd's command line syntax.
+fn quote_arg(arg: &Path) -> String {
+    // `%` introduces a specifier in unit files, even inside quotes.
+    let arg = arg.to_string_lossy().replace('%', "%%");
+    if arg.is_empty()
+        || arg
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';'))
+    {
+        format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        arg
+    }
+}
+
+/// Runs `systemctl --user` commands.
+#[derive(Debug, Clone)]
+pub struct Systemctl {
+    program: PathBuf,
+}
+
+impl Default for Systemctl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Systemctl {
+    /// Use the `systemctl` found on `PATH`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_program("systemctl")
+    }
+
+    /// Use a specific `systemctl` executable.
+    #[must_use]
+    pub fn with_program(program: impl Into<PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+        }
+    }
+
+    /// Reload unit files after installing or removing one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `systemctl` fails.
+    pub fn daemon_reload(&self) -> Result<(), SystemdError> {
+        self.run(&["daemon-reload"])
+    }
+
+    /// Enable the unit at login, starting it now if `now` is set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `systemctl` fails.
+    pub fn enable(&self, unit: &str, now: bool) -> Result<(), SystemdError> {
+        self.run(&with_now(&["enable", unit], now))
+    }
+
+    /// Disable the unit, stopping it now if `now` is set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `systemctl` fails.
+    pub fn disable(&self, unit: &str, now: bool) -> Result<(), SystemdError> {
+        self.run(&with_now(&["disable", unit], now))
+    }
+
+    fn run(&self, args: &[&str]) -> Result<(), SystemdError> {
+        let command = format!("{} --user {}", self.program.display(), args.join(" "));
+        let output = Command::new(&self.program)
+            .arg("--user")
+            .args(args)
+            .output()
+            .map_err(|e| SystemdError::Systemctl {
+                command: command.clone(),
+                message: e.to_string(),
+            })?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            Err(SystemdError::Systemctl {
+                command,
+                message: if stderr.is_empty() {
+                    output.status.to_string()
+                } else {
+                    stderr
+                },
+            })
+        }
+    }
+}
+
+fn with_now<'a>(args: &[&'a str], now: bool) -> Vec<&'a str> {
+    let mut args = args.to_vec();
+    if now {
+        args.push("--now");
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fr_systemd_{name}_{}", std::process::id()))
+    }
+
+    #[test]
+    fn test_render_unit() {
+        let unit = ServiceUnit::new("/usr/local/bin/fliterec").render();
+        assert!(unit.contains("Type=notify\n"));
+        assert!(unit.contains("ExecStart=/usr/local/bin/fliterec daemon start --foreground\n"));
+        assert!(unit.contains("WatchdogSec=30\n"));
+        assert!(unit.contains("WantedBy=default.target\n"));
+    }
+
+    #[test]
+    fn test_render_unit_without_watchdog() {
+        let unit = ServiceUnit {
+            watchdog_sec: 0,
+            ..ServiceUnit::new("/bin/fliterec")
+        };
+        assert!(!unit.render().contains("WatchdogSec"));
+    }
+
+    #[test]
+    fn test_exec_start_quotes_arguments() {
+        let unit = ServiceUnit::new("/opt/flight recorder/fliterec")
+            .with_config_path("/home/me/100%/config.toml");
+        assert_eq!(
+            unit.exec_start(),
+            "\"/opt/flight recorder/fliterec\" --config /home/me/100%%/config.toml \
+             daemon start --foreground"
+        );
+    }
+
+    #[test]
+    fn test_install_and_uninstall() {
+        let dir = test_dir("install");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let unit = ServiceUnit::new("/bin/fliterec");
+        let path = unit.install(&dir).unwrap();
+        assert_eq!(path, dir.join(UNIT_NAME));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), unit.render());
+
+        assert!(uninstall(&dir).unwrap());
+        assert!(!uninstall(&dir).unwrap());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_systemctl_success() {
+        // `true` ignores its arguments and succeeds.
+        assert!(Systemctl::with_program("true")
+            .enable(UNIT_NAME, true)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_systemctl_failure() {
+        let err = Systemctl::with_program("false")
+            .disable(UNIT_NAME, false)
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("false --user disable fliterec.service"));
+    }
+
+    #[test]
+    fn test_systemctl_missing_program() {
+        let result = Systemctl::with_program("/nonexistent/systemctl").daemon_reload();
+        assert!(matches!(result, Err(SystemdError::Systemctl { .. })));
+    }
+}
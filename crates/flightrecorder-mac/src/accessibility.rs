@@ -9,7 +9,7 @@
 //! approach that works across macOS versions.
 
 use std::process::Command;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -59,6 +59,9 @@ pub struct TextFieldCapture {
     /// Source application name.
     pub source_app: Option<String>,
 
+    /// Identity of the text field within the application, if known.
+    pub field_id: Option<String>,
+
     /// Whether this is from a password field.
     pub is_password_field: bool,
 }
@@ -73,6 +76,7 @@ impl TextFieldCapture {
             content_hash,
             timestamp: Utc::now(),
             source_app,
+            field_id: None,
             is_password_field,
         }
     }
@@ -113,6 +117,7 @@ impl Default for AccessibilityMonitorConfig {
 pub struct AccessibilityMonitor {
     config: AccessibilityMonitorConfig,
     running: Arc<AtomicBool>,
+    polls: Arc<AtomicU64>,
     last_hash: Option<String>,
 }
 
@@ -129,6 +134,7 @@ impl AccessibilityMonitor {
         Self {
             config,
             running: Arc::new(AtomicBool::new(false)),
+            polls: Arc::new(AtomicU64::new(0)),
             last_hash: None,
         }
     }
@@ -139,6 +145,13 @@ impl AccessibilityMonitor {
         self.running.load(Ordering::SeqCst)
     }
 
+    /// Get a counter that is incremented on every poll, so that a stalled
+    /// monitor can be detected.
+    #[must_use]
+    pub fn poll_counter(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.polls)
+    }
+
     /// Check if accessibility permissions are enabled.
     #[must_use]
     pub fn has_permission(&self) -> bool {
@@ -160,53 +173,7 @@ impl AccessibilityMonitor {
     ///
     /// Returns an error if accessibility permission is not granted.
     pub fn get_focused_text(&self) -> Result<Option<FocusedTextField>> {
-        if !self.has_permission() {
-            return Err(AccessibilityError::PermissionDenied(
-                permissions::get_permission_instructions().to_string(),
-            ));
-        }
-
-        // Use AppleScript to get the focused text field content
-        // This requires accessibility permissions
-        let script = r#"
-            tell application "System Events"
-                set frontApp to first process whose frontmost is true
-                tell frontApp
-                    try
-                        set focusedElement to focused of (first UI element whose focused is true)
-                        set elementValue to value of (first UI element whose focused is true)
-                        if elementValue is not missing value then
-                            return elementValue
-                        end if
-                    end try
-                end tell
-            end tell
-            return ""
-        "#;
-
-        let output = Command::new("osascript")
-            .args(["-e", script])
-            .output()
-            .map_err(|e| AccessibilityError::FocusedElementError(e.to_string()))?;
-
-        if !output.status.success() {
-            trace!("AppleScript returned non-zero exit code");
-            return Ok(None);
-        }
-
-        let content = String::from_utf8_lossy(&output.stdout).trim().to_string();
-        if content.is_empty() {
-            return Ok(None);
-        }
-
-        // Get the source application
-        let source_app = get_frontmost_app_name();
-
-        Ok(Some(FocusedTextField {
-            content,
-            source_app,
-            is_password: false, // We can't easily detect password fields via AppleScript
-        }))
+        read_focused_text()
     }
 
     /// Check for new text field content.
@@ -225,6 +192,21 @@ impl AccessibilityMonitor {
         self.process_field(field)
     }
 
+    /// Check for new text field content as [`check_for_changes`] does, but
+    /// read the focused field on a blocking thread, so that a hung
+    /// `osascript` never holds up the async runtime.
+    ///
+    /// [`check_for_changes`]: Self::check_for_changes
+    async fn poll(&mut self) -> Result<Option<TextFieldCapture>> {
+        let field = tokio::task::spawn_blocking(read_focused_text)
+            .await
+            .map_err(|e| AccessibilityError::FocusedElementError(e.to_string()))??;
+        match field {
+            Some(field) => self.process_field(field),
+            None => Ok(None),
+        }
+    }
+
     /// Process a focused text field and return a capture if it's new content.
     ///
     /// This is separated from `check_for_changes` to allow testing the logic
@@ -269,11 +251,10 @@ impl AccessibilityMonitor {
         );
         self.last_hash = Some(hash);
 
-        Ok(Some(TextFieldCapture::new(
-            content,
-            field.source_app,
-            field.is_password,
-        )))
+        Ok(Some(TextFieldCapture {
+            field_id: field.field_id,
+            ..TextFieldCapture::new(content, field.source_app, field.is_password)
+        }))
     }
 
     /// Start monitoring text fields and send captures through the channel.
@@ -304,8 +285,9 @@ impl AccessibilityMonitor {
 
         while self.running.load(Ordering::SeqCst) {
             ticker.tick().await;
+            self.polls.fetch_add(1, Ordering::Relaxed);
 
-            match self.check_for_changes() {
+            match self.poll().await {
                 Ok(Some(capture)) => {
                     if tx.send(capture).await.is_err() {
                         debug!("Capture channel closed, stopping monitor");
@@ -377,10 +359,98 @@ pub struct FocusedTextField {
     /// The source application name.
     pub source_app: Option<String>,
 
+    /// Identity of the field within the application: its role, followed by
+    /// its accessibility identifier and description when it has them.
+    pub field_id: Option<String>,
+
     /// Whether this is a password field.
     pub is_password: bool,
 }
 
+/// Read the focused text field of the frontmost application, as
+/// [`AccessibilityMonitor::get_focused_text`] does.
+///
+/// This runs `osascript` and blocks until it exits.
+///
+/// # Errors
+///
+/// Returns an error if accessibility permission is not granted.
+pub fn read_focused_text() -> Result<Option<FocusedTextField>> {
+    if !permissions::is_accessibility_enabled() {
+        return Err(AccessibilityError::PermissionDenied(
+            permissions::get_permission_instructions().to_string(),
+        ));
+    }
+
+    // Use AppleScript to get the focused text field's identity and content
+    // This requires accessibility permissions
+    let script = r#"
+        tell application "System Events"
+            set frontApp to first process whose frontmost is true
+            tell frontApp
+                try
+                    set focusedElement to (first UI element whose focused is true)
+                    set elementValue to value of focusedElement
+                    if elementValue is not missing value then
+                        set fieldId to role of focusedElement
+                        try
+                            set fieldId to fieldId & "/" & (value of attribute "AXIdentifier" of focusedElement)
+                        end try
+                        try
+                            set fieldId to fieldId & "/" & (description of focusedElement)
+                        end try
+                        return fieldId & linefeed & elementValue
+                    end if
+                end try
+            end tell
+        end tell
+        return ""
+    "#;
+
+    let output = Command::new("osascript")
+        .args(["-e", script])
+        .output()
+        .map_err(|e| AccessibilityError::FocusedElementError(e.to_string()))?;
+
+    if !output.status.success() {
+        trace!("AppleScript returned non-zero exit code");
+        return Ok(None);
+    }
+
+    let output = String::from_utf8_lossy(&output.stdout);
+    let Some((field_id, content)) = parse_focused_field(&output) else {
+        return Ok(None);
+    };
+
+    // Get the source application
+    let source_app = get_frontmost_app_name();
+
+    Ok(Some(FocusedTextField {
+        content,
+        source_app,
+        field_id,
+        is_password: false, // We can't easily detect password fields via AppleScript
+    }))
+}
+
+/// Split the output of the focused field script into the field's identity
+/// (its first line) and its content.
+///
+/// Returns `None` if there is no content.
+#[must_use]
+pub fn parse_focused_field(output: &str) -> Option<(Option<String>, String)> {
+    let (field_id, content) = output.split_once('\n').unwrap_or(("", output));
+    let content = content.trim();
+    if content.is_empty() {
+        return None;
+    }
+    let field_id = field_id.trim();
+    Some((
+        (!field_id.is_empty()).then(|| field_id.to_string()),
+        content.to_string(),
+    ))
+}
+
 /// Get the name of the frontmost application.
 #[must_use]
 pub fn get_frontmost_app_name() -> Option<String> {
@@ -443,6 +513,23 @@ pub fn text_content_changed(content: &str, last_hash: Option<&str>) -> bool {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_focused_field() {
+        assert_eq!(
+            parse_focused_field("AXTextArea/composer\nHello,\nWorld!\n"),
+            Some((
+                Some("AXTextArea/composer".to_string()),
+                "Hello,\nWorld!".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_focused_field("\nHello"),
+            Some((None, "Hello".to_string()))
+        );
+        assert_eq!(parse_focused_field("AXTextField\n  \n"), None);
+        assert_eq!(parse_focused_field(""), None);
+    }
+
     #[test]
     fn test_text_field_capture_new() {
         let capture = TextFieldCapture::new(
@@ -538,6 +625,7 @@ mod tests {
         let field = FocusedTextField {
             content: "test".to_string(),
             source_app: Some("App".to_string()),
+            field_id: None,
             is_password: false,
         };
         let debug = format!("{field:?}");
@@ -549,6 +637,7 @@ mod tests {
         let field = FocusedTextField {
             content: "test".to_string(),
             source_app: Some("App".to_string()),
+            field_id: None,
             is_password: true,
         };
         let cloned = field.clone();
@@ -660,6 +749,7 @@ mod tests {
         let field = FocusedTextField {
             content: "test".to_string(),
             source_app: None,
+            field_id: None,
             is_password: false,
         };
         assert!(field.source_app.is_none());
@@ -789,6 +879,7 @@ mod tests {
         let field = FocusedTextField {
             content: "secret123".to_string(),
             source_app: Some("1Password".to_string()),
+            field_id: None,
             is_password: true,
         };
 
@@ -801,6 +892,7 @@ mod tests {
         let field = FocusedTextField {
             content: "Hello, World!".to_string(),
             source_app: Some("TextEdit".to_string()),
+            field_id: None,
             is_password: false,
         };
 
@@ -990,6 +1082,7 @@ mod tests {
         let field = FocusedTextField {
             content: "Hello, World!".to_string(),
             source_app: Some("TextEdit".to_string()),
+            field_id: None,
             is_password: false,
         };
 
@@ -1012,6 +1105,7 @@ mod tests {
         let field = FocusedTextField {
             content: "secret123".to_string(),
             source_app: Some("Safari".to_string()),
+            field_id: None,
             is_password: true,
         };
 
@@ -1029,6 +1123,7 @@ mod tests {
         let field = FocusedTextField {
             content: "secret123".to_string(),
             source_app: Some("Safari".to_string()),
+            field_id: None,
             is_password: true,
         };
 
@@ -1047,6 +1142,7 @@ mod tests {
         let field = FocusedTextField {
             content: "short".to_string(),
             source_app: None,
+            field_id: None,
             is_password: false,
         };
 
@@ -1064,6 +1160,7 @@ mod tests {
         let field = FocusedTextField {
             content: "This is a very long string that should be truncated".to_string(),
             source_app: None,
+            field_id: None,
             is_password: false,
         };
 
@@ -1132,6 +1229,7 @@ mod tests {
         let field = FocusedTextField {
             content: "Test content".to_string(),
             source_app: None,
+            field_id: None,
             is_password: false,
         };
 
@@ -1150,6 +1248,7 @@ mod tests {
         let field = FocusedTextField {
             content: "12345".to_string(),
             source_app: None,
+            field_id: None,
             is_password: false,
         };
 
@@ -1167,6 +1266,7 @@ mod tests {
         let field = FocusedTextField {
             content: "12345".to_string(),
             source_app: None,
+            field_id: None,
             is_password: false,
         };
 
@@ -1235,6 +1335,7 @@ mod tests {
         let field = FocusedTextField {
             content: "   \n\t\r  ".to_string(),
             source_app: None,
+            field_id: None,
             is_password: false,
         };
 
@@ -1252,6 +1353,7 @@ mod tests {
         let field = FocusedTextField {
             content: "x".repeat(10000),
             source_app: None,
+            field_id: None,
             is_password: false,
         };
 
@@ -1270,6 +1372,7 @@ mod tests {
         let field = FocusedTextField {
             content: "password123".to_string(),
             source_app: Some("1Password".to_string()),
+            field_id: None,
             is_password: true,
         };
 
@@ -1287,6 +1390,7 @@ mod tests {
         let field = FocusedTextField {
             content: "password123".to_string(),
             source_app: Some("1Password".to_string()),
+            field_id: None,
             is_password: true,
         };
 
@@ -1306,6 +1410,7 @@ mod tests {
         let field = FocusedTextField {
             content: "test content".to_string(),
             source_app: None,
+            field_id: None,
             is_password: false,
         };
 
@@ -1321,6 +1426,7 @@ mod tests {
         let field = FocusedTextField {
             content: special.clone(),
             source_app: None,
+            field_id: None,
             is_password: false,
         };
 
@@ -1339,6 +1445,7 @@ mod tests {
         let field = FocusedTextField {
             content: "1234".to_string(),
             source_app: None,
+            field_id: None,
             is_password: false,
         };
 
@@ -1356,6 +1463,7 @@ mod tests {
         let field = FocusedTextField {
             content: "123456".to_string(),
             source_app: None,
+            field_id: None,
             is_password: false,
         };
 
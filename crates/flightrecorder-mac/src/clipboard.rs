@@ -4,7 +4,7 @@
 //! It monitors the system clipboard for changes and notifies callbacks when new
 //! text content is detected.
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -95,6 +95,7 @@ impl Default for ClipboardMonitorConfig {
 pub struct ClipboardMonitor {
     config: ClipboardMonitorConfig,
     running: Arc<AtomicBool>,
+    polls: Arc<AtomicU64>,
     last_hash: Option<String>,
 }
 
@@ -111,6 +112,7 @@ impl ClipboardMonitor {
         Self {
             config,
             running: Arc::new(AtomicBool::new(false)),
+            polls: Arc::new(AtomicU64::new(0)),
             last_hash: None,
         }
     }
@@ -121,20 +123,20 @@ impl ClipboardMonitor {
         self.running.load(Ordering::SeqCst)
     }
 
+    /// Get a counter that is incremented on every poll, so that a stalled
+    /// monitor can be detected.
+    #[must_use]
+    pub fn poll_counter(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.polls)
+    }
+
     /// Get the current clipboard text content.
     ///
     /// # Errors
     ///
     /// Returns an error if clipboard access fails.
     pub fn get_current_text(&self) -> Result<Option<String>> {
-        let ctx =
-            ClipboardContext::new().map_err(|e| ClipboardError::AccessFailed(e.to_string()))?;
-
-        match ctx.get_text() {
-            Ok(text) if !text.is_empty() => Ok(Some(text)),
-            // No text content or non-text clipboard is not an error
-            Ok(_) | Err(_) => Ok(None),
-        }
+        read_clipboard_text()
     }
 
     /// Check the clipboard for new content.
@@ -153,6 +155,23 @@ impl ClipboardMonitor {
         self.process_text(text)
     }
 
+    /// Check the clipboard for new content as [`check_for_changes`] does, but
+    /// read the clipboard and the frontmost application on a blocking thread,
+    /// so that a hung read never holds up the async runtime.
+    ///
+    /// [`check_for_changes`]: Self::check_for_changes
+    async fn poll(&mut self) -> Result<Option<ClipboardCapture>> {
+        let read = tokio::task::spawn_blocking(|| {
+            Ok::<_, ClipboardError>(read_clipboard_text()?.map(|text| (text, get_frontmost_app())))
+        })
+        .await
+        .map_err(|e| ClipboardError::AccessFailed(e.to_string()))??;
+        match read {
+            Some((text, source_app)) => self.process_text_with_source(text, source_app),
+            None => Ok(None),
+        }
+    }
+
     /// Process clipboard text and return a capture if it's new content.
     ///
     /// This is separated from `check_for_changes` to allow testing the logic
@@ -245,8 +264,9 @@ impl ClipboardMonitor {
 
         while self.running.load(Ordering::SeqCst) {
             ticker.tick().await;
+            self.polls.fetch_add(1, Ordering::Relaxed);
 
-            match self.check_for_changes() {
+            match self.poll().await {
                 Ok(Some(capture)) => {
                     if tx.send(capture).await.is_err() {
                         debug!("Capture channel closed, stopping monitor");
@@ -307,6 +327,21 @@ impl ClipboardMonitorHandle {
     }
 }
 
+/// Read the text on the clipboard, if there is any.
+///
+/// # Errors
+///
+/// Returns an error if clipboard access fails.
+pub fn read_clipboard_text() -> Result<Option<String>> {
+    let ctx = ClipboardContext::new().map_err(|e| ClipboardError::AccessFailed(e.to_string()))?;
+
+    match ctx.get_text() {
+        Ok(text) if !text.is_empty() => Ok(Some(text)),
+        // No text content or non-text clipboard is not an error
+        Ok(_) | Err(_) => Ok(None),
+    }
+}
+
 /// Get the frontmost (active) application name on macOS.
 ///
 /// This uses the macOS Accessibility API to determine which application
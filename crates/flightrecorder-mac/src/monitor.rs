@@ -44,6 +44,9 @@ pub struct CaptureData {
     /// Source application.
     pub source_app: Option<String>,
 
+    /// Identity of the text field within the source application, if known.
+    pub field_id: Option<String>,
+
     /// Type of capture.
     pub capture_type: CaptureType,
 }
@@ -162,6 +165,7 @@ impl MacClipboardMonitor {
                     content_hash: capture.content_hash,
                     timestamp: capture.timestamp,
                     source_app: capture.source_app,
+                    field_id: None,
                     capture_type: CaptureType::Clipboard,
                 };
 
@@ -191,6 +195,12 @@ impl MacClipboardMonitor {
         self.running.store(false, Ordering::SeqCst);
     }
 
+    /// Get a counter that is incremented on every poll.
+    #[must_use]
+    pub fn poll_counter(&self) -> Arc<AtomicU64> {
+        self.inner.poll_counter()
+    }
+
     /// Get a stop handle.
     #[must_use]
     pub fn stop_handle(&self) -> MacMonitorHandle {
@@ -297,6 +307,7 @@ impl MacAccessibilityMonitor {
                     content_hash: capture.content_hash,
                     timestamp: capture.timestamp,
                     source_app: capture.source_app,
+                    field_id: capture.field_id,
                     capture_type: CaptureType::TextField,
                 };
 
@@ -330,6 +341,12 @@ impl MacAccessibilityMonitor {
         self.running.store(false, Ordering::SeqCst);
     }
 
+    /// Get a counter that is incremented on every poll.
+    #[must_use]
+    pub fn poll_counter(&self) -> Arc<AtomicU64> {
+        self.inner.poll_counter()
+    }
+
     /// Get a stop handle.
     #[must_use]
     pub fn stop_handle(&self) -> MacMonitorHandle {
@@ -428,6 +445,7 @@ mod tests {
             content_hash: "hash123".to_string(),
             timestamp: Utc::now(),
             source_app: Some("TestApp".to_string()),
+            field_id: None,
             capture_type: CaptureType::Clipboard,
         };
         let debug_str = format!("{:?}", data);
@@ -444,6 +462,7 @@ mod tests {
             content_hash: "hash".to_string(),
             timestamp: Utc::now(),
             source_app: None,
+            field_id: None,
             capture_type: CaptureType::TextField,
         };
         let cloned = data.clone();
@@ -460,6 +479,7 @@ mod tests {
             content_hash: "hash".to_string(),
             timestamp: Utc::now(),
             source_app: None,
+            field_id: None,
             capture_type: CaptureType::Keystroke,
         };
 
@@ -0,0 +1,129 @@
+//! Disk usage of draft versions with and without delta compression.
+//!
+//! Replays a synthetic editing workload: several drafts typed a few words at
+//! a time, with the occasional correction earlier in the text, snapshotted
+//! after every edit. The same workload is stored once with every version in
+//! full and once with the default keyframe interval, and the resulting
+//! database sizes are compared.
+//!
+//! Run with `cargo bench -p flightrecorder --bench delta_storage`.
+
+use std::path::Path;
+use std::time::Instant;
+
+use chrono::{Duration, Utc};
+use flightrecorder::capture::{Capture, CaptureType};
+use flightrecorder::storage::{Storage, DEFAULT_KEYFRAME_INTERVAL};
+
+const DRAFTS: usize = 20;
+const EDITS_PER_DRAFT: usize = 300;
+
+const WORDS: &[&str] = &[
+    "the",
+    "release",
+    "schedule",
+    "needs",
+    "another",
+    "review",
+    "before",
+    "we",
+    "ship",
+    "and",
+    "customers",
+    "expect",
+    "notes",
+    "about",
+    "every",
+    "change",
+    "so",
+    "please",
+    "check",
+    "draft",
+];
+
+/// A small deterministic pseudo-random generator, so every run stores the
+/// same workload.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        usize::try_from(self.0 >> 33).unwrap_or(0) % bound
+    }
+}
+
+/// The snapshots of every draft, in the order they are taken.
+fn workload() -> Vec<(String, String)> {
+    let mut rng = Lcg(42);
+    let mut snapshots = Vec::new();
+    for draft in 0..DRAFTS {
+        let app = format!("App {draft}");
+        let mut text = String::new();
+        for _ in 0..EDITS_PER_DRAFT {
+            if text.len() > 200 && rng.next(10) == 0 {
+                // Fix a word somewhere earlier in the text.
+                let at = rng.next(text.len() - 1);
+                let at = (at..text.len())
+                    .find(|&i| text.is_char_boundary(i))
+                    .unwrap_or(text.len());
+                text.insert_str(at, WORDS[rng.next(WORDS.len())]);
+            } else {
+                for _ in 0..=rng.next(3) {
+                    text.push_str(WORDS[rng.next(WORDS.len())]);
+                    text.push(' ');
+                }
+            }
+            snapshots.push((app.clone(), text.clone()));
+        }
+    }
+    snapshots
+}
+
+/// Store the workload with the given keyframe interval, returning the size
+/// of the database file.
+fn store(path: &Path, keyframe_interval: u32, snapshots: &[(String, String)]) -> u64 {
+    let storage = Storage::open(path)
+        .expect("failed to open database")
+        .with_keyframe_interval(keyframe_interval);
+    let start = Utc::now() - Duration::days(1);
+    for (i, (app, text)) in snapshots.iter().enumerate() {
+        let mut capture = Capture::new(text.clone(), CaptureType::TextField, Some(app.clone()));
+        capture.timestamp = start + Duration::seconds(i64::try_from(i).unwrap_or(0));
+        storage.insert(&capture).expect("failed to insert capture");
+    }
+    // Closing the connection checkpoints the write-ahead log into the file.
+    drop(storage);
+    std::fs::metadata(path).map_or(0, |m| m.len())
+}
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("fr_bench_delta_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("failed to create temporary directory");
+    let snapshots = workload();
+    let logical: usize = snapshots.iter().map(|(_, text)| text.len()).sum();
+    println!(
+        "{} snapshots of {DRAFTS} drafts, {} KiB of text",
+        snapshots.len(),
+        logical / 1024
+    );
+
+    let mut sizes = Vec::new();
+    for (name, interval) in [("full", 1), ("delta", DEFAULT_KEYFRAME_INTERVAL)] {
+        let started = Instant::now();
+        let size = store(&dir.join(format!("{name}.db")), interval, &snapshots);
+        println!(
+            "{name:>6}: keyframe interval {interval:>2}, {:>7} KiB on disk, stored in {:?}",
+            size / 1024,
+            started.elapsed()
+        );
+        sizes.push(size);
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let ratio = sizes[0] as f64 / sizes[1].max(1) as f64;
+    println!("deltas use {ratio:.1}x less disk");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -53,6 +53,38 @@ pub struct Capture {
 
     /// How this text was captured.
     pub capture_type: CaptureType,
+
+    /// Identifies the text field within its application, as reported by the
+    /// monitor (such as the field's role and accessibility identifier), so
+    /// that snapshots of different fields are not versions of one draft.
+    /// `None` when the monitor cannot tell fields apart. Storage keeps only a
+    /// hash of it, so it is not set on captures read back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field_id: Option<String>,
+
+    /// The draft session this capture is a version of (assigned by storage
+    /// layer). Successive snapshots of one text field share a session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<i64>,
+
+    /// Position of this capture in its session, starting at 1 (assigned by
+    /// storage layer).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+
+    /// Whether the user pinned this capture, which exempts it from pruning
+    /// (set through the storage layer).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+
+    /// The user's tags on this capture, in alphabetical order (set through
+    /// the storage layer).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    /// The user's note on this capture (set through the storage layer).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
 }
 
 impl Capture {
@@ -69,9 +101,22 @@ impl Capture {
             content,
             content_hash,
             capture_type,
+            field_id: None,
+            session_id: None,
+            version: None,
+            pinned: false,
+            tags: Vec::new(),
+            note: None,
         }
     }
 
+    /// Set the text field this capture was taken from.
+    #[must_use]
+    pub fn with_field_id(mut self, field_id: impl Into<String>) -> Self {
+        self.field_id = Some(field_id.into());
+        self
+    }
+
     /// Compute the BLAKE3 hash of the given content.
     #[must_use]
     pub fn compute_hash(content: &str) -> String {
@@ -209,4 +254,31 @@ mod tests {
         assert_eq!(capture.source_app, deserialized.source_app);
         assert_eq!(capture.content_hash, deserialized.content_hash);
     }
+
+    #[test]
+    fn test_annotations_are_omitted_when_unset() {
+        let mut capture = Capture::new("Test".to_string(), CaptureType::Clipboard, None);
+        let json = serde_json::to_value(&capture).unwrap();
+        assert!(json.get("pinned").is_none());
+        assert!(json.get("tags").is_none());
+        assert!(json.get("note").is_none());
+
+        capture.pinned = true;
+        capture.tags = vec!["incident".to_string()];
+        capture.note = Some("send to Sam".to_string());
+        let json = serde_json::to_string(&capture).unwrap();
+        assert_eq!(serde_json::from_str::<Capture>(&json).unwrap(), capture);
+    }
+
+    #[test]
+    fn test_field_id() {
+        let capture = Capture::new("Test".to_string(), CaptureType::TextField, None);
+        let json = serde_json::to_value(&capture).unwrap();
+        assert!(json.get("field_id").is_none());
+
+        let capture = capture.with_field_id("AXTextArea/composer");
+        assert_eq!(capture.field_id.as_deref(), Some("AXTextArea/composer"));
+        let json = serde_json::to_string(&capture).unwrap();
+        assert_eq!(serde_json::from_str::<Capture>(&json).unwrap(), capture);
+    }
 }
@@ -30,6 +30,9 @@ pub enum DaemonCommand {
         foreground: bool,
     },
 
+    /// Reload the running daemon's configuration file
+    Reload,
+
     /// Install daemon as system service
     Install {
         /// Start the service after installation
@@ -56,7 +59,8 @@ pub struct StatusCommand {
 /// Search command arguments.
 #[derive(Debug, Args)]
 pub struct SearchCommand {
-    /// The search query (searches content)
+    /// The search query: words, "quoted phrases", prefix* terms and
+    /// AND/OR/NOT
     pub query: String,
 
     /// Filter by source application
@@ -75,6 +79,14 @@ pub struct SearchCommand {
     #[arg(long)]
     pub until: Option<String>,
 
+    /// Only show captures with this tag
+    #[arg(long)]
+    pub tag: Option<String>,
+
+    /// Only show pinned captures
+    #[arg(long)]
+    pub pinned: bool,
+
     /// Maximum number of results
     #[arg(short, long, default_value = "20")]
     pub limit: usize,
@@ -99,6 +111,22 @@ pub struct RecoverCommand {
     #[arg(long)]
     pub since: Option<String>,
 
+    /// Only show captures with this tag
+    #[arg(long)]
+    pub tag: Option<String>,
+
+    /// Only show pinned captures
+    #[arg(long)]
+    pub pinned: bool,
+
+    /// Show every version of the draft containing this capture ID
+    #[arg(
+        long,
+        value_name = "ID",
+        conflicts_with_all = ["last", "app", "since", "tag", "pinned"]
+    )]
+    pub history: Option<i64>,
+
     /// Copy recovered content to clipboard
     #[arg(long)]
     pub to_clipboard: bool,
@@ -112,6 +140,196 @@ pub struct RecoverCommand {
     pub format: OutputFormat,
 }
 
+/// Tail command arguments.
+#[derive(Debug, Args)]
+pub struct TailCommand {
+    /// Only show captures from this application
+    #[arg(short, long)]
+    pub app: Option<String>,
+
+    /// Only show captures of this type
+    #[arg(short = 't', long = "type", value_enum)]
+    pub capture_type: Option<CaptureTypeArg>,
+
+    /// Output format (json prints one object per line)
+    #[arg(short, long, value_enum, default_value = "plain")]
+    pub format: OutputFormat,
+}
+
+/// Prune command arguments.
+#[derive(Debug, Args)]
+pub struct PruneCommand {
+    /// Report what would be deleted without deleting anything
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Output as JSON
+    #[arg(short, long)]
+    pub json: bool,
+}
+
+/// Delete command arguments.
+#[derive(Debug, Args)]
+pub struct DeleteCommand {
+    /// IDs of the captures to move to the trash
+    #[arg(required = true, value_name = "ID")]
+    pub ids: Vec<i64>,
+}
+
+/// Pin command arguments.
+#[derive(Debug, Args)]
+pub struct PinCommand {
+    /// ID of the capture
+    pub id: i64,
+
+    /// Unpin the capture instead
+    #[arg(short, long)]
+    pub unpin: bool,
+}
+
+/// Tag command arguments.
+#[derive(Debug, Args)]
+pub struct TagCommand {
+    /// ID of the capture
+    pub id: i64,
+
+    /// Tags to add, one word each
+    #[arg(required = true)]
+    pub tags: Vec<String>,
+
+    /// Remove the tags instead
+    #[arg(short, long)]
+    pub remove: bool,
+}
+
+/// Note command arguments.
+#[derive(Debug, Args)]
+pub struct NoteCommand {
+    /// ID of the capture
+    pub id: i64,
+
+    /// The note, replacing any note the capture has
+    #[arg(required_unless_present = "clear")]
+    pub text: Option<String>,
+
+    /// Remove the capture's note
+    #[arg(long, conflicts_with = "text")]
+    pub clear: bool,
+}
+
+/// Trash commands.
+#[derive(Debug, Subcommand)]
+pub enum TrashCommand {
+    /// List the captures in the trash, most recently deleted first
+    List {
+        /// Maximum number of captures to list
+        #[arg(short, long, default_value = "20")]
+        limit: usize,
+
+        /// Output format
+        #[arg(short, long, value_enum, default_value = "table")]
+        format: OutputFormat,
+    },
+
+    /// Take captures out of the trash
+    Restore {
+        /// IDs of the captures to restore
+        #[arg(required = true, value_name = "ID")]
+        ids: Vec<i64>,
+    },
+
+    /// Delete every capture in the trash for good
+    Empty {
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+/// Database maintenance commands.
+#[derive(Debug, Subcommand)]
+pub enum DbCommand {
+    /// Encrypt the database, change its key, or decrypt it
+    ///
+    /// Stop the daemon first, and point `storage.key_file` or
+    /// `storage.passphrase_env` at the new key afterwards.
+    Rekey(RekeyCommand),
+
+    /// Check the database for damage
+    ///
+    /// Runs the database's integrity check and reads back every capture. With
+    /// `--repair` (the daemon must be stopped), damaged captures are moved to
+    /// the quarantine table rather than deleted.
+    Check(CheckCommand),
+
+    /// Back up the database to a file
+    ///
+    /// Uses the `SQLite` online backup API, so the daemon can keep running. An
+    /// encrypted database stays encrypted in the backup, under the same key.
+    Backup(BackupCommand),
+
+    /// Replace the database with a backup
+    ///
+    /// Stops the daemon if it is running. The database being replaced is
+    /// saved next to it as `<database>.before-restore.bak`.
+    Restore(RestoreCommand),
+}
+
+/// Check command arguments.
+#[derive(Debug, Args)]
+pub struct CheckCommand {
+    /// Quarantine damaged captures and remove orphaned index entries
+    #[arg(long)]
+    pub repair: bool,
+
+    /// Output as JSON
+    #[arg(short, long)]
+    pub json: bool,
+}
+
+/// Backup command arguments.
+#[derive(Debug, Args)]
+pub struct BackupCommand {
+    /// File to write the backup to, replacing any file there
+    pub path: PathBuf,
+}
+
+/// Restore command arguments.
+#[derive(Debug, Args)]
+pub struct RestoreCommand {
+    /// Backup to restore
+    pub path: PathBuf,
+
+    /// Skip confirmation prompt
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+/// Rekey command arguments.
+#[derive(Debug, Args)]
+#[command(group(
+    clap::ArgGroup::new("new_key")
+        .required(true)
+        .args(["key_file", "passphrase_env", "decrypt"])
+))]
+pub struct RekeyCommand {
+    /// Encrypt with the key in this file (at least 32 random bytes)
+    #[arg(long, value_name = "FILE")]
+    pub key_file: Option<PathBuf>,
+
+    /// Encrypt with the passphrase in this environment variable
+    #[arg(long, value_name = "VAR")]
+    pub passphrase_env: Option<String>,
+
+    /// Decrypt the database
+    #[arg(long)]
+    pub decrypt: bool,
+
+    /// Also replace the data key, re-encrypting every capture
+    #[arg(long, conflicts_with = "decrypt")]
+    pub rotate_data_key: bool,
+}
+
 /// Configuration commands.
 #[derive(Debug, Subcommand)]
 pub enum ConfigCommand {
@@ -224,6 +442,8 @@ mod tests {
             capture_type: None,
             since: None,
             until: None,
+            tag: None,
+            pinned: false,
             limit: 20,
             format: OutputFormat::Table,
         };
@@ -238,6 +458,9 @@ mod tests {
             last: Some(10),
             app: None,
             since: None,
+            tag: None,
+            pinned: false,
+            history: None,
             to_clipboard: false,
             interactive: false,
             format: OutputFormat::Plain,
@@ -246,6 +469,25 @@ mod tests {
         assert!(debug_str.contains("last"));
     }
 
+    #[test]
+    fn test_tail_command_debug() {
+        let cmd = TailCommand {
+            app: Some("Terminal".to_string()),
+            capture_type: None,
+            format: OutputFormat::Json,
+        };
+        let debug_str = format!("{cmd:?}");
+        assert!(debug_str.contains("Terminal"));
+    }
+
+    #[test]
+    fn test_trash_command_debug() {
+        let cmd = TrashCommand::Restore { ids: vec![3, 4] };
+        let debug_str = format!("{cmd:?}");
+        assert!(debug_str.contains("Restore"));
+        assert!(debug_str.contains('4'));
+    }
+
     #[test]
     fn test_config_command_debug() {
         let cmd = ConfigCommand::Show { json: false };
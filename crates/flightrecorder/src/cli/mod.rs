@@ -4,12 +4,20 @@
 //! `fliterec` binary.
 
 mod commands;
+pub mod output;
+pub mod status;
+pub mod time;
 
 use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
 
-pub use commands::{ConfigCommand, DaemonCommand, RecoverCommand, SearchCommand, StatusCommand};
+pub use commands::{
+    BackupCommand, CaptureTypeArg, CheckCommand, ConfigCommand, DaemonCommand, DbCommand,
+    DeleteCommand, NoteCommand, OutputFormat, PinCommand, PruneCommand, RecoverCommand,
+    RekeyCommand, RestoreCommand, SearchCommand, StatusCommand, TagCommand, TailCommand,
+    TrashCommand,
+};
 
 /// fliterec - Preserve your ephemeral text input
 ///
@@ -53,9 +61,36 @@ pub enum Command {
     /// Recover captured text
     Recover(RecoverCommand),
 
+    /// Follow new captures as the daemon stores them
+    #[command(visible_alias = "stream")]
+    Tail(TailCommand),
+
+    /// Delete captures beyond the configured retention limits
+    Prune(PruneCommand),
+
+    /// Move captures to the trash
+    Delete(DeleteCommand),
+
+    /// List, restore or empty deleted captures
+    #[command(subcommand)]
+    Trash(TrashCommand),
+
+    /// Pin a capture so that it is never pruned
+    Pin(PinCommand),
+
+    /// Add or remove tags on a capture
+    Tag(TagCommand),
+
+    /// Set or clear the note on a capture
+    Note(NoteCommand),
+
     /// View or modify configuration
     #[command(subcommand)]
     Config(ConfigCommand),
+
+    /// Maintain the capture database
+    #[command(subcommand)]
+    Db(DbCommand),
 }
 
 impl Cli {
@@ -78,6 +113,7 @@ impl Cli {
 mod tests {
     use super::*;
     use clap::CommandFactory;
+    use std::path::Path;
 
     #[test]
     fn test_cli_debug() {
@@ -145,6 +181,16 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_parse_daemon_reload() {
+        let args = vec!["fliterec", "daemon", "reload"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Daemon(DaemonCommand::Reload)
+        ));
+    }
+
     #[test]
     fn test_parse_status() {
         let args = vec!["fliterec", "status"];
@@ -159,6 +205,160 @@ mod tests {
         assert!(matches!(cli.command, Command::Search(_)));
     }
 
+    #[test]
+    fn test_parse_tail() {
+        let args = vec![
+            "fliterec",
+            "tail",
+            "--app",
+            "Terminal",
+            "--type",
+            "clipboard",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Command::Tail(tail) => {
+                assert_eq!(tail.app.as_deref(), Some("Terminal"));
+                assert_eq!(tail.capture_type, Some(CaptureTypeArg::Clipboard));
+                assert_eq!(tail.format, OutputFormat::Plain);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_stream_alias() {
+        let args = vec!["fliterec", "stream", "--format", "json"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert!(matches!(cli.command, Command::Tail(t) if t.format == OutputFormat::Json));
+    }
+
+    #[test]
+    fn test_parse_prune() {
+        let cli = Cli::try_parse_from(["fliterec", "prune", "--dry-run"]).unwrap();
+        assert!(matches!(cli.command, Command::Prune(p) if p.dry_run && !p.json));
+        let cli = Cli::try_parse_from(["fliterec", "prune"]).unwrap();
+        assert!(matches!(cli.command, Command::Prune(p) if !p.dry_run));
+    }
+
+    #[test]
+    fn test_parse_delete_and_trash() {
+        let cli = Cli::try_parse_from(["fliterec", "delete", "3", "5"]).unwrap();
+        assert!(matches!(cli.command, Command::Delete(d) if d.ids == [3, 5]));
+        assert!(Cli::try_parse_from(["fliterec", "delete"]).is_err());
+
+        let cli = Cli::try_parse_from(["fliterec", "trash", "list"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Trash(TrashCommand::List {
+                limit: 20,
+                format: OutputFormat::Table
+            })
+        ));
+        let cli = Cli::try_parse_from(["fliterec", "trash", "restore", "3"]).unwrap();
+        assert!(matches!(cli.command, Command::Trash(TrashCommand::Restore { ids }) if ids == [3]));
+        let cli = Cli::try_parse_from(["fliterec", "trash", "empty", "--yes"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Trash(TrashCommand::Empty { yes: true })
+        ));
+    }
+
+    #[test]
+    fn test_parse_pin_tag_and_note() {
+        let cli = Cli::try_parse_from(["fliterec", "pin", "7"]).unwrap();
+        assert!(matches!(cli.command, Command::Pin(p) if p.id == 7 && !p.unpin));
+        let cli = Cli::try_parse_from(["fliterec", "tag", "7", "incident", "work"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Tag(t) if t.id == 7 && t.tags == ["incident", "work"] && !t.remove
+        ));
+        assert!(Cli::try_parse_from(["fliterec", "tag", "7"]).is_err());
+
+        let cli = Cli::try_parse_from(["fliterec", "note", "7", "finish on Monday"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Note(n) if n.text.as_deref() == Some("finish on Monday")
+        ));
+        let cli = Cli::try_parse_from(["fliterec", "note", "7", "--clear"]).unwrap();
+        assert!(matches!(cli.command, Command::Note(n) if n.clear && n.text.is_none()));
+        assert!(Cli::try_parse_from(["fliterec", "note", "7"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_tag_and_pinned_filters() {
+        let args = [
+            "fliterec", "search", "report", "--tag", "incident", "--pinned",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Search(s) if s.tag.as_deref() == Some("incident") && s.pinned
+        ));
+        let cli = Cli::try_parse_from(["fliterec", "recover", "--pinned"]).unwrap();
+        assert!(matches!(cli.command, Command::Recover(r) if r.pinned && r.tag.is_none()));
+        let args = ["fliterec", "recover", "--history", "3", "--tag", "x"];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn test_parse_db_rekey() {
+        let args = vec!["fliterec", "db", "rekey", "--key-file", "/keys/new.key"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Command::Db(DbCommand::Rekey(rekey)) => {
+                assert_eq!(rekey.key_file, Some(PathBuf::from("/keys/new.key")));
+                assert!(!rekey.decrypt && !rekey.rotate_data_key);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        // Exactly one new key is required, and decrypting has no data key.
+        assert!(Cli::try_parse_from(["fliterec", "db", "rekey"]).is_err());
+        assert!(Cli::try_parse_from([
+            "fliterec",
+            "db",
+            "rekey",
+            "--decrypt",
+            "--passphrase-env",
+            "X"
+        ])
+        .is_err());
+        assert!(
+            Cli::try_parse_from(["fliterec", "db", "rekey", "--decrypt", "--rotate-data-key"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_db_check() {
+        let cli = Cli::try_parse_from(["fliterec", "db", "check"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Db(DbCommand::Check(check)) if !check.repair && !check.json
+        ));
+        let cli = Cli::try_parse_from(["fliterec", "db", "check", "--repair"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Db(DbCommand::Check(check)) if check.repair
+        ));
+    }
+
+    #[test]
+    fn test_parse_db_backup_and_restore() {
+        let cli = Cli::try_parse_from(["fliterec", "db", "backup", "backup.db"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Db(DbCommand::Backup(backup)) if backup.path == Path::new("backup.db")
+        ));
+        let cli = Cli::try_parse_from(["fliterec", "db", "restore", "backup.db", "--yes"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Db(DbCommand::Restore(restore)) if restore.yes
+        ));
+        assert!(Cli::try_parse_from(["fliterec", "db", "backup"]).is_err());
+    }
+
     #[test]
     fn test_parse_with_config() {
         let args = vec!["fliterec", "-c", "/custom/config.toml", "status"];
@@ -0,0 +1,483 @@
+//! Rendering of captures, prune reports and database checks for CLI output.
+
+use std::fmt::Write as _;
+
+use super::commands::OutputFormat;
+use super::status::format_bytes;
+use crate::capture::Capture;
+use crate::error::Result;
+use crate::storage::{
+    BackupInfo, CheckReport, PruneReport, RepairReport, SearchHit, TrashedCapture,
+};
+
+/// Maximum number of characters of content shown per row in table output.
+const TABLE_PREVIEW_CHARS: usize = 60;
+
+/// Render captures in the requested output format.
+///
+/// # Errors
+///
+/// Returns an error if JSON serialization fails.
+pub fn format_captures(captures: &[Capture], format: OutputFormat) -> Result<String> {
+    match format {
+        OutputFormat::Json => Ok(serde_json::to_string_pretty(captures)?),
+        OutputFormat::Plain => Ok(format_plain(captures)),
+        OutputFormat::Table => Ok(format_table(captures)),
+    }
+}
+
+/// Render search hits in the requested output format.
+///
+/// Table output previews each hit's snippet, with matched terms highlighted,
+/// instead of the start of its content.
+///
+/// # Errors
+///
+/// Returns an error if JSON serialization fails.
+pub fn format_search_hits(hits: &[SearchHit], format: OutputFormat) -> Result<String> {
+    match format {
+        OutputFormat::Json => Ok(serde_json::to_string_pretty(hits)?),
+        OutputFormat::Plain => {
+            let captures: Vec<Capture> = hits.iter().map(|hit| hit.capture.clone()).collect();
+            Ok(format_plain(&captures))
+        }
+        OutputFormat::Table => {
+            let mut out = table_header();
+            for hit in hits {
+                let preview = hit.snippet.as_deref().unwrap_or(&hit.capture.content);
+                out.push_str(&table_row_with_preview(&hit.capture, preview));
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Render the captures in the trash in the requested output format.
+///
+/// JSON output includes when each capture was trashed; the other formats
+/// show the captures as they were.
+///
+/// # Errors
+///
+/// Returns an error if JSON serialization fails.
+pub fn format_trash(trashed: &[TrashedCapture], format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(serde_json::to_string_pretty(trashed)?);
+    }
+    let captures: Vec<Capture> = trashed.iter().map(|t| t.capture.clone()).collect();
+    format_captures(&captures, format)
+}
+
+/// Render one capture from a live feed in the requested output format.
+///
+/// JSON output is a single compact line (newline-delimited JSON), and table
+/// output is a single row; print [`table_header`] once before the first.
+///
+/// # Errors
+///
+/// Returns an error if JSON serialization fails.
+pub fn format_live_capture(capture: &Capture, format: OutputFormat) -> Result<String> {
+    match format {
+        OutputFormat::Json => Ok(format!("{}\n", serde_json::to_string(capture)?)),
+        OutputFormat::Plain => Ok(format_plain(std::slice::from_ref(capture))),
+        OutputFormat::Table => Ok(table_row(capture)),
+    }
+}
+
+/// Render what a prune deleted, or would delete for a dry run, with a line
+/// per retention limit that deleted captures and how many of them went to the
+/// trash.
+#[must_use]
+pub fn format_prune_report(report: &PruneReport, dry_run: bool) -> String {
+    if report.total() == 0 {
+        return "Nothing to prune.\n".to_string();
+    }
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{} {} capture{}, about {} of {}:",
+        if dry_run { "Would delete" } else { "Deleted" },
+        report.total(),
+        if report.total() == 1 { "" } else { "s" },
+        format_bytes(report.estimated_bytes),
+        format_bytes(report.used_bytes),
+    );
+    for (count, reason) in [
+        (report.expired, "older than the maximum age"),
+        (report.over_count, "over the maximum number of captures"),
+        (report.over_size, "over the maximum database size"),
+        (report.purged, "from the trash"),
+    ] {
+        if count > 0 {
+            let _ = writeln!(out, "  {count:>8}  {reason}");
+        }
+    }
+    if report.trashed > 0 {
+        let _ = writeln!(
+            out,
+            "{} {} of them to the trash.",
+            if dry_run { "Would move" } else { "Moved" },
+            report.trashed,
+        );
+    }
+    if !dry_run {
+        let _ = writeln!(
+            out,
+            "Reclaimed {} of disk space.",
+            format_bytes(report.reclaimed_bytes)
+        );
+    }
+    out
+}
+
+/// Render what a database check found, with a line per damaged capture, and
+/// what repairing it changed.
+#[must_use]
+pub fn format_check_report(report: &CheckReport, repaired: Option<&RepairReport>) -> String {
+    let plural = |count: usize| if count == 1 { "" } else { "s" };
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "Checked {} capture{}",
+        report.checked,
+        plural(report.checked)
+    );
+    if report.is_healthy() {
+        out.push_str(": no problems found.\n");
+        return out;
+    }
+    out.push_str(".\n");
+    if !report.integrity_errors.is_empty() {
+        out.push_str("The integrity check failed:\n");
+        for error in &report.integrity_errors {
+            let _ = writeln!(out, "  {error}");
+        }
+    }
+    for problem in &report.problems {
+        let _ = writeln!(out, "  #{:<6}  {}", problem.capture_id, problem.problem);
+    }
+    let orphans = report.orphaned_index_entries.len();
+    if orphans > 0 {
+        let _ = writeln!(
+            out,
+            "{orphans} search index entr{} without a capture.",
+            if orphans == 1 { "y" } else { "ies" }
+        );
+    }
+    if let Some(repaired) = repaired {
+        let _ = writeln!(
+            out,
+            "Quarantined {} capture{}, detached {} version{} from missing sessions and \
+             removed {} index entr{}.",
+            repaired.quarantined,
+            plural(repaired.quarantined),
+            repaired.detached,
+            plural(repaired.detached),
+            repaired.removed_index_entries,
+            if repaired.removed_index_entries == 1 {
+                "y"
+            } else {
+                "ies"
+            },
+        );
+    }
+    if !report.integrity_errors.is_empty() {
+        out.push_str("The integrity check failures cannot be repaired; restore a backup.\n");
+    }
+    out
+}
+
+/// Render where a backup was written, and which older backups rotating
+/// removed.
+#[must_use]
+pub fn format_backup(info: &BackupInfo) -> String {
+    let mut out = format!(
+        "Backed up to {} ({}).\n",
+        info.path.display(),
+        format_bytes(info.size_bytes)
+    );
+    for removed in &info.removed {
+        let _ = writeln!(out, "Removed the old backup {}.", removed.display());
+    }
+    out
+}
+
+/// Plain output: a header line per capture, with its pin, tags and note,
+/// followed by the full content.
+fn format_plain(captures: &[Capture]) -> String {
+    let mut out = String::new();
+    for (i, capture) in captures.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let _ = write!(
+            out,
+            "--- #{} {} [{}] {}",
+            capture.id.unwrap_or_default(),
+            capture.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            capture.capture_type,
+            capture.source_app.as_deref().unwrap_or("-"),
+        );
+        if let Some(version) = capture.version {
+            let _ = write!(out, " (version {version})");
+        }
+        if capture.pinned {
+            out.push_str(" [pinned]");
+        }
+        for tag in &capture.tags {
+            let _ = write!(out, " #{tag}");
+        }
+        if let Some(note) = &capture.note {
+            let _ = write!(out, " (note: {})", note.replace(['\n', '\r'], " "));
+        }
+        out.push('\n');
+        let _ = writeln!(out, "{}", capture.content);
+    }
+    out
+}
+
+/// Table output: one row per capture with a single-line content preview.
+fn format_table(captures: &[Capture]) -> String {
+    let mut out = table_header();
+    for capture in captures {
+        out.push_str(&table_row(capture));
+    }
+    out
+}
+
+/// The column headings of table output.
+#[must_use]
+pub fn table_header() -> String {
+    format!(
+        "{:>6}  {:<19}  {:<10}  {:<16}  {}\n",
+        "ID", "TIME", "TYPE", "APP", "CONTENT"
+    )
+}
+
+fn table_row(capture: &Capture) -> String {
+    table_row_with_preview(capture, &capture.content)
+}
+
+/// A table row showing `preview` in the content column.
+fn table_row_with_preview(capture: &Capture, preview: &str) -> String {
+    format!(
+        "{:>6}  {:<19}  {:<10}  {:<16}  {}\n",
+        capture.id.unwrap_or_default(),
+        capture.timestamp.format("%Y-%m-%d %H:%M:%S"),
+        capture.capture_type.to_string(),
+        truncate(capture.source_app.as_deref().unwrap_or("-"), 16),
+        truncate(
+            &preview.replace(['\n', '\r', '\t'], " "),
+            TABLE_PREVIEW_CHARS
+        ),
+    )
+}
+
+/// Truncate to at most `max` characters, marking truncation with an ellipsis.
+fn truncate(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        s.to_string()
+    } else {
+        let mut t: String = s.chars().take(max.saturating_sub(1)).collect();
+        t.push('…');
+        t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::CaptureType;
+
+    fn sample() -> Vec<Capture> {
+        let mut capture = Capture::new(
+            "line one\nline two".to_string(),
+            CaptureType::Clipboard,
+            Some("Terminal".to_string()),
+        );
+        capture.id = Some(7);
+        vec![capture]
+    }
+
+    #[test]
+    fn test_format_json() {
+        let out = format_captures(&sample(), OutputFormat::Json).unwrap();
+        let parsed: Vec<Capture> = serde_json::from_str(&out).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].id, Some(7));
+    }
+
+    #[test]
+    fn test_format_plain_keeps_full_content() {
+        let out = format_captures(&sample(), OutputFormat::Plain).unwrap();
+        assert!(out.contains("#7"));
+        assert!(out.contains("line one\nline two"));
+    }
+
+    #[test]
+    fn test_format_plain_shows_annotations() {
+        let mut captures = sample();
+        captures[0].pinned = true;
+        captures[0].tags = vec!["incident".to_string(), "work".to_string()];
+        captures[0].note = Some("send\nto Sam".to_string());
+        let out = format_captures(&captures, OutputFormat::Plain).unwrap();
+        assert!(out
+            .lines()
+            .next()
+            .unwrap()
+            .ends_with("Terminal [pinned] #incident #work (note: send to Sam)"));
+        assert!(out.contains("line one\nline two"));
+    }
+
+    #[test]
+    fn test_format_table_single_line_rows() {
+        let out = format_captures(&sample(), OutputFormat::Table).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        assert!(out.contains("line one line two"));
+        assert!(out.contains("Terminal"));
+    }
+
+    #[test]
+    fn test_format_live_json_is_one_line() {
+        let capture = &sample()[0];
+        let out = format_live_capture(capture, OutputFormat::Json).unwrap();
+        assert_eq!(out.lines().count(), 1);
+        let parsed: Capture = serde_json::from_str(&out).unwrap();
+        assert_eq!(parsed.id, Some(7));
+    }
+
+    #[test]
+    fn test_format_live_table_row_has_no_header() {
+        let capture = &sample()[0];
+        let out = format_live_capture(capture, OutputFormat::Table).unwrap();
+        assert_eq!(out.lines().count(), 1);
+        assert!(!out.contains("CONTENT"));
+    }
+
+    #[test]
+    fn test_format_search_hits_table_shows_snippet() {
+        let hits = vec![SearchHit {
+            capture: sample().remove(0),
+            snippet: Some("line [one] line".to_string()),
+        }];
+        let out = format_search_hits(&hits, OutputFormat::Table).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        assert!(out.contains("line [one] line"));
+
+        let out = format_search_hits(&hits, OutputFormat::Json).unwrap();
+        let parsed: Vec<SearchHit> = serde_json::from_str(&out).unwrap();
+        assert_eq!(parsed, hits);
+    }
+
+    #[test]
+    fn test_format_trash() {
+        let trashed = vec![TrashedCapture {
+            capture: sample().remove(0),
+            trashed_at: chrono::Utc::now(),
+        }];
+        let out = format_trash(&trashed, OutputFormat::Table).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        assert!(out.contains("line one line two"));
+
+        let out = format_trash(&trashed, OutputFormat::Json).unwrap();
+        let parsed: Vec<TrashedCapture> = serde_json::from_str(&out).unwrap();
+        assert_eq!(parsed, trashed);
+    }
+
+    #[test]
+    fn test_format_check_report() {
+        use crate::storage::{CaptureProblem, Problem};
+
+        let report = CheckReport {
+            checked: 1,
+            ..CheckReport::default()
+        };
+        assert_eq!(
+            format_check_report(&report, None),
+            "Checked 1 capture: no problems found.\n"
+        );
+
+        let report = CheckReport {
+            checked: 5,
+            problems: vec![CaptureProblem {
+                capture_id: 42,
+                problem: Problem::HashMismatch,
+            }],
+            orphaned_index_entries: vec![7, 8],
+            ..CheckReport::default()
+        };
+        let out = format_check_report(&report, None);
+        assert!(out.starts_with("Checked 5 captures.\n"));
+        assert!(out.contains("#42      content does not match its hash"));
+        assert!(out.contains("2 search index entries without a capture."));
+        assert!(!out.contains("Quarantined"));
+
+        let repaired = RepairReport {
+            quarantined: 1,
+            detached: 0,
+            removed_index_entries: 2,
+        };
+        let out = format_check_report(&report, Some(&repaired));
+        assert!(out.contains("Quarantined 1 capture, detached 0 versions"));
+        assert!(out.contains("removed 2 index entries."));
+    }
+
+    #[test]
+    fn test_format_backup() {
+        let mut info = BackupInfo {
+            path: "/backups/captures.db".into(),
+            size_bytes: 3072,
+            removed: Vec::new(),
+        };
+        assert_eq!(
+            format_backup(&info),
+            "Backed up to /backups/captures.db (3.0 KiB).\n"
+        );
+        info.removed.push("/backups/old.db".into());
+        assert!(format_backup(&info).ends_with("Removed the old backup /backups/old.db.\n"));
+    }
+
+    #[test]
+    fn test_format_prune_report() {
+        assert_eq!(
+            format_prune_report(&PruneReport::default(), true),
+            "Nothing to prune.\n"
+        );
+
+        let report = PruneReport {
+            expired: 3,
+            over_size: 2,
+            estimated_bytes: 2048,
+            used_bytes: 8192,
+            ..PruneReport::default()
+        };
+        let out = format_prune_report(&report, true);
+        assert!(out.starts_with("Would delete 5 captures, about 2.0 KiB of 8.0 KiB:"));
+        assert!(out.contains("3  older than the maximum age"));
+        assert!(out.contains("2  over the maximum database size"));
+        assert!(!out.contains("number of captures"));
+        assert!(!out.contains("Reclaimed"));
+        assert!(!out.contains("trash"));
+
+        let out = format_prune_report(&report, false);
+        assert!(out.starts_with("Deleted 5 captures"));
+        assert!(out.contains("Reclaimed 0 B of disk space."));
+
+        let report = PruneReport {
+            expired: 3,
+            trashed: 3,
+            purged: 1,
+            ..PruneReport::default()
+        };
+        let out = format_prune_report(&report, false);
+        assert!(out.starts_with("Deleted 4 captures"));
+        assert!(out.contains("1  from the trash"));
+        assert!(out.contains("Moved 3 of them to the trash."));
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("abcdefghij", 5), "abcd…");
+    }
+}
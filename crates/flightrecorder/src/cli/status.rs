@@ -0,0 +1,342 @@
+//! The report printed by `fliterec status`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::ipc::{DaemonStatus, WriterStatus};
+use crate::monitor::MonitorStatus;
+use crate::storage::StorageStats;
+
+/// Everything `fliterec status` reports.
+///
+/// The JSON form of this struct is the `--json` output, which scripts depend
+/// on: every field is always present (`null` or empty when unknown), and
+/// fields are only ever added, never renamed or removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StatusReport {
+    /// Whether the daemon answered the status request.
+    pub daemon_running: bool,
+    /// Name of the platform the CLI is running on.
+    pub platform: String,
+    /// Path of the capture database.
+    pub database_path: PathBuf,
+    /// Path of the daemon's IPC socket.
+    pub socket_path: PathBuf,
+    /// Process ID of the daemon.
+    pub pid: Option<u32>,
+    /// Version of the daemon.
+    pub version: Option<String>,
+    /// Seconds since the daemon started.
+    pub uptime_secs: Option<u64>,
+    /// State of each of the daemon's monitors.
+    pub monitors: Vec<MonitorStatus>,
+    /// Database statistics, from the daemon or read directly when it is down.
+    pub storage: Option<StorageStats>,
+    /// Captures blocked by the privacy filter since the daemon started, by
+    /// rule name.
+    pub blocked: BTreeMap<String, u64>,
+    /// How the daemon's storage writer is keeping up with its monitors.
+    pub writer: Option<WriterStatus>,
+}
+
+impl StatusReport {
+    /// Build a report for a running daemon.
+    #[must_use]
+    pub fn from_daemon(
+        platform: &str,
+        database_path: PathBuf,
+        socket_path: PathBuf,
+        daemon: DaemonStatus,
+    ) -> Self {
+        Self {
+            daemon_running: true,
+            platform: platform.to_string(),
+            database_path,
+            socket_path,
+            pid: Some(daemon.pid),
+            version: Some(daemon.version),
+            uptime_secs: Some(daemon.uptime_secs),
+            monitors: daemon.monitors,
+            storage: daemon.storage,
+            blocked: daemon.blocked,
+            writer: Some(daemon.writer),
+        }
+    }
+
+    /// Build a report when the daemon is not running, from database
+    /// statistics read directly (if the database exists).
+    #[must_use]
+    pub fn offline(
+        platform: &str,
+        database_path: PathBuf,
+        socket_path: PathBuf,
+        storage: Option<StorageStats>,
+    ) -> Self {
+        Self {
+            daemon_running: false,
+            platform: platform.to_string(),
+            database_path,
+            socket_path,
+            pid: None,
+            version: None,
+            uptime_secs: None,
+            monitors: Vec::new(),
+            storage,
+            blocked: BTreeMap::new(),
+            writer: None,
+        }
+    }
+
+    /// Render the report for a terminal.
+    #[must_use]
+    pub fn to_text(&self) -> String {
+        let mut out = String::from("fliterec status\n---------------\n");
+        let _ = writeln!(out, "Platform:      {}", self.platform);
+        match (self.daemon_running, self.pid, &self.version) {
+            (true, Some(pid), Some(version)) => {
+                let _ = writeln!(out, "Daemon:        Running (pid {pid}, version {version})");
+            }
+            (true, ..) => out.push_str("Daemon:        Running\n"),
+            (false, ..) => out.push_str("Daemon:        Not running\n"),
+        }
+        if let Some(uptime) = self.uptime_secs {
+            let _ = writeln!(out, "Uptime:        {}", format_duration(uptime));
+        }
+        let _ = writeln!(out, "Database:      {}", self.database_path.display());
+        let _ = writeln!(out, "Socket:        {}", self.socket_path.display());
+
+        if self.daemon_running {
+            out.push_str("\nMonitors:\n");
+            if self.monitors.is_empty() {
+                out.push_str("  (none running)\n");
+            }
+            for monitor in &self.monitors {
+                let _ = writeln!(out, "  {}", format_monitor(monitor));
+            }
+        }
+
+        out.push_str("\nStorage:\n");
+        match &self.storage {
+            Some(stats) => {
+                let _ = writeln!(out, "  Captures:    {}", stats.total_captures);
+                if stats.trashed_captures > 0 {
+                    let _ = writeln!(out, "  In trash:    {}", stats.trashed_captures);
+                }
+                if let (Some(oldest), Some(newest)) = (stats.oldest_capture, stats.newest_capture) {
+                    let _ = writeln!(out, "  Oldest:      {}", oldest.format("%Y-%m-%d %H:%M:%S"));
+                    let _ = writeln!(out, "  Newest:      {}", newest.format("%Y-%m-%d %H:%M:%S"));
+                }
+                let _ = writeln!(out, "  Size:        {}", format_bytes(stats.db_size_bytes));
+                let _ = writeln!(
+                    out,
+                    "  Content:     {} ({} stored)",
+                    format_bytes(stats.content_bytes),
+                    format_bytes(stats.stored_content_bytes)
+                );
+            }
+            None => out.push_str("  (no database)\n"),
+        }
+
+        if let Some(writer) = &self.writer {
+            out.push_str("\nWriter:\n");
+            let _ = writeln!(
+                out,
+                "  Queue:       {} of {} (most {})",
+                writer.queued, writer.queue_capacity, writer.max_queued
+            );
+            let _ = writeln!(
+                out,
+                "  Batches:     {} (largest {})",
+                writer.batches, writer.largest_batch
+            );
+            let _ = writeln!(out, "  Full waits:  {}", writer.full_waits);
+            if writer.failed > 0 {
+                let _ = writeln!(out, "  Failed:      {}", writer.failed);
+            }
+        }
+
+        if !self.blocked.is_empty() {
+            out.push_str("\nBlocked by privacy filter:\n");
+            for (rule, count) in &self.blocked {
+                let _ = writeln!(out, "  {rule:<20} {count}");
+            }
+        }
+        out
+    }
+}
+
+/// One line describing a monitor.
+fn format_monitor(monitor: &MonitorStatus) -> String {
+    let state = if !monitor.has_permission {
+        "permission required"
+    } else if monitor.is_degraded() {
+        "degraded"
+    } else if monitor.is_running {
+        "running"
+    } else {
+        "stopped"
+    };
+    let mut line = format!(
+        "{:<14} {:<20} {} captures",
+        monitor.monitor_type.to_string(),
+        state,
+        monitor.capture_count
+    );
+    if !monitor.is_running || !monitor.has_permission || monitor.is_degraded() {
+        let _ = write!(line, " ({})", monitor.message);
+    }
+    line
+}
+
+/// Format seconds as e.g. `2d 3h 4m 5s`, omitting leading zero units.
+fn format_duration(secs: u64) -> String {
+    let units = [(86_400, "d"), (3_600, "h"), (60, "m")];
+    let mut out = String::new();
+    let mut rest = secs;
+    for (size, suffix) in units {
+        if rest >= size || !out.is_empty() {
+            let _ = write!(out, "{}{suffix} ", rest / size);
+            rest %= size;
+        }
+    }
+    let _ = write!(out, "{rest}s");
+    out
+}
+
+/// Format a byte count with a binary unit.
+pub(super) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+    format!("{value:.1} {unit}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::MonitorType;
+
+    fn daemon_status() -> DaemonStatus {
+        DaemonStatus {
+            pid: 42,
+            version: "0.1.0".to_string(),
+            uptime_secs: 3_725,
+            monitors: vec![
+                MonitorStatus::running(MonitorType::Clipboard, 12),
+                MonitorStatus::permission_required(MonitorType::Accessibility, "Grant access"),
+            ],
+            storage: Some(StorageStats {
+                total_captures: 12,
+                trashed_captures: 2,
+                oldest_capture: None,
+                newest_capture: None,
+                db_size_bytes: 2048,
+                content_bytes: 3 * 1024 * 1024,
+                stored_content_bytes: 1024 * 1024,
+            }),
+            blocked: BTreeMap::from([("credit_card".to_string(), 3)]),
+            writer: WriterStatus {
+                queue_capacity: 256,
+                queued: 2,
+                max_queued: 40,
+                batches: 9,
+                largest_batch: 31,
+                full_waits: 0,
+                failed: 0,
+            },
+        }
+    }
+
+    fn report() -> StatusReport {
+        StatusReport::from_daemon(
+            "linux",
+            "/data/captures.db".into(),
+            "/run/fliterec.sock".into(),
+            daemon_status(),
+        )
+    }
+
+    #[test]
+    fn test_json_schema_is_stable() {
+        let json = serde_json::to_value(report()).unwrap();
+        let mut keys: Vec<_> = json.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "blocked",
+                "daemon_running",
+                "database_path",
+                "monitors",
+                "pid",
+                "platform",
+                "socket_path",
+                "storage",
+                "uptime_secs",
+                "version",
+                "writer",
+            ]
+        );
+        assert_eq!(json["monitors"][0]["monitor_type"], "clipboard");
+        assert_eq!(json["monitors"][0]["capture_count"], 12);
+        assert_eq!(json["storage"]["total_captures"], 12);
+        assert_eq!(json["blocked"]["credit_card"], 3);
+        assert_eq!(json["writer"]["max_queued"], 40);
+    }
+
+    #[test]
+    fn test_offline_report_keeps_every_field() {
+        let report = StatusReport::offline("linux", "/db".into(), "/sock".into(), None);
+        let json = serde_json::to_value(report).unwrap();
+        assert_eq!(json["daemon_running"], false);
+        assert!(json["pid"].is_null());
+        assert!(json["storage"].is_null());
+        assert!(json["writer"].is_null());
+        assert_eq!(json["monitors"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_text_report() {
+        let text = report().to_text();
+        assert!(text.contains("Running (pid 42, version 0.1.0)"));
+        assert!(text.contains("Uptime:        1h 2m 5s"));
+        assert!(text.contains("clipboard"));
+        assert!(text.contains("permission required"));
+        assert!(text.contains("Grant access"));
+        assert!(text.contains("Size:        2.0 KiB"));
+        assert!(text.contains("Content:     3.0 MiB (1.0 MiB stored)"));
+        assert!(text.contains("In trash:    2"));
+        assert!(text.contains("credit_card"));
+        assert!(text.contains("Queue:       2 of 256 (most 40)"));
+        assert!(!text.contains("Failed:"));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(61), "1m 1s");
+        assert_eq!(format_duration(90_061), "1d 1h 1m 1s");
+        assert_eq!(format_duration(3_600), "1h 0m 0s");
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024), "5.0 MiB");
+    }
+}
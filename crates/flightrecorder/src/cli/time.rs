@@ -0,0 +1,134 @@
+//! Parsing of human-friendly time arguments.
+//!
+//! Accepts the forms documented on `--since`/`--until`:
+//!
+//! - RFC 3339 timestamps (`2024-01-15T10:30:00Z`)
+//! - Calendar dates (`2024-01-15`, interpreted as midnight UTC)
+//! - Relative times (`1 hour ago`, `30m ago`, `2 days ago`)
+//! - The keywords `now`, `today` and `yesterday`
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+
+use crate::error::{Error, Result};
+
+/// Parse a time argument relative to `now`.
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidArgument`] if the input is not a recognized form.
+pub fn parse_time(input: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let trimmed = input.trim();
+    let lower = trimmed.to_ascii_lowercase();
+
+    match lower.as_str() {
+        "now" => return Ok(now),
+        "today" => return Ok(start_of_day(now)),
+        "yesterday" => return Ok(start_of_day(now) - Duration::days(1)),
+        _ => {}
+    }
+
+    if let Ok(dt) = DateTime::parse_from_rfc3339(trimmed) {
+        return Ok(dt.with_timezone(&Utc));
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(trimmed, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+    }
+
+    if let Some(spec) = lower.strip_suffix("ago") {
+        if let Some(duration) = parse_duration(spec.trim()) {
+            return Ok(now - duration);
+        }
+    }
+
+    Err(Error::InvalidArgument {
+        message: format!(
+            "invalid time '{input}': expected RFC 3339, YYYY-MM-DD, or e.g. '2 hours ago'"
+        ),
+    })
+}
+
+/// Parse a duration such as `1 hour`, `30m` or `2 days`.
+fn parse_duration(spec: &str) -> Option<Duration> {
+    let split = spec
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(spec.len());
+    let (amount, unit) = spec.split_at(split);
+    let amount: i64 = amount.parse().ok()?;
+
+    match unit.trim() {
+        "s" | "sec" | "secs" | "second" | "seconds" => Some(Duration::seconds(amount)),
+        "m" | "min" | "mins" | "minute" | "minutes" => Some(Duration::minutes(amount)),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Some(Duration::hours(amount)),
+        "d" | "day" | "days" => Some(Duration::days(amount)),
+        "w" | "week" | "weeks" => Some(Duration::weeks(amount)),
+        _ => None,
+    }
+}
+
+/// Midnight UTC on the day of `dt`.
+fn start_of_day(dt: DateTime<Utc>) -> DateTime<Utc> {
+    dt.date_naive()
+        .and_hms_opt(0, 0, 0)
+        .unwrap_or_default()
+        .and_utc()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-06-15T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        let dt = parse_time("2024-01-15T10:30:00+02:00", now()).unwrap();
+        assert_eq!(dt.to_rfc3339(), "2024-01-15T08:30:00+00:00");
+    }
+
+    #[test]
+    fn test_parse_date() {
+        let dt = parse_time("2024-01-15", now()).unwrap();
+        assert_eq!(dt.to_rfc3339(), "2024-01-15T00:00:00+00:00");
+    }
+
+    #[test]
+    fn test_parse_relative() {
+        assert_eq!(
+            parse_time("1 hour ago", now()).unwrap(),
+            now() - Duration::hours(1)
+        );
+        assert_eq!(
+            parse_time("30m ago", now()).unwrap(),
+            now() - Duration::minutes(30)
+        );
+        assert_eq!(
+            parse_time("2 Days Ago", now()).unwrap(),
+            now() - Duration::days(2)
+        );
+    }
+
+    #[test]
+    fn test_parse_keywords() {
+        assert_eq!(parse_time("now", now()).unwrap(), now());
+        assert_eq!(
+            parse_time("today", now()).unwrap().to_rfc3339(),
+            "2024-06-15T00:00:00+00:00"
+        );
+        assert_eq!(
+            parse_time("yesterday", now()).unwrap().to_rfc3339(),
+            "2024-06-14T00:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(parse_time("last tuesday", now()).is_err());
+        assert!(parse_time("5 fortnights ago", now()).is_err());
+        assert!(parse_time("", now()).is_err());
+    }
+}
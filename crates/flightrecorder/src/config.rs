@@ -12,7 +12,9 @@ use figment::{
 };
 use serde::{Deserialize, Serialize};
 
+use crate::capture::CaptureType;
 use crate::error::{Error, Result};
+use crate::storage::{EncryptionKey, RetentionPolicy, RetentionRule};
 
 /// Default configuration file name.
 const CONFIG_FILE_NAME: &str = "config.toml";
@@ -23,6 +25,9 @@ const DATA_DIR_NAME: &str = "flightrecorder";
 /// Default database file name.
 const DATABASE_FILE_NAME: &str = "captures.db";
 
+/// Log file name used by the background daemon.
+const LOG_FILE_NAME: &str = "fliterec.log";
+
 /// Application configuration.
 ///
 /// Configuration is loaded from (in order of precedence, highest first):
@@ -55,8 +60,50 @@ pub struct StorageConfig {
     /// Maximum age of captures to retain in days.
     /// Set to 0 for unlimited.
     pub max_age_days: u32,
+    /// Maximum size of the database in megabytes, above which the oldest
+    /// captures are deleted.
+    /// Set to 0 for unlimited.
+    pub max_db_size_mb: u64,
     /// Prune interval in hours.
+    /// Set to 0 to prune only with `fliterec prune`.
     pub prune_interval_hours: u32,
+    /// Days a pruned capture stays in the trash, where it can be restored,
+    /// before it is deleted for good.
+    /// Set to 0 to delete pruned captures straight away.
+    pub trash_grace_days: u32,
+    /// Compress content larger than this many bytes with zstd.
+    /// Set to 0 to disable compression.
+    pub compression_threshold_bytes: usize,
+    /// Encrypt stored content with a key read from this file, which must
+    /// hold at least 32 random bytes.
+    pub key_file: Option<PathBuf>,
+    /// Encrypt stored content with a key derived from the passphrase in this
+    /// environment variable.
+    pub passphrase_env: Option<String>,
+    /// Maximum ages of the captures of some applications or types, in place
+    /// of `max_age_days`. The first matching rule applies.
+    pub retention: Vec<RetentionRuleConfig>,
+    /// Interval in hours between backups of the database into the
+    /// `backups` directory beside it.
+    /// Set to 0 to disable scheduled backups.
+    pub backup_interval_hours: u32,
+    /// Number of scheduled backups to keep; older ones are removed.
+    pub backup_count: usize,
+}
+
+/// A maximum age for the captures of some applications, of one type, or
+/// both, written as `[[storage.retention]]`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionRuleConfig {
+    /// Application name, or a glob using `*` and `?`, compared ignoring case.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    /// Type of capture.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_type: Option<CaptureType>,
+    /// Maximum age of matching captures in days.
+    /// Set to 0 to keep them forever.
+    pub max_age_days: u32,
 }
 
 /// Capture-related configuration.
@@ -71,6 +118,9 @@ pub struct CaptureConfig {
     pub keystroke_fallback_enabled: bool,
     /// Interval between text field snapshots in milliseconds.
     pub snapshot_interval_ms: u64,
+    /// Longest pause in seconds between two snapshots of the same text field
+    /// for them to be kept as versions of one draft.
+    pub session_gap_secs: u64,
     /// Minimum content length to capture.
     pub min_content_length: usize,
     /// Maximum content length to capture.
@@ -109,7 +159,15 @@ impl Default for StorageConfig {
             database_path: None, // Will be resolved to default at runtime
             max_captures: 100_000,
             max_age_days: 30,
+            max_db_size_mb: 0,
             prune_interval_hours: 24,
+            trash_grace_days: 7,
+            compression_threshold_bytes: 4096,
+            key_file: None,
+            passphrase_env: None,
+            retention: Vec::new(),
+            backup_interval_hours: 0,
+            backup_count: 7,
         }
     }
 }
@@ -121,6 +179,7 @@ impl Default for CaptureConfig {
             accessibility_enabled: true,
             keystroke_fallback_enabled: false, // Opt-in only
             snapshot_interval_ms: 500,
+            session_gap_secs: 300,
             min_content_length: 1,
             max_content_length: 1_000_000, // 1MB max
         }
@@ -192,7 +251,7 @@ impl Config {
 
         let figment = Figment::new()
             .merge(Serialized::defaults(Config::default()))
-            .merge(Toml::file(&config_file).nested())
+            .merge(Toml::file(&config_file))
             .merge(Env::prefixed("FLIGHTRECORDER_").split("_"));
 
         let config: Config = figment.extract()?;
@@ -239,6 +298,38 @@ impl Config {
             });
         }
 
+        if self.capture.session_gap_secs == 0 {
+            return Err(Error::ConfigValidation {
+                message: "session_gap_secs must be greater than 0".to_string(),
+            });
+        }
+
+        if self.storage.key_file.is_some() && self.storage.passphrase_env.is_some() {
+            return Err(Error::ConfigValidation {
+                message: "set at most one of key_file and passphrase_env".to_string(),
+            });
+        }
+
+        if self.storage.backup_interval_hours > 0 && self.storage.backup_count == 0 {
+            return Err(Error::ConfigValidation {
+                message: "backup_count must be greater than 0 when backups are scheduled"
+                    .to_string(),
+            });
+        }
+
+        for rule in &self.storage.retention {
+            if rule.app.is_none() && rule.capture_type.is_none() {
+                return Err(Error::ConfigValidation {
+                    message: "a retention rule must set app, capture_type or both".to_string(),
+                });
+            }
+            if rule.app.as_deref().is_some_and(str::is_empty) {
+                return Err(Error::ConfigValidation {
+                    message: "the app of a retention rule cannot be empty".to_string(),
+                });
+            }
+        }
+
         // Validate regex patterns
         for pattern in &self.privacy.filter_patterns {
             if regex::Regex::new(pattern).is_err() {
@@ -278,16 +369,16 @@ impl Config {
             .unwrap_or_else(|| Self::default_data_dir().join("fliterec.pid"))
     }
 
+    /// Get the log file path used when the daemon runs in the background.
+    #[must_use]
+    pub fn log_file_path(&self) -> PathBuf {
+        Self::default_data_dir().join(LOG_FILE_NAME)
+    }
+
     /// Get the max age as a Duration.
     #[must_use]
     pub fn max_age(&self) -> Option<Duration> {
-        if self.storage.max_age_days == 0 {
-            None
-        } else {
-            Some(Duration::from_secs(
-                u64::from(self.storage.max_age_days) * 24 * 60 * 60,
-            ))
-        }
+        days(self.storage.max_age_days)
     }
 
     /// Get the prune interval as a Duration.
@@ -296,15 +387,110 @@ impl Config {
         Duration::from_secs(u64::from(self.storage.prune_interval_hours) * 60 * 60)
     }
 
+    /// Get the interval between scheduled backups. Zero means scheduled
+    /// backups are off.
+    #[must_use]
+    pub fn backup_interval(&self) -> Duration {
+        Duration::from_secs(u64::from(self.storage.backup_interval_hours) * 60 * 60)
+    }
+
+    /// Get the directory scheduled backups are written to: `backups` beside
+    /// the database.
+    #[must_use]
+    pub fn backup_dir(&self) -> PathBuf {
+        let database = self.database_path();
+        database
+            .parent()
+            .map_or_else(|| PathBuf::from("backups"), |dir| dir.join("backups"))
+    }
+
+    /// Get the maximum database size in bytes, if there is one.
+    #[must_use]
+    pub fn max_db_size_bytes(&self) -> Option<u64> {
+        match self.storage.max_db_size_mb {
+            0 => None,
+            mb => Some(mb.saturating_mul(1024 * 1024)),
+        }
+    }
+
+    /// Get the retention limits to prune the database with.
+    #[must_use]
+    pub fn retention_policy(&self) -> RetentionPolicy {
+        let to_chrono = |age: Duration| chrono::Duration::from_std(age).ok();
+        let rules = self
+            .storage
+            .retention
+            .iter()
+            .map(|rule| RetentionRule {
+                app: rule.app.clone(),
+                capture_type: rule.capture_type,
+                max_age: days(rule.max_age_days).and_then(to_chrono),
+            })
+            .collect();
+        RetentionPolicy {
+            max_age: self.max_age().and_then(to_chrono),
+            rules,
+            max_captures: Some(self.storage.max_captures).filter(|&max| max > 0),
+            max_size_bytes: self.max_db_size_bytes(),
+            trash_grace: days(self.storage.trash_grace_days).and_then(to_chrono),
+        }
+    }
+
+    /// Get the size in bytes above which content is compressed, if
+    /// compression is enabled.
+    #[must_use]
+    pub fn compression_threshold(&self) -> Option<usize> {
+        match self.storage.compression_threshold_bytes {
+            0 => None,
+            threshold => Some(threshold),
+        }
+    }
+
+    /// Get the key encrypting stored content, if encryption is configured.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `passphrase_env` names an environment variable
+    /// that is not set.
+    pub fn encryption_key(&self) -> Result<Option<EncryptionKey>> {
+        if let Some(path) = &self.storage.key_file {
+            return Ok(Some(EncryptionKey::key_file(path)));
+        }
+        let Some(var) = &self.storage.passphrase_env else {
+            return Ok(None);
+        };
+        match std::env::var(var) {
+            Ok(passphrase) if !passphrase.is_empty() => {
+                Ok(Some(EncryptionKey::passphrase(passphrase)))
+            }
+            _ => Err(Error::ConfigValidation {
+                message: format!("passphrase_env is set, but ${var} is empty or not set"),
+            }),
+        }
+    }
+
     /// Get the snapshot interval as a Duration.
     #[must_use]
     pub fn snapshot_interval(&self) -> Duration {
         Duration::from_millis(self.capture.snapshot_interval_ms)
     }
+
+    /// Get the draft session gap as a Duration.
+    #[must_use]
+    pub fn session_gap(&self) -> Duration {
+        Duration::from_secs(self.capture.session_gap_secs)
+    }
+}
+
+/// A number of days as a duration, or `None` for zero days.
+fn days(days: u32) -> Option<Duration> {
+    (days > 0).then(|| Duration::from_secs(u64::from(days) * 24 * 60 * 60))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+
     use super::*;
 
     #[test]
@@ -326,6 +512,7 @@ mod tests {
         assert_eq!(storage.max_captures, 100_000);
         assert_eq!(storage.max_age_days, 30);
         assert_eq!(storage.prune_interval_hours, 24);
+        assert_eq!(storage.compression_threshold_bytes, 4096);
     }
 
     #[test]
@@ -387,6 +574,15 @@ mod tests {
         assert!(err.contains("snapshot_interval_ms"));
     }
 
+    #[test]
+    fn test_validate_zero_session_gap() {
+        let mut config = Config::default();
+        config.capture.session_gap_secs = 0;
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("session_gap_secs"));
+    }
+
     #[test]
     fn test_validate_invalid_regex() {
         let mut config = Config::default();
@@ -433,6 +629,13 @@ mod tests {
         assert!(path.to_string_lossy().contains("fliterec.pid"));
     }
 
+    #[test]
+    fn test_log_file_path_default() {
+        let path = Config::default().log_file_path();
+        assert!(path.starts_with(Config::default_data_dir()));
+        assert!(path.ends_with("fliterec.log"));
+    }
+
     #[test]
     fn test_max_age_none_when_zero() {
         let mut config = Config::default();
@@ -458,6 +661,87 @@ mod tests {
         assert_eq!(interval, Duration::from_secs(24 * 60 * 60));
     }
 
+    #[test]
+    fn test_backup_schedule() {
+        let mut config = Config::default();
+        assert!(config.backup_interval().is_zero());
+        assert_eq!(config.storage.backup_count, 7);
+
+        config.storage.database_path = Some(PathBuf::from("/data/fr/captures.db"));
+        config.storage.backup_interval_hours = 12;
+        assert_eq!(config.backup_interval(), Duration::from_secs(12 * 60 * 60));
+        assert_eq!(config.backup_dir(), PathBuf::from("/data/fr/backups"));
+        assert!(config.validate().is_ok());
+
+        config.storage.backup_count = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_retention_policy() {
+        let mut config = Config::default();
+        let policy = config.retention_policy();
+        assert_eq!(policy.max_age, Some(chrono::Duration::days(30)));
+        assert_eq!(policy.max_captures, Some(100_000));
+        assert_eq!(policy.max_size_bytes, None);
+        assert_eq!(policy.trash_grace, Some(chrono::Duration::days(7)));
+
+        config.storage.max_age_days = 0;
+        config.storage.max_captures = 0;
+        config.storage.max_db_size_mb = 500;
+        config.storage.trash_grace_days = 0;
+        let policy = config.retention_policy();
+        assert_eq!(policy.max_age, None);
+        assert_eq!(policy.max_captures, None);
+        assert_eq!(policy.max_size_bytes, Some(500 * 1024 * 1024));
+        assert_eq!(policy.trash_grace, None);
+    }
+
+    #[test]
+    fn test_retention_rules() {
+        let path =
+            std::env::temp_dir().join(format!("fr_config_retention_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+            [[storage.retention]]
+            app = "*Terminal*"
+            capture_type = "clipboard"
+            max_age_days = 2
+
+            [[storage.retention]]
+            capture_type = "text_field"
+            max_age_days = 90
+            "#,
+        )
+        .unwrap();
+        let config = Config::load_from(Some(path.clone())).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let policy = config.retention_policy();
+        assert_eq!(policy.rules.len(), 2);
+        assert_eq!(
+            policy.max_age_for(Some("GNOME Terminal"), CaptureType::Clipboard),
+            Some(chrono::Duration::days(2))
+        );
+        assert_eq!(
+            policy.max_age_for(Some("Editor"), CaptureType::TextField),
+            Some(chrono::Duration::days(90))
+        );
+        assert_eq!(
+            policy.max_age_for(Some("Editor"), CaptureType::Clipboard),
+            Some(chrono::Duration::days(30))
+        );
+
+        let mut config = Config::default();
+        config.storage.retention.push(RetentionRuleConfig {
+            app: None,
+            capture_type: None,
+            max_age_days: 1,
+        });
+        assert!(config.validate().is_err());
+    }
+
     #[test]
     fn test_snapshot_interval() {
         let config = Config::default();
@@ -466,6 +750,42 @@ mod tests {
         assert_eq!(interval, Duration::from_millis(500));
     }
 
+    #[test]
+    fn test_compression_threshold() {
+        let mut config = Config::default();
+        assert_eq!(config.compression_threshold(), Some(4096));
+        config.storage.compression_threshold_bytes = 0;
+        assert_eq!(config.compression_threshold(), None);
+    }
+
+    #[test]
+    fn test_encryption_key() {
+        let mut config = Config::default();
+        assert!(config.encryption_key().unwrap().is_none());
+
+        config.storage.key_file = Some(PathBuf::from("/tmp/fliterec.key"));
+        assert!(matches!(
+            config.encryption_key().unwrap(),
+            Some(EncryptionKey::KeyFile(path)) if path == Path::new("/tmp/fliterec.key")
+        ));
+        config.storage.passphrase_env = Some("FR_TEST_PASSPHRASE".to_string());
+        assert!(config.validate().is_err());
+
+        config.storage.key_file = None;
+        assert!(config.encryption_key().is_err());
+        std::env::set_var("FR_TEST_PASSPHRASE", "correct horse");
+        assert!(matches!(
+            config.encryption_key().unwrap(),
+            Some(EncryptionKey::Passphrase(passphrase)) if passphrase.as_str() == "correct horse"
+        ));
+    }
+
+    #[test]
+    fn test_session_gap() {
+        let config = Config::default();
+        assert_eq!(config.session_gap(), Duration::from_secs(300));
+    }
+
     #[test]
     fn test_default_filter_patterns_are_valid() {
         let patterns = default_filter_patterns();
@@ -521,6 +841,22 @@ mod tests {
         assert_eq!(config, Config::default());
     }
 
+    #[test]
+    fn test_load_config_file() {
+        let path = std::env::temp_dir().join(format!("fr_config_load_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[capture]\nclipboard_enabled = false\n\n[privacy]\nexcluded_apps = [\"Vault\"]\n",
+        )
+        .unwrap();
+
+        let config = Config::load_from(Some(path.clone())).unwrap();
+        assert!(!config.capture.clipboard_enabled);
+        assert_eq!(config.privacy.excluded_apps, vec!["Vault"]);
+        assert_eq!(config.storage, StorageConfig::default());
+        let _ = std::fs::remove_file(&path);
+    }
+
     #[test]
     fn test_storage_config_serialize() {
         let storage = StorageConfig::default();
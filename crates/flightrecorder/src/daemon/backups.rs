@@ -0,0 +1,140 @@
+//! Scheduled backups.
+//!
+//! Every `backup_interval_hours` the database is backed up into the
+//! `backups` directory beside it, and the oldest backups beyond
+//! `backup_count` are removed. The first backup is due one interval after
+//! the newest one already there, or straight away if there is none, so
+//! restarting the daemon neither skips backups nor makes extra ones. Backups
+//! are read from a read-only connection when there is one, so capture
+//! carries on while a backup is made.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use tracing::error;
+
+use crate::error::{Error, Result};
+use crate::storage::{ReadPool, Storage};
+
+use super::{lock_storage, ShutdownHandle};
+
+/// Where and how often to back up.
+#[derive(Debug, Clone)]
+pub(crate) struct BackupSchedule {
+    /// Directory the backups are written to.
+    pub dir: PathBuf,
+    /// Time between backups.
+    pub interval: Duration,
+    /// Number of backups to keep.
+    pub keep: usize,
+}
+
+/// Back up the database on `schedule` until shutdown.
+pub(crate) async fn run_backups(
+    storage: Arc<Mutex<Storage>>,
+    reads: Option<ReadPool>,
+    schedule: BackupSchedule,
+    shutdown: ShutdownHandle,
+) {
+    let dir = schedule.dir.clone();
+    let first = match with_source(&storage, reads.as_ref(), move |s| s.latest_backup(&dir)).await {
+        Ok(Some(latest)) => (Utc::now() - latest)
+            .to_std()
+            .map_or(Duration::ZERO, |since| {
+                schedule.interval.saturating_sub(since)
+            }),
+        Ok(None) => Duration::ZERO,
+        Err(e) => {
+            error!("Failed to find earlier backups: {e}");
+            Duration::ZERO
+        }
+    };
+    let start = tokio::time::Instant::now() + first;
+    let mut ticker = tokio::time::interval_at(start, schedule.interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            () = shutdown.wait() => return,
+        }
+        let dir = schedule.dir.clone();
+        let keep = schedule.keep;
+        tokio::select! {
+            result = with_source(&storage, reads.as_ref(), move |s| s.backup_rotating(&dir, keep)) => {
+                if let Err(e) = result {
+                    error!("Scheduled backup failed: {e}");
+                }
+            }
+            () = shutdown.wait() => return,
+        }
+    }
+}
+
+/// Run `f` off the async runtime on a read-only connection, or on the
+/// writer's connection if there is no read pool.
+async fn with_source<T, F>(
+    storage: &Arc<Mutex<Storage>>,
+    reads: Option<&ReadPool>,
+    f: F,
+) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&Storage) -> Result<T> + Send + 'static,
+{
+    let task = if let Some(reads) = reads {
+        let reads = reads.clone();
+        tokio::task::spawn_blocking(move || f(&*reads.get()?))
+    } else {
+        let storage = Arc::clone(storage);
+        tokio::task::spawn_blocking(move || f(&lock_storage(&storage)))
+    };
+    task.await
+        .map_err(|e| Error::Internal(format!("storage task failed: {e}")))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{Capture, CaptureType};
+
+    #[tokio::test]
+    async fn test_first_backup_is_made_at_start() {
+        let dir = std::env::temp_dir().join(format!("fr_daemon_backups_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let storage = Storage::open(dir.join("captures.db")).unwrap();
+        storage
+            .insert(&Capture::new(
+                "backed up".to_string(),
+                CaptureType::Clipboard,
+                None,
+            ))
+            .unwrap();
+        let storage = Arc::new(Mutex::new(storage));
+        let backups = dir.join("backups");
+        let shutdown = ShutdownHandle::new();
+        let task = tokio::spawn(run_backups(
+            Arc::clone(&storage),
+            None,
+            BackupSchedule {
+                dir: backups.clone(),
+                interval: Duration::from_secs(3600),
+                keep: 2,
+            },
+            shutdown.clone(),
+        ));
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        let latest = loop {
+            if let Some(latest) = lock_storage(&storage).latest_backup(&backups).unwrap() {
+                break latest;
+            }
+            assert!(tokio::time::Instant::now() < deadline, "no backup was made");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert!(Utc::now() - latest < chrono::Duration::minutes(1));
+        shutdown.shutdown();
+        task.await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
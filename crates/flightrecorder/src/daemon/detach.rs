@@ -0,0 +1,203 @@
+//! Detaching the daemon from the invoking terminal.
+//!
+//! [`detach`] uses the classic double fork. The first child calls `setsid` to
+//! leave the terminal's session and forks again, so the daemon is not a
+//! session leader and can never reacquire a controlling terminal. The daemon's
+//! standard streams are redirected to a log file.
+//!
+//! The original process waits on a pipe until the daemon reports, through its
+//! [`ReadyNotifier`], that it is accepting requests, so that `daemon start`
+//! only returns once the daemon is actually up.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use nix::sys::wait::waitpid;
+use nix::unistd::{chdir, dup2, fork, pipe, setsid, ForkResult};
+
+use crate::error::{Error, Result};
+
+/// How long the original process waits for the daemon to become ready.
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Which side of [`detach`] the caller is on.
+#[derive(Debug)]
+pub enum Detached {
+    /// The original process. The daemon is running with the given PID.
+    Parent {
+        /// PID of the detached daemon.
+        pid: u32,
+    },
+    /// The detached daemon process.
+    Daemon(ReadyNotifier),
+}
+
+/// Lets the detached daemon tell the original process that it is ready.
+///
+/// Dropping the notifier without calling [`ready`](Self::ready) tells the
+/// original process that startup failed.
+#[derive(Debug)]
+pub struct ReadyNotifier {
+    pipe: File,
+}
+
+impl ReadyNotifier {
+    /// Report that the daemon is up.
+    pub fn ready(mut self) {
+        // If the original process has gone away there is nobody to tell.
+        let _ = writeln!(self.pipe, "{}", std::process::id());
+    }
+}
+
+/// Detach from the terminal and continue as a background daemon.
+///
+/// Returns [`Detached::Parent`] in the original process once the daemon has
+/// called [`ReadyNotifier::ready`], and [`Detached::Daemon`] in the daemon,
+/// whose stdin is `/dev/null` and whose stdout and stderr append to `log_path`.
+///
+/// This must be called before any threads are started, since only the calling
+/// thread survives `fork`.
+///
+/// # Errors
+///
+/// Returns an error if the log file cannot be opened, if forking fails, or if
+/// the daemon exits or times out before becoming ready.
+pub fn detach(log_path: &Path) -> Result<Detached> {
+    let log = open_log(log_path)?;
+    let (read_end, write_end) = pipe().map_err(std::io::Error::from)?;
+
+    // SAFETY: the caller guarantees that no other threads are running, so the
+    // child cannot inherit locks held by threads that no longer exist.
+    #[allow(unsafe_code)]
+    let forked = unsafe { fork() };
+    match forked.map_err(std::io::Error::from)? {
+        ForkResult::Parent { child } => {
+            drop(write_end);
+            // The intermediate child exits as soon as it has forked again.
+            waitpid(child, None).map_err(std::io::Error::from)?;
+            wait_for_ready(File::from(read_end), log_path)
+        }
+        ForkResult::Child => {
+            drop(read_end);
+            if setsid().is_err() {
+                std::process::exit(1);
+            }
+
+            // SAFETY: the intermediate child is single-threaded.
+            #[allow(unsafe_code)]
+            let forked = unsafe { fork() };
+            match forked {
+                Ok(ForkResult::Child) => {}
+                Ok(ForkResult::Parent { .. }) => std::process::exit(0),
+                Err(_) => std::process::exit(1),
+            }
+
+            redirect_stdio(&log)?;
+            chdir("/").map_err(std::io::Error::from)?;
+            Ok(Detached::Daemon(ReadyNotifier {
+                pipe: File::from(write_end),
+            }))
+        }
+    }
+}
+
+/// Open the daemon's log file for appending, creating its directory if needed.
+fn open_log(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        if !parent.exists() {
+            std::fs::create_dir_all(parent).map_err(|source| Error::DirectoryCreate {
+                path: parent.to_path_buf(),
+                source,
+            })?;
+        }
+    }
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+/// Point stdin at `/dev/null` and stdout and stderr at `log`.
+fn redirect_stdio(log: &File) -> Result<()> {
+    let null = File::open("/dev/null")?;
+    dup2(null.as_raw_fd(), std::io::stdin().as_raw_fd()).map_err(std::io::Error::from)?;
+    dup2(log.as_raw_fd(), std::io::stdout().as_raw_fd()).map_err(std::io::Error::from)?;
+    dup2(log.as_raw_fd(), std::io::stderr().as_raw_fd()).map_err(std::io::Error::from)?;
+    Ok(())
+}
+
+/// Wait for the daemon's PID on the ready pipe.
+fn wait_for_ready(pipe: File, log_path: &Path) -> Result<Detached> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut line = String::new();
+        let _ = tx.send(BufReader::new(pipe).read_line(&mut line).map(|_| line));
+    });
+
+    let line = match rx.recv_timeout(READY_TIMEOUT) {
+        Ok(line) => line?,
+        Err(_) => {
+            return Err(Error::Timeout {
+                operation: format!(
+                    "waiting for the daemon to start (see {})",
+                    log_path.display()
+                ),
+            })
+        }
+    };
+
+    line.trim()
+        .parse()
+        .map(|pid| Detached::Parent { pid })
+        .map_err(|_| {
+            Error::platform(format!(
+                "daemon exited during startup; see {} for details",
+                log_path.display()
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn test_pipe() -> (File, File) {
+        let (read_end, write_end) = pipe().unwrap();
+        (File::from(read_end), File::from(write_end))
+    }
+
+    fn test_log_path() -> PathBuf {
+        std::env::temp_dir().join(format!("fr_detach_{}.log", std::process::id()))
+    }
+
+    #[test]
+    fn test_ready_reports_pid() {
+        let (read_end, write_end) = test_pipe();
+        ReadyNotifier { pipe: write_end }.ready();
+
+        let detached = wait_for_ready(read_end, &test_log_path()).unwrap();
+        assert!(matches!(detached, Detached::Parent { pid } if pid == std::process::id()));
+    }
+
+    #[test]
+    fn test_dropped_notifier_reports_failure() {
+        let (read_end, write_end) = test_pipe();
+        drop(ReadyNotifier { pipe: write_end });
+
+        let err = wait_for_ready(read_end, &test_log_path()).unwrap_err();
+        assert!(err.to_string().contains("exited during startup"));
+    }
+
+    #[test]
+    fn test_open_log_creates_directory() {
+        let dir = std::env::temp_dir().join(format!("fr_detach_dir_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let path = dir.join("fliterec.log");
+        open_log(&path).unwrap();
+        assert!(path.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
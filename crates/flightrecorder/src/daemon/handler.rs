@@ -0,0 +1,504 @@
+//! IPC request handling for the daemon.
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::warn;
+
+use crate::capture::Capture;
+use crate::error::{Error, Result};
+use crate::ipc::{DaemonStatus, PruneRequest, Request, RequestHandler, Response};
+use crate::storage::{ReadPool, RetentionPolicy, Storage};
+
+use super::pruner;
+use super::reload::ReloadReply;
+use super::status::DaemonStats;
+use super::{lock_storage, ShutdownHandle};
+
+/// Answers IPC requests using the daemon's shared state.
+#[derive(Debug)]
+pub(crate) struct DaemonHandler {
+    storage: Arc<Mutex<Storage>>,
+    reads: Option<ReadPool>,
+    shutdown: ShutdownHandle,
+    reload: mpsc::Sender<ReloadReply>,
+    feed: broadcast::Sender<Capture>,
+    stats: Arc<DaemonStats>,
+    retention: RetentionPolicy,
+    started_at: Instant,
+}
+
+impl DaemonHandler {
+    pub(crate) fn new(
+        storage: Arc<Mutex<Storage>>,
+        shutdown: ShutdownHandle,
+        reload: mpsc::Sender<ReloadReply>,
+        feed: broadcast::Sender<Capture>,
+        stats: Arc<DaemonStats>,
+        retention: RetentionPolicy,
+        started_at: Instant,
+    ) -> Self {
+        Self {
+            storage,
+            reads: None,
+            shutdown,
+            reload,
+            feed,
+            stats,
+            retention,
+            started_at,
+        }
+    }
+
+    /// Answer queries from `reads` rather than the writer's connection.
+    #[must_use]
+    pub(crate) fn with_read_pool(mut self, reads: ReadPool) -> Self {
+        self.reads = Some(reads);
+        self
+    }
+
+    /// Ask the daemon's main loop to reload the configuration.
+    async fn reload(&self) -> Response {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if self.reload.send(reply_tx).await.is_err() {
+            return Response::error("daemon is shutting down");
+        }
+        match reply_rx.await {
+            Ok(Ok(summary)) => Response::Reloaded(summary),
+            Ok(Err(e)) => Response::error(format!("reload failed: {e}")),
+            Err(_) => Response::error("daemon is shutting down"),
+        }
+    }
+
+    async fn status(&self) -> DaemonStatus {
+        let storage = match self.read(Storage::stats).await {
+            Ok(stats) => Some(stats),
+            Err(e) => {
+                warn!("Failed to read storage statistics: {e}");
+                None
+            }
+        };
+
+        DaemonStatus {
+            pid: std::process::id(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            monitors: self.stats.monitors(),
+            storage,
+            blocked: self.stats.blocked(),
+            writer: self.stats.writer(),
+        }
+    }
+
+    /// Prune the database under the configured retention limits.
+    async fn prune(&self, request: &PruneRequest) -> Response {
+        match pruner::prune(&self.storage, &self.retention, request.dry_run).await {
+            Ok(report) => Response::Pruned(report),
+            Err(e) => Response::error(format!("prune failed: {e}")),
+        }
+    }
+
+    /// Run a read query against storage off the async runtime and answer
+    /// with its response.
+    async fn query<F>(&self, f: F) -> Response
+    where
+        F: FnOnce(&Storage) -> Result<Response> + Send + 'static,
+    {
+        self.read(f)
+            .await
+            .unwrap_or_else(|e| Response::error(e.to_string()))
+    }
+
+    /// Run a change against storage off the async runtime, on the writer's
+    /// connection, and answer with how many captures it changed.
+    async fn modify<F>(&self, f: F) -> Response
+    where
+        F: FnOnce(&Storage) -> Result<usize> + Send + 'static,
+    {
+        let storage = Arc::clone(&self.storage);
+        match tokio::task::spawn_blocking(move || f(&lock_storage(&storage))).await {
+            Ok(Ok(count)) => Response::Changed { count },
+            Ok(Err(e)) => Response::error(e.to_string()),
+            Err(e) => Response::error(format!("storage task failed: {e}")),
+        }
+    }
+
+    /// Run `f` off the async runtime on a read-only connection, or on the
+    /// writer's connection if there is no read pool.
+    async fn read<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Storage) -> Result<T> + Send + 'static,
+    {
+        let task = if let Some(reads) = &self.reads {
+            let reads = reads.clone();
+            tokio::task::spawn_blocking(move || f(&*reads.get()?))
+        } else {
+            let storage = Arc::clone(&self.storage);
+            tokio::task::spawn_blocking(move || f(&lock_storage(&storage)))
+        };
+        task.await
+            .map_err(|e| Error::Internal(format!("query task failed: {e}")))?
+    }
+}
+
+#[async_trait::async_trait]
+impl RequestHandler for DaemonHandler {
+    async fn handle(&self, request: Request) -> Response {
+        match request {
+            Request::Status => Response::Status(self.status().await),
+            Request::Search(search) => {
+                self.query(move |s| {
+                    let hits = search.execute(s)?;
+                    Ok(Response::SearchResults { hits })
+                })
+                .await
+            }
+            Request::Recover(recover) => {
+                self.query(move |s| {
+                    let captures = recover.execute(s)?;
+                    Ok(Response::Captures { captures })
+                })
+                .await
+            }
+            Request::Shutdown => {
+                self.shutdown.shutdown();
+                Response::Ok
+            }
+            Request::Reload => self.reload().await,
+            Request::Subscribe(_) => Response::error("subscriptions are served by the IPC server"),
+            Request::Prune(prune) => self.prune(&prune).await,
+            Request::Delete(delete) => self.modify(move |s| s.trash(&delete.ids)).await,
+            Request::ListTrash(list) => {
+                self.query(move |s| {
+                    let trashed = s.list_trash(list.limit)?;
+                    Ok(Response::Trash { trashed })
+                })
+                .await
+            }
+            Request::Restore(restore) => self.modify(move |s| s.restore(&restore.ids)).await,
+            Request::EmptyTrash => self.modify(Storage::empty_trash).await,
+            Request::Pin(pin) => {
+                self.modify(move |s| Ok(usize::from(s.pin(pin.id, pin.pinned)?)))
+                    .await
+            }
+            Request::Tag(tag) => {
+                self.modify(move |s| {
+                    let found = if tag.remove {
+                        s.remove_tags(tag.id, &tag.tags)?
+                    } else {
+                        s.add_tags(tag.id, &tag.tags)?
+                    };
+                    Ok(usize::from(found))
+                })
+                .await
+            }
+            Request::Note(note) => {
+                self.modify(move |s| Ok(usize::from(s.set_note(note.id, note.note.as_deref())?)))
+                    .await
+            }
+            Request::Backup(backup) => {
+                self.query(move |s| Ok(Response::BackedUp(s.backup(&backup.path)?)))
+                    .await
+            }
+        }
+    }
+
+    fn subscribe(&self) -> Option<broadcast::Receiver<Capture>> {
+        Some(self.feed.subscribe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::CaptureType;
+    use crate::ipc::{
+        BackupRequest, IdsRequest, ListTrashRequest, PinRequest, RecoverRequest, ReloadSummary,
+        SearchRequest, TagRequest,
+    };
+
+    fn handler() -> DaemonHandler {
+        handler_with_reload(mpsc::channel(1).0)
+    }
+
+    fn handler_with_reload(reload: mpsc::Sender<ReloadReply>) -> DaemonHandler {
+        DaemonHandler::new(
+            Arc::new(Mutex::new(Storage::open_in_memory().unwrap())),
+            ShutdownHandle::new(),
+            reload,
+            broadcast::channel(1).0,
+            Arc::default(),
+            RetentionPolicy::new().with_max_captures(1),
+            Instant::now(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let response = handler().handle(Request::Status).await;
+        match response {
+            Response::Status(status) => {
+                assert_eq!(status.pid, std::process::id());
+                assert_eq!(status.version, env!("CARGO_PKG_VERSION"));
+                assert_eq!(status.storage.map(|s| s.total_captures), Some(0));
+            }
+            other => panic!("unexpected response: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recover() {
+        let handler = handler();
+        lock_storage(&handler.storage)
+            .insert(&Capture::new(
+                "draft".to_string(),
+                CaptureType::TextField,
+                None,
+            ))
+            .unwrap();
+
+        let response = handler
+            .handle(Request::Recover(RecoverRequest {
+                app: None,
+                since: None,
+                tag: None,
+                pinned: false,
+                limit: 10,
+                history: None,
+            }))
+            .await;
+        assert!(matches!(response, Response::Captures { captures } if captures.len() == 1));
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let handler = handler();
+        lock_storage(&handler.storage)
+            .insert(&Capture::new(
+                "meeting notes".to_string(),
+                CaptureType::TextField,
+                None,
+            ))
+            .unwrap();
+
+        let response = handler
+            .handle(Request::Search(SearchRequest {
+                query: "meet*".to_string(),
+                app: None,
+                capture_type: None,
+                since: None,
+                until: None,
+                tag: None,
+                pinned: false,
+                limit: 10,
+            }))
+            .await;
+        let Response::SearchResults { hits } = response else {
+            panic!("unexpected response: {response:?}");
+        };
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snippet.as_deref(), Some("[meeting] notes"));
+    }
+
+    #[tokio::test]
+    async fn test_search_from_read_pool() {
+        let dir = std::env::temp_dir().join(format!("fr_handler_pool_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let storage = Storage::open(dir.join("captures.db")).unwrap();
+        storage
+            .insert(&Capture::new(
+                "meeting notes".to_string(),
+                CaptureType::TextField,
+                None,
+            ))
+            .unwrap();
+        let reads = storage.read_pool(1).unwrap();
+        let handler = DaemonHandler::new(
+            Arc::new(Mutex::new(storage)),
+            ShutdownHandle::new(),
+            mpsc::channel(1).0,
+            broadcast::channel(1).0,
+            Arc::default(),
+            RetentionPolicy::new(),
+            Instant::now(),
+        )
+        .with_read_pool(reads);
+
+        let response = handler
+            .handle(Request::Search(SearchRequest {
+                query: "meeting".to_string(),
+                app: None,
+                capture_type: None,
+                since: None,
+                until: None,
+                tag: None,
+                pinned: false,
+                limit: 10,
+            }))
+            .await;
+        assert!(matches!(response, Response::SearchResults { hits } if hits.len() == 1));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_prune() {
+        let handler = handler();
+        for content in ["old", "new"] {
+            lock_storage(&handler.storage)
+                .insert(&Capture::new(
+                    content.to_string(),
+                    CaptureType::Clipboard,
+                    None,
+                ))
+                .unwrap();
+        }
+
+        let response = handler
+            .handle(Request::Prune(PruneRequest { dry_run: true }))
+            .await;
+        assert!(matches!(response, Response::Pruned(report) if report.over_count == 1));
+        assert_eq!(lock_storage(&handler.storage).count().unwrap(), 2);
+
+        let response = handler
+            .handle(Request::Prune(PruneRequest { dry_run: false }))
+            .await;
+        assert!(matches!(response, Response::Pruned(report) if report.over_count == 1));
+        assert_eq!(lock_storage(&handler.storage).count().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_delete_and_restore() {
+        let handler = handler();
+        let id = lock_storage(&handler.storage)
+            .insert(&Capture::new(
+                "oops".to_string(),
+                CaptureType::Clipboard,
+                None,
+            ))
+            .unwrap()
+            .unwrap();
+        let ids = IdsRequest { ids: vec![id] };
+
+        let response = handler.handle(Request::Delete(ids.clone())).await;
+        assert_eq!(response, Response::Changed { count: 1 });
+        let response = handler
+            .handle(Request::ListTrash(ListTrashRequest { limit: 10 }))
+            .await;
+        assert!(matches!(response, Response::Trash { trashed } if trashed.len() == 1));
+
+        let response = handler.handle(Request::Restore(ids)).await;
+        assert_eq!(response, Response::Changed { count: 1 });
+        assert_eq!(lock_storage(&handler.storage).count().unwrap(), 1);
+
+        lock_storage(&handler.storage).delete(id).unwrap();
+        let response = handler.handle(Request::EmptyTrash).await;
+        assert_eq!(response, Response::Changed { count: 1 });
+    }
+
+    #[tokio::test]
+    async fn test_pin_and_tag() {
+        let handler = handler();
+        let id = lock_storage(&handler.storage)
+            .insert(&Capture::new(
+                "incident report".to_string(),
+                CaptureType::TextField,
+                None,
+            ))
+            .unwrap()
+            .unwrap();
+
+        let response = handler
+            .handle(Request::Pin(PinRequest { id, pinned: true }))
+            .await;
+        assert_eq!(response, Response::Changed { count: 1 });
+        let response = handler
+            .handle(Request::Tag(TagRequest {
+                id,
+                tags: vec!["incident".to_string()],
+                remove: false,
+            }))
+            .await;
+        assert_eq!(response, Response::Changed { count: 1 });
+        let response = handler
+            .handle(Request::Pin(PinRequest {
+                id: id + 1,
+                pinned: true,
+            }))
+            .await;
+        assert_eq!(response, Response::Changed { count: 0 });
+
+        let capture = lock_storage(&handler.storage).get(id).unwrap().unwrap();
+        assert!(capture.pinned);
+        assert_eq!(capture.tags, ["incident"]);
+    }
+
+    #[tokio::test]
+    async fn test_backup_request() {
+        let handler = handler();
+        lock_storage(&handler.storage)
+            .insert(&Capture::new(
+                "kept safe".to_string(),
+                CaptureType::Clipboard,
+                None,
+            ))
+            .unwrap();
+        let path =
+            std::env::temp_dir().join(format!("fr_handler_backup_{}.db", std::process::id()));
+
+        let response = handler
+            .handle(Request::Backup(BackupRequest { path: path.clone() }))
+            .await;
+        match response {
+            Response::BackedUp(info) => {
+                assert_eq!(info.path, path);
+                assert!(info.size_bytes > 0);
+            }
+            other => panic!("unexpected response: {other:?}"),
+        }
+        assert_eq!(Storage::open(&path).unwrap().count().unwrap(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_request() {
+        let handler = handler();
+        assert_eq!(handler.handle(Request::Shutdown).await, Response::Ok);
+        assert!(handler.shutdown.is_shutdown());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_receives_feed() {
+        let handler = handler();
+        let mut feed = handler.subscribe().unwrap();
+        let capture = Capture::new("live".to_string(), CaptureType::Clipboard, None);
+        handler.feed.send(capture.clone()).unwrap();
+        assert_eq!(feed.recv().await.unwrap(), capture);
+    }
+
+    #[tokio::test]
+    async fn test_reload_forwarded_to_main_loop() {
+        let (tx, mut rx) = mpsc::channel::<ReloadReply>(1);
+        tokio::spawn(async move {
+            let reply = rx.recv().await.unwrap();
+            let _ = reply.send(Ok(ReloadSummary {
+                changed: vec!["privacy".to_string()],
+                restart_required: Vec::new(),
+                restarted: Vec::new(),
+            }));
+        });
+
+        let response = handler_with_reload(tx).handle(Request::Reload).await;
+        assert!(matches!(response, Response::Reloaded(s) if s.changed == ["privacy"]));
+    }
+
+    #[tokio::test]
+    async fn test_reload_when_main_loop_gone() {
+        let (tx, rx) = mpsc::channel::<ReloadReply>(1);
+        drop(rx);
+
+        let response = handler_with_reload(tx).handle(Request::Reload).await;
+        assert!(matches!(response, Response::Error { .. }));
+    }
+}
@@ -0,0 +1,1022 @@
+//! The capture daemon.
+//!
+//! The daemon owns the capture pipeline. Every enabled [`CaptureMonitor`]
+//! sends captures into a shared, bounded channel, and a single writer task
+//! runs each capture through the [`PrivacyFilter`] before inserting it into
+//! [`Storage`], batching whatever is queued into one transaction. Searches
+//! from the CLI run on a separate pool of read-only connections, so they
+//! never wait for the writer, nor it for them.
+//! The daemon also serves IPC requests from the CLI, and shuts down cleanly on
+//! SIGTERM, SIGINT or a `Shutdown` request: monitors are stopped first, then
+//! the captures still queued in the channel are written before it exits.
+//!
+//! SIGHUP or a `Reload` request makes the daemon re-read its configuration
+//! file, rebuild the privacy filter, start or stop monitors to match and
+//! restart running monitors whose settings changed.
+//!
+//! Each monitor runs under a supervisor that restarts it with exponential
+//! backoff when it fails, panics or stops beating its heartbeat (see
+//! [`RestartPolicy`]); `fliterec status` shows monitors that keep failing as
+//! degraded.
+//!
+//! Every capture the writer stores is also published on a broadcast feed that
+//! IPC clients can subscribe to (`fliterec tail`). The feed is bounded and
+//! never waits for subscribers, so a slow client cannot hold up storage.
+//!
+//! A pruning task deletes the captures beyond the configured retention limits
+//! on a schedule, in batches between captures (see
+//! [`RetentionPolicy`](crate::storage::RetentionPolicy)), and a backup task
+//! can back the database up on a schedule of its own, keeping the newest few
+//! backups.
+//!
+//! Only one daemon runs per PID file: it holds an exclusive lock on the file
+//! for as long as it runs (see [`PidFile`]). Under systemd, the daemon reports
+//! its state to the service manager through a [`SystemdNotifier`].
+
+mod backups;
+mod detach;
+mod handler;
+mod monitors;
+mod notify;
+mod pidfile;
+mod process;
+mod pruner;
+mod reload;
+mod status;
+mod supervisor;
+mod writer;
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::capture::Capture;
+use crate::config::Config;
+use crate::error::Result;
+use crate::ipc::{IpcServer, ReloadSummary};
+use crate::monitor::{CaptureMonitor, MonitorType};
+use crate::privacy::{FilterConfig, FilterResult, PrivacyFilter};
+use crate::storage::{ReadPool, Storage};
+
+pub use detach::{detach, Detached, ReadyNotifier};
+pub use monitors::{enabled_monitor_types, platform_monitor, platform_monitors};
+pub use notify::SystemdNotifier;
+pub use pidfile::PidFile;
+pub use process::{is_alive, terminate, Termination};
+pub use supervisor::RestartPolicy;
+
+use handler::DaemonHandler;
+use status::DaemonStats;
+use supervisor::Supervisor;
+
+/// Capacity of the channel between monitors and the storage writer.
+const CAPTURE_CHANNEL_CAPACITY: usize = 256;
+
+/// How many stored captures a subscriber may fall behind before missing some.
+const FEED_CHANNEL_CAPACITY: usize = 256;
+
+/// How many reload requests may wait for the daemon's main loop.
+const RELOAD_CHANNEL_CAPACITY: usize = 4;
+
+/// Number of read-only connections answering IPC queries.
+const READ_POOL_SIZE: u32 = 4;
+
+/// A cloneable handle used to ask the daemon to shut down.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    tx: Arc<watch::Sender<bool>>,
+}
+
+impl ShutdownHandle {
+    fn new() -> Self {
+        Self {
+            tx: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    /// Ask the daemon to shut down.
+    pub fn shutdown(&self) {
+        self.tx.send_replace(true);
+    }
+
+    /// Check if shutdown has been requested.
+    #[must_use]
+    pub fn is_shutdown(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// Wait until shutdown is requested.
+    pub async fn wait(&self) {
+        let mut rx = self.tx.subscribe();
+        let _ = rx.wait_for(|requested| *requested).await;
+    }
+}
+
+/// Builds the monitor of a given type, used when a reload enables one and
+/// when a failed monitor is restarted.
+pub type MonitorFactory =
+    Arc<dyn Fn(&Config, MonitorType) -> Option<Box<dyn CaptureMonitor>> + Send + Sync>;
+
+/// The capture daemon.
+pub struct Daemon {
+    config: Config,
+    config_path: Option<PathBuf>,
+    storage: Arc<Mutex<Storage>>,
+    filter: Arc<PrivacyFilter>,
+    monitors: Vec<Box<dyn CaptureMonitor>>,
+    monitor_factory: MonitorFactory,
+    restart_policy: RestartPolicy,
+    shutdown: ShutdownHandle,
+    on_ready: Option<Box<dyn FnOnce() + Send>>,
+    notifier: Option<Arc<SystemdNotifier>>,
+}
+
+/// The tasks started by [`Daemon::start_tasks`], awaited on shutdown.
+struct Tasks {
+    pruner: Option<JoinHandle<()>>,
+    backups: Option<JoinHandle<()>>,
+    server: JoinHandle<Result<()>>,
+}
+
+impl std::fmt::Debug for Daemon {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Daemon")
+            .field("config", &self.config)
+            .field("config_path", &self.config_path)
+            .field("storage", &self.storage)
+            .field(
+                "monitors",
+                &self
+                    .monitors
+                    .iter()
+                    .map(|m| m.monitor_type())
+                    .collect::<Vec<_>>(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl Daemon {
+    /// Create a daemon using the configured database and this platform's monitors.
+    ///
+    /// If the daemon was started by systemd, it also notifies the service
+    /// manager (see [`SystemdNotifier::from_env`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened, or the configured
+    /// key does not unlock it.
+    pub fn new(config: Config) -> Result<Self> {
+        let storage =
+            Storage::open_with_key(config.database_path(), config.encryption_key()?.as_ref())?;
+        let monitors = platform_monitors(&config);
+        let daemon = Self::with_monitors(config, storage, monitors);
+        Ok(match SystemdNotifier::from_env() {
+            Some(notifier) => daemon.with_notifier(notifier),
+            None => daemon,
+        })
+    }
+
+    /// Create a daemon with an explicit storage and set of monitors.
+    #[must_use]
+    pub fn with_monitors(
+        config: Config,
+        storage: Storage,
+        monitors: Vec<Box<dyn CaptureMonitor>>,
+    ) -> Self {
+        let filter = PrivacyFilter::with_config(FilterConfig::from(&config.privacy));
+        let storage = storage
+            .with_session_gap(config.session_gap())
+            .with_compression_threshold(config.compression_threshold());
+        Self {
+            config,
+            config_path: None,
+            storage: Arc::new(Mutex::new(storage)),
+            filter: Arc::new(filter),
+            monitors,
+            monitor_factory: Arc::new(platform_monitor),
+            restart_policy: RestartPolicy::default(),
+            shutdown: ShutdownHandle::new(),
+            on_ready: None,
+            notifier: None,
+        }
+    }
+
+    /// Set the configuration file re-read on reload.
+    ///
+    /// `None` means the default location, as for [`Config::load_from`].
+    #[must_use]
+    pub fn with_config_path(mut self, path: Option<PathBuf>) -> Self {
+        self.config_path = path;
+        self
+    }
+
+    /// Set how monitors enabled by a reload or restarted after a failure are
+    /// built.
+    #[must_use]
+    pub fn with_monitor_factory(
+        mut self,
+        factory: impl Fn(&Config, MonitorType) -> Option<Box<dyn CaptureMonitor>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.monitor_factory = Arc::new(factory);
+        self
+    }
+
+    /// Set how failed monitors are restarted.
+    #[must_use]
+    pub fn with_restart_policy(mut self, policy: RestartPolicy) -> Self {
+        self.restart_policy = policy;
+        self
+    }
+
+    /// Send service manager notifications through `notifier`.
+    #[must_use]
+    pub fn with_notifier(mut self, notifier: SystemdNotifier) -> Self {
+        self.notifier = Some(Arc::new(notifier));
+        self
+    }
+
+    /// Set a callback to run once the daemon is accepting IPC requests.
+    #[must_use]
+    pub fn on_ready(mut self, f: impl FnOnce() + Send + 'static) -> Self {
+        self.on_ready = Some(Box::new(f));
+        self
+    }
+
+    /// Get a handle that can be used to shut the daemon down.
+    #[must_use]
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Get shared access to the daemon's storage.
+    #[must_use]
+    pub fn storage(&self) -> Arc<Mutex<Storage>> {
+        Arc::clone(&self.storage)
+    }
+
+    /// Run the daemon until it receives SIGTERM, SIGINT or a shutdown request.
+    ///
+    /// SIGHUP and `Reload` requests reload the configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::DaemonAlreadyRunning`](crate::Error::DaemonAlreadyRunning)
+    /// if another daemon holds the PID file, or an error if the IPC socket or
+    /// signal handlers cannot be set up.
+    pub async fn run(mut self) -> Result<()> {
+        let started_at = Instant::now();
+        let pid_file = PidFile::acquire(self.config.pid_file_path())?;
+        let server = IpcServer::bind(self.config.socket_path())?;
+        let mut sigterm = signal(SignalKind::terminate())?;
+        let mut sigint = signal(SignalKind::interrupt())?;
+        let mut sighup = signal(SignalKind::hangup())?;
+
+        let stats = Arc::new(DaemonStats::default());
+        let (tx, rx) = mpsc::channel(CAPTURE_CHANNEL_CAPACITY);
+        let mut monitors = Supervisor::new(
+            tx,
+            Arc::clone(&stats),
+            Arc::clone(&self.monitor_factory),
+            &self.config,
+            self.restart_policy.clone(),
+        );
+        for monitor in std::mem::take(&mut self.monitors) {
+            monitors.spawn(monitor);
+        }
+
+        let (filter_tx, filter_rx) = watch::channel(Arc::clone(&self.filter));
+        let (feed, _) = broadcast::channel(FEED_CHANNEL_CAPACITY);
+        let (drain_tx, drain_rx) = oneshot::channel();
+        let writer = tokio::spawn(writer::write_captures(
+            rx,
+            Arc::clone(&self.storage),
+            filter_rx,
+            feed.clone(),
+            Arc::clone(&stats),
+            drain_rx,
+        ));
+
+        let (reload_tx, mut reload_rx) = mpsc::channel(RELOAD_CHANNEL_CAPACITY);
+        let handler = DaemonHandler::new(
+            Arc::clone(&self.storage),
+            self.shutdown.clone(),
+            reload_tx,
+            feed,
+            stats,
+            self.config.retention_policy(),
+            started_at,
+        );
+        let tasks = self.start_tasks(server, handler);
+
+        info!(monitors = monitors.count(), "Daemon started");
+        if let Some(on_ready) = self.on_ready.take() {
+            on_ready();
+        }
+
+        let watchdog = self.notify_ready(monitors.count());
+
+        loop {
+            tokio::select! {
+                _ = sigterm.recv() => {
+                    info!("Received SIGTERM");
+                    break;
+                }
+                _ = sigint.recv() => {
+                    info!("Received SIGINT");
+                    break;
+                }
+                () = self.shutdown.wait() => {
+                    info!("Shutdown requested");
+                    break;
+                }
+                _ = sighup.recv() => {
+                    info!("Received SIGHUP");
+                    if let Err(e) = self.reload(&filter_tx, &mut monitors).await {
+                        error!("Configuration reload failed: {e}");
+                    }
+                }
+                Some(reply) = reload_rx.recv() => {
+                    let _ = reply.send(self.reload(&filter_tx, &mut monitors).await);
+                }
+            }
+        }
+        self.shutdown.shutdown();
+        if let Some(notifier) = &self.notifier {
+            if let Err(e) = notifier.stopping() {
+                warn!("Failed to notify service manager: {e}");
+            }
+        }
+        for task in [watchdog, tasks.pruner, tasks.backups]
+            .into_iter()
+            .flatten()
+        {
+            let _ = task.await;
+        }
+
+        info!("Stopping monitors");
+        monitors.stop_all().await;
+
+        let _ = drain_tx.send(());
+        match writer.await {
+            Ok(stored) => info!(stored, "Storage writer finished"),
+            Err(e) => error!("Storage writer failed: {e}"),
+        }
+
+        match tasks.server.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("IPC server failed: {e}"),
+            Err(e) => error!("IPC server task failed: {e}"),
+        }
+
+        drop(pid_file);
+        info!("Daemon stopped");
+        Ok(())
+    }
+
+    /// Start the tasks that run beside the capture path: the pruner,
+    /// scheduled backups and the IPC server, which answers with `handler`.
+    fn start_tasks(&self, server: IpcServer, handler: DaemonHandler) -> Tasks {
+        let pruner = self.start_pruner();
+        let reads = self.open_read_pool();
+        let backups = self.start_backups(reads.clone());
+
+        let handler = Arc::new(match reads {
+            Some(reads) => handler.with_read_pool(reads),
+            None => handler,
+        });
+        let server_shutdown = self.shutdown.clone();
+        let server = tokio::spawn(server.serve(handler, async move {
+            server_shutdown.wait().await;
+        }));
+        Tasks {
+            pruner,
+            backups,
+            server,
+        }
+    }
+
+    /// Tell the service manager, if any, that the daemon is ready, and start
+    /// the watchdog task if it asked for keep-alives.
+    fn notify_ready(&self, monitors: usize) -> Option<JoinHandle<()>> {
+        let notifier = self.notifier.as_ref()?;
+        if let Err(e) = notifier.ready(&format!("Capturing with {monitors} monitors")) {
+            warn!("Failed to notify service manager: {e}");
+        }
+        notifier.watchdog_interval().map(|interval| {
+            tokio::spawn(run_watchdog(
+                Arc::clone(notifier),
+                interval,
+                self.shutdown.clone(),
+            ))
+        })
+    }
+
+    /// Open the read-only connections that answer queries, or `None` to
+    /// answer them through the writer's connection, as for an in-memory
+    /// database.
+    fn open_read_pool(&self) -> Option<ReadPool> {
+        match lock_storage(&self.storage).read_pool(READ_POOL_SIZE) {
+            Ok(pool) => Some(pool),
+            Err(e) => {
+                warn!("Queries will share the writer's connection: {e}");
+                None
+            }
+        }
+    }
+
+    /// Start the pruning task, unless pruning is unscheduled. Without
+    /// retention limits it still empties the trash.
+    fn start_pruner(&self) -> Option<JoinHandle<()>> {
+        let policy = self.config.retention_policy();
+        let interval = self.config.prune_interval();
+        if interval.is_zero() {
+            info!("Scheduled pruning is off");
+            return None;
+        }
+        Some(tokio::spawn(pruner::run_pruner(
+            Arc::clone(&self.storage),
+            policy,
+            interval,
+            self.shutdown.clone(),
+        )))
+    }
+
+    /// Start the scheduled backup task, unless backups are unscheduled.
+    /// Backups are read through `reads` when there is a read pool.
+    fn start_backups(&self, reads: Option<ReadPool>) -> Option<JoinHandle<()>> {
+        let interval = self.config.backup_interval();
+        if interval.is_zero() {
+            info!("Scheduled backups are off");
+            return None;
+        }
+        let schedule = backups::BackupSchedule {
+            dir: self.config.backup_dir(),
+            interval,
+            keep: self.config.storage.backup_count,
+        };
+        Some(tokio::spawn(backups::run_backups(
+            Arc::clone(&self.storage),
+            reads,
+            schedule,
+            self.shutdown.clone(),
+        )))
+    }
+
+    /// Reload the configuration file and apply the changes that can be made
+    /// while running.
+    ///
+    /// Captures already queued keep flowing to the writer, which picks up the
+    /// new privacy filter for the next capture it handles.
+    async fn reload(
+        &mut self,
+        filter: &watch::Sender<Arc<PrivacyFilter>>,
+        monitors: &mut Supervisor,
+    ) -> Result<ReloadSummary> {
+        let config = Config::load_from(self.config_path.clone())?;
+        let mut summary = reload::summarize(&self.config, &config);
+
+        if config.privacy != self.config.privacy {
+            let rebuilt = PrivacyFilter::with_config(FilterConfig::from(&config.privacy));
+            filter.send_replace(Arc::new(rebuilt));
+            info!("Privacy filter rebuilt");
+        }
+
+        if config.capture.session_gap_secs != self.config.capture.session_gap_secs {
+            lock_storage(&self.storage).set_session_gap(config.session_gap());
+        }
+
+        monitors.set_config(&config);
+        let was_enabled = enabled_monitor_types(&self.config);
+        let now_enabled = enabled_monitor_types(&config);
+        for monitor_type in &was_enabled {
+            if !now_enabled.contains(monitor_type) {
+                info!(%monitor_type, "Stopping disabled monitor");
+                monitors.stop(*monitor_type).await;
+            }
+        }
+        for monitor_type in reload::monitors_to_restart(&self.config, &config) {
+            if !monitors.contains(monitor_type) {
+                continue;
+            }
+            monitors.stop(monitor_type).await;
+            if let Some(monitor) = monitors.build(monitor_type) {
+                info!(%monitor_type, "Restarting monitor with new settings");
+                monitors.spawn(monitor);
+                summary.restarted.push(monitor_type.to_string());
+            }
+        }
+        for monitor_type in now_enabled {
+            if !was_enabled.contains(&monitor_type) && !monitors.contains(monitor_type) {
+                if let Some(monitor) = monitors.build(monitor_type) {
+                    info!(%monitor_type, "Starting enabled monitor");
+                    monitors.spawn(monitor);
+                }
+            }
+        }
+
+        if !summary.restart_required.is_empty() {
+            warn!(
+                sections = ?summary.restart_required,
+                "Some configuration changes take effect after a restart"
+            );
+        }
+        info!(changed = ?summary.changed, "Configuration reloaded");
+        self.config = config;
+        Ok(summary)
+    }
+}
+
+/// Send watchdog keep-alives every `interval` until shutdown.
+async fn run_watchdog(
+    notifier: Arc<SystemdNotifier>,
+    interval: Duration,
+    shutdown: ShutdownHandle,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if let Err(e) = notifier.watchdog() {
+                    warn!("Failed to send watchdog keep-alive: {e}");
+                }
+            }
+            () = shutdown.wait() => return,
+        }
+    }
+}
+
+/// Apply app exclusion and content filtering to a capture.
+///
+/// Returns the (possibly redacted) capture, or the name of the rule that
+/// blocked it.
+fn apply_privacy(
+    filter: &PrivacyFilter,
+    mut capture: Capture,
+) -> std::result::Result<Capture, String> {
+    if let Some(app) = &capture.source_app {
+        if filter.is_app_excluded(app) {
+            return Err("excluded_app".to_string());
+        }
+    }
+
+    match filter.filter(&capture.content) {
+        FilterResult::Passed => Ok(capture),
+        FilterResult::Blocked { pattern_name } => Err(pattern_name),
+        FilterResult::Redacted { content, .. } => {
+            capture.content_hash = Capture::compute_hash(&content);
+            capture.content = content;
+            Ok(capture)
+        }
+    }
+}
+
+/// Lock the storage, recovering from a poisoned mutex.
+///
+/// A panic while holding the lock cannot leave the `SQLite` connection in an
+/// inconsistent state, so the guard is safe to reuse.
+fn lock_storage(storage: &Mutex<Storage>) -> MutexGuard<'_, Storage> {
+    storage.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::CaptureType;
+    use crate::error::Error;
+    use crate::monitor::{MonitorStatus, MonitorType};
+
+    /// A monitor that sends a fixed set of captures and then idles.
+    struct FakeMonitor {
+        monitor_type: MonitorType,
+        captures: Vec<Capture>,
+    }
+
+    impl FakeMonitor {
+        fn new(captures: Vec<Capture>) -> Self {
+            Self {
+                monitor_type: MonitorType::Clipboard,
+                captures,
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl CaptureMonitor for FakeMonitor {
+        fn monitor_type(&self) -> MonitorType {
+            self.monitor_type
+        }
+
+        fn is_running(&self) -> bool {
+            true
+        }
+
+        fn has_permission(&self) -> bool {
+            true
+        }
+
+        fn status(&self) -> MonitorStatus {
+            MonitorStatus::running(self.monitor_type, 0)
+        }
+
+        async fn start(&mut self, tx: mpsc::Sender<Capture>) -> crate::monitor::Result<()> {
+            for capture in self.captures.drain(..) {
+                let _ = tx.send(capture).await;
+            }
+            std::future::pending::<()>().await;
+            Ok(())
+        }
+
+        fn stop(&self) -> crate::monitor::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn test_config(name: &str) -> Config {
+        let mut config = Config::default();
+        config.daemon.socket_path = Some(
+            std::env::temp_dir().join(format!("fr_daemon_{name}_{}.sock", std::process::id())),
+        );
+        config.daemon.pid_file_path =
+            Some(std::env::temp_dir().join(format!("fr_daemon_{name}_{}.pid", std::process::id())));
+        config
+    }
+
+    fn clipboard(content: &str, app: Option<&str>) -> Capture {
+        Capture::new(
+            content.to_string(),
+            CaptureType::Clipboard,
+            app.map(String::from),
+        )
+    }
+
+    #[test]
+    fn test_apply_privacy_passes_clean_content() {
+        let filter = PrivacyFilter::new();
+        let capture = clipboard("meeting notes", Some("Notes"));
+        assert!(apply_privacy(&filter, capture).is_ok());
+    }
+
+    #[test]
+    fn test_apply_privacy_blocks_excluded_app() {
+        let filter = PrivacyFilter::new();
+        let capture = clipboard("anything", Some("1Password"));
+        assert_eq!(apply_privacy(&filter, capture).unwrap_err(), "excluded_app");
+    }
+
+    #[test]
+    fn test_apply_privacy_blocks_sensitive_content() {
+        let filter = PrivacyFilter::new();
+        let capture = clipboard("password=hunter2hunter2", None);
+        assert!(apply_privacy(&filter, capture).is_err());
+    }
+
+    #[test]
+    fn test_shutdown_handle() {
+        let handle = ShutdownHandle::new();
+        let clone = handle.clone();
+        assert!(!handle.is_shutdown());
+        clone.shutdown();
+        assert!(handle.is_shutdown());
+    }
+
+    #[tokio::test]
+    async fn test_daemon_stores_filtered_captures() {
+        let config = test_config("pipeline");
+        let monitor = FakeMonitor::new(vec![
+            clipboard("first note", Some("Notes")),
+            clipboard("secret", Some("1Password")),
+            clipboard("password=hunter2hunter2", Some("Terminal")),
+            clipboard("second note", Some("Notes")),
+        ]);
+        let daemon = Daemon::with_monitors(
+            config,
+            Storage::open_in_memory().unwrap(),
+            vec![Box::new(monitor)],
+        );
+        let storage = daemon.storage();
+        let shutdown = daemon.shutdown_handle();
+        let task = tokio::spawn(daemon.run());
+
+        // Shut down immediately: queued captures must still be flushed.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown.shutdown();
+        tokio::time::timeout(Duration::from_secs(10), task)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        let recent = lock_storage(&storage).get_recent(10).unwrap();
+        let contents: Vec<_> = recent.iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(contents.len(), 2);
+        assert!(contents.contains(&"first note"));
+        assert!(contents.contains(&"second note"));
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_monitors_and_blocked_captures() {
+        let config = test_config("status");
+        let socket = config.socket_path();
+        let monitor = FakeMonitor::new(vec![
+            clipboard("kept", Some("Notes")),
+            clipboard("secret", Some("1Password")),
+            clipboard("another secret", Some("1Password")),
+        ]);
+        let daemon = Daemon::with_monitors(
+            config,
+            Storage::open_in_memory().unwrap(),
+            vec![Box::new(monitor)],
+        );
+        let shutdown = daemon.shutdown_handle();
+        let task = tokio::spawn(daemon.run());
+
+        let client = crate::ipc::IpcClient::new(&socket);
+        let status = tokio::task::spawn_blocking(move || {
+            for _ in 0..50 {
+                if let Ok(crate::ipc::Response::Status(status)) =
+                    client.request(&crate::ipc::Request::Status)
+                {
+                    // Wait until the writer has handled every capture.
+                    if status.blocked.values().sum::<u64>() == 2
+                        && status.storage.as_ref().map(|s| s.total_captures) == Some(1)
+                    {
+                        return Some(status);
+                    }
+                }
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            None
+        })
+        .await
+        .unwrap()
+        .expect("status never showed all captures");
+
+        assert_eq!(status.monitors.len(), 1);
+        assert!(status.monitors[0].is_running);
+        assert_eq!(status.monitors[0].monitor_type, MonitorType::Clipboard);
+        assert_eq!(status.monitors[0].capture_count, 3);
+        assert_eq!(status.blocked.get("excluded_app"), Some(&2));
+
+        shutdown.shutdown();
+        tokio::time::timeout(Duration::from_secs(10), task)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_daemon_shutdown_via_ipc() {
+        let config = test_config("ipc_shutdown");
+        let socket = config.socket_path();
+        let pid_path = config.pid_file_path();
+        let daemon = Daemon::with_monitors(config, Storage::open_in_memory().unwrap(), vec![]);
+        let task = tokio::spawn(daemon.run());
+
+        let client = crate::ipc::IpcClient::new(&socket);
+        let response = tokio::task::spawn_blocking(move || {
+            for _ in 0..50 {
+                match client.request(&crate::ipc::Request::Shutdown) {
+                    Err(e) if e.is_daemon_not_running() => {
+                        std::thread::sleep(Duration::from_millis(20));
+                    }
+                    other => return other,
+                }
+            }
+            Err(Error::DaemonNotRunning)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(response, crate::ipc::Response::Ok);
+
+        tokio::time::timeout(Duration::from_secs(10), task)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(!socket.exists());
+        assert!(!pid_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_on_ready_runs_once_serving() {
+        let config = test_config("ready");
+        let socket = config.socket_path();
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let daemon = Daemon::with_monitors(config, Storage::open_in_memory().unwrap(), vec![])
+            .on_ready(move || {
+                let _ = ready_tx.send(());
+            });
+        let shutdown = daemon.shutdown_handle();
+        let task = tokio::spawn(daemon.run());
+
+        tokio::time::timeout(Duration::from_secs(10), ready_rx)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(socket.exists());
+
+        shutdown.shutdown();
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_daemon_notifies_service_manager() {
+        let config = test_config("notify");
+        let notify_path =
+            std::env::temp_dir().join(format!("fr_fake_systemd_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&notify_path);
+        let fake_systemd = tokio::net::UnixDatagram::bind(&notify_path).unwrap();
+
+        let notifier = SystemdNotifier::new(&notify_path)
+            .unwrap()
+            .with_watchdog(Some(Duration::from_millis(20)));
+        let daemon = Daemon::with_monitors(config, Storage::open_in_memory().unwrap(), vec![])
+            .with_notifier(notifier);
+        let shutdown = daemon.shutdown_handle();
+        let task = tokio::spawn(daemon.run());
+
+        let mut messages = Vec::new();
+        let mut buf = [0; 256];
+        while !messages
+            .iter()
+            .any(|m: &String| m.starts_with("WATCHDOG=1"))
+        {
+            let len = tokio::time::timeout(Duration::from_secs(10), fake_systemd.recv(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            messages.push(String::from_utf8_lossy(&buf[..len]).into_owned());
+        }
+        assert!(messages[0].starts_with("READY=1"));
+
+        shutdown.shutdown();
+        task.await.unwrap().unwrap();
+        let mut stopping = false;
+        while let Ok(len) = fake_systemd.try_recv(&mut buf) {
+            stopping |= buf[..len].starts_with(b"STOPPING=1");
+        }
+        assert!(stopping);
+        let _ = std::fs::remove_file(&notify_path);
+    }
+
+    #[tokio::test]
+    async fn test_reload_applies_new_config() {
+        let config = test_config("reload");
+        let socket = config.socket_path();
+        let config_path =
+            std::env::temp_dir().join(format!("fr_daemon_reload_{}.toml", std::process::id()));
+        std::fs::write(
+            &config_path,
+            format!(
+                "[capture]\nclipboard_enabled = false\nkeystroke_fallback_enabled = true\n\n\
+                 [privacy]\nexcluded_apps = [\"Notes\"]\n\n\
+                 [daemon]\nsocket_path = \"{}\"\npid_file_path = \"{}\"\n",
+                socket.display(),
+                config.pid_file_path().display()
+            ),
+        )
+        .unwrap();
+
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let daemon = Daemon::with_monitors(
+            config,
+            Storage::open_in_memory().unwrap(),
+            vec![Box::new(FakeMonitor::new(Vec::new()))],
+        )
+        .with_config_path(Some(config_path.clone()))
+        .with_monitor_factory(|_config, monitor_type| {
+            Some(Box::new(FakeMonitor {
+                monitor_type,
+                captures: vec![
+                    clipboard("now excluded", Some("Notes")),
+                    clipboard("still captured", Some("Editor")),
+                ],
+            }))
+        })
+        .on_ready(move || {
+            let _ = ready_tx.send(());
+        });
+        let storage = daemon.storage();
+        let shutdown = daemon.shutdown_handle();
+        let task = tokio::spawn(daemon.run());
+        ready_rx.await.unwrap();
+
+        let client = crate::ipc::IpcClient::new(&socket);
+        let response =
+            tokio::task::spawn_blocking(move || client.request(&crate::ipc::Request::Reload))
+                .await
+                .unwrap()
+                .unwrap();
+        let crate::ipc::Response::Reloaded(summary) = response else {
+            panic!("unexpected response: {response:?}");
+        };
+        assert_eq!(summary.changed, vec!["capture", "privacy"]);
+        assert!(summary.restart_required.is_empty());
+
+        // The keystroke monitor started by the reload sends its captures
+        // through the rebuilt filter.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown.shutdown();
+        task.await.unwrap().unwrap();
+
+        let recent = lock_storage(&storage).get_recent(10).unwrap();
+        let contents: Vec<_> = recent.iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(contents, vec!["still captured"]);
+        let _ = std::fs::remove_file(&config_path);
+    }
+
+    #[tokio::test]
+    async fn test_reload_with_invalid_config_keeps_running() {
+        let config = test_config("reload_invalid");
+        let config_path = std::env::temp_dir().join(format!(
+            "fr_daemon_reload_invalid_{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(
+            &config_path,
+            "[capture]\nmin_content_length = 10\nmax_content_length = 1\n",
+        )
+        .unwrap();
+
+        let mut daemon = Daemon::with_monitors(config, Storage::open_in_memory().unwrap(), vec![])
+            .with_config_path(Some(config_path.clone()));
+        let (filter_tx, _filter_rx) = watch::channel(Arc::clone(&daemon.filter));
+        let (tx, _rx) = mpsc::channel(1);
+        let mut monitors = Supervisor::new(
+            tx,
+            Arc::default(),
+            Arc::clone(&daemon.monitor_factory),
+            &daemon.config,
+            RestartPolicy::default(),
+        );
+
+        let before = daemon.config.clone();
+        assert!(daemon.reload(&filter_tx, &mut monitors).await.is_err());
+        assert_eq!(daemon.config, before);
+        let _ = std::fs::remove_file(&config_path);
+    }
+
+    #[tokio::test]
+    async fn test_reload_restarts_monitors_with_changed_settings() {
+        let config_path = std::env::temp_dir().join(format!(
+            "fr_daemon_reload_restart_{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(&config_path, "[capture]\nsnapshot_interval_ms = 2000\n").unwrap();
+
+        let mut daemon = Daemon::with_monitors(
+            Config::default(),
+            Storage::open_in_memory().unwrap(),
+            vec![],
+        )
+        .with_config_path(Some(config_path.clone()))
+        .with_monitor_factory(|_config, monitor_type| {
+            Some(Box::new(FakeMonitor {
+                monitor_type,
+                captures: Vec::new(),
+            }))
+        });
+        let (filter_tx, _filter_rx) = watch::channel(Arc::clone(&daemon.filter));
+        let (tx, _rx) = mpsc::channel(1);
+        let mut monitors = Supervisor::new(
+            tx,
+            Arc::default(),
+            Arc::clone(&daemon.monitor_factory),
+            &daemon.config,
+            RestartPolicy::default(),
+        );
+        for monitor_type in [MonitorType::Clipboard, MonitorType::Accessibility] {
+            monitors.spawn(monitors.build(monitor_type).unwrap());
+        }
+
+        let summary = daemon.reload(&filter_tx, &mut monitors).await.unwrap();
+        assert_eq!(summary.changed, vec!["capture"]);
+        assert!(summary.restart_required.is_empty());
+        assert_eq!(summary.restarted, vec!["accessibility"]);
+        assert_eq!(monitors.count(), 2);
+        assert!(monitors.contains(MonitorType::Accessibility));
+        monitors.stop_all().await;
+        let _ = std::fs::remove_file(&config_path);
+    }
+
+    #[tokio::test]
+    async fn test_second_daemon_refused() {
+        let config = test_config("single_instance");
+        let _held = PidFile::acquire(config.pid_file_path()).unwrap();
+
+        let daemon = Daemon::with_monitors(config, Storage::open_in_memory().unwrap(), vec![]);
+        let err = daemon.run().await.unwrap_err();
+        assert!(matches!(err, Error::DaemonAlreadyRunning { .. }));
+    }
+}
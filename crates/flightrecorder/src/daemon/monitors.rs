@@ -0,0 +1,272 @@
+//! Construction of the platform's capture monitors.
+//!
+//! The platform crates expose their own monitor types so that they do not
+//! depend on this crate. This module adapts them to [`CaptureMonitor`] and
+//! selects the ones enabled in the configuration.
+
+use tracing::warn;
+
+use crate::config::Config;
+use crate::monitor::{CaptureMonitor, MonitorType};
+
+/// Get the monitor types enabled in `config`.
+#[must_use]
+pub fn enabled_monitor_types(config: &Config) -> Vec<MonitorType> {
+    [
+        (MonitorType::Clipboard, config.capture.clipboard_enabled),
+        (
+            MonitorType::Accessibility,
+            config.capture.accessibility_enabled,
+        ),
+        (
+            MonitorType::Keystroke,
+            config.capture.keystroke_fallback_enabled,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(monitor_type, enabled)| enabled.then_some(monitor_type))
+    .collect()
+}
+
+/// Build this platform's monitor of the given type, if it has one.
+#[must_use]
+pub fn platform_monitor(
+    config: &Config,
+    monitor_type: MonitorType,
+) -> Option<Box<dyn CaptureMonitor>> {
+    let monitor = imp::monitor(config, monitor_type);
+    if monitor.is_none() {
+        warn!(%monitor_type, "Monitor is not supported on this platform");
+    }
+    monitor
+}
+
+/// Build every capture monitor enabled in `config` that this platform supports.
+#[must_use]
+pub fn platform_monitors(config: &Config) -> Vec<Box<dyn CaptureMonitor>> {
+    let monitors: Vec<_> = enabled_monitor_types(config)
+        .into_iter()
+        .filter_map(|monitor_type| platform_monitor(config, monitor_type))
+        .collect();
+    if monitors.is_empty() {
+        warn!("No capture monitors are enabled or available on this platform");
+    }
+    monitors
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use tokio::sync::mpsc;
+    use tracing::debug;
+
+    use flightrecorder_mac::{
+        AccessibilityMonitorConfig, CaptureData, ClipboardMonitorConfig, MacAccessibilityMonitor,
+        MacClipboardMonitor,
+    };
+
+    use crate::capture::{Capture, CaptureType};
+    use crate::config::Config;
+    use crate::monitor::{
+        CaptureMonitor, Heartbeat, MonitorError, MonitorStatus, MonitorType, Result,
+    };
+
+    /// Capacity of the channel between a platform monitor and its adapter.
+    const FORWARD_CAPACITY: usize = 100;
+
+    pub(super) fn monitor(
+        config: &Config,
+        monitor_type: MonitorType,
+    ) -> Option<Box<dyn CaptureMonitor>> {
+        match monitor_type {
+            MonitorType::Clipboard => Some(Box::new(ClipboardAdapter {
+                inner: MacClipboardMonitor::with_config(ClipboardMonitorConfig {
+                    min_content_length: config.capture.min_content_length,
+                    max_content_length: config.capture.max_content_length,
+                    ..ClipboardMonitorConfig::default()
+                }),
+            })),
+            MonitorType::Accessibility => Some(Box::new(AccessibilityAdapter {
+                inner: MacAccessibilityMonitor::with_config(AccessibilityMonitorConfig {
+                    snapshot_interval: config.snapshot_interval(),
+                    skip_password_fields: config.privacy.skip_password_fields,
+                    min_content_length: config.capture.min_content_length,
+                    max_content_length: config.capture.max_content_length,
+                }),
+            })),
+            MonitorType::Keystroke => None,
+        }
+    }
+
+    /// Convert platform capture data into a [`Capture`].
+    fn to_capture(data: CaptureData) -> Capture {
+        let capture_type = match data.capture_type {
+            flightrecorder_mac::CaptureType::Clipboard => CaptureType::Clipboard,
+            flightrecorder_mac::CaptureType::TextField => CaptureType::TextField,
+            flightrecorder_mac::CaptureType::Keystroke => CaptureType::Keystroke,
+        };
+        Capture {
+            id: None,
+            timestamp: data.timestamp,
+            source_app: data.source_app,
+            content: data.content,
+            content_hash: data.content_hash,
+            capture_type,
+            field_id: data.field_id,
+            session_id: None,
+            version: None,
+            pinned: false,
+            tags: Vec::new(),
+            note: None,
+        }
+    }
+
+    /// Convert a platform monitor status into a [`MonitorStatus`].
+    fn to_status(
+        monitor_type: MonitorType,
+        status: flightrecorder_mac::MonitorStatus,
+    ) -> MonitorStatus {
+        MonitorStatus {
+            monitor_type,
+            is_running: status.is_running,
+            has_permission: status.has_permission,
+            capture_count: status.capture_count,
+            message: status.message,
+        }
+    }
+
+    /// Forward platform captures to the daemon's channel.
+    fn spawn_forwarder(tx: mpsc::Sender<Capture>) -> mpsc::Sender<CaptureData> {
+        let (data_tx, mut data_rx) = mpsc::channel(FORWARD_CAPACITY);
+        tokio::spawn(async move {
+            while let Some(data) = data_rx.recv().await {
+                if tx.send(to_capture(data)).await.is_err() {
+                    debug!("Capture channel closed");
+                    break;
+                }
+            }
+        });
+        data_tx
+    }
+
+    #[derive(Debug)]
+    struct ClipboardAdapter {
+        inner: MacClipboardMonitor,
+    }
+
+    #[async_trait::async_trait]
+    impl CaptureMonitor for ClipboardAdapter {
+        fn monitor_type(&self) -> MonitorType {
+            MonitorType::Clipboard
+        }
+
+        fn is_running(&self) -> bool {
+            self.inner.is_running()
+        }
+
+        fn has_permission(&self) -> bool {
+            self.inner.has_permission()
+        }
+
+        fn status(&self) -> MonitorStatus {
+            to_status(MonitorType::Clipboard, self.inner.status())
+        }
+
+        async fn start(&mut self, tx: mpsc::Sender<Capture>) -> Result<()> {
+            self.inner
+                .start(spawn_forwarder(tx))
+                .await
+                .map_err(MonitorError::StartFailed)
+        }
+
+        fn stop(&self) -> Result<()> {
+            self.inner.stop();
+            Ok(())
+        }
+
+        fn heartbeat(&self) -> Option<Heartbeat> {
+            Some(Heartbeat::from(self.inner.poll_counter()))
+        }
+    }
+
+    #[derive(Debug)]
+    struct AccessibilityAdapter {
+        inner: MacAccessibilityMonitor,
+    }
+
+    #[async_trait::async_trait]
+    impl CaptureMonitor for AccessibilityAdapter {
+        fn monitor_type(&self) -> MonitorType {
+            MonitorType::Accessibility
+        }
+
+        fn is_running(&self) -> bool {
+            self.inner.is_running()
+        }
+
+        fn has_permission(&self) -> bool {
+            self.inner.has_permission()
+        }
+
+        fn status(&self) -> MonitorStatus {
+            to_status(MonitorType::Accessibility, self.inner.status())
+        }
+
+        async fn start(&mut self, tx: mpsc::Sender<Capture>) -> Result<()> {
+            if !self.inner.has_permission() {
+                return Err(MonitorError::PermissionRequired(
+                    "Accessibility permission required".to_string(),
+                ));
+            }
+            self.inner
+                .start(spawn_forwarder(tx))
+                .await
+                .map_err(MonitorError::StartFailed)
+        }
+
+        fn stop(&self) -> Result<()> {
+            self.inner.stop();
+            Ok(())
+        }
+
+        fn heartbeat(&self) -> Option<Heartbeat> {
+            Some(Heartbeat::from(self.inner.poll_counter()))
+        }
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+mod imp {
+    use crate::config::Config;
+    use crate::monitor::{CaptureMonitor, MonitorType};
+
+    /// No capture backends exist for this platform yet.
+    pub(super) fn monitor(
+        _config: &Config,
+        _monitor_type: MonitorType,
+    ) -> Option<Box<dyn CaptureMonitor>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enabled_monitor_types() {
+        let mut config = Config::default();
+        config.capture.clipboard_enabled = true;
+        config.capture.accessibility_enabled = false;
+        config.capture.keystroke_fallback_enabled = true;
+        assert_eq!(
+            enabled_monitor_types(&config),
+            vec![MonitorType::Clipboard, MonitorType::Keystroke]
+        );
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn test_no_monitors_without_backend() {
+        assert!(platform_monitors(&Config::default()).is_empty());
+    }
+}
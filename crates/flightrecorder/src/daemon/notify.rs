@@ -0,0 +1,216 @@
+//! Service manager notifications (the systemd `sd_notify` protocol).
+//!
+//! When the daemon runs as a `Type=notify` systemd service, systemd passes the
+//! address of a datagram socket in `NOTIFY_SOCKET`. The daemon sends
+//! newline-separated `KEY=VALUE` assignments to it: `READY=1` once it is
+//! serving, `STOPPING=1` when it begins shutting down and, if the unit sets
+//! `WatchdogSec=`, a periodic `WATCHDOG=1` keep-alive. Outside systemd the
+//! variable is unset and no notifications are sent.
+
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::Path;
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+use crate::error::{Error, Result};
+
+/// Sends state changes to the service manager.
+#[derive(Debug)]
+pub struct SystemdNotifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    watchdog_interval: Option<Duration>,
+}
+
+impl SystemdNotifier {
+    /// Create a notifier from `NOTIFY_SOCKET` and `WATCHDOG_USEC`.
+    ///
+    /// Returns `None` if the daemon was not started by a service manager, or
+    /// if the socket address is unusable (which is logged).
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        let address = std::env::var_os("NOTIFY_SOCKET")?;
+        let notifier = match Self::new(Path::new(&address)) {
+            Ok(notifier) => notifier,
+            Err(e) => {
+                warn!("Ignoring NOTIFY_SOCKET: {e}");
+                return None;
+            }
+        };
+        let watchdog = watchdog_interval(
+            std::env::var("WATCHDOG_USEC").ok().as_deref(),
+            std::env::var("WATCHDOG_PID").ok().as_deref(),
+        );
+        Some(notifier.with_watchdog(watchdog))
+    }
+
+    /// Create a notifier that sends to the socket at `address`.
+    ///
+    /// On Linux, an address starting with `@` names a socket in the abstract
+    /// namespace, as in `NOTIFY_SOCKET`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address is invalid or a socket cannot be created.
+    pub fn new(address: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            addr: parse_address(address.as_ref())?,
+            watchdog_interval: None,
+        })
+    }
+
+    /// Set how often the service manager expects a watchdog keep-alive.
+    #[must_use]
+    pub fn with_watchdog(mut self, interval: Option<Duration>) -> Self {
+        self.watchdog_interval = interval;
+        self
+    }
+
+    /// Get the interval at which [`watchdog`](Self::watchdog) must be called,
+    /// if the service manager enabled the watchdog.
+    #[must_use]
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog_interval
+    }
+
+    /// Send raw `KEY=VALUE` assignments, one per line.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the datagram cannot be sent.
+    pub fn notify(&self, state: &str) -> Result<()> {
+        debug!(state, "Notifying service manager");
+        self.socket.send_to_addr(state.as_bytes(), &self.addr)?;
+        Ok(())
+    }
+
+    /// Report that startup has finished.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the notification cannot be sent.
+    pub fn ready(&self, status: &str) -> Result<()> {
+        self.notify(&format!("READY=1\nSTATUS={status}"))
+    }
+
+    /// Report that the daemon is shutting down.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the notification cannot be sent.
+    pub fn stopping(&self) -> Result<()> {
+        self.notify("STOPPING=1\nSTATUS=Shutting down")
+    }
+
+    /// Send a watchdog keep-alive.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the notification cannot be sent.
+    pub fn watchdog(&self) -> Result<()> {
+        self.notify("WATCHDOG=1")
+    }
+}
+
+/// Convert a `NOTIFY_SOCKET` value into a socket address.
+fn parse_address(address: &Path) -> Result<SocketAddr> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::ffi::OsStrExt;
+
+        if let Some(name) = address.as_os_str().as_bytes().strip_prefix(b"@") {
+            return Ok(SocketAddr::from_abstract_name(name)?);
+        }
+    }
+
+    if !address.is_absolute() {
+        return Err(Error::InvalidArgument {
+            message: format!("notify socket path must be absolute: {}", address.display()),
+        });
+    }
+    Ok(SocketAddr::from_pathname(address)?)
+}
+
+/// Interpret `WATCHDOG_USEC` and `WATCHDOG_PID`, returning how often to send
+/// keep-alives. Pings go out at half the timeout, as systemd recommends.
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>) -> Option<Duration> {
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = usec?.parse().ok().filter(|&usec| usec > 0)?;
+    Some(Duration::from_micros(usec) / 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn test_socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fr_notify_{name}_{}.sock", std::process::id()))
+    }
+
+    fn receive(socket: &UnixDatagram) -> String {
+        let mut buf = [0; 256];
+        let len = socket.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    #[test]
+    fn test_notifications_reach_socket() {
+        let path = test_socket_path("states");
+        let _ = std::fs::remove_file(&path);
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        let notifier = SystemdNotifier::new(&path).unwrap();
+        notifier.ready("Capturing").unwrap();
+        notifier.watchdog().unwrap();
+        notifier.stopping().unwrap();
+
+        assert_eq!(receive(&server), "READY=1\nSTATUS=Capturing");
+        assert_eq!(receive(&server), "WATCHDOG=1");
+        assert!(receive(&server).starts_with("STOPPING=1"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_abstract_socket_address() {
+        use std::os::linux::net::SocketAddrExt;
+
+        let name = format!("fr_notify_abstract_{}", std::process::id());
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let server = UnixDatagram::bind_addr(&addr).unwrap();
+
+        let notifier = SystemdNotifier::new(format!("@{name}")).unwrap();
+        notifier.watchdog().unwrap();
+        assert_eq!(receive(&server), "WATCHDOG=1");
+    }
+
+    #[test]
+    fn test_relative_address_rejected() {
+        assert!(SystemdNotifier::new("relative.sock").is_err());
+    }
+
+    #[test]
+    fn test_watchdog_interval() {
+        let own_pid = std::process::id().to_string();
+        assert_eq!(
+            watchdog_interval(Some("30000000"), None),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval(Some("30000000"), Some(&own_pid)),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(watchdog_interval(Some("30000000"), Some("1")), None);
+        assert_eq!(watchdog_interval(Some("0"), None), None);
+        assert_eq!(watchdog_interval(Some("soon"), None), None);
+        assert_eq!(watchdog_interval(None, None), None);
+    }
+}
@@ -0,0 +1,230 @@
+//! PID file handling for single-instance enforcement.
+//!
+//! The running daemon holds an exclusive advisory lock (`flock`) on its PID
+//! file for its whole lifetime. The kernel releases the lock when the process
+//! exits, even after a crash, so a PID file that exists but is not locked is
+//! stale and can be reclaimed. Readers only trust the PID written in the file
+//! while the lock is held, which prevents signalling an unrelated process that
+//! happens to have reused the PID.
+//!
+//! The daemon removes its PID file on exit while still holding the lock, so
+//! another process may have opened the file just before it was removed and
+//! then lock a file that is no longer at the path. After locking,
+//! [`PidFile::acquire`] checks that the locked file is still the one at the
+//! path, and starts over if it is not.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use tracing::{info, warn};
+
+use crate::error::{Error, Result};
+
+/// An exclusively locked PID file owned by the running daemon.
+///
+/// The file is removed and the lock released when this value is dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    /// Held only for its lock, which is released when it is dropped.
+    _lock: Flock<File>,
+}
+
+impl PidFile {
+    /// Lock the PID file at `path` and write this process's PID to it.
+    ///
+    /// Creates the parent directory if needed. A stale PID file left behind by
+    /// a process that no longer holds the lock is reclaimed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::DaemonAlreadyRunning`] if another process holds the
+    /// lock, or an I/O error if the file cannot be created or written.
+    pub fn acquire(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent).map_err(|source| Error::DirectoryCreate {
+                    path: parent.to_path_buf(),
+                    source,
+                })?;
+            }
+        }
+
+        let mut file = loop {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)?;
+
+            let locked = match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+                Ok(locked) => locked,
+                Err((mut file, Errno::EWOULDBLOCK)) => {
+                    return Err(Error::DaemonAlreadyRunning {
+                        pid: read_pid(&mut file)?,
+                        path,
+                    });
+                }
+                Err((_, errno)) => return Err(std::io::Error::from(errno).into()),
+            };
+            // The previous owner may have removed the file between our open
+            // and lock; a lock on a removed file protects nothing.
+            if is_at(&locked, &path)? {
+                break locked;
+            }
+        };
+
+        if let Some(stale) = read_pid(&mut file)? {
+            info!("Reclaiming stale PID file {} (pid {stale})", path.display());
+        }
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        writeln!(file, "{}", std::process::id())?;
+        file.sync_all()?;
+
+        Ok(Self { path, _lock: file })
+    }
+
+    /// Get the path of the PID file.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the PID of the daemon holding the lock on `path`, if any.
+    ///
+    /// Returns `None` if the file does not exist or is stale.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read.
+    pub fn owner(path: impl AsRef<Path>) -> Result<Option<u32>> {
+        let mut file = match File::open(path.as_ref()) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let pid = read_pid(&mut file)?;
+        match Flock::lock(file, FlockArg::LockSharedNonblock) {
+            // We could take the lock, so nobody else holds it.
+            Ok(_) => Ok(None),
+            Err((_, Errno::EWOULDBLOCK)) => Ok(pid),
+            Err((_, errno)) => Err(std::io::Error::from(errno).into()),
+        }
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Remove the file while still holding the lock so that a concurrent
+        // `acquire` never sees our PID in an unlocked file.
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove PID file {}: {e}", self.path.display());
+            }
+        }
+    }
+}
+
+/// Whether `file` is the file currently at `path`.
+fn is_at(file: &File, path: &Path) -> Result<bool> {
+    let open = file.metadata()?;
+    match std::fs::metadata(path) {
+        Ok(current) => Ok(open.dev() == current.dev() && open.ino() == current.ino()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Read the PID stored in `file`, ignoring empty or malformed contents.
+fn read_pid(file: &mut File) -> Result<Option<u32>> {
+    let mut contents = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut contents)?;
+    Ok(contents.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_pid_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fr_pid_{name}_{}.pid", std::process::id()))
+    }
+
+    #[test]
+    fn test_acquire_writes_pid() {
+        let path = test_pid_path("write");
+        let pid_file = PidFile::acquire(&path).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.trim(), std::process::id().to_string());
+        assert_eq!(pid_file.path(), path);
+    }
+
+    #[test]
+    fn test_second_acquire_fails() {
+        let path = test_pid_path("second");
+        let _first = PidFile::acquire(&path).unwrap();
+
+        let err = PidFile::acquire(&path).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::DaemonAlreadyRunning { pid: Some(pid), .. } if pid == std::process::id()
+        ));
+    }
+
+    #[test]
+    fn test_drop_removes_file() {
+        let path = test_pid_path("drop");
+        drop(PidFile::acquire(&path).unwrap());
+        assert!(!path.exists());
+
+        // The lock is released too, so the file can be acquired again.
+        assert!(PidFile::acquire(&path).is_ok());
+    }
+
+    #[test]
+    fn test_removed_or_replaced_file_is_not_at_path() {
+        let path = test_pid_path("replaced");
+        std::fs::write(&path, "1\n").unwrap();
+        let file = File::open(&path).unwrap();
+        assert!(is_at(&file, &path).unwrap());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(!is_at(&file, &path).unwrap());
+
+        std::fs::write(&path, "2\n").unwrap();
+        assert!(!is_at(&file, &path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_stale_pid_file_is_reclaimed() {
+        let path = test_pid_path("stale");
+        std::fs::write(&path, "999999999\n").unwrap();
+        assert_eq!(PidFile::owner(&path).unwrap(), None);
+
+        let _pid_file = PidFile::acquire(&path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.trim(), std::process::id().to_string());
+    }
+
+    #[test]
+    fn test_owner_of_locked_file() {
+        let path = test_pid_path("owner");
+        assert_eq!(PidFile::owner(&path).unwrap(), None);
+
+        let _pid_file = PidFile::acquire(&path).unwrap();
+        assert_eq!(PidFile::owner(&path).unwrap(), Some(std::process::id()));
+    }
+}
@@ -0,0 +1,164 @@
+//! Signalling the daemon process from the CLI.
+
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use tracing::{info, warn};
+
+use crate::error::{Error, Result};
+
+/// How often to check whether a signalled process has exited.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long to wait for a process to disappear after SIGKILL.
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How a process was stopped by [`terminate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    /// The process was not running.
+    NotRunning,
+    /// The process exited after SIGTERM.
+    Terminated,
+    /// The process ignored SIGTERM and was killed with SIGKILL.
+    Killed,
+}
+
+/// Check whether a process with the given PID exists.
+#[must_use]
+pub fn is_alive(pid: u32) -> bool {
+    let Ok(pid) = to_pid(pid) else {
+        return false;
+    };
+    // EPERM means the process exists but belongs to someone else.
+    matches!(kill(pid, None), Ok(()) | Err(Errno::EPERM))
+}
+
+/// Stop a process, escalating from SIGTERM to SIGKILL.
+///
+/// Sends SIGTERM and waits up to `timeout` for the process to exit. If it is
+/// still running, sends SIGKILL and waits for it to disappear.
+///
+/// # Errors
+///
+/// Returns an error if the process cannot be signalled, or if it survives
+/// SIGKILL.
+pub fn terminate(pid: u32, timeout: Duration) -> Result<Termination> {
+    let target = to_pid(pid)?;
+
+    match kill(target, Signal::SIGTERM) {
+        Ok(()) => {}
+        Err(Errno::ESRCH) => return Ok(Termination::NotRunning),
+        Err(errno) => return Err(signal_error(pid, Signal::SIGTERM, errno)),
+    }
+    info!(pid, "Sent SIGTERM");
+
+    if wait_for_exit(pid, timeout) {
+        return Ok(Termination::Terminated);
+    }
+
+    warn!(
+        pid,
+        "Process did not exit within {timeout:?}; sending SIGKILL"
+    );
+    match kill(target, Signal::SIGKILL) {
+        Ok(()) => {}
+        Err(Errno::ESRCH) => return Ok(Termination::Terminated),
+        Err(errno) => return Err(signal_error(pid, Signal::SIGKILL, errno)),
+    }
+
+    if wait_for_exit(pid, KILL_TIMEOUT) {
+        Ok(Termination::Killed)
+    } else {
+        Err(Error::Timeout {
+            operation: format!("waiting for process {pid} to exit after SIGKILL"),
+        })
+    }
+}
+
+/// Poll until the process exits. Returns `false` if `timeout` elapses first.
+fn wait_for_exit(pid: u32, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while is_alive(pid) {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(EXIT_POLL_INTERVAL);
+    }
+    true
+}
+
+fn to_pid(pid: u32) -> Result<Pid> {
+    i32::try_from(pid)
+        .ok()
+        .filter(|&raw| raw > 0)
+        .map(Pid::from_raw)
+        .ok_or_else(|| Error::InvalidArgument {
+            message: format!("invalid PID: {pid}"),
+        })
+}
+
+fn signal_error(pid: u32, signal: Signal, errno: Errno) -> Error {
+    Error::platform(format!("failed to send {signal} to process {pid}: {errno}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    /// Spawn a shell running `script`, reaped on a background thread so that
+    /// it does not linger as a zombie once killed.
+    fn spawn_reaped(script: &str) -> u32 {
+        let mut child = Command::new("sh")
+            .args(["-c", script])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+
+        // Wait until the script signals that its traps are installed.
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        assert_eq!(line.trim(), "ready");
+
+        std::thread::spawn(move || child.wait());
+        pid
+    }
+
+    #[test]
+    fn test_is_alive() {
+        assert!(is_alive(std::process::id()));
+        assert!(!is_alive(0));
+        assert!(!is_alive(u32::MAX));
+    }
+
+    #[test]
+    fn test_terminate_with_sigterm() {
+        let pid = spawn_reaped("echo ready; exec sleep 30");
+        let outcome = terminate(pid, Duration::from_secs(5)).unwrap();
+        assert_eq!(outcome, Termination::Terminated);
+        assert!(!is_alive(pid));
+    }
+
+    #[test]
+    fn test_terminate_escalates_to_sigkill() {
+        let pid = spawn_reaped("trap '' TERM; echo ready; exec sleep 30");
+        let outcome = terminate(pid, Duration::from_millis(200)).unwrap();
+        assert_eq!(outcome, Termination::Killed);
+        assert!(!is_alive(pid));
+    }
+
+    #[test]
+    fn test_terminate_invalid_pid() {
+        assert!(matches!(
+            terminate(0, Duration::from_millis(10)),
+            Err(Error::InvalidArgument { .. })
+        ));
+    }
+}
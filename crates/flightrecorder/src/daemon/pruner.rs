@@ -0,0 +1,179 @@
+//! Scheduled pruning.
+//!
+//! The pruner moves the captures beyond the configured retention limits to
+//! the trash, and deletes those at the end of their grace period there, every
+//! `prune_interval_hours`, first when the daemon starts. It runs beside the
+//! storage writer rather than on its path: the prune is planned in one short
+//! hold of the storage lock, then captures are trashed and deleted in
+//! batches, releasing the lock between batches so that new captures are
+//! still stored while a large prune runs.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tracing::{error, info};
+
+use crate::error::{Error, Result};
+use crate::storage::{PrunePlan, PruneReport, RetentionPolicy, Storage};
+
+use super::{lock_storage, ShutdownHandle};
+
+/// Number of captures trashed or deleted under one hold of the storage lock.
+const PRUNE_BATCH_SIZE: usize = 500;
+
+/// Prune the captures beyond the limits of `policy`, or only report what
+/// would be pruned if `dry_run` is set.
+pub(crate) async fn prune(
+    storage: &Arc<Mutex<Storage>>,
+    policy: &RetentionPolicy,
+    dry_run: bool,
+) -> Result<PruneReport> {
+    let policy = policy.clone();
+    let PrunePlan {
+        ids,
+        trash_ids,
+        mut report,
+    } = with_storage(storage, move |s| s.plan_prune(&policy)).await?;
+    if dry_run {
+        return Ok(report);
+    }
+
+    for batch in trash_ids.chunks(PRUNE_BATCH_SIZE) {
+        let batch = batch.to_vec();
+        with_storage(storage, move |s| s.trash(&batch)).await?;
+    }
+    if ids.is_empty() {
+        return Ok(report);
+    }
+    for batch in ids.chunks(PRUNE_BATCH_SIZE) {
+        let batch = batch.to_vec();
+        with_storage(storage, move |s| s.purge(&batch)).await?;
+    }
+    report.reclaimed_bytes = with_storage(storage, Storage::reclaim_space).await?;
+    info!(
+        deleted = report.total(),
+        reclaimed_bytes = report.reclaimed_bytes,
+        "Pruned captures"
+    );
+    Ok(report)
+}
+
+/// Prune every `interval` until shutdown.
+pub(crate) async fn run_pruner(
+    storage: Arc<Mutex<Storage>>,
+    policy: RetentionPolicy,
+    interval: Duration,
+    shutdown: ShutdownHandle,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            () = shutdown.wait() => return,
+        }
+        // Shutdown stops a prune between batches.
+        tokio::select! {
+            result = prune(&storage, &policy, false) => {
+                if let Err(e) = result {
+                    error!("Pruning failed: {e}");
+                }
+            }
+            () = shutdown.wait() => return,
+        }
+    }
+}
+
+/// Run `f` against storage off the async runtime.
+async fn with_storage<T, F>(storage: &Arc<Mutex<Storage>>, f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&Storage) -> Result<T> + Send + 'static,
+{
+    let storage = Arc::clone(storage);
+    tokio::task::spawn_blocking(move || f(&lock_storage(&storage)))
+        .await
+        .map_err(|e| Error::Internal(format!("storage task failed: {e}")))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{Capture, CaptureType};
+
+    fn storage_with(count: usize) -> Arc<Mutex<Storage>> {
+        let storage = Storage::open_in_memory().unwrap();
+        for i in 0..count {
+            storage
+                .insert(&Capture::new(
+                    format!("capture {i}"),
+                    CaptureType::Clipboard,
+                    None,
+                ))
+                .unwrap();
+        }
+        Arc::new(Mutex::new(storage))
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_deletes_nothing() {
+        let storage = storage_with(10);
+        let policy = RetentionPolicy::new().with_max_captures(4);
+
+        let report = prune(&storage, &policy, true).await.unwrap();
+        assert_eq!(report.over_count, 6);
+        assert_eq!(report.reclaimed_bytes, 0);
+        assert_eq!(lock_storage(&storage).count().unwrap(), 10);
+
+        let report = prune(&storage, &policy, false).await.unwrap();
+        assert_eq!(report.over_count, 6);
+        assert_eq!(lock_storage(&storage).count().unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_prunes_in_batches() {
+        let storage = storage_with(PRUNE_BATCH_SIZE * 2 + 10);
+        let policy = RetentionPolicy::new().with_max_captures(5);
+
+        let report = prune(&storage, &policy, false).await.unwrap();
+        assert_eq!(report.total(), PRUNE_BATCH_SIZE * 2 + 5);
+        assert_eq!(lock_storage(&storage).count().unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_trashes_in_batches() {
+        let storage = storage_with(PRUNE_BATCH_SIZE + 10);
+        let policy = RetentionPolicy::new()
+            .with_max_captures(5)
+            .with_trash_grace(chrono::Duration::days(1));
+
+        let report = prune(&storage, &policy, false).await.unwrap();
+        assert_eq!(report.trashed, PRUNE_BATCH_SIZE + 5);
+        assert_eq!(report.reclaimed_bytes, 0);
+        let storage = lock_storage(&storage);
+        assert_eq!(storage.count().unwrap(), 5);
+        assert_eq!(
+            storage.stats().unwrap().trashed_captures,
+            i64::try_from(PRUNE_BATCH_SIZE + 5).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pruner_runs_at_start_and_stops_on_shutdown() {
+        let storage = storage_with(3);
+        let shutdown = ShutdownHandle::new();
+        let task = tokio::spawn(run_pruner(
+            Arc::clone(&storage),
+            RetentionPolicy::new().with_max_captures(1),
+            Duration::from_secs(3600),
+            shutdown.clone(),
+        ));
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while lock_storage(&storage).count().unwrap() > 1 {
+            assert!(tokio::time::Instant::now() < deadline, "pruner did not run");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        shutdown.shutdown();
+        task.await.unwrap();
+    }
+}
@@ -0,0 +1,98 @@
+//! Configuration reloading.
+//!
+//! A reload re-reads the configuration file and applies what can change while
+//! the daemon runs: the privacy filter is rebuilt and monitors are started or
+//! stopped to match the `*_enabled` flags. Monitors read their settings only
+//! when they are built, so running monitors whose settings changed are
+//! restarted. Paths in the `storage` and `daemon` sections are only read at
+//! startup, so changes there need a restart.
+
+use tokio::sync::oneshot;
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::ipc::ReloadSummary;
+use crate::monitor::MonitorType;
+
+/// A reload request from the IPC handler, answered once the reload is done.
+pub(crate) type ReloadReply = oneshot::Sender<Result<ReloadSummary>>;
+
+/// Compare two configurations section by section.
+pub(crate) fn summarize(old: &Config, new: &Config) -> ReloadSummary {
+    let sections = [
+        ("storage", old.storage != new.storage, true),
+        ("capture", old.capture != new.capture, false),
+        ("privacy", old.privacy != new.privacy, false),
+        ("daemon", old.daemon != new.daemon, true),
+    ];
+
+    let mut summary = ReloadSummary::default();
+    for (name, changed, needs_restart) in sections {
+        if changed {
+            summary.changed.push(name.to_string());
+            if needs_restart {
+                summary.restart_required.push(name.to_string());
+            }
+        }
+    }
+    summary
+}
+
+/// The monitors built with different settings under `new` than under `old`,
+/// which must be restarted to use them.
+pub(crate) fn monitors_to_restart(old: &Config, new: &Config) -> Vec<MonitorType> {
+    let content_limits = old.capture.min_content_length != new.capture.min_content_length
+        || old.capture.max_content_length != new.capture.max_content_length;
+    let text_fields = content_limits
+        || old.capture.snapshot_interval_ms != new.capture.snapshot_interval_ms
+        || old.privacy.skip_password_fields != new.privacy.skip_password_fields;
+    [
+        (MonitorType::Clipboard, content_limits),
+        (MonitorType::Accessibility, text_fields),
+    ]
+    .into_iter()
+    .filter_map(|(monitor_type, changed)| changed.then_some(monitor_type))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize_unchanged() {
+        let config = Config::default();
+        assert_eq!(summarize(&config, &config), ReloadSummary::default());
+    }
+
+    #[test]
+    fn test_summarize_changed_sections() {
+        let old = Config::default();
+        let mut new = old.clone();
+        new.privacy.excluded_apps.push("Secrets".to_string());
+        new.daemon.socket_path = Some("/tmp/other.sock".into());
+
+        let summary = summarize(&old, &new);
+        assert_eq!(summary.changed, vec!["privacy", "daemon"]);
+        assert_eq!(summary.restart_required, vec!["daemon"]);
+    }
+
+    #[test]
+    fn test_monitors_to_restart() {
+        let old = Config::default();
+        assert!(monitors_to_restart(&old, &old).is_empty());
+
+        let mut new = old.clone();
+        new.capture.snapshot_interval_ms += 1000;
+        assert_eq!(
+            monitors_to_restart(&old, &new),
+            [MonitorType::Accessibility]
+        );
+
+        new.capture.max_content_length += 1;
+        assert_eq!(
+            monitors_to_restart(&old, &new),
+            [MonitorType::Clipboard, MonitorType::Accessibility]
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Runtime state reported by `fliterec status`.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::ipc::WriterStatus;
+use crate::monitor::MonitorStatus;
+
+/// The status of one monitor task, updated by the task as it runs.
+pub(crate) type SharedMonitorStatus = Arc<Mutex<MonitorStatus>>;
+
+/// Monitor states, privacy filter counters and writer queue metrics, shared
+/// between the daemon's tasks and the IPC handler.
+#[derive(Debug, Default)]
+pub(crate) struct DaemonStats {
+    monitors: Mutex<Vec<SharedMonitorStatus>>,
+    blocked: Mutex<BTreeMap<String, u64>>,
+    writer: Mutex<WriterStatus>,
+}
+
+impl DaemonStats {
+    /// Start tracking a monitor, returning the handle its task updates.
+    pub(crate) fn add_monitor(&self, status: MonitorStatus) -> SharedMonitorStatus {
+        let status = Arc::new(Mutex::new(status));
+        lock(&self.monitors).push(Arc::clone(&status));
+        status
+    }
+
+    /// Stop tracking a monitor that has been removed.
+    pub(crate) fn remove_monitor(&self, status: &SharedMonitorStatus) {
+        lock(&self.monitors).retain(|tracked| !Arc::ptr_eq(tracked, status));
+    }
+
+    /// Get a snapshot of every tracked monitor's status.
+    pub(crate) fn monitors(&self) -> Vec<MonitorStatus> {
+        lock(&self.monitors)
+            .iter()
+            .map(|status| lock(status).clone())
+            .collect()
+    }
+
+    /// Count a capture dropped by the privacy rule `pattern_name`.
+    pub(crate) fn record_blocked(&self, pattern_name: &str) {
+        *lock(&self.blocked)
+            .entry(pattern_name.to_string())
+            .or_default() += 1;
+    }
+
+    /// Get the number of captures blocked by each privacy rule.
+    pub(crate) fn blocked(&self) -> BTreeMap<String, u64> {
+        lock(&self.blocked).clone()
+    }
+
+    /// Record the capacity of the writer's queue.
+    pub(crate) fn set_queue_capacity(&self, capacity: usize) {
+        lock(&self.writer).queue_capacity = capacity;
+    }
+
+    /// Record a batch of `size` captures taken by the writer, with `queued`
+    /// more left waiting behind it.
+    pub(crate) fn record_batch(&self, size: usize, queued: usize) {
+        let mut writer = lock(&self.writer);
+        writer.batches += 1;
+        writer.largest_batch = writer.largest_batch.max(size);
+        writer.queued = queued;
+        writer.max_queued = writer.max_queued.max(size + queued);
+    }
+
+    /// Count a capture that had to wait for room in the writer's queue.
+    pub(crate) fn record_full_wait(&self) {
+        lock(&self.writer).full_waits += 1;
+    }
+
+    /// Count captures the writer failed to store.
+    pub(crate) fn record_failed(&self, count: u64) {
+        lock(&self.writer).failed += count;
+    }
+
+    /// Get a snapshot of the writer's queue metrics.
+    pub(crate) fn writer(&self) -> WriterStatus {
+        *lock(&self.writer)
+    }
+}
+
+/// Lock a status mutex. The guarded values are plain data, so a panic while
+/// holding the lock cannot leave them inconsistent.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::MonitorType;
+
+    #[test]
+    fn test_monitor_tracking() {
+        let stats = DaemonStats::default();
+        let clipboard = stats.add_monitor(MonitorStatus::running(MonitorType::Clipboard, 0));
+        let keystroke = stats.add_monitor(MonitorStatus::running(MonitorType::Keystroke, 0));
+
+        lock(&clipboard).capture_count += 2;
+        stats.remove_monitor(&keystroke);
+
+        let monitors = stats.monitors();
+        assert_eq!(monitors.len(), 1);
+        assert_eq!(monitors[0].monitor_type, MonitorType::Clipboard);
+        assert_eq!(monitors[0].capture_count, 2);
+    }
+
+    #[test]
+    fn test_blocked_counts() {
+        let stats = DaemonStats::default();
+        stats.record_blocked("credit_card");
+        stats.record_blocked("excluded_app");
+        stats.record_blocked("credit_card");
+
+        let blocked = stats.blocked();
+        assert_eq!(blocked["credit_card"], 2);
+        assert_eq!(blocked["excluded_app"], 1);
+    }
+
+    #[test]
+    fn test_writer_metrics() {
+        let stats = DaemonStats::default();
+        stats.set_queue_capacity(256);
+        stats.record_batch(10, 30);
+        stats.record_batch(25, 0);
+        stats.record_full_wait();
+        stats.record_failed(2);
+
+        let writer = stats.writer();
+        assert_eq!(writer.queue_capacity, 256);
+        assert_eq!(writer.batches, 2);
+        assert_eq!(writer.largest_batch, 25);
+        assert_eq!(writer.queued, 0);
+        assert_eq!(writer.max_queued, 40);
+        assert_eq!(writer.full_waits, 1);
+        assert_eq!(writer.failed, 2);
+    }
+}
@@ -0,0 +1,682 @@
+//! Supervision of the daemon's capture monitors.
+//!
+//! Each monitor runs under its own supervisor task, which owns the monitor's
+//! [`JoinHandle`]. When a monitor fails, panics, or stops beating its
+//! [`Heartbeat`], the supervisor builds a replacement with the daemon's
+//! [`MonitorFactory`] and starts it again after an exponential backoff. After
+//! [`RestartPolicy::degraded_after`] consecutive failures the monitor's status
+//! message marks it as degraded; it keeps being retried at the maximum delay,
+//! since the cause (a missing permission, say) may go away.
+//!
+//! Aborting a stalled monitor only takes effect when its task next yields,
+//! which a monitor stuck in a blocking call never does. The supervisor marks
+//! the monitor as stalled first, waits for the aborted run only as long
+//! again as the stall timeout, and otherwise leaves it behind and starts a
+//! replacement.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::{JoinError, JoinHandle};
+use tracing::{debug, error, info, warn};
+
+use crate::capture::Capture;
+use crate::config::Config;
+use crate::monitor::{
+    CaptureMonitor, Heartbeat, MonitorError, MonitorHandle, MonitorStatus, MonitorType,
+    DEGRADED_PREFIX,
+};
+
+use super::status::{self, DaemonStats, SharedMonitorStatus};
+use super::MonitorFactory;
+
+/// Capacity of the channel between a monitor and its capture counter.
+const MONITOR_CHANNEL_CAPACITY: usize = 16;
+
+/// How long to wait for each monitor task to finish after being stopped.
+const MONITOR_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often monitor tasks check their stop signal.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How the supervisor restarts failed monitors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Delay before the first restart. Each further consecutive failure
+    /// doubles it.
+    pub initial_backoff: Duration,
+    /// Upper bound on the restart delay. A restarted monitor that runs this
+    /// long without failing is no longer counted as failing.
+    pub max_backoff: Duration,
+    /// Number of consecutive failures after which a monitor is reported as
+    /// degraded.
+    pub degraded_after: u32,
+    /// How long a monitor's heartbeat may stay silent before the monitor is
+    /// considered stalled and restarted. Must be longer than any monitor's
+    /// poll interval.
+    pub stall_timeout: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            degraded_after: 3,
+            stall_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Get the delay before restarting after `failures` consecutive failures.
+    #[must_use]
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let doublings = failures.saturating_sub(1).min(31);
+        self.initial_backoff
+            .saturating_mul(1 << doublings)
+            .min(self.max_backoff)
+    }
+}
+
+/// Owns the supervisor task of every running monitor.
+pub(crate) struct Supervisor {
+    tx: mpsc::Sender<Capture>,
+    stats: Arc<DaemonStats>,
+    factory: MonitorFactory,
+    config: Arc<Config>,
+    policy: RestartPolicy,
+    tasks: Vec<SupervisedMonitor>,
+}
+
+/// A supervised monitor and the status its supervisor keeps up to date.
+struct SupervisedMonitor {
+    handle: MonitorHandle,
+    status: SharedMonitorStatus,
+    task: JoinHandle<()>,
+}
+
+/// What the supervisor needs to build a replacement monitor.
+struct Restarter {
+    factory: MonitorFactory,
+    config: Arc<Config>,
+    policy: RestartPolicy,
+}
+
+impl Supervisor {
+    pub(crate) fn new(
+        tx: mpsc::Sender<Capture>,
+        stats: Arc<DaemonStats>,
+        factory: MonitorFactory,
+        config: &Config,
+        policy: RestartPolicy,
+    ) -> Self {
+        Self {
+            tx,
+            stats,
+            factory,
+            config: Arc::new(config.clone()),
+            policy,
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Use `config` when building monitors from now on.
+    pub(crate) fn set_config(&mut self, config: &Config) {
+        self.config = Arc::new(config.clone());
+    }
+
+    /// Build a monitor of the given type with the factory.
+    pub(crate) fn build(&self, monitor_type: MonitorType) -> Option<Box<dyn CaptureMonitor>> {
+        (self.factory)(&self.config, monitor_type)
+    }
+
+    /// Start supervising a monitor on its own task.
+    pub(crate) fn spawn(&mut self, monitor: Box<dyn CaptureMonitor>) {
+        let monitor_type = monitor.monitor_type();
+        let handle = MonitorHandle::new(monitor_type);
+        let status = self.stats.add_monitor(MonitorStatus {
+            has_permission: monitor.has_permission(),
+            ..MonitorStatus::running(monitor_type, 0)
+        });
+        let restarter = Restarter {
+            factory: Arc::clone(&self.factory),
+            config: Arc::clone(&self.config),
+            policy: self.policy.clone(),
+        };
+        let task = tokio::spawn(supervise(
+            monitor,
+            restarter,
+            self.tx.clone(),
+            Arc::clone(&self.stats),
+            handle.clone(),
+            Arc::clone(&status),
+        ));
+        self.tasks.push(SupervisedMonitor {
+            handle,
+            status,
+            task,
+        });
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub(crate) fn contains(&self, monitor_type: MonitorType) -> bool {
+        self.tasks
+            .iter()
+            .any(|task| task.handle.monitor_type() == monitor_type)
+    }
+
+    /// Stop every monitor of the given type and wait for it to finish.
+    pub(crate) async fn stop(&mut self, monitor_type: MonitorType) {
+        let (stopped, running): (Vec<_>, _) = std::mem::take(&mut self.tasks)
+            .into_iter()
+            .partition(|task| task.handle.monitor_type() == monitor_type);
+        self.tasks = running;
+        for task in &stopped {
+            self.stats.remove_monitor(&task.status);
+        }
+        stop_tasks(stopped).await;
+    }
+
+    /// Stop every monitor and wait for them to finish.
+    pub(crate) async fn stop_all(self) {
+        stop_tasks(self.tasks).await;
+    }
+}
+
+/// Signal supervised monitors to stop, then wait for each in turn.
+async fn stop_tasks(tasks: Vec<SupervisedMonitor>) {
+    for task in &tasks {
+        task.handle.stop();
+    }
+    for SupervisedMonitor { handle, task, .. } in tasks {
+        if tokio::time::timeout(MONITOR_STOP_TIMEOUT, task)
+            .await
+            .is_err()
+        {
+            warn!(
+                monitor_type = %handle.monitor_type(),
+                "Monitor did not stop within {:?}", MONITOR_STOP_TIMEOUT
+            );
+        }
+    }
+}
+
+/// How one run of a monitor ended.
+#[derive(Debug)]
+enum RunEnd {
+    /// The monitor's handle was stopped.
+    Stopped,
+    /// The monitor returned without an error.
+    Exited,
+    /// The monitor failed because it lacks a permission.
+    PermissionRequired(String),
+    /// The monitor failed, panicked or stalled.
+    Failed(String),
+}
+
+/// Run a monitor, restarting it whenever it fails, until its handle is
+/// stopped.
+///
+/// The monitor's captures pass through a counter on their way to `tx`.
+async fn supervise(
+    mut monitor: Box<dyn CaptureMonitor>,
+    restarter: Restarter,
+    tx: mpsc::Sender<Capture>,
+    daemon_stats: Arc<DaemonStats>,
+    handle: MonitorHandle,
+    status: SharedMonitorStatus,
+) {
+    let monitor_type = monitor.monitor_type();
+    let policy = &restarter.policy;
+    let (monitor_tx, monitor_rx) = mpsc::channel(MONITOR_CHANNEL_CAPACITY);
+    let forwarder = tokio::spawn(count_captures(
+        monitor_rx,
+        tx,
+        daemon_stats,
+        Arc::clone(&status),
+    ));
+    let mut failures = 0;
+    let mut last_error = String::new();
+
+    loop {
+        debug!(%monitor_type, "Starting monitor");
+        {
+            let mut status = status::lock(&status);
+            status.is_running = true;
+            status.has_permission = monitor.has_permission();
+            if failures == 0 {
+                status.message = "Monitor running".to_string();
+            } else if failures < policy.degraded_after {
+                status.message = format!("Restarted after failure: {last_error}");
+            }
+        }
+
+        let heartbeat = monitor.heartbeat();
+        let run = tokio::spawn(run_monitor(monitor, monitor_tx.clone(), handle.clone()));
+        let watch = watch_run(run, heartbeat, policy.stall_timeout, &status);
+        tokio::pin!(watch);
+        // A monitor that stays up for the longest backoff has recovered.
+        let end = tokio::select! {
+            end = &mut watch => end,
+            () = tokio::time::sleep(policy.max_backoff), if failures > 0 => {
+                info!(%monitor_type, "Monitor recovered");
+                failures = 0;
+                status::lock(&status).message = "Monitor running".to_string();
+                watch.await
+            }
+        };
+        status::lock(&status).is_running = false;
+
+        let reason = match end {
+            RunEnd::Stopped => {
+                debug!(%monitor_type, "Monitor stopped");
+                status::lock(&status).message = "Monitor stopped".to_string();
+                break;
+            }
+            RunEnd::Exited => {
+                info!(%monitor_type, "Monitor exited");
+                status::lock(&status).message = "Monitor exited".to_string();
+                break;
+            }
+            RunEnd::PermissionRequired(reason) => {
+                status::lock(&status).has_permission = false;
+                reason
+            }
+            RunEnd::Failed(reason) => reason,
+        };
+
+        failures += 1;
+        let delay = policy.backoff(failures);
+        let message = if failures >= policy.degraded_after {
+            warn!(%monitor_type, failures, "Monitor degraded: {reason}");
+            format!("{DEGRADED_PREFIX} failed {failures} times in a row; last error: {reason}")
+        } else {
+            error!(%monitor_type, "Monitor failed: {reason}; restarting in {delay:?}");
+            format!("Failed: {reason} (restarting in {delay:?})")
+        };
+        status::lock(&status).message = message;
+        last_error = reason;
+
+        tokio::select! {
+            () = tokio::time::sleep(delay) => {}
+            () = wait_for_stop(&handle) => break,
+        }
+
+        let Some(replacement) = (restarter.factory)(&restarter.config, monitor_type) else {
+            warn!(%monitor_type, "Cannot rebuild monitor; giving up");
+            status::lock(&status).message = format!("Gave up after failure: {last_error}");
+            break;
+        };
+        monitor = replacement;
+    }
+
+    // Every sender is gone once the last run has ended, so the forwarder
+    // finishes after passing on what it has already received.
+    drop(monitor_tx);
+    let _ = forwarder.await;
+}
+
+/// Wait for a run of a monitor to end, aborting it if its heartbeat stalls.
+///
+/// A stalled run is marked in `status` straight away, and is given
+/// `stall_timeout` to end once aborted before it is left behind.
+async fn watch_run(
+    mut run: JoinHandle<RunEnd>,
+    heartbeat: Option<Heartbeat>,
+    stall_timeout: Duration,
+    status: &SharedMonitorStatus,
+) -> RunEnd {
+    let Some(heartbeat) = heartbeat else {
+        return run_result(run.await);
+    };
+
+    let mut ticker = tokio::time::interval(stall_timeout / 4);
+    let mut last_count = heartbeat.count();
+    let mut last_beat = Instant::now();
+    loop {
+        tokio::select! {
+            result = &mut run => return run_result(result),
+            _ = ticker.tick() => {
+                let count = heartbeat.count();
+                if count != last_count {
+                    last_count = count;
+                    last_beat = Instant::now();
+                } else if last_beat.elapsed() >= stall_timeout {
+                    let reason = format!("no heartbeat for {:?}", last_beat.elapsed());
+                    {
+                        let mut status = status::lock(status);
+                        status.is_running = false;
+                        status.message = format!("Stalled: {reason}");
+                    }
+                    run.abort();
+                    // The run may have finished just before being aborted.
+                    return match tokio::time::timeout(stall_timeout, run).await {
+                        Ok(Ok(end)) => end,
+                        Ok(Err(_)) => RunEnd::Failed(reason),
+                        Err(_) => {
+                            warn!("Stalled monitor did not stop when aborted; leaving it behind");
+                            RunEnd::Failed(format!("{reason}; stuck in a blocking call"))
+                        }
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// Interpret the result of a monitor's task.
+fn run_result(result: Result<RunEnd, JoinError>) -> RunEnd {
+    match result {
+        Ok(end) => end,
+        Err(e) if e.is_panic() => RunEnd::Failed(format!("panicked: {}", panic_message(e))),
+        Err(e) => RunEnd::Failed(e.to_string()),
+    }
+}
+
+/// Get the message a task panicked with.
+fn panic_message(error: JoinError) -> String {
+    let payload = error.into_panic();
+    payload
+        .downcast_ref::<&str>()
+        .map(ToString::to_string)
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// Run a monitor once, until it returns or its handle is stopped.
+async fn run_monitor(
+    mut monitor: Box<dyn CaptureMonitor>,
+    tx: mpsc::Sender<Capture>,
+    handle: MonitorHandle,
+) -> RunEnd {
+    let result = tokio::select! {
+        result = monitor.start(tx) => Some(result),
+        () = wait_for_stop(&handle) => None,
+    };
+
+    match result {
+        Some(Ok(())) => RunEnd::Exited,
+        Some(Err(e @ MonitorError::PermissionRequired(_))) => {
+            RunEnd::PermissionRequired(e.to_string())
+        }
+        Some(Err(e)) => RunEnd::Failed(e.to_string()),
+        None => {
+            if let Err(e) = monitor.stop() {
+                warn!(
+                    monitor_type = %monitor.monitor_type(),
+                    "Monitor did not stop cleanly: {e}"
+                );
+            }
+            RunEnd::Stopped
+        }
+    }
+}
+
+/// Pass captures from one monitor on to the writer, counting them and the
+/// times the writer's queue was full.
+async fn count_captures(
+    mut rx: mpsc::Receiver<Capture>,
+    tx: mpsc::Sender<Capture>,
+    daemon_stats: Arc<DaemonStats>,
+    status: SharedMonitorStatus,
+) {
+    while let Some(capture) = rx.recv().await {
+        status::lock(&status).capture_count += 1;
+        let sent = match tx.try_send(capture) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(capture)) => {
+                daemon_stats.record_full_wait();
+                tx.send(capture).await.map_err(drop)
+            }
+            Err(TrySendError::Closed(_)) => Err(()),
+        };
+        if sent.is_err() {
+            debug!("Capture channel closed");
+            break;
+        }
+    }
+}
+
+/// Wait until the stop signal on `handle` is set.
+async fn wait_for_stop(handle: &MonitorHandle) {
+    while !handle.should_stop() {
+        tokio::time::sleep(STOP_POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    use crate::capture::CaptureType;
+
+    /// What a [`ScriptedMonitor`] does when started.
+    #[derive(Debug, Clone, Copy)]
+    enum Behavior {
+        /// Send one capture, then keep running and beating.
+        Run,
+        /// Send one capture, then fail.
+        Fail,
+        /// Panic straight away.
+        Panic,
+        /// Keep running without ever beating.
+        Hang,
+        /// Block the thread without ever yielding or beating, as a monitor
+        /// stuck in a blocking call does.
+        Block,
+    }
+
+    struct ScriptedMonitor {
+        behavior: Behavior,
+        heartbeat: Heartbeat,
+    }
+
+    #[async_trait::async_trait]
+    impl CaptureMonitor for ScriptedMonitor {
+        fn monitor_type(&self) -> MonitorType {
+            MonitorType::Clipboard
+        }
+
+        fn is_running(&self) -> bool {
+            false
+        }
+
+        fn has_permission(&self) -> bool {
+            true
+        }
+
+        fn status(&self) -> MonitorStatus {
+            MonitorStatus::stopped(MonitorType::Clipboard)
+        }
+
+        async fn start(&mut self, tx: mpsc::Sender<Capture>) -> crate::monitor::Result<()> {
+            let capture = Capture::new("copied".to_string(), CaptureType::Clipboard, None);
+            match self.behavior {
+                Behavior::Run => {
+                    let _ = tx.send(capture).await;
+                    loop {
+                        self.heartbeat.beat();
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                    }
+                }
+                Behavior::Fail => {
+                    let _ = tx.send(capture).await;
+                    Err(MonitorError::Internal("poll failed".to_string()))
+                }
+                Behavior::Panic => panic!("monitor bug"),
+                Behavior::Hang => std::future::pending().await,
+                Behavior::Block => {
+                    std::thread::sleep(Duration::from_secs(3));
+                    Ok(())
+                }
+            }
+        }
+
+        fn stop(&self) -> crate::monitor::Result<()> {
+            Ok(())
+        }
+
+        fn heartbeat(&self) -> Option<Heartbeat> {
+            Some(self.heartbeat.clone())
+        }
+    }
+
+    /// A supervisor whose factory builds monitors following `script`, then
+    /// healthy ones once the script runs out.
+    fn supervisor(script: &[Behavior]) -> (Supervisor, Arc<DaemonStats>, mpsc::Receiver<Capture>) {
+        let script = Mutex::new(script.iter().copied().collect::<VecDeque<_>>());
+        let factory: MonitorFactory = Arc::new(move |_: &Config, _| {
+            let behavior = status::lock(&script).pop_front().unwrap_or(Behavior::Run);
+            Some(Box::new(ScriptedMonitor {
+                behavior,
+                heartbeat: Heartbeat::new(),
+            }) as Box<dyn CaptureMonitor>)
+        });
+        let policy = RestartPolicy {
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(5),
+            degraded_after: 3,
+            stall_timeout: Duration::from_millis(200),
+        };
+        let daemon_stats = Arc::new(DaemonStats::default());
+        let (tx, rx) = mpsc::channel(64);
+        let supervisor = Supervisor::new(
+            tx,
+            Arc::clone(&daemon_stats),
+            factory,
+            &Config::default(),
+            policy,
+        );
+        (supervisor, daemon_stats, rx)
+    }
+
+    /// Wait until the only monitor's status satisfies `predicate`.
+    async fn wait_for_status(
+        daemon_stats: &DaemonStats,
+        predicate: impl Fn(&MonitorStatus) -> bool,
+    ) -> MonitorStatus {
+        for _ in 0..200 {
+            let status = daemon_stats.monitors().remove(0);
+            if predicate(&status) {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!(
+            "monitor never reached the expected state: {:?}",
+            daemon_stats.monitors()
+        );
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let policy = RestartPolicy::default();
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(2), Duration::from_secs(2));
+        assert_eq!(policy.backoff(4), Duration::from_secs(8));
+        assert_eq!(policy.backoff(7), Duration::from_secs(60));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_failed_monitor_is_restarted() {
+        let (mut monitors, daemon_stats, mut rx) = supervisor(&[Behavior::Fail]);
+        let first = monitors.build(MonitorType::Clipboard).unwrap();
+        monitors.spawn(first);
+
+        let status = wait_for_status(&daemon_stats, |status| status.capture_count == 2).await;
+        assert!(status.is_running);
+        assert!(status.message.contains("poll failed"), "{}", status.message);
+        assert!(!status.is_degraded());
+        assert!(rx.recv().await.is_some());
+        assert!(rx.recv().await.is_some());
+
+        monitors.stop_all().await;
+        assert_eq!(daemon_stats.monitors()[0].message, "Monitor stopped");
+    }
+
+    #[tokio::test]
+    async fn test_panicking_monitor_is_restarted() {
+        let (mut monitors, daemon_stats, _rx) = supervisor(&[Behavior::Panic]);
+        let first = monitors.build(MonitorType::Clipboard).unwrap();
+        monitors.spawn(first);
+
+        let status = wait_for_status(&daemon_stats, |status| status.capture_count == 1).await;
+        assert!(status.message.contains("monitor bug"), "{}", status.message);
+        monitors.stop_all().await;
+    }
+
+    #[tokio::test]
+    async fn test_repeated_failures_mark_monitor_degraded() {
+        let (mut monitors, daemon_stats, _rx) =
+            supervisor(&[Behavior::Fail, Behavior::Fail, Behavior::Fail]);
+        let first = monitors.build(MonitorType::Clipboard).unwrap();
+        monitors.spawn(first);
+
+        let status = wait_for_status(&daemon_stats, MonitorStatus::is_degraded).await;
+        assert!(status.message.contains("3 times"), "{}", status.message);
+
+        // The monitor keeps being retried and shows as degraded while it runs.
+        let status = wait_for_status(&daemon_stats, |status| status.capture_count == 4).await;
+        assert!(status.is_running);
+        assert!(status.is_degraded());
+        monitors.stop_all().await;
+    }
+
+    #[tokio::test]
+    async fn test_stalled_monitor_is_restarted() {
+        let (mut monitors, daemon_stats, _rx) = supervisor(&[Behavior::Hang]);
+        let first = monitors.build(MonitorType::Clipboard).unwrap();
+        monitors.spawn(first);
+
+        let status = wait_for_status(&daemon_stats, |status| status.capture_count == 1).await;
+        assert!(
+            status.message.contains("no heartbeat"),
+            "{}",
+            status.message
+        );
+        monitors.stop_all().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_blocked_monitor_is_left_behind_and_replaced() {
+        let (mut monitors, daemon_stats, _rx) = supervisor(&[Behavior::Block]);
+        let first = monitors.build(MonitorType::Clipboard).unwrap();
+        monitors.spawn(first);
+
+        let status = wait_for_status(&daemon_stats, |status| {
+            status.message.starts_with("Stalled")
+        })
+        .await;
+        assert!(!status.is_running);
+
+        // The replacement runs while the first is still blocked.
+        let status = wait_for_status(&daemon_stats, |status| status.capture_count == 1).await;
+        assert!(status.is_running);
+        assert!(
+            status.message.contains("blocking call"),
+            "{}",
+            status.message
+        );
+        monitors.stop_all().await;
+    }
+
+    #[tokio::test]
+    async fn test_stopped_monitor_is_not_restarted() {
+        let (mut monitors, daemon_stats, _rx) = supervisor(&[]);
+        let first = monitors.build(MonitorType::Clipboard).unwrap();
+        monitors.spawn(first);
+        wait_for_status(&daemon_stats, |status| status.capture_count == 1).await;
+
+        monitors.stop(MonitorType::Clipboard).await;
+        assert!(!monitors.contains(MonitorType::Clipboard));
+        assert!(daemon_stats.monitors().is_empty());
+    }
+}
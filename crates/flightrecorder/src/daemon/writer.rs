@@ -0,0 +1,212 @@
+//! The storage writer.
+//!
+//! One task owns every write of a capture. It takes whatever captures are
+//! waiting in the queue, up to [`MAX_BATCH_SIZE`], runs each through the
+//! privacy filter and inserts the survivors in a single transaction. Under a
+//! burst the batches grow, so a busy clipboard or text field costs one commit
+//! per batch instead of one per capture; when captures trickle in, each is
+//! written as soon as it arrives.
+//!
+//! The queue is bounded: when the writer falls behind, monitors wait for room
+//! rather than growing memory without limit. [`DaemonStats`] records how full
+//! the queue gets and how often monitors had to wait.
+
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tracing::{debug, error, warn};
+
+use crate::capture::Capture;
+use crate::error::Result;
+use crate::privacy::PrivacyFilter;
+use crate::storage::Storage;
+
+use super::status::DaemonStats;
+use super::{apply_privacy, lock_storage};
+
+/// Most captures inserted in one transaction.
+const MAX_BATCH_SIZE: usize = 128;
+
+/// Drain captures from the channel into storage, a batch at a time.
+///
+/// Runs until every sender is dropped or `drain` fires; in the latter case the
+/// channel is closed and any captures already queued are still written.
+/// Each stored capture is published on `feed`, and each blocked one counted in
+/// `stats`. Returns the number of captures stored.
+pub(crate) async fn write_captures(
+    mut rx: mpsc::Receiver<Capture>,
+    storage: Arc<Mutex<Storage>>,
+    filter: watch::Receiver<Arc<PrivacyFilter>>,
+    feed: broadcast::Sender<Capture>,
+    stats: Arc<DaemonStats>,
+    mut drain: oneshot::Receiver<()>,
+) -> u64 {
+    stats.set_queue_capacity(rx.max_capacity());
+    let mut stored = 0;
+    let mut batch = Vec::with_capacity(MAX_BATCH_SIZE);
+    // The filter may be replaced by a reload at any time; each batch uses
+    // whichever one is current when it is taken.
+    let current = || Arc::clone(&filter.borrow());
+
+    loop {
+        tokio::select! {
+            biased;
+            received = rx.recv_many(&mut batch, MAX_BATCH_SIZE) => {
+                if received == 0 {
+                    break;
+                }
+                stats.record_batch(batch.len(), rx.len());
+                let batch = std::mem::take(&mut batch);
+                stored += store_batch(&storage, &current(), &feed, &stats, batch).await;
+            }
+            _ = &mut drain => {
+                rx.close();
+                while rx.recv_many(&mut batch, MAX_BATCH_SIZE).await > 0 {
+                    stats.record_batch(batch.len(), rx.len());
+                    let batch = std::mem::take(&mut batch);
+                    stored += store_batch(&storage, &current(), &feed, &stats, batch).await;
+                }
+                break;
+            }
+        }
+    }
+
+    stored
+}
+
+/// Filter and store a batch of captures in one transaction, returning how
+/// many were stored.
+///
+/// If the transaction fails, the captures are inserted one at a time so that
+/// one bad capture does not cost the rest of its batch.
+async fn store_batch(
+    storage: &Arc<Mutex<Storage>>,
+    filter: &PrivacyFilter,
+    feed: &broadcast::Sender<Capture>,
+    stats: &DaemonStats,
+    batch: Vec<Capture>,
+) -> u64 {
+    let captures: Vec<Capture> = batch
+        .into_iter()
+        .filter_map(|capture| match apply_privacy(filter, capture) {
+            Ok(capture) => Some(capture),
+            Err(reason) => {
+                debug!(%reason, "Capture dropped by privacy filter");
+                stats.record_blocked(&reason);
+                None
+            }
+        })
+        .collect();
+    if captures.is_empty() {
+        return 0;
+    }
+
+    let count = captures.len() as u64;
+    let storage = Arc::clone(storage);
+    let inserted = tokio::task::spawn_blocking(move || {
+        let storage = lock_storage(&storage);
+        let ids: Vec<Result<Option<i64>>> = match storage.insert_batch(&captures) {
+            Ok(ids) => ids.into_iter().map(Ok).collect(),
+            Err(e) => {
+                warn!(
+                    captures = captures.len(),
+                    "Failed to store batch, storing captures one at a time: {e}"
+                );
+                captures.iter().map(|c| storage.insert(c)).collect()
+            }
+        };
+        captures.into_iter().zip(ids).collect::<Vec<_>>()
+    });
+    let inserted = match inserted.await {
+        Ok(inserted) => inserted,
+        Err(e) => {
+            error!("Storage task failed: {e}");
+            stats.record_failed(count);
+            return 0;
+        }
+    };
+
+    let mut stored = 0;
+    for (capture, id) in inserted {
+        match id {
+            Ok(Some(id)) => {
+                // Sending only fails when nobody is subscribed.
+                let _ = feed.send(Capture {
+                    id: Some(id),
+                    ..capture
+                });
+                stored += 1;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("Failed to store capture: {e}");
+                stats.record_failed(1);
+            }
+        }
+    }
+    stored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::CaptureType;
+
+    fn clipboard(content: &str) -> Capture {
+        Capture::new(content.to_string(), CaptureType::Clipboard, None)
+    }
+
+    #[tokio::test]
+    async fn test_queued_captures_are_written_in_one_batch() {
+        let storage = Arc::new(Mutex::new(Storage::open_in_memory().unwrap()));
+        let filter = Arc::new(PrivacyFilter::new());
+        let (_filter_tx, filter_rx) = watch::channel(filter);
+        let (feed, mut subscriber) = broadcast::channel(16);
+        let stats = Arc::new(DaemonStats::default());
+        let (tx, rx) = mpsc::channel(16);
+        for i in 0..10 {
+            tx.send(clipboard(&format!("capture {i}"))).await.unwrap();
+        }
+        drop(tx);
+
+        let (_drain_tx, drain_rx) = oneshot::channel();
+        let stored = write_captures(
+            rx,
+            Arc::clone(&storage),
+            filter_rx,
+            feed,
+            Arc::clone(&stats),
+            drain_rx,
+        )
+        .await;
+
+        assert_eq!(stored, 10);
+        assert_eq!(lock_storage(&storage).count().unwrap(), 10);
+        let writer = stats.writer();
+        assert_eq!(writer.queue_capacity, 16);
+        assert_eq!(writer.batches, 1);
+        assert_eq!(writer.largest_batch, 10);
+        assert_eq!(writer.max_queued, 10);
+        assert_eq!(subscriber.recv().await.unwrap().content, "capture 0");
+    }
+
+    #[tokio::test]
+    async fn test_blocked_captures_are_counted_not_stored() {
+        let storage = Arc::new(Mutex::new(Storage::open_in_memory().unwrap()));
+        let filter = PrivacyFilter::new();
+        let stats = DaemonStats::default();
+        let (feed, _) = broadcast::channel(16);
+
+        let stored = store_batch(
+            &storage,
+            &filter,
+            &feed,
+            &stats,
+            vec![clipboard("hello"), clipboard("password=hunter2hunter2")],
+        )
+        .await;
+
+        assert_eq!(stored, 1);
+        assert_eq!(stats.blocked().values().sum::<u64>(), 1);
+    }
+}
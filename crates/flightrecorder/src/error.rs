@@ -31,6 +31,33 @@ pub enum Error {
         message: String,
     },
 
+    /// The database was created by a newer release with a schema this build
+    /// does not understand.
+    #[error(
+        "database schema version {found} is newer than this build supports (version {supported}); upgrade fliterec to open it"
+    )]
+    SchemaTooNew {
+        /// Schema version recorded in the database.
+        found: i32,
+        /// Newest schema version this build can migrate to.
+        supported: i32,
+    },
+
+    /// The database is encrypted, and no key was given or the key given does
+    /// not unlock it.
+    #[error("cannot unlock the encrypted database: {message}")]
+    DatabaseLocked {
+        /// Description of what went wrong.
+        message: String,
+    },
+
+    /// Content could not be encrypted or decrypted.
+    #[error("encryption error: {message}")]
+    Encryption {
+        /// Description of what went wrong.
+        message: String,
+    },
+
     // === Configuration Errors ===
     /// Failed to load configuration.
     #[error("failed to load configuration: {0}")]
@@ -83,6 +110,15 @@ pub enum Error {
     #[error("daemon is not running")]
     DaemonNotRunning,
 
+    /// Another daemon instance holds the PID file lock.
+    #[error("daemon is already running{} (PID file {path})", pid.map(|p| format!(" with pid {p}")).unwrap_or_default())]
+    DaemonAlreadyRunning {
+        /// PID recorded in the PID file, if readable.
+        pid: Option<u32>,
+        /// Path to the PID file.
+        path: PathBuf,
+    },
+
     /// IPC communication failed.
     #[error("IPC error: {0}")]
     Ipc(String),
@@ -121,6 +157,14 @@ pub enum Error {
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
 
+    // === CLI Errors ===
+    /// A command-line argument could not be interpreted.
+    #[error("invalid argument: {message}")]
+    InvalidArgument {
+        /// Description of the problem.
+        message: String,
+    },
+
     // === Generic Errors ===
     /// An operation timed out.
     #[error("operation timed out: {operation}")]
@@ -254,6 +298,18 @@ mod tests {
         assert_eq!(err.to_string(), "IPC error: connection refused");
     }
 
+    #[test]
+    fn test_daemon_already_running_display() {
+        let err = Error::DaemonAlreadyRunning {
+            pid: Some(42),
+            path: PathBuf::from("/tmp/fliterec.pid"),
+        };
+        assert_eq!(
+            err.to_string(),
+            "daemon is already running with pid 42 (PID file /tmp/fliterec.pid)"
+        );
+    }
+
     #[test]
     fn test_capture_source_start_error() {
         let err = Error::capture_source_start("clipboard", "failed to initialize");
@@ -308,6 +364,29 @@ mod tests {
         assert!(err.to_string().contains("version mismatch"));
     }
 
+    #[test]
+    fn test_schema_too_new_error_display() {
+        let err = Error::SchemaTooNew {
+            found: 5,
+            supported: 3,
+        };
+        let msg = err.to_string();
+        assert!(msg.contains("version 5"));
+        assert!(msg.contains("version 3"));
+        assert!(msg.contains("upgrade"));
+    }
+
+    #[test]
+    fn test_database_locked_error_display() {
+        let err = Error::DatabaseLocked {
+            message: "wrong key".to_string(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "cannot unlock the encrypted database: wrong key"
+        );
+    }
+
     #[test]
     fn test_config_validation_error_display() {
         let err = Error::ConfigValidation {
@@ -335,6 +414,14 @@ mod tests {
         assert!(msg.contains("connection refused"));
     }
 
+    #[test]
+    fn test_invalid_argument_error_display() {
+        let err = Error::InvalidArgument {
+            message: "bad time".to_string(),
+        };
+        assert_eq!(err.to_string(), "invalid argument: bad time");
+    }
+
     #[test]
     fn test_timeout_error_display() {
         let err = Error::Timeout {
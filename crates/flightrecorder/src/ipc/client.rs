@@ -0,0 +1,229 @@
+//! Blocking IPC client.
+//!
+//! The CLI is synchronous, so the client uses standard library sockets rather
+//! than Tokio. Every operation is bounded by a timeout so that a wedged daemon
+//! cannot hang the CLI.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use super::protocol::{self, Event, Request, Response, SubscribeRequest};
+use crate::error::{Error, Result};
+
+/// Default timeout for connecting to and talking with the daemon.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A blocking client for the daemon's IPC socket.
+#[derive(Debug, Clone)]
+pub struct IpcClient {
+    path: PathBuf,
+    timeout: Duration,
+}
+
+impl IpcClient {
+    /// Create a client for the socket at the given path.
+    #[must_use]
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Set the timeout used for connecting, sending and receiving.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Get the socket path.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Send a request and wait for its response.
+    ///
+    /// An [`Response::Error`] from the daemon is returned as [`Error::Ipc`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::DaemonNotRunning`] if nothing is listening on the
+    /// socket, [`Error::Timeout`] if the daemon does not answer in time, and
+    /// [`Error::Ipc`] for protocol failures.
+    pub fn request(&self, request: &Request) -> Result<Response> {
+        let mut stream = self.connect()?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        stream
+            .write_all(&protocol::encode(request)?)
+            .map_err(|e| self.io_error(&e, "sending request to daemon"))?;
+
+        self.read_response(&mut BufReader::new(&stream))
+    }
+
+    /// Subscribe to the daemon's live feed of stored captures.
+    ///
+    /// Only the subscription itself is bounded by the timeout; the returned
+    /// [`Subscription`] waits as long as it takes for the next event.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`request`](Self::request), or
+    /// [`Error::Ipc`] if the daemon does not accept the subscription.
+    pub fn subscribe(&self, filter: &SubscribeRequest) -> Result<Subscription> {
+        let stream = self.connect()?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        (&stream)
+            .write_all(&protocol::encode(Request::Subscribe(filter.clone()))?)
+            .map_err(|e| self.io_error(&e, "sending request to daemon"))?;
+
+        let mut reader = BufReader::new(stream);
+        match self.read_response(&mut reader)? {
+            Response::Subscribed => {
+                reader.get_ref().set_read_timeout(None)?;
+                Ok(Subscription { reader })
+            }
+            other => Err(Error::ipc(format!("unexpected response: {other:?}"))),
+        }
+    }
+
+    /// Read a single response line.
+    fn read_response(&self, reader: &mut impl BufRead) -> Result<Response> {
+        let mut line = String::new();
+        let read = reader
+            .read_line(&mut line)
+            .map_err(|e| self.io_error(&e, "waiting for daemon response"))?;
+        if read == 0 {
+            return Err(Error::ipc(
+                "daemon closed the connection without responding",
+            ));
+        }
+
+        match protocol::decode::<Response>(&line)? {
+            Response::Error { message } => Err(Error::Ipc(message)),
+            response => Ok(response),
+        }
+    }
+
+    /// Connect to the socket, giving up after the configured timeout.
+    fn connect(&self) -> Result<UnixStream> {
+        // `UnixStream` has no `connect_timeout`, so connect on a helper thread.
+        let (tx, rx) = mpsc::channel();
+        let path = self.path.clone();
+        std::thread::spawn(move || {
+            let _ = tx.send(UnixStream::connect(path));
+        });
+
+        match rx.recv_timeout(self.timeout) {
+            Ok(Ok(stream)) => Ok(stream),
+            Ok(Err(e)) => match e.kind() {
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused => {
+                    Err(Error::DaemonNotRunning)
+                }
+                _ => Err(Error::DaemonConnect {
+                    path: self.path.clone(),
+                    message: e.to_string(),
+                }),
+            },
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(Error::Timeout {
+                operation: format!("connecting to daemon at {}", self.path.display()),
+            }),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(Error::internal("connect thread exited without a result"))
+            }
+        }
+    }
+
+    /// Map an I/O error during a request, turning timeouts into [`Error::Timeout`].
+    fn io_error(&self, err: &std::io::Error, operation: &str) -> Error {
+        match err.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => Error::Timeout {
+                operation: format!("{operation} ({:?})", self.timeout),
+            },
+            _ => Error::ipc(format!("{operation}: {err}")),
+        }
+    }
+}
+
+/// A live feed of captures from the daemon, created by [`IpcClient::subscribe`].
+///
+/// Iterating blocks until the daemon sends the next [`Event`], and ends when
+/// the daemon closes the connection.
+#[derive(Debug)]
+pub struct Subscription {
+    reader: BufReader<UnixStream>,
+}
+
+impl Iterator for Subscription {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(protocol::decode(&line)),
+            Err(e) => Some(Err(Error::ipc(format!("reading from daemon: {e}")))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fr_client_{name}_{}.sock", std::process::id()))
+    }
+
+    #[test]
+    fn test_client_default_timeout() {
+        let client = IpcClient::new("/tmp/test.sock");
+        assert_eq!(client.timeout, DEFAULT_TIMEOUT);
+        assert_eq!(client.path(), Path::new("/tmp/test.sock"));
+    }
+
+    #[test]
+    fn test_client_with_timeout() {
+        let client = IpcClient::new("/tmp/test.sock").with_timeout(Duration::from_millis(10));
+        assert_eq!(client.timeout, Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_missing_socket_is_not_running() {
+        let client = IpcClient::new("/nonexistent/fliterec.sock");
+        let err = client.request(&Request::Status).unwrap_err();
+        assert!(err.is_daemon_not_running());
+    }
+
+    #[test]
+    fn test_stale_socket_is_not_running() {
+        let path = test_socket_path("stale");
+        let _ = std::fs::remove_file(&path);
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let err = IpcClient::new(&path).request(&Request::Status).unwrap_err();
+        assert!(err.is_daemon_not_running());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_unresponsive_daemon_times_out() {
+        let path = test_socket_path("silent");
+        let _ = std::fs::remove_file(&path);
+        // Accepts connections (via the backlog) but never answers.
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        let client = IpcClient::new(&path).with_timeout(Duration::from_millis(100));
+        let err = client.request(&Request::Status).unwrap_err();
+        assert!(matches!(err, Error::Timeout { .. }));
+        let _ = std::fs::remove_file(&path);
+    }
+}
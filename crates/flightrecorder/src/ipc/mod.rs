@@ -0,0 +1,26 @@
+//! Inter-process communication between the daemon and the CLI.
+//!
+//! The daemon listens on a Unix domain socket (see [`Config::socket_path`])
+//! and the `fliterec` CLI connects to it to query status, search captures and
+//! control the daemon.
+//!
+//! - [`protocol`]: versioned, newline-delimited JSON request/response types.
+//! - [`server`]: a Tokio server that dispatches requests to a [`RequestHandler`].
+//! - [`client`]: a blocking client with connect and read timeouts.
+//!
+//! A client can also subscribe to the daemon's live feed: the connection then
+//! carries an [`Event`] line for every capture the daemon stores.
+//!
+//! [`Config::socket_path`]: crate::config::Config::socket_path
+
+pub mod client;
+pub mod protocol;
+pub mod server;
+
+pub use client::{IpcClient, Subscription};
+pub use protocol::{
+    BackupRequest, DaemonStatus, Event, IdsRequest, ListTrashRequest, NoteRequest, PinRequest,
+    PruneRequest, RecoverRequest, ReloadSummary, Request, Response, SearchRequest,
+    SubscribeRequest, TagRequest, WriterStatus, PROTOCOL_VERSION,
+};
+pub use server::{IpcServer, RequestHandler};
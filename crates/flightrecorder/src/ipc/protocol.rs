@@ -0,0 +1,794 @@
+//! IPC protocol definitions.
+//!
+//! Messages are exchanged as newline-delimited JSON. Every message is wrapped
+//! in an envelope carrying the protocol version so that a CLI and daemon built
+//! from different releases can detect the mismatch instead of misinterpreting
+//! each other.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::capture::{Capture, CaptureType};
+use crate::error::{Error, Result};
+use crate::monitor::MonitorStatus;
+use crate::storage::{
+    BackupInfo, CaptureQuery, PruneReport, SearchHit, Storage, StorageStats, TrashedCapture,
+};
+
+/// The version of the IPC protocol spoken by this build.
+///
+/// Version 2 answers search requests with [`Response::SearchResults`].
+/// Version 3 adds draft history to recover requests.
+/// Version 4 adds prune requests.
+/// Version 5 adds deleting captures and the trash.
+/// Version 6 adds pins, tags and notes.
+/// Version 7 adds backups.
+pub const PROTOCOL_VERSION: u32 = 7;
+
+/// A request sent from a client to the daemon.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// Ask the daemon for its current status.
+    Status,
+    /// Search captured text.
+    Search(SearchRequest),
+    /// Recover recent captures.
+    Recover(RecoverRequest),
+    /// Ask the daemon to shut down gracefully.
+    Shutdown,
+    /// Ask the daemon to reload its configuration.
+    Reload,
+    /// Receive captures as they are stored. See [`Event`].
+    Subscribe(SubscribeRequest),
+    /// Delete the captures beyond the configured retention limits.
+    Prune(PruneRequest),
+    /// Move captures to the trash.
+    Delete(IdsRequest),
+    /// List the captures in the trash.
+    ListTrash(ListTrashRequest),
+    /// Take captures out of the trash.
+    Restore(IdsRequest),
+    /// Delete every capture in the trash for good.
+    EmptyTrash,
+    /// Pin or unpin a capture.
+    Pin(PinRequest),
+    /// Add or remove tags on a capture.
+    Tag(TagRequest),
+    /// Set or clear the note on a capture.
+    Note(NoteRequest),
+    /// Back up the database to a file.
+    Backup(BackupRequest),
+}
+
+/// Parameters for a search request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchRequest {
+    /// Full-text query for capture content (see [`CaptureQuery::with_text`]).
+    pub query: String,
+    /// Only return captures from this application.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    /// Only return captures of this type.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_type: Option<CaptureType>,
+    /// Only return captures at or after this time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+    /// Only return captures at or before this time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<DateTime<Utc>>,
+    /// Only return captures with this tag.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Only return pinned captures.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// Maximum number of results.
+    pub limit: usize,
+}
+
+impl SearchRequest {
+    /// Build the storage query for this search.
+    #[must_use]
+    pub fn to_query(&self) -> CaptureQuery {
+        let mut query = CaptureQuery::new()
+            .with_text(self.query.clone())
+            .with_limit(self.limit);
+        if let Some(app) = &self.app {
+            query = query.with_app(app.clone());
+        }
+        if let Some(capture_type) = self.capture_type {
+            query = query.with_type(capture_type);
+        }
+        if let Some(since) = self.since {
+            query = query.with_since(since);
+        }
+        if let Some(until) = self.until {
+            query = query.with_until(until);
+        }
+        if let Some(tag) = &self.tag {
+            query = query.with_tag(tag.clone());
+        }
+        if self.pinned {
+            query = query.pinned_only();
+        }
+        query
+    }
+
+    /// Run this search against the given storage.
+    ///
+    /// Hits are ordered by relevance, most relevant first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database query fails.
+    pub fn execute(&self, storage: &Storage) -> Result<Vec<SearchHit>> {
+        Ok(storage.query(&self.to_query())?.hits)
+    }
+}
+
+/// Parameters for a recover request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoverRequest {
+    /// Only return captures from this application.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    /// Only return captures at or after this time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+    /// Only return captures with this tag.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Only return pinned captures.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// Maximum number of captures to return.
+    pub limit: usize,
+    /// Instead of recent captures, return every version of the draft this
+    /// capture belongs to, oldest first. The other fields are ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<i64>,
+}
+
+impl RecoverRequest {
+    /// Build the storage query for this recovery.
+    #[must_use]
+    pub fn to_query(&self) -> CaptureQuery {
+        let mut query = CaptureQuery::new().with_limit(self.limit);
+        if let Some(app) = &self.app {
+            query = query.with_app(app.clone());
+        }
+        if let Some(since) = self.since {
+            query = query.with_since(since);
+        }
+        if let Some(tag) = &self.tag {
+            query = query.with_tag(tag.clone());
+        }
+        if self.pinned {
+            query = query.pinned_only();
+        }
+        query
+    }
+
+    /// Run this recovery against the given storage.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database query fails.
+    pub fn execute(&self, storage: &Storage) -> Result<Vec<Capture>> {
+        if let Some(id) = self.history {
+            return storage.history(id);
+        }
+        Ok(storage.query(&self.to_query())?.into_captures())
+    }
+}
+
+/// Parameters for a subscribe request.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubscribeRequest {
+    /// Only send captures from this application.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    /// Only send captures of this type.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_type: Option<CaptureType>,
+}
+
+impl SubscribeRequest {
+    /// Check whether a stored capture should be sent to this subscriber.
+    #[must_use]
+    pub fn matches(&self, capture: &Capture) -> bool {
+        self.app
+            .as_deref()
+            .map_or(true, |app| capture.source_app.as_deref() == Some(app))
+            && self
+                .capture_type
+                .map_or(true, |t| capture.capture_type == t)
+    }
+}
+
+/// Parameters for a prune request.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PruneRequest {
+    /// Only report what would be deleted.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Parameters for a request naming captures by ID.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdsRequest {
+    /// IDs of the captures.
+    pub ids: Vec<i64>,
+}
+
+/// Parameters for a request listing the trash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListTrashRequest {
+    /// Maximum number of captures to return.
+    pub limit: usize,
+}
+
+/// Parameters for a pin request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinRequest {
+    /// ID of the capture.
+    pub id: i64,
+    /// Whether to pin the capture or unpin it.
+    pub pinned: bool,
+}
+
+/// Parameters for a tag request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagRequest {
+    /// ID of the capture.
+    pub id: i64,
+    /// The tags to add or remove.
+    pub tags: Vec<String>,
+    /// Remove the tags instead of adding them.
+    #[serde(default)]
+    pub remove: bool,
+}
+
+/// Parameters for a note request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NoteRequest {
+    /// ID of the capture.
+    pub id: i64,
+    /// The note, or `None` to clear it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// Parameters for a backup request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupRequest {
+    /// Where to write the backup. The daemon resolves relative paths against
+    /// its own working directory, so clients send absolute ones.
+    pub path: PathBuf,
+}
+
+/// A response sent from the daemon to a client.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    /// The daemon's current status.
+    Status(DaemonStatus),
+    /// Captures matching a recover request.
+    Captures {
+        /// The matching captures, most recent first.
+        captures: Vec<Capture>,
+    },
+    /// Captures matching a search request.
+    SearchResults {
+        /// The matching captures, most relevant first.
+        hits: Vec<SearchHit>,
+    },
+    /// The configuration was reloaded.
+    Reloaded(ReloadSummary),
+    /// What a prune request deleted, or would delete.
+    Pruned(PruneReport),
+    /// The captures in the trash.
+    Trash {
+        /// The trashed captures, most recently trashed first.
+        trashed: Vec<TrashedCapture>,
+    },
+    /// How many captures a delete, restore, empty-trash, pin, tag or note
+    /// request changed.
+    Changed {
+        /// Number of captures changed.
+        count: usize,
+    },
+    /// The backup written by a backup request.
+    BackedUp(BackupInfo),
+    /// The subscription is active; [`Event`]s follow on the same connection.
+    Subscribed,
+    /// The request was carried out.
+    Ok,
+    /// The request failed.
+    Error {
+        /// Description of what went wrong.
+        message: String,
+    },
+}
+
+impl Response {
+    /// Create an error response.
+    #[must_use]
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::Error {
+            message: message.into(),
+        }
+    }
+}
+
+/// Status information reported by a running daemon.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    /// Process ID of the daemon.
+    pub pid: u32,
+    /// Version of the daemon binary.
+    pub version: String,
+    /// Seconds since the daemon started.
+    pub uptime_secs: u64,
+    /// State of each running monitor.
+    #[serde(default)]
+    pub monitors: Vec<MonitorStatus>,
+    /// Database statistics, if they could be read.
+    #[serde(default)]
+    pub storage: Option<StorageStats>,
+    /// Captures dropped by the privacy filter since the daemon started, by
+    /// the name of the rule that blocked them.
+    #[serde(default)]
+    pub blocked: BTreeMap<String, u64>,
+    /// State of the queue between the monitors and the storage writer.
+    #[serde(default)]
+    pub writer: WriterStatus,
+}
+
+/// How the storage writer is keeping up with the monitors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriterStatus {
+    /// Number of captures the queue can hold before monitors must wait.
+    pub queue_capacity: usize,
+    /// Captures waiting in the queue when the last batch was taken.
+    pub queued: usize,
+    /// Most captures ever waiting in the queue at once.
+    pub max_queued: usize,
+    /// Batches written, each in one transaction.
+    pub batches: u64,
+    /// Most captures written in one batch.
+    pub largest_batch: usize,
+    /// Captures a monitor had to wait to queue because the queue was full.
+    pub full_waits: u64,
+    /// Captures that could not be stored.
+    pub failed: u64,
+}
+
+/// What changed when the daemon reloaded its configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReloadSummary {
+    /// Configuration sections that differ from the previous configuration.
+    pub changed: Vec<String>,
+    /// Changed sections that only take effect after a restart.
+    pub restart_required: Vec<String>,
+    /// Running monitors restarted to pick up changes to their settings.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub restarted: Vec<String>,
+}
+
+/// A message pushed to a subscribed client.
+///
+/// After answering a [`Request::Subscribe`] with [`Response::Subscribed`], the
+/// daemon sends one event line per stored capture until either side closes
+/// the connection.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A capture was stored, after privacy filtering.
+    Capture {
+        /// The stored capture.
+        capture: Capture,
+    },
+    /// The client fell behind and this many captures were not sent to it.
+    Lagged {
+        /// Number of captures skipped.
+        skipped: u64,
+    },
+}
+
+/// A message wrapped with the protocol version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    /// Protocol version of the sender.
+    pub protocol: u32,
+    /// The wrapped message.
+    #[serde(flatten)]
+    pub message: T,
+}
+
+impl<T> Envelope<T> {
+    /// Wrap a message with the current protocol version.
+    #[must_use]
+    pub fn new(message: T) -> Self {
+        Self {
+            protocol: PROTOCOL_VERSION,
+            message,
+        }
+    }
+}
+
+/// Encode a message as a single newline-terminated JSON line.
+///
+/// # Errors
+///
+/// Returns an error if the message cannot be serialized.
+pub fn encode<T: Serialize>(message: T) -> Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(&Envelope::new(message))?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Decode a single JSON line, checking the protocol version.
+///
+/// # Errors
+///
+/// Returns an error if the line is not valid JSON for `T` or was produced by
+/// a different protocol version.
+pub fn decode<T: for<'de> Deserialize<'de>>(line: &str) -> Result<T> {
+    let envelope: Envelope<T> = serde_json::from_str(line.trim_end())?;
+    if envelope.protocol != PROTOCOL_VERSION {
+        return Err(Error::ipc(format!(
+            "protocol version mismatch: expected {PROTOCOL_VERSION}, got {}",
+            envelope.protocol
+        )));
+    }
+    Ok(envelope.message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_roundtrip() {
+        let requests = vec![
+            Request::Status,
+            Request::Search(SearchRequest {
+                query: "hello".to_string(),
+                app: Some("Terminal".to_string()),
+                capture_type: Some(CaptureType::Clipboard),
+                since: None,
+                until: None,
+                tag: None,
+                pinned: false,
+                limit: 20,
+            }),
+            Request::Recover(RecoverRequest {
+                app: None,
+                since: Some(Utc::now()),
+                tag: None,
+                pinned: false,
+                limit: 5,
+                history: None,
+            }),
+            Request::Shutdown,
+            Request::Reload,
+            Request::Subscribe(SubscribeRequest {
+                app: Some("Editor".to_string()),
+                capture_type: Some(CaptureType::TextField),
+            }),
+            Request::Prune(PruneRequest { dry_run: true }),
+            Request::Delete(IdsRequest { ids: vec![1, 2] }),
+            Request::ListTrash(ListTrashRequest { limit: 10 }),
+            Request::Restore(IdsRequest { ids: vec![2] }),
+            Request::EmptyTrash,
+            Request::Pin(PinRequest {
+                id: 3,
+                pinned: true,
+            }),
+            Request::Tag(TagRequest {
+                id: 3,
+                tags: vec!["incident".to_string()],
+                remove: false,
+            }),
+            Request::Note(NoteRequest { id: 3, note: None }),
+            Request::Backup(BackupRequest {
+                path: PathBuf::from("/backups/captures.db"),
+            }),
+        ];
+
+        for request in requests {
+            let line = encode(&request).unwrap();
+            let decoded: Request = decode(std::str::from_utf8(&line).unwrap()).unwrap();
+            assert_eq!(decoded, request);
+        }
+    }
+
+    #[test]
+    fn test_response_roundtrip() {
+        let responses = vec![
+            Response::Status(DaemonStatus {
+                pid: 42,
+                version: "0.1.0".to_string(),
+                uptime_secs: 10,
+                monitors: vec![MonitorStatus::running(
+                    crate::monitor::MonitorType::Clipboard,
+                    3,
+                )],
+                storage: Some(StorageStats {
+                    total_captures: 3,
+                    trashed_captures: 1,
+                    oldest_capture: Some(Utc::now()),
+                    newest_capture: Some(Utc::now()),
+                    db_size_bytes: 4096,
+                    content_bytes: 2048,
+                    stored_content_bytes: 1024,
+                }),
+                blocked: BTreeMap::from([("credit_card".to_string(), 2)]),
+                writer: WriterStatus {
+                    queue_capacity: 256,
+                    queued: 1,
+                    max_queued: 12,
+                    batches: 4,
+                    largest_batch: 8,
+                    full_waits: 0,
+                    failed: 0,
+                },
+            }),
+            Response::Captures {
+                captures: vec![Capture::new(
+                    "text".to_string(),
+                    CaptureType::TextField,
+                    None,
+                )],
+            },
+            Response::SearchResults {
+                hits: vec![SearchHit {
+                    capture: Capture::new("some text".to_string(), CaptureType::Clipboard, None),
+                    snippet: Some("some [text]".to_string()),
+                }],
+            },
+            Response::Reloaded(ReloadSummary {
+                changed: vec!["privacy".to_string(), "storage".to_string()],
+                restart_required: vec!["storage".to_string()],
+                restarted: vec!["accessibility".to_string()],
+            }),
+            Response::Pruned(PruneReport {
+                expired: 3,
+                over_count: 2,
+                over_size: 1,
+                trashed: 2,
+                purged: 1,
+                estimated_bytes: 6000,
+                used_bytes: 40960,
+                reclaimed_bytes: 4096,
+            }),
+            Response::Trash {
+                trashed: vec![TrashedCapture {
+                    capture: Capture::new("gone".to_string(), CaptureType::Clipboard, None),
+                    trashed_at: Utc::now(),
+                }],
+            },
+            Response::Changed { count: 2 },
+            Response::BackedUp(BackupInfo {
+                path: PathBuf::from("/backups/captures-20260101T000000.000Z.db"),
+                size_bytes: 8192,
+                removed: vec![PathBuf::from("/backups/captures-20251225T000000.000Z.db")],
+            }),
+            Response::Subscribed,
+            Response::Ok,
+            Response::error("boom"),
+        ];
+
+        for response in responses {
+            let line = encode(&response).unwrap();
+            let decoded: Response = decode(std::str::from_utf8(&line).unwrap()).unwrap();
+            assert_eq!(decoded, response);
+        }
+    }
+
+    #[test]
+    fn test_event_roundtrip() {
+        let events = vec![
+            Event::Capture {
+                capture: Capture::new("text".to_string(), CaptureType::Clipboard, None),
+            },
+            Event::Lagged { skipped: 3 },
+        ];
+
+        for event in events {
+            let line = encode(&event).unwrap();
+            let decoded: Event = decode(std::str::from_utf8(&line).unwrap()).unwrap();
+            assert_eq!(decoded, event);
+        }
+    }
+
+    #[test]
+    fn test_subscribe_request_matches() {
+        let capture = Capture::new(
+            "text".to_string(),
+            CaptureType::TextField,
+            Some("Editor".to_string()),
+        );
+        assert!(SubscribeRequest::default().matches(&capture));
+        assert!(SubscribeRequest {
+            app: Some("Editor".to_string()),
+            capture_type: Some(CaptureType::TextField),
+        }
+        .matches(&capture));
+        assert!(!SubscribeRequest {
+            app: Some("Terminal".to_string()),
+            capture_type: None,
+        }
+        .matches(&capture));
+        assert!(!SubscribeRequest {
+            app: None,
+            capture_type: Some(CaptureType::Clipboard),
+        }
+        .matches(&capture));
+    }
+
+    #[test]
+    fn test_encode_is_single_line() {
+        let line = encode(Request::Status).unwrap();
+        assert_eq!(line.last(), Some(&b'\n'));
+        assert!(!line[..line.len() - 1].contains(&b'\n'));
+    }
+
+    #[test]
+    fn test_wire_format() {
+        let line = encode(Request::Status).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&line).unwrap();
+        assert_eq!(json["protocol"], PROTOCOL_VERSION);
+        assert_eq!(json["type"], "status");
+    }
+
+    #[test]
+    fn test_status_from_older_daemon() {
+        let line = format!(
+            r#"{{"protocol": {PROTOCOL_VERSION}, "type": "status", "pid": 7, "version": "0.1.0", "uptime_secs": 1}}"#
+        );
+        let response: Response = decode(&line).unwrap();
+        assert!(
+            matches!(response, Response::Status(s) if s.monitors.is_empty() && s.storage.is_none())
+        );
+    }
+
+    #[test]
+    fn test_decode_version_mismatch() {
+        let line = r#"{"protocol": 999, "type": "status"}"#;
+        let err = decode::<Request>(line).unwrap_err();
+        assert!(err.to_string().contains("version mismatch"));
+    }
+
+    #[test]
+    fn test_decode_invalid_json() {
+        let err = decode::<Request>("not json").unwrap_err();
+        assert!(matches!(err, Error::Json(_)));
+    }
+
+    #[test]
+    fn test_search_request_execute_filters() {
+        let storage = Storage::open_in_memory().unwrap();
+        storage
+            .insert(&Capture::new(
+                "hello from terminal".to_string(),
+                CaptureType::Clipboard,
+                Some("Terminal".to_string()),
+            ))
+            .unwrap();
+        storage
+            .insert(&Capture::new(
+                "hello from editor".to_string(),
+                CaptureType::TextField,
+                Some("Editor".to_string()),
+            ))
+            .unwrap();
+
+        let request = SearchRequest {
+            query: "hello".to_string(),
+            app: Some("Editor".to_string()),
+            capture_type: None,
+            since: None,
+            until: None,
+            tag: None,
+            pinned: false,
+            limit: 10,
+        };
+        let results = request.execute(&storage).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].capture.source_app.as_deref(), Some("Editor"));
+        assert_eq!(results[0].snippet.as_deref(), Some("[hello] from editor"));
+    }
+
+    #[test]
+    fn test_recover_request_execute() {
+        let storage = Storage::open_in_memory().unwrap();
+        for i in 0..5 {
+            storage
+                .insert(&Capture::new(
+                    format!("capture {i}"),
+                    CaptureType::Clipboard,
+                    None,
+                ))
+                .unwrap();
+        }
+
+        let request = RecoverRequest {
+            app: None,
+            since: None,
+            tag: None,
+            pinned: false,
+            limit: 3,
+            history: None,
+        };
+        assert_eq!(request.execute(&storage).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_recover_request_history() {
+        let storage = Storage::open_in_memory().unwrap();
+        let mut last = None;
+        for draft in ["H", "Hel", "Hello"] {
+            last = storage
+                .insert(&Capture::new(
+                    draft.to_string(),
+                    CaptureType::TextField,
+                    Some("Mail".to_string()),
+                ))
+                .unwrap();
+        }
+
+        let request = RecoverRequest {
+            app: None,
+            since: None,
+            tag: None,
+            pinned: false,
+            limit: 1,
+            history: last,
+        };
+        let versions = request.execute(&storage).unwrap();
+        let contents: Vec<_> = versions.iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(contents, ["H", "Hel", "Hello"]);
+    }
+
+    #[test]
+    fn test_recover_request_tag_and_pinned_filters() {
+        let storage = Storage::open_in_memory().unwrap();
+        let mut ids = Vec::new();
+        for i in 0..3 {
+            ids.push(
+                storage
+                    .insert(&Capture::new(
+                        format!("capture {i}"),
+                        CaptureType::Clipboard,
+                        None,
+                    ))
+                    .unwrap()
+                    .unwrap(),
+            );
+        }
+        storage.add_tags(ids[0], &["work".to_string()]).unwrap();
+        storage.add_tags(ids[1], &["work".to_string()]).unwrap();
+        storage.pin(ids[1], true).unwrap();
+
+        let mut request = RecoverRequest {
+            app: None,
+            since: None,
+            tag: Some("work".to_string()),
+            pinned: false,
+            limit: 10,
+            history: None,
+        };
+        assert_eq!(request.execute(&storage).unwrap().len(), 2);
+        request.pinned = true;
+        let captures = request.execute(&storage).unwrap();
+        assert_eq!(captures.len(), 1);
+        assert_eq!(captures[0].id, Some(ids[1]));
+    }
+}
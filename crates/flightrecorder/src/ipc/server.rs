@@ -0,0 +1,705 @@
+//! Unix socket IPC server.
+//!
+//! The server accepts connections on a Unix domain socket and dispatches each
+//! decoded [`Request`] to a [`RequestHandler`]. A connection may carry any
+//! number of requests; each one receives exactly one response line.
+//!
+//! A [`Request::Subscribe`] turns the connection into a one-way feed: after
+//! the [`Response::Subscribed`] line, the server writes an [`Event`] for each
+//! capture the handler publishes, until the client disconnects or the server
+//! shuts down.
+//!
+//! Captures are sensitive, so the socket is only reachable by the user running
+//! the server: the socket file has mode 0600 inside a directory with mode
+//! 0700, and every connection's peer credentials are checked against the
+//! server's user ID before any request is handled. A refused peer is sent an
+//! error response explaining why, after its first request line is read and
+//! discarded.
+
+use std::fs::{DirBuilder, Permissions};
+use std::future::Future;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use nix::unistd::Uid;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{broadcast, watch};
+use tracing::{debug, info, warn};
+
+use super::protocol::{self, Event, Request, Response, SubscribeRequest};
+use crate::capture::Capture;
+use crate::error::{Error, Result};
+
+/// Mode of the socket file: read and write for its owner only.
+const SOCKET_MODE: u32 = 0o600;
+
+/// Mode of the directory holding the socket.
+const SOCKET_DIR_MODE: u32 = 0o700;
+
+/// How long to pause after failing to accept a connection, such as when the
+/// process is out of file descriptors, before accepting again.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Most bytes read from a refused client before answering it.
+const REFUSED_READ_LIMIT: u64 = 64 * 1024;
+
+/// How long to wait for a refused client's request before answering it.
+const REFUSED_READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Handles requests received by the IPC server.
+#[async_trait::async_trait]
+pub trait RequestHandler: Send + Sync {
+    /// Handle a single request and produce its response.
+    ///
+    /// [`Request::Subscribe`] is handled by the server using
+    /// [`subscribe`](Self::subscribe) and never reaches this method.
+    async fn handle(&self, request: Request) -> Response;
+
+    /// Start receiving captures as they are stored.
+    ///
+    /// Returns `None` if the handler has no capture feed, in which case
+    /// subscribe requests are refused.
+    fn subscribe(&self) -> Option<broadcast::Receiver<Capture>> {
+        None
+    }
+}
+
+/// A Unix socket server that dispatches requests to a [`RequestHandler`].
+///
+/// The socket file is removed when the server is dropped.
+#[derive(Debug)]
+pub struct IpcServer {
+    path: PathBuf,
+    listener: UnixListener,
+    allowed_uid: u32,
+}
+
+impl IpcServer {
+    /// Bind a new server to the given socket path.
+    ///
+    /// Creates the parent directory with mode 0700 if needed, and restricts an
+    /// existing parent directory owned by the current user to that mode. If a
+    /// socket file already exists at `path` but nothing is listening on it
+    /// (for example, after a crash), the stale file is removed before binding.
+    /// Anything at `path` that is not a socket is refused rather than removed.
+    /// The new socket file is given mode 0600.
+    ///
+    /// Only processes running as the current user may connect; see
+    /// [`with_allowed_uid`](Self::with_allowed_uid).
+    ///
+    /// Must be called from within a Tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if another process is already listening on the socket,
+    /// if something other than a socket is at `path`, or if the socket or its
+    /// directory cannot be created or secured.
+    pub fn bind(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        if let Some(parent) = path.parent() {
+            secure_socket_dir(parent)?;
+        }
+
+        remove_stale_socket(&path)?;
+
+        let listener = UnixListener::bind(&path)
+            .map_err(|e| Error::ipc(format!("failed to bind socket {}: {e}", path.display())))?;
+        std::fs::set_permissions(&path, Permissions::from_mode(SOCKET_MODE))?;
+
+        info!("IPC server listening on {}", path.display());
+        Ok(Self {
+            path,
+            listener,
+            allowed_uid: Uid::effective().as_raw(),
+        })
+    }
+
+    /// Accept connections only from processes running as `uid`, instead of
+    /// the user running the server.
+    #[must_use]
+    pub fn with_allowed_uid(mut self, uid: u32) -> Self {
+        self.allowed_uid = uid;
+        self
+    }
+
+    /// Get the path of the socket file.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Accept connections until `shutdown` resolves.
+    ///
+    /// Each connection is served on its own task, so a slow client cannot
+    /// block others. Connections from other users have their first request
+    /// line read and discarded, then are sent an error response and closed
+    /// without any request being handled. A failure to accept a connection
+    /// is logged and accepting resumes after a short pause, so it does not
+    /// stop the server.
+    ///
+    /// # Errors
+    ///
+    /// Does not currently fail; the `Result` leaves room for errors that
+    /// should end the server.
+    pub async fn serve<H, F>(self, handler: Arc<H>, shutdown: F) -> Result<()>
+    where
+        H: RequestHandler + ?Sized + 'static,
+        F: Future<Output = ()> + Send,
+    {
+        tokio::pin!(shutdown);
+        // Tells open subscriptions that the server is going away.
+        let (closed_tx, closed_rx) = watch::channel(false);
+
+        loop {
+            tokio::select! {
+                () = &mut shutdown => {
+                    debug!("IPC server shutting down");
+                    closed_tx.send_replace(true);
+                    return Ok(());
+                }
+                accepted = self.listener.accept() => {
+                    let stream = match accepted {
+                        Ok((stream, _addr)) => stream,
+                        Err(e) => {
+                            warn!("Failed to accept IPC connection: {e}");
+                            tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                            continue;
+                        }
+                    };
+                    let handler = Arc::clone(&handler);
+                    let closed = closed_rx.clone();
+                    let allowed_uid = self.allowed_uid;
+                    tokio::spawn(async move {
+                        let result = match check_peer(&stream, allowed_uid) {
+                            Ok(()) => handle_connection(stream, handler, closed).await,
+                            Err(reason) => {
+                                warn!("Refused IPC connection: {reason}");
+                                refuse_connection(stream, &reason).await
+                            }
+                        };
+                        if let Err(e) = result {
+                            warn!("IPC connection error: {e}");
+                        }
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove socket {}: {e}", self.path.display());
+            }
+        }
+    }
+}
+
+/// Make sure the directory holding the socket is private to the current user.
+///
+/// A missing directory is created with mode 0700, and an existing one owned by
+/// the current user has any group and other permissions removed. A directory
+/// owned by someone else (such as `/tmp`) is left alone with a warning; the
+/// socket's own mode and the peer check still apply.
+fn secure_socket_dir(dir: &Path) -> Result<()> {
+    if !dir.exists() {
+        return DirBuilder::new()
+            .recursive(true)
+            .mode(SOCKET_DIR_MODE)
+            .create(dir)
+            .map_err(|source| Error::DirectoryCreate {
+                path: dir.to_path_buf(),
+                source,
+            });
+    }
+
+    let metadata = std::fs::metadata(dir)?;
+    if metadata.uid() != Uid::effective().as_raw() {
+        warn!(
+            "Socket directory {} belongs to another user; use a private directory for the socket",
+            dir.display()
+        );
+    } else if metadata.mode() & 0o077 != 0 {
+        info!(
+            "Restricting socket directory {} to mode 0700",
+            dir.display()
+        );
+        std::fs::set_permissions(dir, Permissions::from_mode(SOCKET_DIR_MODE))?;
+    }
+    Ok(())
+}
+
+/// Check that the process at the other end of `stream` runs as `allowed_uid`.
+///
+/// Returns a description of the peer if it does not.
+fn check_peer(stream: &UnixStream, allowed_uid: u32) -> std::result::Result<(), String> {
+    let cred = stream
+        .peer_cred()
+        .map_err(|e| format!("cannot read peer credentials: {e}"))?;
+    if cred.uid() == allowed_uid {
+        return Ok(());
+    }
+    let pid = cred
+        .pid()
+        .map_or_else(|| "unknown".to_string(), |pid| pid.to_string());
+    Err(format!(
+        "peer uid {} (pid {pid}) is not the daemon's user (uid {allowed_uid})",
+        cred.uid()
+    ))
+}
+
+/// Tell a refused client why, then close the connection.
+///
+/// The client's first request line is read (and discarded) first: closing
+/// the connection while the client is still writing would leave it with a
+/// broken pipe instead of the explanation.
+async fn refuse_connection(stream: UnixStream, reason: &str) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader.take(REFUSED_READ_LIMIT));
+    let mut request = Vec::new();
+    let _ =
+        tokio::time::timeout(REFUSED_READ_TIMEOUT, reader.read_until(b'\n', &mut request)).await;
+    write_line(
+        &mut writer,
+        &Response::error(format!("connection refused: {reason}")),
+    )
+    .await
+}
+
+/// Remove a socket file that no process is listening on. Anything else at
+/// `path` is left alone.
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(Error::ipc(format!(
+            "{} exists and is not a socket",
+            path.display()
+        )));
+    }
+
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(Error::ipc(format!(
+            "another process is already listening on {}",
+            path.display()
+        )));
+    }
+
+    info!("Removing stale socket {}", path.display());
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Serve requests on a single connection until the client disconnects.
+async fn handle_connection<H>(
+    stream: UnixStream,
+    handler: Arc<H>,
+    closed: watch::Receiver<bool>,
+) -> Result<()>
+where
+    H: RequestHandler + ?Sized,
+{
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match protocol::decode::<Request>(&line) {
+            Ok(Request::Subscribe(filter)) => {
+                debug!(?filter, "IPC subscription");
+                let Some(feed) = handler.subscribe() else {
+                    let refusal = Response::error("subscriptions are not supported");
+                    return write_line(&mut writer, &refusal).await;
+                };
+                write_line(&mut writer, &Response::Subscribed).await?;
+                return stream_captures(lines.into_inner(), writer, feed, &filter, closed).await;
+            }
+            Ok(request) => {
+                debug!(?request, "IPC request");
+                handler.handle(request).await
+            }
+            Err(e) => Response::error(format!("invalid request: {e}")),
+        };
+
+        write_line(&mut writer, &response).await?;
+    }
+
+    Ok(())
+}
+
+/// Forward captures from `feed` that match `filter` to a subscribed client.
+///
+/// The feed is a bounded broadcast channel, so a client that reads too slowly
+/// never holds up the publisher: it misses captures instead, and is sent an
+/// [`Event::Lagged`] saying how many.
+async fn stream_captures(
+    mut reader: BufReader<OwnedReadHalf>,
+    mut writer: OwnedWriteHalf,
+    mut feed: broadcast::Receiver<Capture>,
+    filter: &SubscribeRequest,
+    mut closed: watch::Receiver<bool>,
+) -> Result<()> {
+    let mut discard = Vec::new();
+    loop {
+        let event = tokio::select! {
+            received = feed.recv() => match received {
+                Ok(capture) if filter.matches(&capture) => Event::Capture { capture },
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!(skipped, "Subscriber lagged");
+                    Event::Lagged { skipped }
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            // The client sends nothing after subscribing; reading only
+            // notices when it disconnects.
+            read = reader.read_until(b'\n', &mut discard) => {
+                if read? == 0 {
+                    debug!("Subscriber disconnected");
+                    return Ok(());
+                }
+                discard.clear();
+                continue;
+            }
+            _ = closed.wait_for(|closed| *closed) => return Ok(()),
+        };
+
+        write_line(&mut writer, &event).await?;
+    }
+}
+
+/// Write one encoded message and flush it.
+async fn write_line<T: serde::Serialize>(writer: &mut OwnedWriteHalf, message: &T) -> Result<()> {
+    writer.write_all(&protocol::encode(message)?).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::client::IpcClient;
+    use crate::ipc::protocol::DaemonStatus;
+
+    struct TestHandler;
+
+    #[async_trait::async_trait]
+    impl RequestHandler for TestHandler {
+        async fn handle(&self, request: Request) -> Response {
+            match request {
+                Request::Status => Response::Status(DaemonStatus {
+                    pid: std::process::id(),
+                    version: "test".to_string(),
+                    uptime_secs: 0,
+                    monitors: Vec::new(),
+                    storage: None,
+                    blocked: std::collections::BTreeMap::new(),
+                    writer: crate::ipc::WriterStatus::default(),
+                }),
+                Request::Shutdown => Response::Ok,
+                _ => Response::error("unsupported"),
+            }
+        }
+    }
+
+    fn test_socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fr_ipc_{name}_{}.sock", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_request_response() {
+        let path = test_socket_path("roundtrip");
+        let server = IpcServer::bind(&path).unwrap();
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(server.serve(Arc::new(TestHandler), async {
+            let _ = rx.await;
+        }));
+
+        let client = IpcClient::new(&path);
+        let response = tokio::task::spawn_blocking(move || client.request(&Request::Status))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(response, Response::Status(s) if s.version == "test"));
+
+        tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_error_response_maps_to_ipc_error() {
+        let path = test_socket_path("error");
+        let server = IpcServer::bind(&path).unwrap();
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(server.serve(Arc::new(TestHandler), async {
+            let _ = rx.await;
+        }));
+
+        let client = IpcClient::new(&path);
+        let result = tokio::task::spawn_blocking(move || client.request(&Request::Reload))
+            .await
+            .unwrap();
+        assert!(matches!(result, Err(Error::Ipc(msg)) if msg.contains("unsupported")));
+
+        tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bind_removes_stale_socket() {
+        let path = test_socket_path("stale");
+        let _ = std::fs::remove_file(&path);
+
+        // A bound-then-dropped listener leaves its socket file behind.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let server = IpcServer::bind(&path);
+        assert!(server.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_bind_leaves_other_files_alone() {
+        let path = test_socket_path("regular");
+        std::fs::write(&path, "not a socket").unwrap();
+
+        let server = IpcServer::bind(&path);
+        assert!(server.unwrap_err().to_string().contains("not a socket"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bind_refuses_live_socket() {
+        let path = test_socket_path("live");
+        let _first = IpcServer::bind(&path).unwrap();
+
+        let second = IpcServer::bind(&path);
+        assert!(second.is_err());
+        assert!(second
+            .unwrap_err()
+            .to_string()
+            .contains("already listening"));
+    }
+
+    #[tokio::test]
+    async fn test_socket_is_private() {
+        let dir = std::env::temp_dir().join(format!("fr_ipc_private_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("nested").join("fliterec.sock");
+
+        let server = IpcServer::bind(&path).unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(path.parent().unwrap()), 0o700);
+
+        // An existing directory of ours is tightened on the next bind.
+        drop(server);
+        std::fs::set_permissions(path.parent().unwrap(), Permissions::from_mode(0o755)).unwrap();
+        let _server = IpcServer::bind(&path).unwrap();
+        assert_eq!(mode(path.parent().unwrap()), 0o700);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_other_users_are_refused() {
+        let path = test_socket_path("other_user");
+        let other_uid = Uid::effective().as_raw().wrapping_add(1);
+        let server = IpcServer::bind(&path).unwrap().with_allowed_uid(other_uid);
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(server.serve(Arc::new(TestHandler), async {
+            let _ = rx.await;
+        }));
+
+        let client = IpcClient::new(&path);
+        let result = tokio::task::spawn_blocking(move || client.request(&Request::Status))
+            .await
+            .unwrap();
+        let Err(Error::Ipc(message)) = result else {
+            panic!("expected a refusal, got {result:?}");
+        };
+        assert!(message.contains("connection refused"), "{message}");
+        assert!(message.contains(&format!("uid {other_uid}")), "{message}");
+
+        tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_other_users_cannot_subscribe() {
+        let path = test_socket_path("other_user_feed");
+        let (feed, _) = broadcast::channel(4);
+        let other_uid = Uid::effective().as_raw().wrapping_add(1);
+        let server = IpcServer::bind(&path).unwrap().with_allowed_uid(other_uid);
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(server.serve(Arc::new(FeedHandler { feed }), async {
+            let _ = rx.await;
+        }));
+
+        let client = IpcClient::new(&path);
+        let result = tokio::task::spawn_blocking(move || {
+            client.subscribe(&SubscribeRequest::default()).map(|_| ())
+        })
+        .await
+        .unwrap();
+        assert!(matches!(result, Err(Error::Ipc(msg)) if msg.contains("connection refused")));
+
+        tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+    }
+
+    struct FeedHandler {
+        feed: broadcast::Sender<Capture>,
+    }
+
+    #[async_trait::async_trait]
+    impl RequestHandler for FeedHandler {
+        async fn handle(&self, _request: Request) -> Response {
+            Response::Ok
+        }
+
+        fn subscribe(&self) -> Option<broadcast::Receiver<Capture>> {
+            Some(self.feed.subscribe())
+        }
+    }
+
+    fn capture(content: &str, app: &str) -> Capture {
+        Capture::new(
+            content.to_string(),
+            crate::capture::CaptureType::Clipboard,
+            Some(app.to_string()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_subscription_streams_matching_captures() {
+        let path = test_socket_path("subscribe");
+        let server = IpcServer::bind(&path).unwrap();
+        let (feed, _) = broadcast::channel(16);
+        let handler = Arc::new(FeedHandler { feed: feed.clone() });
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(server.serve(handler, async {
+            let _ = rx.await;
+        }));
+
+        let client = IpcClient::new(&path);
+        let mut subscription = tokio::task::spawn_blocking(move || {
+            client.subscribe(&SubscribeRequest {
+                app: Some("Editor".to_string()),
+                capture_type: None,
+            })
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+        feed.send(capture("skipped", "Terminal")).unwrap();
+        feed.send(capture("wanted", "Editor")).unwrap();
+        let event = tokio::task::spawn_blocking(move || subscription.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(matches!(event, Event::Capture { capture } if capture.content == "wanted"));
+
+        tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_lagging_subscriber_is_told_what_it_missed() {
+        let path = test_socket_path("lagged");
+        let server = IpcServer::bind(&path).unwrap();
+        let (feed, _) = broadcast::channel(2);
+        let handler = Arc::new(FeedHandler { feed: feed.clone() });
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(server.serve(handler, async {
+            let _ = rx.await;
+        }));
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(&protocol::encode(Request::Subscribe(SubscribeRequest::default())).unwrap())
+            .await
+            .unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        let ack: Response = protocol::decode(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(ack, Response::Subscribed);
+
+        // Publishing never waits for the subscriber, which misses the oldest.
+        for i in 0..5 {
+            feed.send(capture(&format!("capture {i}"), "Editor"))
+                .unwrap();
+        }
+        let event: Event = protocol::decode(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(event, Event::Lagged { skipped: 3 });
+        let event: Event = protocol::decode(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert!(matches!(event, Event::Capture { capture } if capture.content == "capture 3"));
+        let event: Event = protocol::decode(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert!(matches!(event, Event::Capture { capture } if capture.content == "capture 4"));
+
+        tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+        // Shutting the server down ends the subscription.
+        assert!(lines.next_line().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_subscription_refused_without_feed() {
+        let path = test_socket_path("no_feed");
+        let server = IpcServer::bind(&path).unwrap();
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(server.serve(Arc::new(TestHandler), async {
+            let _ = rx.await;
+        }));
+
+        let client = IpcClient::new(&path);
+        let result =
+            tokio::task::spawn_blocking(move || client.subscribe(&SubscribeRequest::default()))
+                .await
+                .unwrap();
+        assert!(matches!(result, Err(Error::Ipc(msg)) if msg.contains("not supported")));
+
+        tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_request_gets_error_response() {
+        use tokio::io::AsyncReadExt;
+
+        let path = test_socket_path("invalid");
+        let server = IpcServer::bind(&path).unwrap();
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(server.serve(Arc::new(TestHandler), async {
+            let _ = rx.await;
+        }));
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        stream.write_all(b"garbage\n").await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).await.unwrap();
+
+        let response: Response = protocol::decode(&reply).unwrap();
+        assert!(matches!(response, Response::Error { message } if message.contains("invalid")));
+
+        tx.send(()).unwrap();
+        task.await.unwrap().unwrap();
+    }
+}
@@ -10,7 +10,9 @@
 pub mod capture;
 pub mod cli;
 pub mod config;
+pub mod daemon;
 pub mod error;
+pub mod ipc;
 pub mod logging;
 pub mod monitor;
 pub mod privacy;
@@ -19,8 +21,12 @@ pub mod storage;
 pub use capture::{Capture, CaptureSource, CaptureType};
 pub use cli::Cli;
 pub use config::Config;
+pub use daemon::Daemon;
 pub use error::{Error, Result};
+pub use ipc::{IpcClient, IpcServer, Request, RequestHandler, Response};
 pub use logging::init_logging;
-pub use monitor::{CaptureMonitor, MonitorConfig, MonitorHandle, MonitorManager, MonitorType};
+pub use monitor::{
+    CaptureMonitor, Heartbeat, MonitorConfig, MonitorHandle, MonitorManager, MonitorType,
+};
 pub use privacy::{FilterConfig, FilterMode, FilterResult, PrivacyFilter};
 pub use storage::{Storage, StorageStats};
@@ -3,6 +3,8 @@
 //! This module provides initialization and configuration for the tracing-based
 //! logging system used throughout flightrecorder.
 
+use std::io::IsTerminal;
+
 use tracing::Level;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
@@ -62,6 +64,7 @@ pub fn init_logging(verbosity: Verbosity) {
     // Configure the subscriber
     let subscriber = tracing_subscriber::registry().with(env_filter).with(
         fmt::layer()
+            .with_ansi(std::io::stderr().is_terminal())
             .with_target(true)
             .with_thread_ids(false)
             .with_file(false)
@@ -111,7 +114,7 @@ mod tests {
     #[test]
     fn test_verbosity_debug() {
         let v = Verbosity::Trace;
-        let debug_str = format!("{:?}", v);
+        let debug_str = format!("{v:?}");
         assert_eq!(debug_str, "Trace");
     }
 
@@ -6,10 +6,35 @@
 #![warn(missing_debug_implementations)]
 #![deny(unsafe_code)]
 
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
 use clap::Parser;
 
-use flightrecorder::cli::{Cli, Command, ConfigCommand, DaemonCommand};
-use flightrecorder::{init_logging, Config};
+use flightrecorder::cli::output::{
+    format_backup, format_captures, format_check_report, format_live_capture, format_prune_report,
+    format_search_hits, format_trash, table_header,
+};
+use flightrecorder::cli::status::StatusReport;
+use flightrecorder::cli::time::parse_time;
+use flightrecorder::cli::{
+    BackupCommand, CheckCommand, Cli, Command, ConfigCommand, DaemonCommand, DbCommand,
+    DeleteCommand, NoteCommand, OutputFormat, PinCommand, PruneCommand, RekeyCommand,
+    RestoreCommand, TagCommand, TrashCommand,
+};
+use flightrecorder::daemon::{self, Detached, PidFile, ReadyNotifier, Termination};
+use flightrecorder::ipc::{
+    BackupRequest, DaemonStatus, Event, IdsRequest, ListTrashRequest, NoteRequest, PinRequest,
+    PruneRequest, RecoverRequest, SearchRequest, SubscribeRequest, TagRequest,
+};
+use flightrecorder::logging::Verbosity;
+use flightrecorder::storage::EncryptionKey;
+use flightrecorder::{
+    init_logging, Capture, Config, Daemon, Error, IpcClient, Request, Response, Storage,
+};
 
 // Platform-specific imports using conditional compilation
 #[cfg(target_os = "linux")]
@@ -18,135 +43,904 @@ use flightrecorder_linux as platform;
 #[cfg(target_os = "macos")]
 use flightrecorder_mac as platform;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli = Cli::parse();
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    // Initialize logging based on verbosity
-    init_logging(cli.verbosity());
+fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let verbosity = cli.verbosity();
 
-    // Load configuration
-    let config = Config::load_from(cli.config.clone())?;
+    // Initialize logging based on verbosity. A daemon started in the
+    // background does this itself once it has detached from the terminal.
+    if !starts_in_background(&cli.command) {
+        init_logging(verbosity);
+    }
+
+    // Load configuration. The path is made absolute because a daemon that
+    // detaches changes directory, and re-reads the file on reload.
+    let config_path = cli
+        .config
+        .as_ref()
+        .map(|path| std::env::current_dir().map(|dir| dir.join(path)))
+        .transpose()?;
+    let config = Config::load_from(config_path.clone())?;
 
     // Execute the command
     match cli.command {
-        Command::Daemon(daemon_cmd) => handle_daemon(&daemon_cmd),
+        Command::Daemon(daemon_cmd) => handle_daemon(config, &daemon_cmd, config_path, verbosity),
         Command::Status(status_cmd) => handle_status(&config, status_cmd.json),
-        Command::Search(search_cmd) => {
-            handle_search(&search_cmd);
-            Ok(())
-        }
-        Command::Recover(recover_cmd) => {
-            handle_recover(&recover_cmd);
-            Ok(())
-        }
+        Command::Search(search_cmd) => handle_search(&config, &search_cmd),
+        Command::Recover(recover_cmd) => handle_recover(&config, &recover_cmd),
+        Command::Tail(tail_cmd) => handle_tail(&config, &tail_cmd),
+        Command::Prune(prune_cmd) => handle_prune(&config, &prune_cmd),
+        Command::Delete(delete_cmd) => handle_delete(&config, &delete_cmd),
+        Command::Trash(trash_cmd) => handle_trash(&config, &trash_cmd),
+        Command::Pin(pin_cmd) => handle_pin(&config, &pin_cmd),
+        Command::Tag(tag_cmd) => handle_tag(&config, &tag_cmd),
+        Command::Note(note_cmd) => handle_note(&config, &note_cmd),
         Command::Config(config_cmd) => handle_config(&config, config_cmd),
+        Command::Db(db_cmd) => handle_db(&config, &db_cmd),
     }
 }
 
-fn handle_daemon(cmd: &DaemonCommand) -> Result<(), Box<dyn std::error::Error>> {
+/// Check whether `command` will detach a daemon into the background.
+fn starts_in_background(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::Daemon(
+            DaemonCommand::Start { foreground: false }
+                | DaemonCommand::Restart { foreground: false }
+        )
+    )
+}
+
+fn handle_daemon(
+    config: Config,
+    cmd: &DaemonCommand,
+    config_path: Option<PathBuf>,
+    verbosity: Verbosity,
+) -> Result<(), Box<dyn std::error::Error>> {
     match cmd {
         DaemonCommand::Start { foreground } => {
-            if *foreground {
-                println!("Starting daemon in foreground mode...");
-            } else {
-                println!("Starting daemon...");
-            }
-            println!("Platform: {}", platform::platform_name());
-            platform::init()?;
-            println!("[Not yet implemented]");
+            start(config, config_path, *foreground, verbosity)?;
         }
         DaemonCommand::Stop { force } => {
             if *force {
-                println!("Force stopping daemon...");
+                force_stop_daemon(&config)?;
             } else {
-                println!("Stopping daemon...");
+                stop_daemon(&config)?;
             }
-            println!("[Not yet implemented]");
         }
         DaemonCommand::Restart { foreground } => {
-            println!(
-                "Restarting daemon{}...",
-                if *foreground { " in foreground" } else { "" }
-            );
-            println!("[Not yet implemented]");
+            if stop_daemon(&config)? {
+                wait_for_daemon_exit(&config)?;
+            }
+            start(config, config_path, *foreground, verbosity)?;
         }
+        DaemonCommand::Reload => reload_daemon(&config)?,
         DaemonCommand::Install { start } => {
-            println!("Installing daemon as system service...");
-            if *start {
-                println!("Will start after installation.");
+            install_service(&config, config_path.as_deref(), *start)?;
+        }
+        DaemonCommand::Uninstall { stop } => uninstall_service(*stop)?,
+    }
+    Ok(())
+}
+
+/// Install the daemon as a systemd user service, optionally starting it.
+#[cfg(target_os = "linux")]
+fn install_service(
+    config: &Config,
+    config_path: Option<&Path>,
+    start: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use platform::systemd::{self, ServiceUnit, Systemctl, UNIT_NAME};
+
+    // The service could not take the PID file lock from a daemon started by hand.
+    let pid_path = config.pid_file_path();
+    if start {
+        if let Some(pid) = PidFile::owner(&pid_path)? {
+            return Err(Error::DaemonAlreadyRunning {
+                pid: Some(pid),
+                path: pid_path,
             }
-            println!("[Not yet implemented]");
+            .into());
+        }
+    }
+
+    let mut unit = ServiceUnit::new(std::env::current_exe()?);
+    if let Some(path) = config_path {
+        unit = unit.with_config_path(path);
+    }
+    let unit_path = unit.install(&systemd::user_unit_dir()?)?;
+    println!("Installed {}", unit_path.display());
+
+    let systemctl = Systemctl::new();
+    systemctl.daemon_reload()?;
+    if start {
+        systemctl.enable(UNIT_NAME, true)?;
+        println!("Enabled and started {UNIT_NAME}.");
+    } else {
+        println!("Start it with `systemctl --user enable --now {UNIT_NAME}`.");
+    }
+    Ok(())
+}
+
+/// Remove the systemd user service, optionally stopping it first.
+#[cfg(target_os = "linux")]
+fn uninstall_service(stop: bool) -> Result<(), Box<dyn std::error::Error>> {
+    use platform::systemd::{self, Systemctl, UNIT_NAME};
+
+    let dir = systemd::user_unit_dir()?;
+    if !dir.join(UNIT_NAME).exists() {
+        println!("{UNIT_NAME} is not installed.");
+        return Ok(());
+    }
+
+    let systemctl = Systemctl::new();
+    systemctl.disable(UNIT_NAME, stop)?;
+    systemd::uninstall(&dir)?;
+    systemctl.daemon_reload()?;
+    if stop {
+        println!("Stopped and removed {UNIT_NAME}.");
+    } else {
+        println!("Removed {UNIT_NAME}; a running daemon keeps running until stopped.");
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn install_service(
+    _config: &Config,
+    _config_path: Option<&Path>,
+    _start: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err(Error::platform(format!(
+        "service installation is not supported on {}",
+        platform::platform_name()
+    ))
+    .into())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn uninstall_service(_stop: bool) -> Result<(), Box<dyn std::error::Error>> {
+    Err(Error::platform(format!(
+        "service installation is not supported on {}",
+        platform::platform_name()
+    ))
+    .into())
+}
+
+/// How long `daemon restart` waits for the old daemon to exit.
+const DAEMON_EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long `daemon stop --force` waits after SIGTERM before sending SIGKILL.
+const FORCE_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Start the daemon, detaching from the terminal unless `foreground` is set.
+fn start(
+    config: Config,
+    config_path: Option<PathBuf>,
+    foreground: bool,
+    verbosity: Verbosity,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if foreground {
+        return start_daemon(config, config_path, None);
+    }
+
+    // Fail early rather than leaving the error in the daemon's log.
+    let pid_path = config.pid_file_path();
+    if let Some(pid) = PidFile::owner(&pid_path)? {
+        return Err(Error::DaemonAlreadyRunning {
+            pid: Some(pid),
+            path: pid_path,
+        }
+        .into());
+    }
+
+    let log_path = config.log_file_path();
+    match daemon::detach(&log_path)? {
+        Detached::Parent { pid } => {
+            println!("Daemon started (pid {pid}).");
+            println!("Socket: {}", config.socket_path().display());
+            println!("Log:    {}", log_path.display());
+            Ok(())
+        }
+        Detached::Daemon(notifier) => {
+            init_logging(verbosity);
+            start_daemon(config, config_path, Some(notifier))
         }
-        DaemonCommand::Uninstall { stop } => {
-            if *stop {
-                println!("Stopping and uninstalling daemon...");
+    }
+}
+
+/// Run the daemon in this process until it is shut down.
+///
+/// `config_path` is re-read when the daemon reloads. If `notifier` is set, it
+/// is told once the daemon is ready.
+fn start_daemon(
+    config: Config,
+    config_path: Option<PathBuf>,
+    notifier: Option<ReadyNotifier>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Starting daemon on {}...", platform::platform_name());
+    platform::init()?;
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let mut daemon = Daemon::new(config)?.with_config_path(config_path);
+        if let Some(notifier) = notifier {
+            daemon = daemon.on_ready(move || notifier.ready());
+        }
+        daemon.run().await
+    })?;
+    Ok(())
+}
+
+/// Ask a running daemon to shut down. Returns `false` if none was running.
+fn stop_daemon(config: &Config) -> Result<bool, Error> {
+    match IpcClient::new(config.socket_path()).request(&Request::Shutdown) {
+        Ok(_) => {
+            println!("Daemon is shutting down.");
+            Ok(true)
+        }
+        Err(e) if e.is_daemon_not_running() => {
+            match PidFile::owner(config.pid_file_path())? {
+                Some(pid) => println!(
+                    "Daemon (pid {pid}) is not answering on {}; use --force to stop it.",
+                    config.socket_path().display()
+                ),
+                None => println!("Daemon is not running."),
+            }
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Ask the running daemon to reload its configuration file.
+fn reload_daemon(config: &Config) -> Result<(), Error> {
+    match IpcClient::new(config.socket_path()).request(&Request::Reload)? {
+        Response::Reloaded(summary) => {
+            if summary.changed.is_empty() {
+                println!("Configuration reloaded; nothing changed.");
             } else {
-                println!("Uninstalling daemon...");
+                println!(
+                    "Configuration reloaded; changed: {}.",
+                    summary.changed.join(", ")
+                );
+            }
+            if !summary.restarted.is_empty() {
+                println!(
+                    "Restarted monitors to apply their new settings: {}.",
+                    summary.restarted.join(", ")
+                );
+            }
+            if !summary.restart_required.is_empty() {
+                println!(
+                    "Restart the daemon to apply changes to: {}.",
+                    summary.restart_required.join(", ")
+                );
             }
-            println!("[Not yet implemented]");
+            Ok(())
+        }
+        other => Err(unexpected_response(&other)),
+    }
+}
+
+/// Stop the daemon with SIGTERM, escalating to SIGKILL if it does not exit.
+fn force_stop_daemon(config: &Config) -> Result<(), Error> {
+    let Some(pid) = PidFile::owner(config.pid_file_path())? else {
+        println!("Daemon is not running.");
+        return Ok(());
+    };
+
+    match daemon::terminate(pid, FORCE_STOP_TIMEOUT)? {
+        Termination::NotRunning => println!("Daemon is not running."),
+        Termination::Terminated => println!("Daemon (pid {pid}) stopped."),
+        Termination::Killed => {
+            println!("Daemon (pid {pid}) did not exit after SIGTERM and was killed.");
         }
     }
     Ok(())
 }
 
+/// Wait until the daemon releases its PID file.
+fn wait_for_daemon_exit(config: &Config) -> Result<(), Error> {
+    let deadline = Instant::now() + DAEMON_EXIT_TIMEOUT;
+    while PidFile::owner(config.pid_file_path())?.is_some() {
+        if Instant::now() >= deadline {
+            return Err(Error::Timeout {
+                operation: "waiting for daemon to exit".to_string(),
+            });
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+/// Default number of captures shown by `recover` without `--last`.
+const DEFAULT_RECOVER_LIMIT: usize = 10;
+
 fn handle_status(config: &Config, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let report = if let Some(daemon) = query_daemon_status(config)? {
+        StatusReport::from_daemon(
+            platform::platform_name(),
+            config.database_path(),
+            config.socket_path(),
+            daemon,
+        )
+    } else {
+        // Don't create an empty database just to report on it.
+        let database = config.database_path();
+        let storage = if database.exists() {
+            Some(open_storage(config)?.stats()?)
+        } else {
+            None
+        };
+        StatusReport::offline(
+            platform::platform_name(),
+            database,
+            config.socket_path(),
+            storage,
+        )
+    };
+
     if json {
-        let status = serde_json::json!({
-            "daemon_running": false,
-            "platform": platform::platform_name(),
-            "database_path": config.database_path(),
-            "status": "not_implemented"
-        });
-        println!("{}", serde_json::to_string_pretty(&status)?);
+        println!("{}", serde_json::to_string_pretty(&report)?);
     } else {
-        println!("fliterec status");
-        println!("---------------");
-        println!("Platform:      {}", platform::platform_name());
-        println!("Daemon:        Not running");
-        println!("Database:      {}", config.database_path().display());
-        println!();
-        println!("[Full status not yet implemented]");
+        print!("{}", report.to_text());
     }
     Ok(())
 }
 
-fn handle_search(cmd: &flightrecorder::cli::SearchCommand) {
-    println!("Searching for: \"{}\"", cmd.query);
-    if let Some(app) = &cmd.app {
-        println!("  Filtered by app: {app}");
+/// Open the database, with the configured encryption key if there is one.
+fn open_storage(config: &Config) -> Result<Storage, Error> {
+    Storage::open_with_key(config.database_path(), config.encryption_key()?.as_ref())
+}
+
+/// Ask the daemon for its status, returning `None` if it is not running.
+fn query_daemon_status(config: &Config) -> Result<Option<DaemonStatus>, Error> {
+    match IpcClient::new(config.socket_path()).request(&Request::Status) {
+        Ok(Response::Status(status)) => Ok(Some(status)),
+        Ok(other) => Err(unexpected_response(&other)),
+        Err(e) if e.is_daemon_not_running() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Ask the daemon for captures, reading the database directly if it isn't running.
+fn fetch_captures(
+    config: &Config,
+    request: &Request,
+    local: impl FnOnce(&Storage) -> Result<Vec<Capture>, Error>,
+) -> Result<Vec<Capture>, Error> {
+    let response = fetch(config, request, |storage| {
+        Ok(Response::Captures {
+            captures: local(storage)?,
+        })
+    })?;
+    match response {
+        Response::Captures { captures } => Ok(captures),
+        other => Err(unexpected_response(&other)),
+    }
+}
+
+/// Ask the daemon to change captures, changing the database directly if it
+/// isn't running, and return how many captures changed.
+fn fetch_changed(
+    config: &Config,
+    request: &Request,
+    local: impl FnOnce(&Storage) -> Result<usize, Error>,
+) -> Result<usize, Error> {
+    let response = fetch(config, request, |storage| {
+        Ok(Response::Changed {
+            count: local(storage)?,
+        })
+    })?;
+    match response {
+        Response::Changed { count } => Ok(count),
+        other => Err(unexpected_response(&other)),
+    }
+}
+
+fn unexpected_response(response: &Response) -> Error {
+    Error::ipc(format!("unexpected response: {response:?}"))
+}
+
+/// Send a request to the daemon, running `local` against the database to
+/// produce the response instead if the daemon isn't running.
+fn fetch(
+    config: &Config,
+    request: &Request,
+    local: impl FnOnce(&Storage) -> Result<Response, Error>,
+) -> Result<Response, Error> {
+    match IpcClient::new(config.socket_path()).request(request) {
+        Ok(response) => Ok(response),
+        Err(e) if e.is_daemon_not_running() => {
+            tracing::debug!("Daemon not running, reading database directly");
+            local(&open_storage(config)?)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn print_captures(captures: &[Capture], format: OutputFormat) -> Result<(), Error> {
+    if captures.is_empty() && format != OutputFormat::Json {
+        println!("No captures found.");
+    } else {
+        print!("{}", format_captures(captures, format)?);
     }
-    if let Some(capture_type) = &cmd.capture_type {
-        println!("  Filtered by type: {capture_type:?}");
+    Ok(())
+}
+
+fn handle_search(
+    config: &Config,
+    cmd: &flightrecorder::cli::SearchCommand,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let now = Utc::now();
+    let request = SearchRequest {
+        query: cmd.query.clone(),
+        app: cmd.app.clone(),
+        capture_type: cmd.capture_type.map(Into::into),
+        since: cmd
+            .since
+            .as_deref()
+            .map(|s| parse_time(s, now))
+            .transpose()?,
+        until: cmd
+            .until
+            .as_deref()
+            .map(|s| parse_time(s, now))
+            .transpose()?,
+        tag: cmd.tag.clone(),
+        pinned: cmd.pinned,
+        limit: cmd.limit,
+    };
+
+    let response = fetch(config, &Request::Search(request.clone()), |storage| {
+        Ok(Response::SearchResults {
+            hits: request.execute(storage)?,
+        })
+    })?;
+    let Response::SearchResults { hits } = response else {
+        return Err(unexpected_response(&response).into());
+    };
+    if hits.is_empty() && cmd.format != OutputFormat::Json {
+        println!("No captures found.");
+    } else {
+        print!("{}", format_search_hits(&hits, cmd.format)?);
     }
-    println!("  Limit: {}", cmd.limit);
-    println!("  Format: {:?}", cmd.format);
-    println!();
-    println!("[Search not yet implemented]");
+    Ok(())
 }
 
-fn handle_recover(cmd: &flightrecorder::cli::RecoverCommand) {
+fn handle_recover(
+    config: &Config,
+    cmd: &flightrecorder::cli::RecoverCommand,
+) -> Result<(), Box<dyn std::error::Error>> {
     if cmd.interactive {
         println!("Launching interactive recovery TUI...");
         println!("[Interactive mode not yet implemented]");
-        return;
+        return Ok(());
+    }
+
+    let request = RecoverRequest {
+        app: cmd.app.clone(),
+        since: cmd
+            .since
+            .as_deref()
+            .map(|s| parse_time(s, Utc::now()))
+            .transpose()?,
+        tag: cmd.tag.clone(),
+        pinned: cmd.pinned,
+        limit: cmd.last.unwrap_or(DEFAULT_RECOVER_LIMIT),
+        history: cmd.history,
+    };
+
+    let captures = fetch_captures(config, &Request::Recover(request.clone()), |storage| {
+        request.execute(storage)
+    })?;
+    print_captures(&captures, cmd.format)?;
+
+    if cmd.to_clipboard {
+        println!("[Copy to clipboard not yet implemented]");
+    }
+    Ok(())
+}
+
+/// Print captures as the daemon stores them, until it stops or the output
+/// is closed.
+fn handle_tail(
+    config: &Config,
+    cmd: &flightrecorder::cli::TailCommand,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let filter = SubscribeRequest {
+        app: cmd.app.clone(),
+        capture_type: cmd.capture_type.map(Into::into),
+    };
+    let subscription = IpcClient::new(config.socket_path()).subscribe(&filter)?;
+
+    let mut out = std::io::stdout().lock();
+    let mut print = |text: &str| match out.write_all(text.as_bytes()).and_then(|()| out.flush()) {
+        // The reader went away, as with `fliterec tail | head`.
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(false),
+        Err(e) => Err(e),
+        Ok(()) => Ok(true),
+    };
+
+    if cmd.format == OutputFormat::Table && !print(&table_header())? {
+        return Ok(());
+    }
+    for event in subscription {
+        match event? {
+            Event::Capture { capture } => {
+                if !print(&format_live_capture(&capture, cmd.format)?)? {
+                    return Ok(());
+                }
+            }
+            Event::Lagged { skipped } => {
+                eprintln!("warning: output fell behind; {skipped} captures were skipped");
+            }
+        }
     }
+    Ok(())
+}
 
-    if let Some(n) = cmd.last {
-        println!("Recovering last {n} captures...");
-    } else if let Some(app) = &cmd.app {
-        println!("Recovering captures from app: {app}");
-    } else if let Some(since) = &cmd.since {
-        println!("Recovering captures since: {since}");
+/// Prune the database, through the daemon if it is running so that the
+/// deletes are batched between captures.
+fn handle_prune(config: &Config, cmd: &PruneCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let request = PruneRequest {
+        dry_run: cmd.dry_run,
+    };
+    let response = fetch(config, &Request::Prune(request), |storage| {
+        let policy = config.retention_policy();
+        let report = if cmd.dry_run {
+            storage.plan_prune(&policy)?.report
+        } else {
+            storage.prune(&policy)?
+        };
+        Ok(Response::Pruned(report))
+    })?;
+    let Response::Pruned(report) = response else {
+        return Err(unexpected_response(&response).into());
+    };
+    if cmd.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
     } else {
-        println!("Recovering recent captures...");
+        print!("{}", format_prune_report(&report, cmd.dry_run));
     }
+    Ok(())
+}
 
-    if cmd.to_clipboard {
-        println!("  Will copy to clipboard");
+/// Move captures to the trash.
+fn handle_delete(config: &Config, cmd: &DeleteCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let request = Request::Delete(IdsRequest {
+        ids: cmd.ids.clone(),
+    });
+    let count = fetch_changed(config, &request, |storage| storage.trash(&cmd.ids))?;
+    println!(
+        "Moved {count} capture{} to the trash.",
+        if count == 1 { "" } else { "s" }
+    );
+    if count < cmd.ids.len() {
+        println!("The others were not found or already in the trash.");
+    }
+    Ok(())
+}
+
+fn handle_trash(config: &Config, cmd: &TrashCommand) -> Result<(), Box<dyn std::error::Error>> {
+    match cmd {
+        TrashCommand::List { limit, format } => {
+            let request = Request::ListTrash(ListTrashRequest { limit: *limit });
+            let response = fetch(config, &request, |storage| {
+                Ok(Response::Trash {
+                    trashed: storage.list_trash(*limit)?,
+                })
+            })?;
+            let Response::Trash { trashed } = response else {
+                return Err(unexpected_response(&response).into());
+            };
+            if trashed.is_empty() && *format != OutputFormat::Json {
+                println!("The trash is empty.");
+            } else {
+                print!("{}", format_trash(&trashed, *format)?);
+            }
+        }
+        TrashCommand::Restore { ids } => {
+            let request = Request::Restore(IdsRequest { ids: ids.clone() });
+            let count = fetch_changed(config, &request, |storage| storage.restore(ids))?;
+            println!(
+                "Restored {count} capture{}.",
+                if count == 1 { "" } else { "s" }
+            );
+            if count < ids.len() {
+                println!("The others were not in the trash.");
+            }
+        }
+        TrashCommand::Empty { yes } => {
+            if !yes {
+                println!("This will delete every capture in the trash for good.");
+                println!("Use --yes to confirm.");
+                return Ok(());
+            }
+            let count = fetch_changed(config, &Request::EmptyTrash, Storage::empty_trash)?;
+            println!(
+                "Deleted {count} capture{} for good.",
+                if count == 1 { "" } else { "s" }
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Pin or unpin a capture.
+fn handle_pin(config: &Config, cmd: &PinCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let pinned = !cmd.unpin;
+    let request = Request::Pin(PinRequest { id: cmd.id, pinned });
+    let found = fetch_changed(config, &request, |storage| {
+        Ok(usize::from(storage.pin(cmd.id, pinned)?))
+    })?;
+    require_capture(cmd.id, found)?;
+    if pinned {
+        println!("Pinned capture #{}; pruning will keep it.", cmd.id);
+    } else {
+        println!("Unpinned capture #{}.", cmd.id);
+    }
+    Ok(())
+}
+
+/// Add or remove tags on a capture.
+fn handle_tag(config: &Config, cmd: &TagCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let request = Request::Tag(TagRequest {
+        id: cmd.id,
+        tags: cmd.tags.clone(),
+        remove: cmd.remove,
+    });
+    let found = fetch_changed(config, &request, |storage| {
+        let found = if cmd.remove {
+            storage.remove_tags(cmd.id, &cmd.tags)?
+        } else {
+            storage.add_tags(cmd.id, &cmd.tags)?
+        };
+        Ok(usize::from(found))
+    })?;
+    require_capture(cmd.id, found)?;
+    let tags = cmd.tags.join(", ");
+    if cmd.remove {
+        println!("Removed {tags} from capture #{}.", cmd.id);
+    } else {
+        println!("Tagged capture #{} with {tags}.", cmd.id);
+    }
+    Ok(())
+}
+
+/// Set or clear the note on a capture.
+fn handle_note(config: &Config, cmd: &NoteCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let note = if cmd.clear { None } else { cmd.text.clone() };
+    let request = Request::Note(NoteRequest {
+        id: cmd.id,
+        note: note.clone(),
+    });
+    let found = fetch_changed(config, &request, |storage| {
+        Ok(usize::from(storage.set_note(cmd.id, note.as_deref())?))
+    })?;
+    require_capture(cmd.id, found)?;
+    if note.is_some() {
+        println!("Noted capture #{}.", cmd.id);
+    } else {
+        println!("Cleared the note on capture #{}.", cmd.id);
+    }
+    Ok(())
+}
+
+/// Fail unless a change to capture `id` found it.
+fn require_capture(id: i64, changed: usize) -> Result<(), Error> {
+    if changed == 0 {
+        return Err(Error::InvalidArgument {
+            message: format!("no capture #{id}, or it is in the trash"),
+        });
+    }
+    Ok(())
+}
+
+fn handle_db(config: &Config, cmd: &DbCommand) -> Result<(), Box<dyn std::error::Error>> {
+    match cmd {
+        DbCommand::Rekey(rekey) => handle_rekey(config, rekey),
+        DbCommand::Check(check) => handle_check(config, check),
+        DbCommand::Backup(backup) => handle_backup(config, backup),
+        DbCommand::Restore(restore) => handle_restore(config, restore),
+    }
+}
+
+/// Fail if the daemon is running, for database maintenance that cannot run
+/// alongside it.
+fn require_daemon_stopped(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(pid) = PidFile::owner(config.pid_file_path())? {
+        return Err(format!(
+            "the daemon (pid {pid}) is running; stop it with `fliterec daemon stop` first"
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Encrypt, rekey or decrypt the database. The daemon must be stopped, since
+/// it holds the old key.
+fn handle_rekey(config: &Config, cmd: &RekeyCommand) -> Result<(), Box<dyn std::error::Error>> {
+    require_daemon_stopped(config)?;
+    let key = if let Some(path) = &cmd.key_file {
+        Some(EncryptionKey::key_file(path))
+    } else if let Some(var) = &cmd.passphrase_env {
+        let passphrase = std::env::var(var).map_err(|_| Error::InvalidArgument {
+            message: format!("${var} is not set"),
+        })?;
+        Some(EncryptionKey::passphrase(passphrase))
+    } else {
+        None
+    };
+
+    let mut storage = open_storage(config)?;
+    let was_encrypted = storage.is_encrypted();
+    storage.rekey(key.as_ref(), cmd.rotate_data_key)?;
+
+    let database = config.database_path();
+    match (&key, was_encrypted) {
+        (None, _) => println!("Decrypted {}.", database.display()),
+        (Some(_), false) => println!("Encrypted {}.", database.display()),
+        (Some(_), true) if cmd.rotate_data_key => {
+            println!("Re-encrypted {} with a new data key.", database.display());
+        }
+        (Some(_), true) => println!("Changed the key of {}.", database.display()),
+    }
+    let change = match (&cmd.key_file, &cmd.passphrase_env) {
+        (Some(path), _) => format!("Set key_file = \"{}\" in", path.display()),
+        (_, Some(var)) => format!("Set passphrase_env = \"{var}\" in"),
+        _ => "Remove key_file and passphrase_env from".to_string(),
+    };
+    println!("{change} the [storage] configuration before starting the daemon.");
+    Ok(())
+}
+
+/// Check the database for damage, and repair what can be repaired. Checking
+/// reads the database alongside the daemon, through a read-only connection;
+/// repairing needs it stopped.
+fn handle_check(config: &Config, cmd: &CheckCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let storage = if cmd.repair {
+        require_daemon_stopped(config)?;
+        open_storage(config)?
+    } else {
+        Storage::open_read_only(config.database_path(), config.encryption_key()?.as_ref())?
+    };
+    let report = storage.check()?;
+    let repaired = if cmd.repair && report.needs_repair() {
+        Some(storage.repair(&report)?)
+    } else {
+        None
+    };
+
+    if cmd.json {
+        let output = serde_json::json!({ "check": report, "repair": repaired });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+    } else {
+        print!("{}", format_check_report(&report, repaired.as_ref()));
+    }
+    if !report.integrity_errors.is_empty() {
+        return Err("the database is damaged".into());
+    }
+    if report.needs_repair() && repaired.is_none() {
+        return Err("the database has damaged rows; run `fliterec db check --repair`".into());
+    }
+    Ok(())
+}
+
+/// Back up the database, through the daemon if it is running.
+fn handle_backup(config: &Config, cmd: &BackupCommand) -> Result<(), Box<dyn std::error::Error>> {
+    // The daemon may run in another directory, so send it an absolute path.
+    let path = std::env::current_dir()?.join(&cmd.path);
+    let request = Request::Backup(BackupRequest { path: path.clone() });
+    let response = fetch(config, &request, |storage| {
+        Ok(Response::BackedUp(storage.backup(&path)?))
+    })?;
+    match response {
+        Response::BackedUp(info) => print!("{}", format_backup(&info)),
+        other => return Err(unexpected_response(&other).into()),
+    }
+    Ok(())
+}
+
+/// Replace the database with a backup, stopping the daemon first.
+fn handle_restore(config: &Config, cmd: &RestoreCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let database = config.database_path();
+    if !cmd.path.is_file() {
+        return Err(Error::InvalidArgument {
+            message: format!("no backup at {}", cmd.path.display()),
+        }
+        .into());
+    }
+    if !cmd.yes {
+        println!(
+            "This will replace {} with {}, stopping the daemon if it is running.",
+            database.display(),
+            cmd.path.display()
+        );
+        println!("Use --yes to confirm.");
+        return Ok(());
+    }
+
+    let was_running = PidFile::owner(config.pid_file_path())?.is_some();
+    if was_running && stop_daemon(config)? {
+        wait_for_daemon_exit(config)?;
+    }
+    require_daemon_stopped(config)?;
+
+    let key = config.encryption_key()?;
+    let restored = Storage::restore_backup(&cmd.path, &database, key.as_ref())?;
+    // Opening the database migrates an older schema to the current one.
+    open_storage(config)?;
+    println!(
+        "Restored {} from {}.",
+        database.display(),
+        cmd.path.display()
+    );
+    if let Some(previous) = &restored.previous {
+        println!(
+            "The database it replaced was saved to {}.",
+            previous.display()
+        );
+    }
+    if was_running {
+        println!("Start the daemon again with `fliterec daemon start`.");
+    }
+    Ok(())
+}
+
+/// Print the effective retention policy, with the maximum age of each kind
+/// of capture in the order the rules are matched.
+fn print_retention(config: &Config) {
+    let policy = config.retention_policy();
+    let or_unlimited = |limit: Option<String>| limit.unwrap_or_else(|| "unlimited".to_string());
+    println!("[Retention]");
+    println!(
+        "  Max captures:       {}",
+        or_unlimited(policy.max_captures.map(|max| max.to_string()))
+    );
+    println!(
+        "  Max size:           {}",
+        or_unlimited(
+            policy
+                .max_size_bytes
+                .map(|_| format!("{} MB", config.storage.max_db_size_mb))
+        )
+    );
+    match config.storage.prune_interval_hours {
+        0 => println!("  Prune every:        never (only `fliterec prune`)"),
+        hours => println!("  Prune every:        {hours} h"),
+    }
+    match config.storage.trash_grace_days {
+        0 => println!("  Keep in trash:      no (delete at once)"),
+        days => println!("  Keep in trash:      {days} days"),
+    }
+    println!("  Max age (first match applies):");
+    let age = |age: Option<chrono::Duration>| {
+        age.map_or_else(
+            || "forever".to_string(),
+            |age| format!("{} days", age.num_days()),
+        )
+    };
+    let rows: Vec<(String, String)> = policy
+        .rules
+        .iter()
+        .map(|rule| (rule.to_string(), age(rule.max_age)))
+        .chain([("other captures".to_string(), age(policy.max_age))])
+        .collect();
+    let width = rows.iter().map(|(kind, _)| kind.len()).max().unwrap_or(0);
+    for (kind, age) in rows {
+        println!("    {kind:<width$}  {age}");
     }
-    println!("  Format: {:?}", cmd.format);
-    println!();
-    println!("[Recovery not yet implemented]");
 }
 
 fn handle_config(config: &Config, cmd: ConfigCommand) -> Result<(), Box<dyn std::error::Error>> {
@@ -160,8 +954,22 @@ fn handle_config(config: &Config, cmd: ConfigCommand) -> Result<(), Box<dyn std:
                 println!();
                 println!("[Storage]");
                 println!("  Database path:      {}", config.database_path().display());
-                println!("  Max captures:       {}", config.storage.max_captures);
-                println!("  Max age (days):     {}", config.storage.max_age_days);
+                let encryption = match (&config.storage.key_file, &config.storage.passphrase_env) {
+                    (Some(path), _) => format!("key file {}", path.display()),
+                    (_, Some(var)) => format!("passphrase from ${var}"),
+                    _ => "off".to_string(),
+                };
+                println!("  Encryption:         {encryption}");
+                match config.storage.backup_interval_hours {
+                    0 => println!("  Backups:            off (only `fliterec db backup`)"),
+                    hours => println!(
+                        "  Backups:            every {hours} h, keeping {} in {}",
+                        config.storage.backup_count,
+                        config.backup_dir().display()
+                    ),
+                }
+                println!();
+                print_retention(config);
                 println!();
                 println!("[Capture]");
                 println!("  Clipboard:          {}", config.capture.clipboard_enabled);
@@ -3,8 +3,11 @@
 //! This module defines the core traits and types for capture monitoring
 //! that platform-specific implementations must fulfill.
 
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::sync::mpsc;
 
@@ -42,7 +45,8 @@ pub enum MonitorError {
 pub type Result<T> = std::result::Result<T, MonitorError>;
 
 /// The type of capture source.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum MonitorType {
     /// Monitors clipboard changes.
     Clipboard,
@@ -95,8 +99,11 @@ impl Default for MonitorConfig {
     }
 }
 
+/// Start of [`MonitorStatus::message`] for a monitor that keeps failing.
+pub const DEGRADED_PREFIX: &str = "Degraded:";
+
 /// Status of a capture monitor.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MonitorStatus {
     /// The type of monitor.
     pub monitor_type: MonitorType,
@@ -139,6 +146,13 @@ impl MonitorStatus {
         }
     }
 
+    /// Check if the daemon has marked the monitor as degraded after repeated
+    /// failures.
+    #[must_use]
+    pub fn is_degraded(&self) -> bool {
+        self.message.starts_with(DEGRADED_PREFIX)
+    }
+
     /// Create a status indicating missing permissions.
     #[must_use]
     pub fn permission_required(monitor_type: MonitorType, message: &str) -> Self {
@@ -187,6 +201,51 @@ pub trait CaptureMonitor: Send + Sync {
     ///
     /// Returns an error if the monitor fails to stop cleanly.
     fn stop(&self) -> Result<()>;
+
+    /// Get the heartbeat this monitor beats on every iteration of its poll
+    /// loop, if it has one.
+    ///
+    /// The daemon restarts a monitor whose heartbeat stops, so monitors that
+    /// poll should provide one. The default is `None`, which disables the
+    /// check.
+    fn heartbeat(&self) -> Option<Heartbeat> {
+        None
+    }
+}
+
+/// A liveness signal from a monitor's poll loop.
+///
+/// Clones share the same counter: the monitor calls [`beat`](Self::beat) and
+/// the daemon watches [`count`](Self::count) for progress.
+#[derive(Debug, Clone, Default)]
+pub struct Heartbeat {
+    beats: Arc<AtomicU64>,
+}
+
+impl Heartbeat {
+    /// Create a heartbeat that has not beaten yet.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record one iteration of the poll loop.
+    pub fn beat(&self) {
+        self.beats.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get the number of beats so far.
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.beats.load(Ordering::Relaxed)
+    }
+}
+
+impl From<Arc<AtomicU64>> for Heartbeat {
+    /// Watch a counter that a platform monitor increments as it polls.
+    fn from(beats: Arc<AtomicU64>) -> Self {
+        Self { beats }
+    }
 }
 
 /// A handle to control capture monitors.
@@ -291,6 +350,16 @@ mod tests {
         assert!(config.enabled);
     }
 
+    #[test]
+    fn test_heartbeat_shared_between_clones() {
+        let heartbeat = Heartbeat::new();
+        let clone = heartbeat.clone();
+        assert_eq!(heartbeat.count(), 0);
+        clone.beat();
+        clone.beat();
+        assert_eq!(heartbeat.count(), 2);
+    }
+
     #[test]
     fn test_monitor_status_stopped() {
         let status = MonitorStatus::stopped(MonitorType::Clipboard);
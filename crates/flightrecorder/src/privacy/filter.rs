@@ -82,6 +82,17 @@ impl Default for FilterConfig {
     }
 }
 
+impl From<&crate::config::PrivacyConfig> for FilterConfig {
+    fn from(config: &crate::config::PrivacyConfig) -> Self {
+        Self {
+            enabled: config.filters_enabled,
+            custom_patterns: config.filter_patterns.clone(),
+            excluded_apps: config.excluded_apps.clone(),
+            ..Self::default()
+        }
+    }
+}
+
 /// Privacy filter for captured content.
 #[derive(Debug)]
 pub struct PrivacyFilter {
@@ -271,6 +282,21 @@ impl Default for PrivacyFilter {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_filter_config_from_privacy_config() {
+        let privacy = crate::config::PrivacyConfig {
+            filters_enabled: false,
+            excluded_apps: vec!["Vault".to_string()],
+            ..Default::default()
+        };
+
+        let config = FilterConfig::from(&privacy);
+        assert!(!config.enabled);
+        assert!(config.use_builtin_patterns);
+        assert_eq!(config.excluded_apps, vec!["Vault".to_string()]);
+        assert_eq!(config.custom_patterns, privacy.filter_patterns);
+    }
+
     #[test]
     fn test_filter_result_passed() {
         let result = FilterResult::Passed;
@@ -0,0 +1,258 @@
+//! Pins, tags and notes.
+//!
+//! A capture can be pinned, which exempts it from pruning, tagged, and given
+//! a free-text note. The pin and the note are columns of `captures`; tags are
+//! rows of `capture_tags`, deleted along with their capture. Only captures
+//! outside the trash can be annotated.
+//!
+//! In an encrypted database, notes and tags are encrypted like content: they
+//! are stored as blobs, where a note or tag in the clear is text. Each tag is
+//! stored with its hash, a keyed MAC in an encrypted database, by which tags
+//! are added, removed and filtered on.
+
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::crypto::{self, Keys};
+use crate::capture::Capture;
+use crate::error::{Error, Result};
+
+/// Check that `tag` is one word, returning it without surrounding spaces.
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidArgument`] for an empty tag or one containing
+/// whitespace.
+pub(crate) fn normalize_tag(tag: &str) -> Result<&str> {
+    let tag = tag.trim();
+    if tag.is_empty() || tag.contains(char::is_whitespace) {
+        return Err(Error::InvalidArgument {
+            message: format!("tags must be a single word, not {tag:?}"),
+        });
+    }
+    Ok(tag)
+}
+
+/// The hash stored with `tag`: a keyed MAC when the database is encrypted.
+pub(crate) fn tag_hash(keys: Option<&Keys>, tag: &str) -> String {
+    keys.map_or_else(|| Capture::compute_hash(tag), |keys| keys.tag_mac(tag))
+}
+
+/// The value stored for a note or tag in `column` of capture `id`,
+/// encrypted with `keys` if given.
+fn seal(keys: Option<&Keys>, id: i64, column: &str, text: &str) -> Result<Value> {
+    Ok(match keys {
+        Some(keys) => Value::Blob(keys.encrypt(id, column, text.as_bytes())?),
+        None => Value::Text(text.to_string()),
+    })
+}
+
+/// Read a note or tag stored in the column `name` of capture `id`,
+/// decrypting it with `keys` if it is encrypted. `column` is the index of
+/// the column, for error reports.
+fn unseal(
+    value: ValueRef<'_>,
+    keys: Option<&Keys>,
+    id: i64,
+    name: &str,
+    column: usize,
+) -> rusqlite::Result<String> {
+    match value {
+        ValueRef::Blob(_) => {
+            let bytes = crypto::decrypt_column(value, keys, id, name, column)?;
+            String::from_utf8(bytes).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    column,
+                    rusqlite::types::Type::Blob,
+                    Box::new(e),
+                )
+            })
+        }
+        value => Ok(value.as_str()?.to_string()),
+    }
+}
+
+/// Read the note of capture `id`, which may be missing.
+pub(crate) fn unseal_note(
+    value: ValueRef<'_>,
+    keys: Option<&Keys>,
+    id: i64,
+    column: usize,
+) -> rusqlite::Result<Option<String>> {
+    match value {
+        ValueRef::Null => Ok(None),
+        value => unseal(value, keys, id, "note", column).map(Some),
+    }
+}
+
+/// Whether a capture exists outside the trash.
+fn exists(conn: &Connection, id: i64) -> Result<bool> {
+    let found = conn
+        .query_row(
+            "SELECT 1 FROM captures WHERE id = ?1 AND trashed_at IS NULL",
+            [id],
+            |_| Ok(()),
+        )
+        .optional()?;
+    Ok(found.is_some())
+}
+
+/// Pin or unpin a capture, returning whether it was found.
+pub(crate) fn pin(conn: &Connection, id: i64, pinned: bool) -> Result<bool> {
+    let updated = conn.execute(
+        "UPDATE captures SET pinned = ?2 WHERE id = ?1 AND trashed_at IS NULL",
+        params![id, pinned],
+    )?;
+    Ok(updated > 0)
+}
+
+/// Add tags to a capture, returning whether it was found. Tags it already
+/// has are ignored.
+pub(crate) fn add_tags(
+    conn: &Connection,
+    keys: Option<&Keys>,
+    id: i64,
+    tags: &[String],
+) -> Result<bool> {
+    let tags = tags
+        .iter()
+        .map(|tag| normalize_tag(tag))
+        .collect::<Result<Vec<_>>>()?;
+    let tx = conn.unchecked_transaction()?;
+    if !exists(&tx, id)? {
+        return Ok(false);
+    }
+    let mut insert = tx.prepare(
+        "INSERT OR IGNORE INTO capture_tags (capture_id, tag_hash, tag) VALUES (?1, ?2, ?3)",
+    )?;
+    for tag in tags {
+        insert.execute(params![
+            id,
+            tag_hash(keys, tag),
+            seal(keys, id, "tag", tag)?
+        ])?;
+    }
+    drop(insert);
+    tx.commit()?;
+    Ok(true)
+}
+
+/// Remove tags from a capture, returning whether it was found. Tags it does
+/// not have are ignored.
+pub(crate) fn remove_tags(
+    conn: &Connection,
+    keys: Option<&Keys>,
+    id: i64,
+    tags: &[String],
+) -> Result<bool> {
+    let tx = conn.unchecked_transaction()?;
+    if !exists(&tx, id)? {
+        return Ok(false);
+    }
+    let mut delete =
+        tx.prepare("DELETE FROM capture_tags WHERE capture_id = ?1 AND tag_hash = ?2")?;
+    for tag in tags {
+        delete.execute(params![id, tag_hash(keys, tag.trim())])?;
+    }
+    drop(delete);
+    tx.commit()?;
+    Ok(true)
+}
+
+/// Set or clear the note on a capture, returning whether it was found.
+pub(crate) fn set_note(
+    conn: &Connection,
+    keys: Option<&Keys>,
+    id: i64,
+    note: Option<&str>,
+) -> Result<bool> {
+    let note = note
+        .map(str::trim)
+        .filter(|note| !note.is_empty())
+        .map(|note| seal(keys, id, "note", note))
+        .transpose()?;
+    let updated = conn.execute(
+        "UPDATE captures SET note = ?2 WHERE id = ?1 AND trashed_at IS NULL",
+        params![id, note],
+    )?;
+    Ok(updated > 0)
+}
+
+/// Load the tags of a capture, in alphabetical order.
+pub(crate) fn load_tags(
+    conn: &Connection,
+    keys: Option<&Keys>,
+    id: i64,
+) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare_cached("SELECT tag FROM capture_tags WHERE capture_id = ?1")?;
+    let mut tags = stmt
+        .query_map([id], |row| unseal(row.get_ref(0)?, keys, id, "tag", 0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    tags.sort();
+    Ok(tags)
+}
+
+/// Store every note and tag again for `keys` (or in the clear), reading
+/// them with `old`.
+pub(crate) fn reencrypt(conn: &Connection, old: Option<&Keys>, keys: Option<&Keys>) -> Result<()> {
+    let notes: Vec<(i64, String)> = conn
+        .prepare("SELECT id, note FROM captures WHERE note IS NOT NULL")?
+        .query_map([], |row| {
+            let id = row.get(0)?;
+            Ok((id, unseal(row.get_ref(1)?, old, id, "note", 1)?))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut update = conn.prepare("UPDATE captures SET note = ?2 WHERE id = ?1")?;
+    for (id, note) in notes {
+        update.execute(params![id, seal(keys, id, "note", &note)?])?;
+    }
+
+    let tags: Vec<(i64, String)> = conn
+        .prepare("SELECT capture_id, tag FROM capture_tags")?
+        .query_map([], |row| {
+            let id = row.get(0)?;
+            Ok((id, unseal(row.get_ref(1)?, old, id, "tag", 1)?))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    conn.execute("DELETE FROM capture_tags", [])?;
+    let mut insert = conn.prepare(
+        "INSERT OR IGNORE INTO capture_tags (capture_id, tag_hash, tag) VALUES (?1, ?2, ?3)",
+    )?;
+    for (id, tag) in tags {
+        insert.execute(params![
+            id,
+            tag_hash(keys, &tag),
+            seal(keys, id, "tag", &tag)?
+        ])?;
+    }
+    Ok(())
+}
+
+/// Encrypt the notes and tags still stored in the clear in an encrypted
+/// database, as written before they were encrypted.
+pub(crate) fn encrypt_plain(conn: &Connection, keys: &Keys) -> Result<()> {
+    let plain: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM captures WHERE typeof(note) = 'text')
+             OR EXISTS (SELECT 1 FROM capture_tags WHERE typeof(tag) = 'text')",
+        [],
+        |row| row.get(0),
+    )?;
+    if plain {
+        let tx = conn.unchecked_transaction()?;
+        reencrypt(&tx, Some(keys), Some(keys))?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_tag() {
+        assert_eq!(normalize_tag(" incident ").unwrap(), "incident");
+        assert!(normalize_tag("").is_err());
+        assert!(normalize_tag("two words").is_err());
+    }
+}
@@ -0,0 +1,329 @@
+//! Backups of the database file.
+//!
+//! Backups are made with the `SQLite` online backup API from any connection
+//! to the database, including a read-only one. Pages are copied a few
+//! hundred at a time with a short pause between steps, so the daemon can
+//! keep writing while a backup runs. A write through another connection
+//! restarts the copy, so a backup is still a consistent snapshot. A backup
+//! is written next to its destination first and renamed into place, so an
+//! interrupted backup never leaves a partial file behind under the final
+//! name.
+//!
+//! Restoring copies a backup back over the database the same way, after
+//! checking that it is a flightrecorder database this release can open,
+//! that it passes the `SQLite` integrity check and that the configured key
+//! unlocks it. The database it replaces is backed up first.
+//!
+//! Scheduled backups go to a directory of their own, named after the
+//! database and the time they were made, and the oldest are removed beyond
+//! the number to keep. Backups hold the captures as stored, encrypted or
+//! not, including those in the trash.
+
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rusqlite::backup::Backup;
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::error::{Error, Result};
+
+use super::crypto::{self, EncryptionKey};
+use super::{check, migrations};
+
+/// Format of the time in the file name of a scheduled backup, to the
+/// millisecond. It sorts in the order the backups were made.
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// Number of pages copied in each step of a backup.
+const PAGES_PER_STEP: i32 = 256;
+
+/// Pause between the steps of a backup, in which the database is not locked.
+const STEP_PAUSE: Duration = Duration::from_millis(10);
+
+/// A backup written to a file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupInfo {
+    /// Where the backup was written.
+    pub path: PathBuf,
+    /// Size of the backup in bytes.
+    pub size_bytes: u64,
+    /// Older scheduled backups removed to keep the number configured.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<PathBuf>,
+}
+
+/// A database restored from a backup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestoreInfo {
+    /// Schema version of the backup, which is migrated to the current
+    /// version when the database is next opened.
+    pub schema_version: i32,
+    /// Where the database the backup replaced was saved, if there was one.
+    pub previous: Option<PathBuf>,
+}
+
+/// Copy the database of `conn` to `path`, replacing any file there.
+/// Returns the size of the backup in bytes.
+pub(crate) fn write(conn: &Connection, path: &Path) -> Result<u64> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|source| Error::DirectoryCreate {
+            path: parent.to_path_buf(),
+            source,
+        })?;
+    }
+    let partial = with_suffix(path, ".partial");
+    remove_if_exists(&partial)?;
+    let result = open(&partial).and_then(|mut dest| copy(conn, &mut dest));
+    if let Err(e) = result {
+        let _ = remove_if_exists(&partial);
+        return Err(e);
+    }
+    std::fs::rename(&partial, path)?;
+    Ok(std::fs::metadata(path)?.len())
+}
+
+/// Write a scheduled backup of the database at `database` into `dir`, then
+/// remove the oldest scheduled backups there beyond `keep`.
+pub(crate) fn write_rotating(
+    conn: &Connection,
+    database: &Path,
+    dir: &Path,
+    keep: usize,
+    now: DateTime<Utc>,
+) -> Result<BackupInfo> {
+    let stem = scheduled_stem(database);
+    let path = reserve(dir, &stem, now)?;
+    let size_bytes = match write(conn, &path) {
+        Ok(size_bytes) => size_bytes,
+        Err(e) => {
+            let _ = remove_if_exists(&path);
+            return Err(e);
+        }
+    };
+    let removed = rotate(dir, &stem, keep.max(1))?;
+    info!(path = %path.display(), size_bytes, "Backed up the database");
+    Ok(BackupInfo {
+        path,
+        size_bytes,
+        removed,
+    })
+}
+
+/// When the newest scheduled backup of the database at `database` in `dir`
+/// was made, if there is one.
+pub(crate) fn latest(database: &Path, dir: &Path) -> Result<Option<DateTime<Utc>>> {
+    if !dir.exists() {
+        return Ok(None);
+    }
+    let backups = scheduled(dir, &scheduled_stem(database))?;
+    Ok(backups.last().map(|(made, _)| *made))
+}
+
+/// Replace the database at `database` with the backup at `backup`, which
+/// must be unlocked by `key` if it is encrypted.
+pub(crate) fn restore(
+    backup: &Path,
+    database: &Path,
+    key: Option<&EncryptionKey>,
+) -> Result<RestoreInfo> {
+    let source = Connection::open_with_flags(backup, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(
+        |source| Error::DatabaseOpen {
+            path: backup.to_path_buf(),
+            source,
+        },
+    )?;
+    let schema_version = migrations::check_schema_version(&source)?;
+    if let Some(error) = check::integrity_errors(&source)?.into_iter().next() {
+        return Err(Error::InvalidArgument {
+            message: format!("{} is damaged: {error}", backup.display()),
+        });
+    }
+    if crypto::is_encrypted(&source)? {
+        let key = key.ok_or_else(|| Error::DatabaseLocked {
+            message: "the backup is encrypted and no key is configured".to_string(),
+        })?;
+        crypto::unlock(&source, key)?;
+    }
+
+    let previous = if database.exists() {
+        let previous = with_suffix(database, ".before-restore.bak");
+        write(&open(database)?, &previous)?;
+        Some(previous)
+    } else {
+        None
+    };
+    copy(&source, &mut open(database)?)?;
+    info!(
+        backup = %backup.display(),
+        database = %database.display(),
+        "Restored the database from a backup"
+    );
+    Ok(RestoreInfo {
+        schema_version,
+        previous,
+    })
+}
+
+/// Copy every page of `source` to `dest`, [`PAGES_PER_STEP`] at a time.
+fn copy(source: &Connection, dest: &mut Connection) -> Result<()> {
+    let backup = Backup::new(source, dest)?;
+    backup.run_to_completion(PAGES_PER_STEP, STEP_PAUSE, None)?;
+    Ok(())
+}
+
+/// Open a connection to write a backup to, or to restore one into.
+fn open(path: &Path) -> Result<Connection> {
+    Connection::open(path).map_err(|source| Error::DatabaseOpen {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Claim the file name of a scheduled backup made at `now` in `dir` by
+/// creating an empty file under it, which the backup then replaces. If
+/// another backup already has the name, the time is moved on a millisecond
+/// at a time until a name is free, so that backups made at once never
+/// overwrite each other.
+fn reserve(dir: &Path, stem: &str, mut now: DateTime<Utc>) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir).map_err(|source| Error::DirectoryCreate {
+        path: dir.to_path_buf(),
+        source,
+    })?;
+    loop {
+        let path = dir.join(format!("{stem}{}.db", now.format(TIMESTAMP_FORMAT)));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                now += chrono::Duration::milliseconds(1);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Remove the oldest scheduled backups in `dir` named after `stem`, beyond
+/// `keep`. Returns the paths removed.
+fn rotate(dir: &Path, stem: &str, keep: usize) -> Result<Vec<PathBuf>> {
+    let backups = scheduled(dir, stem)?;
+    let excess = backups.len().saturating_sub(keep);
+    let mut removed = Vec::with_capacity(excess);
+    for (_, path) in backups.into_iter().take(excess) {
+        match std::fs::remove_file(&path) {
+            Ok(()) => removed.push(path),
+            Err(e) => warn!(path = %path.display(), "Failed to remove an old backup: {e}"),
+        }
+    }
+    Ok(removed)
+}
+
+/// The scheduled backups in `dir` named after `stem`, with the times they
+/// were made, oldest first.
+fn scheduled(dir: &Path, stem: &str) -> Result<Vec<(DateTime<Utc>, PathBuf)>> {
+    let mut backups = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let made = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(stem)?.strip_suffix(".db"))
+            .and_then(|time| NaiveDateTime::parse_from_str(time, TIMESTAMP_FORMAT).ok());
+        if let Some(made) = made {
+            backups.push((made.and_utc(), path));
+        }
+    }
+    backups.sort();
+    Ok(backups)
+}
+
+/// The start of the file names of the scheduled backups of `database`.
+fn scheduled_stem(database: &Path) -> String {
+    let stem = database
+        .file_stem()
+        .map_or_else(|| "captures".into(), |stem| stem.to_string_lossy());
+    format!("{stem}-")
+}
+
+/// Whether `a` and `b` name the same file once both are made absolute and
+/// their symbolic links resolved. A path that does not exist is the same as
+/// no other.
+pub(crate) fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// `path` with `suffix` appended to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Remove a file, if there is one.
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation_keeps_the_newest_backups() {
+        let dir = std::env::temp_dir().join(format!("fr_backup_rotate_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let names = [
+            "captures-20260101T000000.000Z.db",
+            "captures-20260102T000000.000Z.db",
+            "captures-20260103T000000.000Z.db",
+            "other-20260101T000000.000Z.db",
+        ];
+        for name in names {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let removed = rotate(&dir, "captures-", 2).unwrap();
+        assert_eq!(removed, [dir.join(names[0])]);
+        assert!(dir.join(names[1]).exists() && dir.join(names[2]).exists());
+        assert!(dir.join(names[3]).exists());
+        let newest = latest(Path::new("/data/captures.db"), &dir).unwrap();
+        assert_eq!(newest.unwrap().to_rfc3339(), "2026-01-03T00:00:00+00:00");
+        assert_eq!(latest(Path::new("/data/x.db"), &dir).unwrap(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backups_made_at_once_get_their_own_names() {
+        let dir = std::env::temp_dir().join(format!("fr_backup_reserve_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let now = "2026-01-01T00:00:00.250Z".parse().unwrap();
+        let first = reserve(&dir, "captures-", now).unwrap();
+        let second = reserve(&dir, "captures-", now).unwrap();
+        assert_eq!(first, dir.join("captures-20260101T000000.250Z.db"));
+        assert_eq!(second, dir.join("captures-20260101T000000.251Z.db"));
+        let times: Vec<_> = scheduled(&dir, "captures-")
+            .unwrap()
+            .into_iter()
+            .map(|(made, _)| made.timestamp_subsec_millis())
+            .collect();
+        assert_eq!(times, [250, 251]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_scheduled_backup_names() {
+        assert_eq!(scheduled_stem(Path::new("/data/captures.db")), "captures-");
+        assert_eq!(
+            with_suffix(Path::new("/data/captures.db"), ".partial"),
+            Path::new("/data/captures.db.partial")
+        );
+    }
+}
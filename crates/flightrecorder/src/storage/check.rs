@@ -0,0 +1,310 @@
+//! Checking the database for damage.
+//!
+//! A crash, a full disk or a failing drive can leave rows that no longer
+//! read back: a timestamp or capture type the storage does not know, content
+//! that cannot be decoded, decrypted or rebuilt from its deltas, or content
+//! that no longer matches its hash. Versions can outlive the capture their
+//! delta applies to or the session they belong to, and the search index can
+//! keep entries for captures that are gone. [`Storage::check`] looks for all
+//! of these, after the database's own `PRAGMA integrity_check`.
+//!
+//! [`Storage::repair`] moves damaged captures to the `quarantine` table, as
+//! stored, rather than deleting them, detaches versions from missing
+//! sessions and drops orphaned index entries. Damage found by the integrity
+//! check itself is beyond repair; restore a backup instead.
+//!
+//! [`Storage::check`]: super::Storage::check
+//! [`Storage::repair`]: super::Storage::repair
+
+use std::fmt;
+
+use chrono::DateTime;
+use rusqlite::types::Value;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::capture::Capture;
+use crate::error::Result;
+
+use super::crypto::Keys;
+use super::{codec, delta, stored_capture_type};
+
+/// What checking the database found.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckReport {
+    /// Number of captures checked, including those in the trash.
+    pub checked: usize,
+    /// Problems reported by `PRAGMA integrity_check`, which cannot be
+    /// repaired.
+    pub integrity_errors: Vec<String>,
+    /// Captures that cannot be read back as stored.
+    pub problems: Vec<CaptureProblem>,
+    /// Search index entries whose capture no longer exists.
+    pub orphaned_index_entries: Vec<i64>,
+}
+
+impl CheckReport {
+    /// Whether nothing is wrong.
+    #[must_use]
+    pub fn is_healthy(&self) -> bool {
+        self.integrity_errors.is_empty()
+            && self.problems.is_empty()
+            && self.orphaned_index_entries.is_empty()
+    }
+
+    /// Whether [`Storage::repair`](super::Storage::repair) has anything to
+    /// do.
+    #[must_use]
+    pub fn needs_repair(&self) -> bool {
+        !self.problems.is_empty() || !self.orphaned_index_entries.is_empty()
+    }
+}
+
+/// A problem with one capture.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureProblem {
+    /// The ID of the capture.
+    pub capture_id: i64,
+    /// What is wrong with it.
+    pub problem: Problem,
+}
+
+/// What can be wrong with a stored capture.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Problem {
+    /// The timestamp is not a time in microseconds that can be represented.
+    UnparseableTimestamp {
+        /// The stored timestamp.
+        stored: String,
+    },
+    /// The capture type is not one this release knows.
+    UnknownCaptureType {
+        /// The stored capture type.
+        stored: String,
+    },
+    /// The version is stored as a delta against a capture that no longer
+    /// exists.
+    MissingBase {
+        /// The ID of the missing capture.
+        base_id: i64,
+    },
+    /// The content cannot be decoded, decrypted or rebuilt.
+    UnreadableContent {
+        /// Why reading it failed.
+        message: String,
+    },
+    /// The content does not match its content hash.
+    HashMismatch,
+    /// The version belongs to a session that no longer exists.
+    MissingSession {
+        /// The ID of the missing session.
+        session_id: i64,
+    },
+}
+
+impl Problem {
+    /// Whether repairing moves the capture to the quarantine. A version of a
+    /// missing session is only detached from it, since its content is fine.
+    #[must_use]
+    pub fn quarantines(&self) -> bool {
+        !matches!(self, Self::MissingSession { .. })
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnparseableTimestamp { stored } => write!(f, "unparseable timestamp {stored}"),
+            Self::UnknownCaptureType { stored } => write!(f, "unknown capture type {stored:?}"),
+            Self::MissingBase { base_id } => write!(f, "delta base #{base_id} is missing"),
+            Self::UnreadableContent { message } => write!(f, "unreadable content: {message}"),
+            Self::HashMismatch => write!(f, "content does not match its hash"),
+            Self::MissingSession { session_id } => write!(f, "session {session_id} is missing"),
+        }
+    }
+}
+
+/// What repairing the database changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepairReport {
+    /// Captures moved to the quarantine.
+    pub quarantined: usize,
+    /// Versions detached from their missing session.
+    pub detached: usize,
+    /// Orphaned search index entries removed.
+    pub removed_index_entries: usize,
+}
+
+/// Run `PRAGMA integrity_check`, returning the problems it reports.
+pub(crate) fn integrity_errors(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+    let messages = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(messages.into_iter().filter(|m| m != "ok").collect())
+}
+
+/// Read back every capture, in the trash or not, with `keys` for the
+/// encrypted ones. Returns how many were checked and the problems found.
+pub(crate) fn check_captures(
+    conn: &Connection,
+    keys: Option<&Keys>,
+) -> Result<(usize, Vec<CaptureProblem>)> {
+    let mut stmt = conn.prepare(
+        r"
+        SELECT c.id, c.timestamp, CAST(c.capture_type AS TEXT), c.content,
+               CAST(c.content_hash AS TEXT), c.codec, c.encrypted, c.base_id,
+               b.id IS NOT NULL, c.session_id, s.id IS NOT NULL
+        FROM captures c
+        LEFT JOIN captures b ON b.id = c.base_id
+        LEFT JOIN sessions s ON s.id = c.session_id
+        ORDER BY c.id
+        ",
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut checked = 0;
+    let mut problems = Vec::new();
+    while let Some(row) = rows.next()? {
+        checked += 1;
+        let id: i64 = row.get(0)?;
+        let mut report = |problem| {
+            problems.push(CaptureProblem {
+                capture_id: id,
+                problem,
+            });
+        };
+
+        let timestamp: Value = row.get(1)?;
+        let valid = match timestamp {
+            Value::Integer(micros) => DateTime::from_timestamp_micros(micros).is_some(),
+            _ => false,
+        };
+        if !valid {
+            report(Problem::UnparseableTimestamp {
+                stored: describe(&timestamp),
+            });
+        }
+
+        let capture_type: String = row.get(2)?;
+        if stored_capture_type(&capture_type).is_none() {
+            report(Problem::UnknownCaptureType {
+                stored: capture_type,
+            });
+        }
+
+        let base_id: Option<i64> = row.get(7)?;
+        let base_exists: bool = row.get(8)?;
+        if let (Some(base_id), false) = (base_id, base_exists) {
+            report(Problem::MissingBase { base_id });
+        } else {
+            let codec: Option<String> = row.get(5)?;
+            let encrypted: bool = row.get(6)?;
+            let content = if base_id.is_some() {
+                delta::load_content(conn, keys, id)
+            } else {
+                codec::decode_stored(row.get_ref(3)?, codec.as_deref(), encrypted, keys, id, 3)
+            };
+            match content {
+                Ok(content) => {
+                    let stored_hash: String = row.get(4)?;
+                    let hash = match keys {
+                        Some(keys) if encrypted => keys.mac(&content),
+                        _ => Capture::compute_hash(&content),
+                    };
+                    if stored_hash != hash {
+                        report(Problem::HashMismatch);
+                    }
+                }
+                Err(e) => report(Problem::UnreadableContent {
+                    message: e.to_string(),
+                }),
+            }
+        }
+
+        let session_id: Option<i64> = row.get(9)?;
+        let session_exists: bool = row.get(10)?;
+        if let (Some(session_id), false) = (session_id, session_exists) {
+            report(Problem::MissingSession { session_id });
+        }
+    }
+    Ok((checked, problems))
+}
+
+/// Find the search index entries whose capture no longer exists.
+pub(crate) fn orphaned_index_entries(conn: &Connection) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT rowid FROM captures_fts WHERE rowid NOT IN (SELECT id FROM captures)
+         ORDER BY rowid",
+    )?;
+    let ids = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<i64>>>()?;
+    Ok(ids)
+}
+
+/// Copy a capture to the quarantine table as it is stored, returning
+/// whether it was found. The capture itself is left in place.
+pub(crate) fn quarantine(conn: &Connection, id: i64, reason: &str, now: i64) -> Result<bool> {
+    let copied = conn.execute(
+        "INSERT INTO quarantine
+            (capture_id, timestamp, source_app, content, content_hash, capture_type,
+             codec, delta, base_id, encrypted, reason, quarantined_at)
+         SELECT id, timestamp, source_app, content, content_hash, capture_type,
+                codec, delta, base_id, encrypted, ?2, ?3
+         FROM captures WHERE id = ?1",
+        params![id, reason, now],
+    )?;
+    Ok(copied > 0)
+}
+
+/// Detach a version from its session, leaving it a capture of its own.
+pub(crate) fn detach(conn: &Connection, id: i64) -> Result<bool> {
+    let updated = conn.execute(
+        "UPDATE captures SET session_id = NULL, version = NULL WHERE id = ?1",
+        [id],
+    )?;
+    Ok(updated > 0)
+}
+
+/// Remove a search index entry.
+pub(crate) fn remove_index_entry(conn: &Connection, rowid: i64) -> Result<bool> {
+    let removed = conn.execute("DELETE FROM captures_fts WHERE rowid = ?1", [rowid])?;
+    Ok(removed > 0)
+}
+
+/// Describe a stored value for a report.
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Real(r) => r.to_string(),
+        Value::Text(text) => format!("{text:?}"),
+        Value::Blob(bytes) => format!("a blob of {} bytes", bytes.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_version_problems_keep_the_capture() {
+        assert!(!Problem::MissingSession { session_id: 1 }.quarantines());
+        assert!(Problem::HashMismatch.quarantines());
+        assert!(Problem::MissingBase { base_id: 1 }.quarantines());
+    }
+
+    #[test]
+    fn test_problems_read_as_reasons() {
+        let problem = Problem::UnknownCaptureType {
+            stored: "fax".to_string(),
+        };
+        assert_eq!(problem.to_string(), "unknown capture type \"fax\"");
+        assert_eq!(
+            describe(&Value::Text("yesterday".to_string())),
+            "\"yesterday\""
+        );
+        assert_eq!(describe(&Value::Integer(i64::MAX)), i64::MAX.to_string());
+    }
+}
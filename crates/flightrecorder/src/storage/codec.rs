@@ -0,0 +1,157 @@
+//! Compression of stored content.
+//!
+//! Content longer than the compression threshold is compressed with zstd as
+//! it is stored, and the codec is recorded in the row's `codec` column. Rows
+//! without a codec hold their content as plain text. Every read decodes the
+//! content, so compression is invisible outside the storage. In an encrypted
+//! database the content is compressed before it is encrypted.
+
+use rusqlite::types::{Type, ValueRef};
+use rusqlite::ToSql;
+
+use super::crypto::{self, Keys};
+
+/// Default size in bytes above which content is compressed.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 4096;
+
+/// Codec name recorded for zstd-compressed content.
+const ZSTD: &str = "zstd";
+
+/// Content in the form it is stored.
+#[derive(Debug)]
+pub(crate) enum Stored<'a> {
+    /// Plain text.
+    Text(&'a str),
+    /// zstd-compressed text.
+    Zstd(Vec<u8>),
+}
+
+impl Stored<'_> {
+    /// The codec to record for this content, if any.
+    pub(crate) fn codec(&self) -> Option<&'static str> {
+        match self {
+            Self::Text(_) => None,
+            Self::Zstd(_) => Some(ZSTD),
+        }
+    }
+
+    /// The stored bytes.
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Text(text) => text.as_bytes(),
+            Self::Zstd(bytes) => bytes,
+        }
+    }
+}
+
+impl ToSql for Stored<'_> {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        match self {
+            Self::Text(text) => text.to_sql(),
+            Self::Zstd(bytes) => bytes.to_sql(),
+        }
+    }
+}
+
+/// Prepare content for storage, compressing it if it is longer than
+/// `threshold` and compression makes it smaller. A `threshold` of `None`
+/// disables compression.
+pub(crate) fn encode(content: &str, threshold: Option<usize>) -> Stored<'_> {
+    match threshold {
+        Some(threshold) if content.len() > threshold => {
+            match zstd::bulk::compress(content.as_bytes(), 0) {
+                Ok(compressed) if compressed.len() < content.len() => Stored::Zstd(compressed),
+                _ => Stored::Text(content),
+            }
+        }
+        _ => Stored::Text(content),
+    }
+}
+
+/// Read stored content back as text. `column` is the index of the content
+/// column, for error reports.
+pub(crate) fn decode(
+    value: ValueRef<'_>,
+    codec: Option<&str>,
+    column: usize,
+) -> rusqlite::Result<String> {
+    let invalid = |message: String| {
+        rusqlite::Error::FromSqlConversionFailure(column, value.data_type(), message.into())
+    };
+    match (codec, value) {
+        (None, ValueRef::Text(text)) => String::from_utf8(text.to_vec())
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(column, Type::Text, e.into())),
+        (Some(ZSTD), ValueRef::Blob(bytes)) => {
+            let text = zstd::decode_all(bytes)
+                .map_err(|e| invalid(format!("corrupt zstd content: {e}")))?;
+            String::from_utf8(text).map_err(|e| invalid(format!("corrupt zstd content: {e}")))
+        }
+        (Some(codec), _) if codec != ZSTD => Err(invalid(format!("unknown codec: {codec}"))),
+        _ => Err(rusqlite::Error::InvalidColumnType(
+            column,
+            "content".to_string(),
+            value.data_type(),
+        )),
+    }
+}
+
+/// Read the stored content of capture `id` back as text, decrypting it
+/// first if it is `encrypted` with the data key of `keys`.
+pub(crate) fn decode_stored(
+    value: ValueRef<'_>,
+    codec: Option<&str>,
+    encrypted: bool,
+    keys: Option<&Keys>,
+    id: i64,
+    column: usize,
+) -> rusqlite::Result<String> {
+    if !encrypted {
+        return decode(value, codec, column);
+    }
+    let bytes = crypto::decrypt_column(value, keys, id, "content", column)?;
+    let value = match codec {
+        None => ValueRef::Text(&bytes),
+        Some(_) => ValueRef::Blob(&bytes),
+    };
+    decode(value, codec, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(stored: &Stored) -> String {
+        let value = match stored {
+            Stored::Text(text) => ValueRef::Text(text.as_bytes()),
+            Stored::Zstd(bytes) => ValueRef::Blob(bytes),
+        };
+        decode(value, stored.codec(), 3).unwrap()
+    }
+
+    #[test]
+    fn test_large_content_is_compressed() {
+        let log = "2024-01-01 INFO request handled in 12ms\n".repeat(500);
+        let stored = encode(&log, Some(DEFAULT_COMPRESSION_THRESHOLD));
+        assert_eq!(stored.codec(), Some("zstd"));
+        let Stored::Zstd(bytes) = &stored else {
+            unreachable!()
+        };
+        assert!(bytes.len() < log.len() / 10);
+        assert_eq!(round_trip(&stored), log);
+    }
+
+    #[test]
+    fn test_small_or_disabled_content_is_text() {
+        let log = "x".repeat(100);
+        assert_eq!(encode(&log, Some(1000)).codec(), None);
+        assert_eq!(encode(&log.repeat(100), None).codec(), None);
+        assert_eq!(round_trip(&encode(&log, Some(1000))), log);
+    }
+
+    #[test]
+    fn test_undecodable_content_is_an_error() {
+        assert!(decode(ValueRef::Blob(b"not zstd"), Some("zstd"), 3).is_err());
+        assert!(decode(ValueRef::Text(b"text"), Some("lz4"), 3).is_err());
+        assert!(decode(ValueRef::Blob(b"bytes"), None, 3).is_err());
+    }
+}
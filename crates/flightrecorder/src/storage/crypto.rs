@@ -0,0 +1,529 @@
+//! Encryption at rest.
+//!
+//! Encryption is envelope encryption. A random data key encrypts the content
+//! (and deltas) of every capture, and the user's notes and tags on it, with
+//! XChaCha20-Poly1305, under a random nonce per value. Each value is bound to
+//! the capture and column holding it, which are its associated data, so that
+//! a value copied into another row or column does not decrypt. The data key
+//! is kept in the `metadata` table, itself encrypted ("wrapped") under a key
+//! derived from a key file or from a passphrase with Argon2id. Changing the
+//! key only rewraps the data key; rotating the data key re-encrypts every
+//! capture (see [`Storage::rekey`](super::Storage::rekey)).
+//!
+//! Databases encrypted before values were bound to their place have no
+//! format in the `metadata` table. Their values are decrypted without
+//! associated data, and opening such a database re-encrypts it.
+//!
+//! Three more keys are derived from the data key. Content hashes, which find
+//! duplicates, become keyed BLAKE3 MACs, so that they cannot be compared with
+//! the hash of a guessed text. Tags are looked up by MACs under a key of
+//! their own, so that a tag cannot be matched with a capture of the same
+//! text. The full-text index holds a MAC of each word
+//! in place of the word (a blind index), and searches look up the MACs of
+//! their words. The index still reveals how often each word occurs, and a
+//! prefix search only finds the word itself.
+//!
+//! Captures moved to the `quarantine` table by a migration or a repair are
+//! left as they were.
+
+use std::fmt;
+use std::path::PathBuf;
+
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OptionalExtension};
+use zeroize::Zeroizing;
+
+use super::search::word_spans;
+use crate::error::{Error, Result};
+
+/// Minimum length in bytes of a key file.
+pub const MIN_KEY_FILE_LEN: usize = 32;
+
+/// Metadata key of the wrapped data key.
+const DATA_KEY: &str = "encryption_data_key";
+/// Metadata key of the name of the function deriving the key encryption key.
+const KDF: &str = "encryption_kdf";
+/// Metadata key of the salt of the passphrase.
+const SALT: &str = "encryption_salt";
+/// Metadata key of the format of encrypted values.
+const FORMAT: &str = "encryption_format";
+
+/// The format of encrypted values: bound to their capture and column.
+const CURRENT_FORMAT: &str = "2";
+
+const KDF_KEY_FILE: &str = "key_file";
+const KDF_ARGON2ID: &str = "argon2id";
+
+/// Associated data of the wrapped data key.
+const DATA_KEY_AAD: &[u8] = b"flightrecorder data key";
+
+const NONCE_LEN: usize = 24;
+const SALT_LEN: usize = 16;
+
+/// Number of bytes of a word's MAC kept in the blind index.
+const INDEX_TOKEN_LEN: usize = 8;
+
+/// The key protecting an encrypted database.
+#[derive(Clone)]
+pub enum EncryptionKey {
+    /// A file of at least [`MIN_KEY_FILE_LEN`] random bytes, such as one
+    /// created with `head -c 32 /dev/urandom`.
+    KeyFile(PathBuf),
+    /// A passphrase.
+    Passphrase(Zeroizing<String>),
+}
+
+impl EncryptionKey {
+    /// A key read from a key file.
+    #[must_use]
+    pub fn key_file(path: impl Into<PathBuf>) -> Self {
+        Self::KeyFile(path.into())
+    }
+
+    /// A key derived from a passphrase.
+    #[must_use]
+    pub fn passphrase(passphrase: impl Into<String>) -> Self {
+        Self::Passphrase(Zeroizing::new(passphrase.into()))
+    }
+
+    fn kdf(&self) -> &'static str {
+        match self {
+            Self::KeyFile(_) => KDF_KEY_FILE,
+            Self::Passphrase(_) => KDF_ARGON2ID,
+        }
+    }
+
+    /// Derive the key encryption key. `salt` is only used for passphrases.
+    fn derive(&self, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+        let mut key = Zeroizing::new([0; 32]);
+        match self {
+            Self::KeyFile(path) => {
+                let contents =
+                    Zeroizing::new(std::fs::read(path).map_err(|e| Error::DatabaseLocked {
+                        message: format!("cannot read key file {}: {e}", path.display()),
+                    })?);
+                if contents.len() < MIN_KEY_FILE_LEN {
+                    return Err(Error::DatabaseLocked {
+                        message: format!(
+                            "key file {} is shorter than {MIN_KEY_FILE_LEN} bytes",
+                            path.display()
+                        ),
+                    });
+                }
+                *key = blake3::derive_key("flightrecorder key file", &contents);
+            }
+            Self::Passphrase(passphrase) => {
+                Argon2::default()
+                    .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+                    .map_err(|e| Error::Encryption {
+                        message: format!("cannot derive a key from the passphrase: {e}"),
+                    })?;
+            }
+        }
+        Ok(key)
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::KeyFile(path) => f.debug_tuple("KeyFile").field(path).finish(),
+            Self::Passphrase(_) => f.write_str("Passphrase(..)"),
+        }
+    }
+}
+
+/// The data key of an encrypted database, and the keys derived from it.
+pub(crate) struct Keys {
+    data_key: Zeroizing<[u8; 32]>,
+    cipher: XChaCha20Poly1305,
+    mac_key: Zeroizing<[u8; 32]>,
+    tag_key: Zeroizing<[u8; 32]>,
+    index_key: Zeroizing<[u8; 32]>,
+    /// Whether stored values were encrypted without associated data, before
+    /// [`CURRENT_FORMAT`], and are decrypted without it. New values are
+    /// always encrypted with it.
+    legacy_format: bool,
+}
+
+impl Keys {
+    fn from_data_key(data_key: Zeroizing<[u8; 32]>) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(data_key.as_ref().into()),
+            mac_key: Zeroizing::new(blake3::derive_key(
+                "flightrecorder content mac",
+                data_key.as_ref(),
+            )),
+            tag_key: Zeroizing::new(blake3::derive_key(
+                "flightrecorder tag mac",
+                data_key.as_ref(),
+            )),
+            index_key: Zeroizing::new(blake3::derive_key(
+                "flightrecorder search index",
+                data_key.as_ref(),
+            )),
+            data_key,
+            legacy_format: false,
+        }
+    }
+
+    /// Keys with a new random data key.
+    pub(crate) fn generate() -> Self {
+        let mut data_key = Zeroizing::new([0; 32]);
+        OsRng.fill_bytes(data_key.as_mut());
+        Self::from_data_key(data_key)
+    }
+
+    /// Whether stored values are in a format before [`CURRENT_FORMAT`], and
+    /// must be encrypted again.
+    pub(crate) fn is_legacy_format(&self) -> bool {
+        self.legacy_format
+    }
+
+    /// The same keys, for values in the current format.
+    pub(crate) fn current_format(&self) -> Self {
+        Self::from_data_key(self.data_key.clone())
+    }
+
+    /// Encrypt the value of `column` of capture `id`, returning the nonce
+    /// followed by the ciphertext.
+    pub(crate) fn encrypt(&self, id: i64, column: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        seal(&self.cipher, plaintext, &associated_data(id, column))
+    }
+
+    /// Encrypt a value without associated data, as before [`CURRENT_FORMAT`].
+    #[cfg(test)]
+    pub(crate) fn encrypt_legacy(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        seal(&self.cipher, plaintext, b"")
+    }
+
+    /// Decrypt the value of `column` of capture `id`, encrypted by
+    /// [`Keys::encrypt`]. Returns `None` if it was not encrypted with this
+    /// data key for that capture and column, or has been altered.
+    pub(crate) fn decrypt(&self, id: i64, column: &str, data: &[u8]) -> Option<Vec<u8>> {
+        if self.legacy_format {
+            return open(&self.cipher, data, b"");
+        }
+        open(&self.cipher, data, &associated_data(id, column))
+    }
+
+    /// The keyed hash stored in place of the content hash.
+    pub(crate) fn mac(&self, content: &str) -> String {
+        blake3::keyed_hash(&self.mac_key, content.as_bytes())
+            .to_hex()
+            .to_string()
+    }
+
+    /// The keyed hash stored with a tag, by which it is looked up.
+    pub(crate) fn tag_mac(&self, tag: &str) -> String {
+        blake3::keyed_hash(&self.tag_key, tag.as_bytes())
+            .to_hex()
+            .to_string()
+    }
+
+    /// The text indexed in place of `text`: one token per word, derived from
+    /// the lower-cased word.
+    pub(crate) fn blind(&self, text: &str) -> String {
+        let text = text.to_lowercase();
+        word_spans(&text)
+            .into_iter()
+            .map(|(start, end)| {
+                let hash = blake3::keyed_hash(&self.index_key, &text.as_bytes()[start..end]);
+                to_hex(&hash.as_bytes()[..INDEX_TOKEN_LEN])
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl fmt::Debug for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keys").finish_non_exhaustive()
+    }
+}
+
+/// The associated data of the value of `column` of capture `id`.
+fn associated_data(id: i64, column: &str) -> Vec<u8> {
+    format!("{column} of capture {id}").into_bytes()
+}
+
+/// Decrypt the value of the encrypted column `name` of capture `id`.
+/// `column` is the index of the column, for error reports.
+pub(crate) fn decrypt_column(
+    value: ValueRef<'_>,
+    keys: Option<&Keys>,
+    id: i64,
+    name: &str,
+    column: usize,
+) -> rusqlite::Result<Vec<u8>> {
+    let failure = |message: &str| {
+        rusqlite::Error::FromSqlConversionFailure(column, value.data_type(), message.into())
+    };
+    let keys = keys.ok_or_else(|| failure("content is encrypted and no key was given"))?;
+    let ValueRef::Blob(data) = value else {
+        return Err(failure("encrypted content is not a blob"));
+    };
+    keys.decrypt(id, name, data)
+        .ok_or_else(|| failure("content cannot be decrypted with the database key"))
+}
+
+/// Whether the database holds a wrapped data key.
+pub(crate) fn is_encrypted(conn: &Connection) -> Result<bool> {
+    Ok(get(conn, DATA_KEY)?.is_some())
+}
+
+/// Unwrap the data key of an encrypted database with `key`.
+pub(crate) fn unlock(conn: &Connection, key: &EncryptionKey) -> Result<Keys> {
+    let locked = |message: &str| Error::DatabaseLocked {
+        message: message.to_string(),
+    };
+    let wrapped = get(conn, DATA_KEY)?
+        .and_then(|hex| from_hex(&hex))
+        .ok_or_else(|| locked("the database has no valid data key"))?;
+    let kdf = get(conn, KDF)?.unwrap_or_default();
+    if kdf != key.kdf() {
+        return Err(locked(match kdf.as_str() {
+            KDF_KEY_FILE => "the database is encrypted with a key file, not a passphrase",
+            KDF_ARGON2ID => "the database is encrypted with a passphrase, not a key file",
+            _ => "the database is encrypted with an unknown kind of key",
+        }));
+    }
+    let salt = get(conn, SALT)?.and_then(|hex| from_hex(&hex));
+    let kek = key.derive(salt.as_deref().unwrap_or_default())?;
+    let data_key = open(
+        &XChaCha20Poly1305::new(kek.as_ref().into()),
+        &wrapped,
+        DATA_KEY_AAD,
+    )
+    .map(Zeroizing::new)
+    .and_then(|bytes| <[u8; 32]>::try_from(bytes.as_slice()).ok())
+    .ok_or_else(|| locked("wrong key or passphrase"))?;
+    let mut keys = Keys::from_data_key(Zeroizing::new(data_key));
+    keys.legacy_format = get(conn, FORMAT)?.as_deref() != Some(CURRENT_FORMAT);
+    Ok(keys)
+}
+
+/// Store the data key of `keys`, wrapped with `key`, replacing any data key
+/// stored before.
+pub(crate) fn store(conn: &Connection, keys: &Keys, key: &EncryptionKey) -> Result<()> {
+    let mut salt = [0; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let kek = key.derive(&salt)?;
+    let wrapped = seal(
+        &XChaCha20Poly1305::new(kek.as_ref().into()),
+        keys.data_key.as_ref(),
+        DATA_KEY_AAD,
+    )?;
+    let mut set = conn.prepare("INSERT OR REPLACE INTO metadata (key, value) VALUES (?1, ?2)")?;
+    set.execute(params![DATA_KEY, to_hex(&wrapped)])?;
+    set.execute(params![KDF, key.kdf()])?;
+    match key {
+        EncryptionKey::Passphrase(_) => set.execute(params![SALT, to_hex(&salt)])?,
+        EncryptionKey::KeyFile(_) => conn.execute("DELETE FROM metadata WHERE key = ?1", [SALT])?,
+    };
+    Ok(())
+}
+
+/// Record that every encrypted value is in the current format.
+pub(crate) fn set_current_format(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO metadata (key, value) VALUES (?1, ?2)",
+        [FORMAT, CURRENT_FORMAT],
+    )?;
+    Ok(())
+}
+
+/// Forget the data key of a database that is no longer encrypted.
+pub(crate) fn remove(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "DELETE FROM metadata WHERE key IN (?1, ?2, ?3, ?4)",
+        [DATA_KEY, KDF, SALT, FORMAT],
+    )?;
+    Ok(())
+}
+
+fn get(conn: &Connection, key: &str) -> Result<Option<String>> {
+    Ok(conn
+        .query_row("SELECT value FROM metadata WHERE key = ?1", [key], |row| {
+            row.get(0)
+        })
+        .optional()?)
+}
+
+fn seal(cipher: &XChaCha20Poly1305, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| Error::Encryption {
+            message: "cannot encrypt content".to_string(),
+        })?;
+    let mut out = nonce.to_vec();
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+fn open(cipher: &XChaCha20Poly1305, data: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    cipher
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+    bytes.iter().fold(String::new(), |mut out, b| {
+        let _ = write!(out, "{b:02x}");
+        out
+    })
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::schema;
+
+    fn key_file(name: &str, contents: &[u8]) -> EncryptionKey {
+        let path =
+            std::env::temp_dir().join(format!("fr_crypto_{name}_{}.key", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        EncryptionKey::key_file(path)
+    }
+
+    fn metadata() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(schema::CREATE_METADATA_TABLE, []).unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let keys = Keys::generate();
+        let sealed = keys.encrypt(1, "content", b"secret").unwrap();
+        assert_eq!(sealed.len(), NONCE_LEN + 6 + 16);
+        assert_ne!(keys.encrypt(1, "content", b"secret").unwrap(), sealed);
+        assert_eq!(
+            keys.decrypt(1, "content", &sealed).as_deref(),
+            Some(&b"secret"[..])
+        );
+
+        let mut altered = sealed.clone();
+        altered[NONCE_LEN] ^= 1;
+        assert_eq!(keys.decrypt(1, "content", &altered), None);
+        assert_eq!(Keys::generate().decrypt(1, "content", &sealed), None);
+        assert_eq!(keys.decrypt(1, "content", b"short"), None);
+    }
+
+    #[test]
+    fn test_values_are_bound_to_their_place() {
+        let keys = Keys::generate();
+        let sealed = keys.encrypt(1, "content", b"secret").unwrap();
+        assert_eq!(keys.decrypt(2, "content", &sealed), None);
+        assert_eq!(keys.decrypt(1, "note", &sealed), None);
+
+        let legacy = keys.encrypt_legacy(b"secret").unwrap();
+        assert_eq!(keys.decrypt(1, "content", &legacy), None);
+        let mut old = keys.current_format();
+        old.legacy_format = true;
+        assert_eq!(
+            old.decrypt(1, "content", &legacy).as_deref(),
+            Some(&b"secret"[..])
+        );
+    }
+
+    #[test]
+    fn test_macs_and_blind_tokens_depend_on_the_key() {
+        let (a, b) = (Keys::generate(), Keys::generate());
+        assert_eq!(a.mac("hello"), a.mac("hello"));
+        assert_ne!(a.mac("hello"), a.mac("hello!"));
+        assert_ne!(a.mac("hello"), b.mac("hello"));
+        assert_ne!(a.mac("hello"), blake3::hash(b"hello").to_hex().to_string());
+        assert_ne!(a.tag_mac("hello"), a.mac("hello"));
+        assert_ne!(a.tag_mac("hello"), b.tag_mac("hello"));
+
+        let blind = a.blind("Hello, world");
+        assert_eq!(blind, a.blind("hello WORLD!"));
+        assert_eq!(blind.split(' ').count(), 2);
+        assert!(!blind.contains("hello"));
+        assert_ne!(blind, b.blind("hello world"));
+    }
+
+    #[test]
+    fn test_unlock_with_key_file() {
+        let conn = metadata();
+        let key = key_file("unlock", &[7; 32]);
+        let keys = Keys::generate();
+        store(&conn, &keys, &key).unwrap();
+        assert!(is_encrypted(&conn).unwrap());
+
+        let unlocked = unlock(&conn, &key).unwrap();
+        assert_eq!(unlocked.mac("x"), keys.mac("x"));
+        assert!(unlocked.is_legacy_format());
+        set_current_format(&conn).unwrap();
+        assert!(!unlock(&conn, &key).unwrap().is_legacy_format());
+
+        let wrong = key_file("unlock_wrong", &[8; 32]);
+        assert!(matches!(
+            unlock(&conn, &wrong),
+            Err(Error::DatabaseLocked { .. })
+        ));
+        let short = key_file("unlock_short", &[7; 16]);
+        assert!(unlock(&conn, &short).is_err());
+        assert!(unlock(&conn, &EncryptionKey::passphrase("x")).is_err());
+
+        remove(&conn).unwrap();
+        assert!(!is_encrypted(&conn).unwrap());
+    }
+
+    #[test]
+    fn test_unlock_with_passphrase() {
+        let conn = metadata();
+        let keys = Keys::generate();
+        store(&conn, &keys, &EncryptionKey::passphrase("correct horse")).unwrap();
+        let unlocked = unlock(&conn, &EncryptionKey::passphrase("correct horse")).unwrap();
+        assert_eq!(unlocked.mac("x"), keys.mac("x"));
+        assert!(unlock(&conn, &EncryptionKey::passphrase("battery staple")).is_err());
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        assert_eq!(to_hex(&[0, 15, 255]), "000fff");
+        assert_eq!(from_hex("000fff"), Some(vec![0, 15, 255]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+
+    #[test]
+    fn test_passphrase_is_not_debug_printed() {
+        let key = EncryptionKey::passphrase("hunter2");
+        assert!(!format!("{key:?}").contains("hunter2"));
+    }
+}
@@ -0,0 +1,333 @@
+//! Delta encoding of draft versions.
+//!
+//! Successive snapshots of a text field mostly repeat each other, so a
+//! version is usually stored as a delta against the version before it: byte
+//! ranges to copy from that version, and the literal bytes inserted between
+//! them. Every [`DEFAULT_KEYFRAME_INTERVAL`]th version is stored in full as a
+//! keyframe, which bounds the number of deltas applied to rebuild any
+//! version. So is a version whose delta would save little over its content.
+//!
+//! A delta is a format byte and the length of the content it rebuilds,
+//! followed by operations:
+//!
+//! - `0x00 offset length`: copy `length` bytes at `offset` in the base
+//! - `0x01 length bytes`: insert `length` literal bytes
+//!
+//! Numbers are unsigned LEB128 varints.
+
+use std::collections::HashMap;
+
+use rusqlite::types::Type;
+use rusqlite::Connection;
+
+use super::codec;
+use super::crypto::{self, Keys};
+
+/// Default number of versions from one keyframe to the next.
+pub const DEFAULT_KEYFRAME_INTERVAL: u32 = 16;
+
+/// Format byte of the deltas written by this module.
+const FORMAT: u8 = 1;
+
+const OP_COPY: u8 = 0;
+const OP_INSERT: u8 = 1;
+
+/// Length of the blocks of the base that are looked for in the target.
+/// Shorter matches are inserted literally.
+const BLOCK: usize = 16;
+
+/// Whether a version is stored in full when keyframes are `interval`
+/// versions apart. Version 1 of every draft is a keyframe.
+pub(crate) fn is_keyframe(version: u32, interval: u32) -> bool {
+    interval <= 1 || version % interval == 1
+}
+
+/// Encode `target` as a delta against `base`, if the delta is less than
+/// half the size of `target`.
+pub(crate) fn encode(base: &str, target: &str) -> Option<Vec<u8>> {
+    let (base, target) = (base.as_bytes(), target.as_bytes());
+    let mut out = vec![FORMAT];
+    write_varint(&mut out, target.len());
+
+    let prefix = base.iter().zip(target).take_while(|(a, b)| a == b).count();
+    let suffix = base[prefix..]
+        .iter()
+        .rev()
+        .zip(target[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    push_copy(&mut out, 0, prefix);
+    encode_middle(
+        &mut out,
+        base,
+        prefix..base.len() - suffix,
+        &target[prefix..target.len() - suffix],
+    );
+    push_copy(&mut out, base.len() - suffix, suffix);
+
+    (out.len() < target.len() / 2).then_some(out)
+}
+
+/// Encode the part of the target between the common prefix and suffix,
+/// copying every block of the base (within `range`) found in it.
+fn encode_middle(out: &mut Vec<u8>, base: &[u8], range: std::ops::Range<usize>, target: &[u8]) {
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    let mut offset = range.start;
+    while offset + BLOCK <= range.end {
+        blocks
+            .entry(&base[offset..offset + BLOCK])
+            .or_insert(offset);
+        offset += BLOCK;
+    }
+
+    let mut literal = 0;
+    let mut pos = 0;
+    while pos + BLOCK <= target.len() {
+        let Some(&found) = blocks.get(&target[pos..pos + BLOCK]) else {
+            pos += 1;
+            continue;
+        };
+        let (mut start, mut source) = (pos, found);
+        while start > literal && source > range.start && base[source - 1] == target[start - 1] {
+            start -= 1;
+            source -= 1;
+        }
+        let len = base[source..range.end]
+            .iter()
+            .zip(&target[start..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        push_insert(out, &target[literal..start]);
+        push_copy(out, source, len);
+        pos = start + len;
+        literal = pos;
+    }
+    push_insert(out, &target[literal..]);
+}
+
+/// Rebuild content from its base and delta. Returns `None` if the delta is
+/// malformed or does not fit the base.
+pub(crate) fn apply(base: &str, delta: &[u8]) -> Option<String> {
+    let base = base.as_bytes();
+    let (&format, mut rest) = delta.split_first()?;
+    if format != FORMAT {
+        return None;
+    }
+    let len = read_varint(&mut rest)?;
+    let mut out = Vec::with_capacity(len);
+    while let Some((&op, tail)) = rest.split_first() {
+        rest = tail;
+        match op {
+            OP_COPY => {
+                let offset = read_varint(&mut rest)?;
+                let count = read_varint(&mut rest)?;
+                out.extend_from_slice(base.get(offset..offset.checked_add(count)?)?);
+            }
+            OP_INSERT => {
+                let count = read_varint(&mut rest)?;
+                if count > rest.len() {
+                    return None;
+                }
+                let (bytes, tail) = rest.split_at(count);
+                out.extend_from_slice(bytes);
+                rest = tail;
+            }
+            _ => return None,
+        }
+    }
+    if out.len() != len {
+        return None;
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Load the full content of a capture, applying the deltas from its
+/// keyframe onwards. `keys` decrypt the versions that are encrypted.
+pub(crate) fn load_content(
+    conn: &Connection,
+    keys: Option<&Keys>,
+    id: i64,
+) -> rusqlite::Result<String> {
+    let mut stmt = conn.prepare_cached(
+        r"
+        WITH RECURSIVE chain (id, base_id, content, codec, delta, encrypted, depth) AS (
+            SELECT id, base_id, content, codec, delta, encrypted, 0 FROM captures WHERE id = ?1
+            UNION ALL
+            SELECT c.id, c.base_id, c.content, c.codec, c.delta, c.encrypted, chain.depth + 1
+            FROM captures c JOIN chain ON c.id = chain.base_id
+            WHERE c.id < chain.id
+        )
+        SELECT id, base_id, content, codec, delta, encrypted FROM chain ORDER BY depth DESC
+        ",
+    )?;
+    let mut rows = stmt.query([id])?;
+    let mut content: Option<String> = None;
+    while let Some(row) = rows.next()? {
+        let version_id: i64 = row.get(0)?;
+        let base_id: Option<i64> = row.get(1)?;
+        let encrypted: bool = row.get(5)?;
+        content = Some(match (content, base_id) {
+            (_, None) => {
+                let codec: Option<String> = row.get(3)?;
+                codec::decode_stored(
+                    row.get_ref(2)?,
+                    codec.as_deref(),
+                    encrypted,
+                    keys,
+                    version_id,
+                    2,
+                )?
+            }
+            (Some(base), Some(_)) => {
+                let delta: Vec<u8> = if encrypted {
+                    crypto::decrypt_column(row.get_ref(4)?, keys, version_id, "delta", 4)?
+                } else {
+                    row.get(4)?
+                };
+                apply(&base, &delta).ok_or_else(|| corrupt(version_id, "delta is corrupt"))?
+            }
+            (None, Some(_)) => return Err(corrupt(version_id, "delta base is missing")),
+        });
+    }
+    content.ok_or(rusqlite::Error::QueryReturnedNoRows)
+}
+
+/// Read the length of the content a delta rebuilds.
+pub(crate) fn content_len(delta: &[u8]) -> Option<usize> {
+    let (&format, mut rest) = delta.split_first()?;
+    if format != FORMAT {
+        return None;
+    }
+    read_varint(&mut rest)
+}
+
+fn corrupt(id: i64, reason: &str) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(
+        3,
+        Type::Blob,
+        format!("capture {id} cannot be rebuilt: {reason}").into(),
+    )
+}
+
+fn push_copy(out: &mut Vec<u8>, offset: usize, len: usize) {
+    if len > 0 {
+        out.push(OP_COPY);
+        write_varint(out, offset);
+        write_varint(out, len);
+    }
+}
+
+fn push_insert(out: &mut Vec<u8>, bytes: &[u8]) {
+    if !bytes.is_empty() {
+        out.push(OP_INSERT);
+        write_varint(out, bytes.len());
+        out.extend_from_slice(bytes);
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        // Truncation keeps the low seven bits, which is the point.
+        #[allow(clippy::cast_possible_truncation)]
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    out.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> Option<usize> {
+    let mut value: usize = 0;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = input.split_first()?;
+        *input = rest;
+        value |= usize::from(byte & 0x7f).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+        if shift >= usize::BITS {
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+
+    use super::*;
+
+    fn round_trip(base: &str, target: &str) -> Option<usize> {
+        let delta = encode(base, target)?;
+        assert_eq!(apply(base, &delta).as_deref(), Some(target));
+        Some(delta.len())
+    }
+
+    #[test]
+    fn test_typing_at_the_end_is_small() {
+        let base = "Dear team, the release is scheduled for Thursday. ".repeat(4);
+        let target = format!("{base}Please review");
+        let len = round_trip(&base, &target).unwrap();
+        assert!(len < 24, "delta was {len} bytes");
+        assert_eq!(
+            content_len(&encode(&base, &target).unwrap()),
+            Some(target.len())
+        );
+    }
+
+    #[test]
+    fn test_edits_in_several_places() {
+        let mut base = String::new();
+        for i in 0..40 {
+            writeln!(base, "line {i} of the draft").unwrap();
+        }
+        let target = base
+            .replace("line 3 of", "line three of")
+            .replace("line 30 of", "the thirtieth line of");
+        let len = round_trip(&base, &target).unwrap();
+        assert!(len < target.len() / 8, "delta was {len} bytes");
+    }
+
+    #[test]
+    fn test_unicode_content() {
+        let base = "café ☕ and croissant 🥐, ".repeat(8);
+        let target = base.replacen("café", "thé", 3);
+        round_trip(&base, &target).unwrap();
+    }
+
+    #[test]
+    fn test_unrelated_content_is_not_encoded() {
+        assert_eq!(encode("hello", "completely different text"), None);
+        assert_eq!(encode("", ""), None);
+    }
+
+    #[test]
+    fn test_malformed_deltas_are_rejected() {
+        let base = "x".repeat(100);
+        let delta = encode(&base, &format!("{base}y")).unwrap();
+        assert_eq!(apply("short", &delta), None);
+        assert_eq!(apply(&base, &delta[..delta.len() - 1]), None);
+        assert_eq!(apply(&base, &[FORMAT + 1]), None);
+        assert_eq!(apply(&base, &[FORMAT, 1, 9]), None);
+    }
+
+    #[test]
+    fn test_keyframes() {
+        assert!(is_keyframe(1, 16));
+        assert!(!is_keyframe(2, 16));
+        assert!(is_keyframe(17, 16));
+        assert!(is_keyframe(5, 1));
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        for value in [0, 1, 127, 128, 300, usize::MAX] {
+            let mut out = Vec::new();
+            write_varint(&mut out, value);
+            assert_eq!(read_varint(&mut out.as_slice()), Some(value));
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Edit history of captured text.
+//!
+//! Text fields are captured as a series of snapshots while they are edited.
+//! Snapshots of the same kind from the same field of the same application,
+//! each taken within the session gap of the one before, form a session: the
+//! versions of one draft, numbered from 1. A session records a hash of the
+//! field's identity (see [`Capture::field_id`](crate::Capture::field_id))
+//! rather than the identity itself; snapshots from monitors that cannot tell
+//! fields apart share the sessions of their application. Clipboard captures are not drafts and have no
+//! session; copying the same text again records another occurrence of the
+//! existing capture instead of storing it twice.
+
+use std::time::Duration;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::capture::CaptureType;
+use crate::error::Result;
+
+/// Default longest pause between two snapshots of the same draft.
+pub const DEFAULT_SESSION_GAP: Duration = Duration::from_secs(5 * 60);
+
+/// The latest version of a session still open for more snapshots.
+#[derive(Debug)]
+pub(crate) struct OpenSession {
+    pub id: i64,
+    pub latest_id: i64,
+    pub latest_hash: String,
+    pub latest_version: u32,
+}
+
+/// Convert a session gap to microseconds.
+pub(crate) fn gap_micros(gap: Duration) -> i64 {
+    i64::try_from(gap.as_micros()).unwrap_or(i64::MAX)
+}
+
+/// Find the session a snapshot taken at `timestamp` continues: the most
+/// recently updated one for the same capture type, application and field, if
+/// it was updated within `gap` of the snapshot and has a version outside the
+/// trash. The latest version is the newest one outside the trash, but the
+/// next version is still numbered after any in the trash.
+pub(crate) fn find_open_session(
+    conn: &Connection,
+    capture_type: CaptureType,
+    source_app: Option<&str>,
+    field_hash: Option<&str>,
+    timestamp: i64,
+    gap: Duration,
+) -> Result<Option<OpenSession>> {
+    let session = conn
+        .query_row(
+            r"
+            SELECT s.id, c.id, c.content_hash,
+                   (SELECT MAX(version) FROM captures WHERE session_id = s.id)
+            FROM sessions s JOIN captures c ON c.session_id = s.id
+            WHERE s.capture_type = ?1 AND s.source_app IS ?2 AND s.field_hash IS ?3
+              AND s.updated_at >= ?4 AND c.trashed_at IS NULL
+            ORDER BY s.updated_at DESC, c.version DESC
+            LIMIT 1
+            ",
+            params![
+                capture_type.to_string(),
+                source_app,
+                field_hash,
+                timestamp.saturating_sub(gap_micros(gap)),
+            ],
+            |row| {
+                Ok(OpenSession {
+                    id: row.get(0)?,
+                    latest_id: row.get(1)?,
+                    latest_hash: row.get(2)?,
+                    latest_version: row.get(3)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(session)
+}
+
+/// Start a new session at `timestamp`, returning its id.
+pub(crate) fn start_session(
+    conn: &Connection,
+    capture_type: CaptureType,
+    source_app: Option<&str>,
+    field_hash: Option<&str>,
+    timestamp: i64,
+) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO sessions (capture_type, source_app, field_hash, started_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?4)",
+        params![capture_type.to_string(), source_app, field_hash, timestamp],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Record that a session was still being edited at `timestamp`.
+pub(crate) fn touch_session(conn: &Connection, session_id: i64, timestamp: i64) -> Result<()> {
+    conn.execute(
+        "UPDATE sessions SET updated_at = MAX(updated_at, ?2) WHERE id = ?1",
+        params![session_id, timestamp],
+    )?;
+    Ok(())
+}
+
+/// Whether captures of this type are versioned drafts rather than
+/// standalone values.
+pub(crate) fn is_draft(capture_type: CaptureType) -> bool {
+    match capture_type {
+        CaptureType::TextField | CaptureType::Keystroke => true,
+        CaptureType::Clipboard => false,
+    }
+}
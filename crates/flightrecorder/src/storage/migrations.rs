@@ -2,39 +2,615 @@
 //!
 //! This module handles database schema versioning and migrations,
 //! ensuring the database schema stays up-to-date as the application evolves.
+//!
+//! Migrations are listed in [`MIGRATIONS`], one step per schema version, in
+//! order. Opening a database applies every step newer than its recorded
+//! version, each in its own transaction, so a failing step leaves the
+//! database at the previous version. After a step commits, its checksum is
+//! recorded in the `metadata` table; a step whose checksum later differs from
+//! the recorded one has been edited after release and is reported.
+//!
+//! Steps marked [`Migration::destructive`] rewrite or drop existing data, so
+//! the database file is copied next to itself before they run.
 
-use rusqlite::Connection;
+use std::path::{Path, PathBuf};
 
+use chrono::{DateTime, Utc};
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, OptionalExtension};
+use tracing::{info, warn};
+
+use crate::capture::{Capture, CaptureType};
 use crate::error::{Error, Result};
 
-use super::schema::SCHEMA_STATEMENTS;
+use super::delta::{self, DEFAULT_KEYFRAME_INTERVAL};
+use super::history::{self, DEFAULT_SESSION_GAP};
+use super::schema::{
+    ADD_CAPTURE_ANNOTATION_COLUMNS, ADD_CAPTURE_CODEC_COLUMNS, ADD_CAPTURE_DELTA_COLUMNS,
+    ADD_CAPTURE_ENCRYPTED_COLUMN, ADD_CAPTURE_SESSION_COLUMNS, ADD_CAPTURE_TRASHED_COLUMN,
+    ADD_QUARANTINE_STORAGE_COLUMNS, ADD_SESSION_FIELD_COLUMN, CREATE_APP_INDEX, CREATE_BASE_INDEX,
+    CREATE_CAPTURES_FTS_DELETE_TRIGGER, CREATE_CAPTURES_FTS_TABLE_V2,
+    CREATE_CAPTURES_FTS_TRIGGERS_V2, CREATE_CAPTURES_TABLE_V1, CREATE_CAPTURES_V3_TABLE,
+    CREATE_HASH_INDEX, CREATE_HISTORY_TRIGGERS, CREATE_METADATA_TABLE, CREATE_OCCURRENCES_INDEX,
+    CREATE_OCCURRENCES_TABLE, CREATE_QUARANTINE_TABLE, CREATE_SESSIONS_INDEX,
+    CREATE_SESSIONS_TABLE, CREATE_SESSION_VERSION_INDEX, CREATE_TAGS_DELETE_TRIGGER,
+    CREATE_TAGS_INDEX, CREATE_TAGS_TABLE, CREATE_TAGS_V13_TABLE, CREATE_TAG_HASH_INDEX,
+    CREATE_TIMESTAMP_INDEX, CREATE_TIMESTAMP_INDEX_V1, CREATE_TRASH_INDEX, CREATE_TYPE_INDEX,
+    ENABLE_CAPTURES_FTS_SECURE_DELETE, REBUILD_CAPTURES_FTS_V2, REPLACE_CAPTURES_FTS,
+    REPLACE_CAPTURES_WITH_V3, REPLACE_TAGS_WITH_V13,
+};
 
 /// The current schema version.
-pub const CURRENT_VERSION: i32 = 1;
+pub const CURRENT_VERSION: i32 = 13;
 
 /// Key used to store the schema version in the metadata table.
 const VERSION_KEY: &str = "schema_version";
 
+/// One migration: the changes taking the schema to a new version.
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    /// The schema version this migration leads to.
+    pub version: i32,
+    /// What the migration changes.
+    pub description: &'static str,
+    /// The steps of the migration, run in order in one transaction.
+    pub steps: &'static [Step],
+    /// Whether the migration rewrites or drops existing data. The database
+    /// file is backed up before a destructive migration runs.
+    pub destructive: bool,
+}
+
+/// One step of a [`Migration`].
+#[derive(Debug, Clone, Copy)]
+pub enum Step {
+    /// SQL run as a batch.
+    Sql(&'static str),
+    /// A data conversion, for changes SQL alone cannot express.
+    Convert(fn(&Connection) -> Result<()>),
+}
+
+impl Migration {
+    /// Checksum of the migration's definition, recorded when it is applied.
+    ///
+    /// Covers the version, description and SQL. Changes inside a
+    /// [`Step::Convert`] function are not detected, so a migration whose
+    /// conversion changes must also change its description.
+    #[must_use]
+    pub fn checksum(&self) -> String {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&self.version.to_le_bytes());
+        hasher.update(self.description.as_bytes());
+        for step in self.steps {
+            match step {
+                Step::Sql(sql) => {
+                    hasher.update(&[0]);
+                    hasher.update(sql.as_bytes());
+                }
+                Step::Convert(_) => {
+                    hasher.update(&[1]);
+                }
+            }
+        }
+        hasher.update(&[u8::from(self.destructive)]);
+        hasher.finalize().to_hex().to_string()
+    }
+}
+
+/// Every migration, in version order. The last one is [`CURRENT_VERSION`].
+///
+/// Released migrations must never change: the SQL they run is kept in
+/// [`schema`](super::schema) under versioned names even after later
+/// migrations replace it.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        steps: &[
+            Step::Sql(CREATE_CAPTURES_TABLE_V1),
+            Step::Sql(CREATE_TIMESTAMP_INDEX_V1),
+            Step::Sql(CREATE_HASH_INDEX),
+            Step::Sql(CREATE_APP_INDEX),
+            Step::Sql(CREATE_TYPE_INDEX),
+            Step::Sql(CREATE_METADATA_TABLE),
+        ],
+        destructive: false,
+    },
+    Migration {
+        version: 2,
+        description: "full-text search index",
+        steps: &[
+            Step::Sql(CREATE_CAPTURES_FTS_TABLE_V2),
+            Step::Sql(CREATE_CAPTURES_FTS_TRIGGERS_V2[0]),
+            Step::Sql(CREATE_CAPTURES_FTS_TRIGGERS_V2[1]),
+            Step::Sql(CREATE_CAPTURES_FTS_TRIGGERS_V2[2]),
+            Step::Sql(REBUILD_CAPTURES_FTS_V2),
+        ],
+        destructive: false,
+    },
+    Migration {
+        version: 3,
+        description: "integer microsecond timestamps",
+        steps: &[
+            Step::Sql(CREATE_QUARANTINE_TABLE),
+            Step::Sql(CREATE_CAPTURES_V3_TABLE),
+            Step::Convert(convert_timestamps_v3),
+            Step::Sql(REPLACE_CAPTURES_WITH_V3),
+            Step::Sql(CREATE_TIMESTAMP_INDEX),
+            Step::Sql(CREATE_HASH_INDEX),
+            Step::Sql(CREATE_APP_INDEX),
+            Step::Sql(CREATE_TYPE_INDEX),
+            Step::Sql(CREATE_CAPTURES_FTS_TRIGGERS_V2[0]),
+            Step::Sql(CREATE_CAPTURES_FTS_TRIGGERS_V2[1]),
+            Step::Sql(CREATE_CAPTURES_FTS_TRIGGERS_V2[2]),
+            Step::Sql(REBUILD_CAPTURES_FTS_V2),
+        ],
+        destructive: true,
+    },
+    Migration {
+        version: 4,
+        description: "draft sessions and occurrences",
+        steps: &[
+            Step::Sql(CREATE_SESSIONS_TABLE),
+            Step::Sql(CREATE_SESSIONS_INDEX),
+            Step::Sql(ADD_CAPTURE_SESSION_COLUMNS),
+            Step::Sql(CREATE_SESSION_VERSION_INDEX),
+            Step::Sql(CREATE_OCCURRENCES_TABLE),
+            Step::Sql(CREATE_OCCURRENCES_INDEX),
+            Step::Sql(CREATE_HISTORY_TRIGGERS[0]),
+            Step::Sql(CREATE_HISTORY_TRIGGERS[1]),
+            Step::Convert(group_drafts_v4),
+        ],
+        destructive: false,
+    },
+    Migration {
+        version: 5,
+        description: "delta-compressed draft versions",
+        steps: &[
+            Step::Sql(ADD_CAPTURE_DELTA_COLUMNS),
+            Step::Sql(CREATE_BASE_INDEX),
+            Step::Sql(REPLACE_CAPTURES_FTS),
+            Step::Sql(CREATE_CAPTURES_FTS_DELETE_TRIGGER),
+            Step::Convert(encode_drafts_v5),
+        ],
+        destructive: true,
+    },
+    Migration {
+        version: 6,
+        description: "content compression",
+        steps: &[
+            Step::Sql(ADD_CAPTURE_CODEC_COLUMNS),
+            Step::Convert(measure_content_v6),
+        ],
+        destructive: false,
+    },
+    Migration {
+        version: 7,
+        description: "encryption at rest",
+        steps: &[Step::Sql(ADD_CAPTURE_ENCRYPTED_COLUMN)],
+        destructive: false,
+    },
+    Migration {
+        version: 8,
+        description: "trash",
+        steps: &[
+            Step::Sql(ADD_CAPTURE_TRASHED_COLUMN),
+            Step::Sql(CREATE_TRASH_INDEX),
+        ],
+        destructive: false,
+    },
+    Migration {
+        version: 9,
+        description: "pins, tags and notes",
+        steps: &[
+            Step::Sql(ADD_CAPTURE_ANNOTATION_COLUMNS),
+            Step::Sql(CREATE_TAGS_TABLE),
+            Step::Sql(CREATE_TAGS_INDEX),
+            Step::Sql(CREATE_TAGS_DELETE_TRIGGER),
+        ],
+        destructive: false,
+    },
+    Migration {
+        version: 10,
+        description: "quarantine of stored captures",
+        steps: &[Step::Sql(ADD_QUARANTINE_STORAGE_COLUMNS)],
+        destructive: false,
+    },
+    Migration {
+        version: 11,
+        description: "text field identity of draft sessions",
+        steps: &[Step::Sql(ADD_SESSION_FIELD_COLUMN)],
+        destructive: false,
+    },
+    Migration {
+        version: 12,
+        description: "secure delete in the search index",
+        steps: &[Step::Sql(ENABLE_CAPTURES_FTS_SECURE_DELETE)],
+        destructive: false,
+    },
+    Migration {
+        version: 13,
+        description: "tags looked up by hash",
+        steps: &[
+            Step::Sql(CREATE_TAGS_V13_TABLE),
+            Step::Convert(hash_tags_v13),
+            Step::Sql(REPLACE_TAGS_WITH_V13),
+            Step::Sql(CREATE_TAG_HASH_INDEX),
+            Step::Sql(CREATE_TAGS_DELETE_TRIGGER),
+        ],
+        destructive: true,
+    },
+];
+
 /// Initialize the database schema.
 ///
-/// Creates all tables and indexes if they don't exist, then runs any
-/// pending migrations to bring the schema up to the current version.
+/// Brings the schema up to [`CURRENT_VERSION`] by running every pending
+/// migration. `path` is the database file, used for backups before
+/// destructive migrations; pass `None` for in-memory databases.
 ///
 /// # Errors
 ///
-/// Returns an error if schema creation or migration fails.
-pub fn initialize_schema(conn: &Connection) -> Result<()> {
-    // Create base schema
-    for statement in SCHEMA_STATEMENTS {
-        conn.execute(statement, [])?;
+/// Returns [`Error::SchemaTooNew`] if the database was created by a newer
+/// release, or an error if a migration fails.
+pub fn initialize_schema(conn: &Connection, path: Option<&Path>) -> Result<()> {
+    run_migrations(conn, MIGRATIONS, path)
+}
+
+/// Read the schema version of a database without migrating it, checking
+/// that this release can open it. Older versions are migrated when the
+/// database is opened.
+///
+/// # Errors
+///
+/// Returns [`Error::SchemaTooNew`] if the database was created by a newer
+/// release, or [`Error::DatabaseMigration`] if it has no schema version, as
+/// for a file that is not a flightrecorder database.
+pub fn check_schema_version(conn: &Connection) -> Result<i32> {
+    let has_metadata = conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'metadata'",
+            [],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+    let version = if has_metadata {
+        get_schema_version(conn)?
+    } else {
+        0
+    };
+    if version == 0 {
+        return Err(Error::DatabaseMigration {
+            message: "not a flightrecorder database: it has no schema version".to_string(),
+        });
+    }
+    if version > CURRENT_VERSION {
+        return Err(Error::SchemaTooNew {
+            found: version,
+            supported: CURRENT_VERSION,
+        });
     }
+    Ok(version)
+}
+
+/// Apply the migrations in `registry` that are newer than the database.
+fn run_migrations(conn: &Connection, registry: &[Migration], path: Option<&Path>) -> Result<()> {
+    conn.execute(CREATE_METADATA_TABLE, [])?;
 
-    // Check and run migrations
     let version = get_schema_version(conn)?;
-    if version < CURRENT_VERSION {
-        run_migrations(conn, version)?;
+    let latest = registry.last().map_or(0, |m| m.version);
+    if version > latest {
+        return Err(Error::SchemaTooNew {
+            found: version,
+            supported: latest,
+        });
+    }
+
+    for migration in registry {
+        if migration.version <= version {
+            verify_checksum(conn, migration)?;
+            continue;
+        }
+        if migration.destructive {
+            if let Some(path) = path {
+                let backup = backup_path(path, migration.version - 1);
+                backup_database(conn, &backup)?;
+                info!(
+                    "Backed up database to {} before migration {}",
+                    backup.display(),
+                    migration.version
+                );
+            }
+        }
+        apply(conn, migration).map_err(|e| Error::DatabaseMigration {
+            message: format!(
+                "migration {} ({}) failed: {e}",
+                migration.version, migration.description
+            ),
+        })?;
+        info!(
+            "Migrated database to version {} ({})",
+            migration.version, migration.description
+        );
+    }
+    Ok(())
+}
+
+/// Run one migration in its own transaction, recording the new version and
+/// the step's checksum.
+fn apply(conn: &Connection, migration: &Migration) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for step in migration.steps {
+        match step {
+            Step::Sql(sql) => tx.execute_batch(sql)?,
+            Step::Convert(convert) => convert(&tx)?,
+        }
+    }
+    set_schema_version(&tx, migration.version)?;
+    tx.execute(
+        "INSERT OR REPLACE INTO metadata (key, value) VALUES (?1, ?2)",
+        (checksum_key(migration.version), migration.checksum()),
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Metadata key holding the checksum of an applied migration.
+fn checksum_key(version: i32) -> String {
+    format!("migration_{version}_checksum")
+}
+
+/// Warn if an applied migration no longer matches the checksum recorded
+/// when it ran. Databases migrated before checksums were recorded have none.
+fn verify_checksum(conn: &Connection, migration: &Migration) -> Result<()> {
+    let recorded: Option<String> = conn
+        .query_row(
+            "SELECT value FROM metadata WHERE key = ?1",
+            [checksum_key(migration.version)],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(recorded) = recorded {
+        if recorded != migration.checksum() {
+            warn!(
+                "Migration {} ({}) has changed since it was applied to this database",
+                migration.version, migration.description
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Path of the backup taken before migrating away from `version`.
+fn backup_path(path: &Path, version: i32) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{version}.bak"));
+    path.with_file_name(name)
+}
+
+/// Write a consistent copy of the database to `backup`, replacing any
+/// earlier backup there.
+fn backup_database(conn: &Connection, backup: &Path) -> Result<()> {
+    match std::fs::remove_file(backup) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let target = backup.to_str().ok_or_else(|| Error::DatabaseMigration {
+        message: format!("backup path is not valid UTF-8: {}", backup.display()),
+    })?;
+    conn.execute("VACUUM INTO ?1", [target])?;
+    Ok(())
+}
+
+/// Copy every capture into `captures_v3`, converting its RFC 3339 timestamp
+/// to microseconds since the Unix epoch. Rows whose timestamp cannot be
+/// parsed are moved to the quarantine table instead.
+fn convert_timestamps_v3(conn: &Connection) -> Result<()> {
+    let mut select = conn.prepare(
+        "SELECT id, timestamp, source_app, content, content_hash, capture_type, created_at
+         FROM captures ORDER BY id",
+    )?;
+    let mut insert = conn.prepare(
+        "INSERT INTO captures_v3
+            (id, timestamp, source_app, content, content_hash, capture_type, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )?;
+    let mut quarantine = conn.prepare(
+        "INSERT INTO quarantine
+            (capture_id, timestamp, source_app, content, content_hash, capture_type,
+             reason, quarantined_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?;
+    let now = Utc::now().timestamp_micros();
+
+    let mut rows = select.query([])?;
+    let mut quarantined = 0;
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let raw: Value = row.get(1)?;
+        let source_app: Option<String> = row.get(2)?;
+        let content: Value = row.get(3)?;
+        let content_hash: Value = row.get(4)?;
+        let capture_type: Value = row.get(5)?;
+        let created_at: Value = row.get(6)?;
+
+        let parsed = match &raw {
+            Value::Text(text) => DateTime::parse_from_rfc3339(text).ok(),
+            _ => None,
+        };
+        if let Some(timestamp) = parsed {
+            insert.execute(params![
+                id,
+                timestamp.timestamp_micros(),
+                source_app,
+                content,
+                content_hash,
+                capture_type,
+                created_at,
+            ])?;
+        } else {
+            quarantine.execute(params![
+                id,
+                raw,
+                source_app,
+                content,
+                content_hash,
+                capture_type,
+                "unparseable timestamp",
+                now,
+            ])?;
+            quarantined += 1;
+        }
+    }
+    if quarantined > 0 {
+        warn!("Quarantined {quarantined} captures with unparseable timestamps");
+    }
+
+    // Keep AUTOINCREMENT from reusing the ids of captures deleted earlier.
+    conn.execute("DELETE FROM sqlite_sequence WHERE name = 'captures_v3'", [])?;
+    conn.execute(
+        "INSERT INTO sqlite_sequence (name, seq)
+         SELECT 'captures_v3', MAX(COALESCE((SELECT seq FROM sqlite_sequence WHERE name = 'captures'), 0),
+                                   COALESCE((SELECT MAX(id) FROM captures_v3), 0))",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Group the text field and keystroke captures already stored into draft
+/// sessions, as if they had been inserted with the default session gap.
+///
+/// Sessions are found and started with the SQL of version 4 rather than
+/// [`history`]'s, which relies on columns added by later versions.
+fn group_drafts_v4(conn: &Connection) -> Result<()> {
+    for capture_type in [CaptureType::TextField, CaptureType::Keystroke] {
+        let rows: Vec<(i64, i64, Option<String>)> = conn
+            .prepare(
+                "SELECT id, timestamp, source_app FROM captures
+                 WHERE capture_type = ?1 ORDER BY timestamp, id",
+            )?
+            .query_map([capture_type.to_string()], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<std::result::Result<_, _>>()?;
+
+        for (id, timestamp, source_app) in rows {
+            let app = source_app.as_deref();
+            let open: Option<(i64, u32)> = conn
+                .query_row(
+                    r"
+                    SELECT s.id, c.version
+                    FROM sessions s JOIN captures c ON c.session_id = s.id
+                    WHERE s.capture_type = ?1 AND s.source_app IS ?2 AND s.updated_at >= ?3
+                    ORDER BY s.updated_at DESC, c.version DESC
+                    LIMIT 1
+                    ",
+                    params![
+                        capture_type.to_string(),
+                        app,
+                        timestamp.saturating_sub(history::gap_micros(DEFAULT_SESSION_GAP)),
+                    ],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+            let (session_id, version) = if let Some((session_id, latest_version)) = open {
+                history::touch_session(conn, session_id, timestamp)?;
+                (session_id, latest_version + 1)
+            } else {
+                conn.execute(
+                    "INSERT INTO sessions (capture_type, source_app, started_at, updated_at)
+                     VALUES (?1, ?2, ?3, ?3)",
+                    params![capture_type.to_string(), app, timestamp],
+                )?;
+                (conn.last_insert_rowid(), 1)
+            };
+            conn.execute(
+                "UPDATE captures SET session_id = ?1, version = ?2 WHERE id = ?3",
+                params![session_id, version, id],
+            )?;
+        }
     }
+    Ok(())
+}
+
+/// Index every capture in the contentless search index, and store the
+/// versions of each draft as deltas between keyframes, as if they had been
+/// inserted with the default keyframe interval.
+fn encode_drafts_v5(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "INSERT INTO captures_fts (rowid, content) SELECT id, content FROM captures",
+        [],
+    )?;
+
+    let versions: Vec<(i64, i64, u32)> = conn
+        .prepare(
+            "SELECT id, session_id, version FROM captures
+             WHERE session_id IS NOT NULL ORDER BY session_id, version",
+        )?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<std::result::Result<_, _>>()?;
+
+    let mut previous: Option<(i64, i64, String)> = None;
+    for (id, session_id, version) in versions {
+        let content: String =
+            conn.query_row("SELECT content FROM captures WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })?;
+        if let Some((base_session, base_id, base)) = &previous {
+            if *base_session == session_id
+                && !delta::is_keyframe(version, DEFAULT_KEYFRAME_INTERVAL)
+            {
+                if let Some(delta) = delta::encode(base, &content) {
+                    conn.execute(
+                        "UPDATE captures SET content = '', base_id = ?2, delta = ?3 WHERE id = ?1",
+                        params![id, base_id, delta],
+                    )?;
+                }
+            }
+        }
+        previous = Some((session_id, id, content));
+    }
+    Ok(())
+}
 
+/// Record the size of every capture's content. Nothing is compressed yet,
+/// so full content is measured directly, and a delta records the length of
+/// the content it rebuilds.
+fn measure_content_v6(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "UPDATE captures SET content_size = octet_length(content) WHERE base_id IS NULL",
+        [],
+    )?;
+    let deltas: Vec<(i64, Vec<u8>)> = conn
+        .prepare("SELECT id, delta FROM captures WHERE base_id IS NOT NULL")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<std::result::Result<_, _>>()?;
+    for (id, delta) in deltas {
+        let size = delta::content_len(&delta).ok_or_else(|| Error::DatabaseMigration {
+            message: format!("capture {id} has a corrupt delta"),
+        })?;
+        conn.execute(
+            "UPDATE captures SET content_size = ?2 WHERE id = ?1",
+            params![id, i64::try_from(size).unwrap_or(i64::MAX)],
+        )?;
+    }
+    Ok(())
+}
+
+/// Migration 13: copy every tag to `capture_tags_v13` with its hash.
+///
+/// Tags are copied in the clear, hashed as in an unencrypted database;
+/// opening an encrypted database encrypts them (see
+/// [`annotations::encrypt_plain`](super::annotations::encrypt_plain)).
+fn hash_tags_v13(conn: &Connection) -> Result<()> {
+    let tags: Vec<(i64, String)> = conn
+        .prepare("SELECT capture_id, tag FROM capture_tags")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<std::result::Result<_, _>>()?;
+    let mut insert = conn
+        .prepare("INSERT INTO capture_tags_v13 (capture_id, tag_hash, tag) VALUES (?1, ?2, ?3)")?;
+    for (id, tag) in tags {
+        insert.execute(params![id, Capture::compute_hash(&tag), tag])?;
+    }
     Ok(())
 }
 
@@ -66,39 +642,6 @@ fn set_schema_version(conn: &Connection, version: i32) -> Result<()> {
     Ok(())
 }
 
-/// Run migrations from the given version to the current version.
-fn run_migrations(conn: &Connection, from_version: i32) -> Result<()> {
-    let mut current = from_version;
-
-    while current < CURRENT_VERSION {
-        current += 1;
-        run_migration(conn, current)?;
-    }
-
-    set_schema_version(conn, CURRENT_VERSION)?;
-    Ok(())
-}
-
-/// Run a specific migration version.
-fn run_migration(conn: &Connection, version: i32) -> Result<()> {
-    match version {
-        1 => migrate_v1(conn),
-        _ => Err(Error::DatabaseMigration {
-            message: format!("unknown migration version: {version}"),
-        }),
-    }
-}
-
-/// Migration to version 1 (initial schema).
-///
-/// This is a no-op since version 1 is the base schema created by `SCHEMA_STATEMENTS`.
-fn migrate_v1(conn: &Connection) -> Result<()> {
-    // Version 1 is the initial schema, which is already created.
-    // Just set the version.
-    set_schema_version(conn, 1)?;
-    Ok(())
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -110,7 +653,7 @@ mod tests {
     #[test]
     fn test_initialize_schema_creates_tables() {
         let conn = create_test_db();
-        initialize_schema(&conn).expect("failed to initialize schema");
+        initialize_schema(&conn, None).expect("failed to initialize schema");
 
         // Verify captures table exists
         let count: i32 = conn
@@ -136,7 +679,7 @@ mod tests {
     #[test]
     fn test_initialize_schema_sets_version() {
         let conn = create_test_db();
-        initialize_schema(&conn).expect("failed to initialize schema");
+        initialize_schema(&conn, None).expect("failed to initialize schema");
 
         let version = get_schema_version(&conn).unwrap();
         assert_eq!(version, CURRENT_VERSION);
@@ -147,8 +690,8 @@ mod tests {
         let conn = create_test_db();
 
         // Initialize twice - should not error
-        initialize_schema(&conn).expect("first init failed");
-        initialize_schema(&conn).expect("second init failed");
+        initialize_schema(&conn, None).expect("first init failed");
+        initialize_schema(&conn, None).expect("second init failed");
 
         let version = get_schema_version(&conn).unwrap();
         assert_eq!(version, CURRENT_VERSION);
@@ -200,24 +743,432 @@ mod tests {
 
     #[test]
     fn test_current_version_constant() {
-        assert!(CURRENT_VERSION >= 1);
+        const _: () = assert!(CURRENT_VERSION >= 1);
+    }
+
+    #[test]
+    fn test_registry_is_in_version_order() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i32::try_from(i).unwrap() + 1);
+        }
+        assert_eq!(MIGRATIONS.last().unwrap().version, CURRENT_VERSION);
+    }
+
+    #[test]
+    fn test_newer_schema_is_refused() {
+        let conn = create_test_db();
+        initialize_schema(&conn, None).unwrap();
+        set_schema_version(&conn, CURRENT_VERSION + 1).unwrap();
+
+        let err = initialize_schema(&conn, None).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::SchemaTooNew { found, supported }
+                if found == CURRENT_VERSION + 1 && supported == CURRENT_VERSION
+        ));
+    }
+
+    #[test]
+    fn test_checksums_are_recorded() {
+        let conn = create_test_db();
+        initialize_schema(&conn, None).unwrap();
+
+        for migration in MIGRATIONS {
+            let recorded: String = conn
+                .query_row(
+                    "SELECT value FROM metadata WHERE key = ?1",
+                    [checksum_key(migration.version)],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(recorded, migration.checksum());
+        }
+        assert_ne!(MIGRATIONS[0].checksum(), MIGRATIONS[1].checksum());
+        assert!(MIGRATIONS.iter().any(|m| m.destructive));
+    }
+
+    fn fail(_: &Connection) -> Result<()> {
+        Err(Error::internal("conversion failed"))
+    }
+
+    #[test]
+    fn test_check_schema_version() {
+        let conn = create_test_db();
+        assert!(matches!(
+            check_schema_version(&conn),
+            Err(Error::DatabaseMigration { .. })
+        ));
+
+        initialize_schema(&conn, None).unwrap();
+        assert_eq!(check_schema_version(&conn).unwrap(), CURRENT_VERSION);
+
+        set_schema_version(&conn, 2).unwrap();
+        assert_eq!(check_schema_version(&conn).unwrap(), 2);
+        set_schema_version(&conn, CURRENT_VERSION + 1).unwrap();
+        assert!(matches!(
+            check_schema_version(&conn),
+            Err(Error::SchemaTooNew { .. })
+        ));
+    }
+
+    #[test]
+    fn test_failed_step_is_rolled_back() {
+        let conn = create_test_db();
+        let registry = [
+            MIGRATIONS[0],
+            Migration {
+                version: 2,
+                description: "half-finished step",
+                steps: &[
+                    Step::Sql("CREATE TABLE scratch (x INTEGER)"),
+                    Step::Convert(fail),
+                ],
+                destructive: false,
+            },
+        ];
+
+        let err = run_migrations(&conn, &registry, None).unwrap_err();
+        assert!(err.to_string().contains("migration 2 (half-finished step)"));
+        assert!(err.to_string().contains("conversion failed"));
+
+        // Step 1 stays applied; step 2 left nothing behind.
+        assert_eq!(get_schema_version(&conn).unwrap(), 1);
+        let scratch: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name = 'scratch'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(scratch, 0);
+    }
+
+    #[test]
+    fn test_destructive_step_backs_up_first() {
+        let dir = std::env::temp_dir().join(format!("fr_migrations_backup_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("captures.db");
+        let conn = Connection::open(&path).unwrap();
+        initialize_schema(&conn, Some(&path)).unwrap();
+        conn.execute(
+            "INSERT INTO captures (timestamp, content, content_hash, capture_type)
+             VALUES (1704067200000000, 'precious', 'h1', 'clipboard')",
+            [],
+        )
+        .unwrap();
+
+        let mut registry = MIGRATIONS.to_vec();
+        registry.push(Migration {
+            version: CURRENT_VERSION + 1,
+            description: "drop captures",
+            steps: &[Step::Sql("DELETE FROM captures")],
+            destructive: true,
+        });
+        run_migrations(&conn, &registry, Some(&path)).unwrap();
+
+        let backup =
+            Connection::open(dir.join(format!("captures.db.v{CURRENT_VERSION}.bak"))).unwrap();
+        let content: String = backup
+            .query_row("SELECT content FROM captures", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(content, "precious");
+        assert_eq!(get_schema_version(&backup).unwrap(), CURRENT_VERSION);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_migration_v2_backfills_search_index() {
+        let conn = create_test_db();
+        run_migrations(&conn, &MIGRATIONS[..1], None).unwrap();
+        conn.execute(
+            "INSERT INTO captures (timestamp, content, content_hash, capture_type)
+             VALUES ('2024-01-01T00:00:00+00:00', 'existing capture', 'h1', 'clipboard')",
+            [],
+        )
+        .unwrap();
+
+        run_migrations(&conn, &MIGRATIONS[..2], None).unwrap();
+        assert_eq!(get_schema_version(&conn).unwrap(), 2);
+
+        let matches = |query: &str| -> i64 {
+            conn.query_row(
+                "SELECT COUNT(*) FROM captures_fts WHERE captures_fts MATCH ?1",
+                [query],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(matches("existing"), 1);
+
+        // The triggers keep the index in sync from now on.
+        conn.execute(
+            "INSERT INTO captures (timestamp, content, content_hash, capture_type)
+             VALUES (1704153600000000, 'new capture', 'h2', 'clipboard')",
+            [],
+        )
+        .unwrap();
+        assert_eq!(matches("capture"), 2);
+        conn.execute(
+            "UPDATE captures SET content = 'edited' WHERE content_hash = 'h2'",
+            [],
+        )
+        .unwrap();
+        assert_eq!(matches("new"), 0);
+        assert_eq!(matches("edited"), 1);
+        conn.execute("DELETE FROM captures WHERE content_hash = 'h1'", [])
+            .unwrap();
+        assert_eq!(matches("existing"), 0);
+    }
+
+    #[test]
+    fn test_migration_v3_converts_timestamps() {
+        let conn = create_test_db();
+        run_migrations(&conn, &MIGRATIONS[..2], None).unwrap();
+        let rows = [
+            (1, "2024-01-01T12:00:00+00:00", "utc noon"),
+            // An hour earlier than noon UTC, though it sorts later as text.
+            (2, "2024-01-01T12:00:00+01:00", "paris noon"),
+            (3, "2024-01-01T12:00:00.000123Z", "with micros"),
+            (4, "yesterday-ish", "corrupt"),
+            (5, "2024-01-02T00:00:00+00:00", "deleted"),
+        ];
+        for (id, timestamp, content) in rows {
+            conn.execute(
+                "INSERT INTO captures (id, timestamp, content, content_hash, capture_type)
+                 VALUES (?1, ?2, ?3, ?3, 'clipboard')",
+                params![id, timestamp, content],
+            )
+            .unwrap();
+        }
+        conn.execute("DELETE FROM captures WHERE id = 5", [])
+            .unwrap();
+
+        run_migrations(&conn, &MIGRATIONS[..3], None).unwrap();
+
+        let converted: Vec<(i64, i64)> = conn
+            .prepare("SELECT id, timestamp FROM captures ORDER BY timestamp")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        let noon = 1_704_110_400_000_000;
+        assert_eq!(
+            converted,
+            [(2, noon - 3_600_000_000), (1, noon), (3, noon + 123)]
+        );
+
+        let (capture_id, raw, reason): (i64, String, String) = conn
+            .query_row(
+                "SELECT capture_id, timestamp, reason FROM quarantine",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(capture_id, 4);
+        assert_eq!(raw, "yesterday-ish");
+        assert!(reason.contains("timestamp"));
+
+        // Text timestamps are refused from now on.
+        let text = conn.execute(
+            "INSERT INTO captures (timestamp, content, content_hash, capture_type)
+             VALUES ('2024-01-03T00:00:00Z', 'text', 'text', 'clipboard')",
+            [],
+        );
+        assert!(text.is_err());
+
+        // Ids of deleted captures are not reused, and search still works.
+        conn.execute(
+            "INSERT INTO captures (timestamp, content, content_hash, capture_type)
+             VALUES (0, 'after migration', 'h6', 'clipboard')",
+            [],
+        )
+        .unwrap();
+        assert_eq!(conn.last_insert_rowid(), 6);
+        let matches: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM captures_fts WHERE captures_fts MATCH 'noon OR migration'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(matches, 3);
+    }
+
+    #[test]
+    fn test_migration_v4_groups_existing_drafts() {
+        let conn = create_test_db();
+        run_migrations(&conn, &MIGRATIONS[..3], None).unwrap();
+        let minute: i64 = 60_000_000;
+        let rows = [
+            (1, 0, "Mail", "text_field"),
+            (2, minute, "Mail", "text_field"),
+            (3, 2 * minute, "Notes", "text_field"),
+            (4, 3 * minute, "Mail", "clipboard"),
+            // Long after the last Mail snapshot: a new draft.
+            (5, 60 * minute, "Mail", "text_field"),
+        ];
+        for (id, timestamp, app, capture_type) in rows {
+            conn.execute(
+                "INSERT INTO captures (id, timestamp, source_app, content, content_hash, capture_type)
+                 VALUES (?1, ?2, ?3, ?1, ?1, ?4)",
+                params![id, timestamp, app, capture_type],
+            )
+            .unwrap();
+        }
+
+        initialize_schema(&conn, None).unwrap();
+
+        let grouped: Vec<(i64, Option<i64>, Option<i64>)> = conn
+            .prepare("SELECT id, session_id, version FROM captures ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        let session = |id: usize| grouped[id - 1].1;
+        assert_eq!(session(1), session(2));
+        assert_ne!(session(1), session(3));
+        assert_ne!(session(1), session(5));
+        assert_eq!(grouped[1].2, Some(2));
+        assert_eq!(grouped[3], (4, None, None));
+        assert_eq!(grouped[4].2, Some(1));
+    }
+
+    #[test]
+    fn test_migration_v5_encodes_existing_drafts() {
+        use std::fmt::Write;
+
+        let conn = create_test_db();
+        run_migrations(&conn, &MIGRATIONS[..4], None).unwrap();
+        let mut expected = vec![String::new()];
+        let mut draft = "Dear team, here are the notes from this week's planning.\n".repeat(4);
+        for version in 1..=20 {
+            write!(draft, " sentence number {version} of a long draft.").unwrap();
+            expected.push(draft.clone());
+        }
+        conn.execute(
+            "INSERT INTO sessions (id, capture_type, started_at, updated_at)
+             VALUES (1, 'text_field', 0, 0)",
+            [],
+        )
+        .unwrap();
+        for (version, content) in expected.iter().enumerate().skip(1) {
+            conn.execute(
+                "INSERT INTO captures
+                    (id, timestamp, content, content_hash, capture_type, session_id, version)
+                 VALUES (?1, ?1, ?2, ?1, 'text_field', 1, ?1)",
+                params![i64::try_from(version).unwrap(), content],
+            )
+            .unwrap();
+        }
+
+        initialize_schema(&conn, None).unwrap();
+
+        let bases: Vec<Option<i64>> = conn
+            .prepare("SELECT base_id FROM captures ORDER BY version")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(bases[0], None);
+        assert_eq!(bases[1], Some(1));
+        assert_eq!(bases[15], Some(15));
+        assert_eq!(bases[16], None);
+        assert_eq!(bases[17], Some(17));
+        for (id, content) in expected.iter().enumerate().skip(1) {
+            let id = i64::try_from(id).unwrap();
+            assert_eq!(&delta::load_content(&conn, None, id).unwrap(), content);
+        }
+
+        // The whole draft is still searchable, and deletes reach the index.
+        let matches = |query: &str| -> i64 {
+            conn.query_row(
+                "SELECT COUNT(*) FROM captures_fts WHERE captures_fts MATCH ?1",
+                [query],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(matches("\"number 20\""), 1);
+        assert_eq!(matches("dear"), 20);
+        conn.execute("DELETE FROM captures WHERE id = 20", [])
+            .unwrap();
+        assert_eq!(matches("\"number 20\""), 0);
     }
 
     #[test]
-    fn test_run_migration_unknown_version() {
+    fn test_migration_v6_measures_content() {
         let conn = create_test_db();
-        initialize_schema(&conn).unwrap();
+        run_migrations(&conn, &MIGRATIONS[..5], None).unwrap();
+        let first = "é".repeat(100);
+        let second = format!("{first} and more");
+        conn.execute(
+            "INSERT INTO captures (id, timestamp, content, content_hash, capture_type)
+             VALUES (1, 0, ?1, 'h1', 'text_field')",
+            [&first],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO captures (id, timestamp, content, content_hash, capture_type, base_id, delta)
+             VALUES (2, 1, '', 'h2', 'text_field', 1, ?1)",
+            [delta::encode(&first, &second).unwrap()],
+        )
+        .unwrap();
+
+        initialize_schema(&conn, None).unwrap();
 
-        let result = run_migration(&conn, 999);
-        assert!(result.is_err());
-        let err = result.unwrap_err();
-        assert!(err.to_string().contains("unknown migration version"));
+        let sizes: Vec<(i64, Option<String>)> = conn
+            .prepare("SELECT content_size, codec FROM captures ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        let len = |text: &str| i64::try_from(text.len()).unwrap();
+        assert_eq!(sizes, [(len(&first), None), (len(&second), None)]);
+        assert_eq!(delta::load_content(&conn, None, 2).unwrap(), second);
+    }
+
+    #[test]
+    fn test_migration_v13_hashes_tags() {
+        let conn = create_test_db();
+        run_migrations(&conn, &MIGRATIONS[..12], None).unwrap();
+        conn.execute_batch(
+            "INSERT INTO captures (id, timestamp, content, content_hash, capture_type)
+             VALUES (1, 0, 'report', 'h1', 'text_field');
+             INSERT INTO capture_tags (capture_id, tag) VALUES (1, 'outage'), (1, 'incident');",
+        )
+        .unwrap();
+
+        initialize_schema(&conn, None).unwrap();
+
+        let tags: Vec<(String, String)> = conn
+            .prepare("SELECT tag_hash, tag FROM capture_tags ORDER BY tag")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        let hashed = |tag: &str| (Capture::compute_hash(tag), tag.to_string());
+        assert_eq!(tags, [hashed("incident"), hashed("outage")]);
+
+        // Tags still go with their capture.
+        conn.execute("DELETE FROM captures WHERE id = 1", [])
+            .unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM capture_tags", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
     }
 
     #[test]
     fn test_indexes_created() {
         let conn = create_test_db();
-        initialize_schema(&conn).expect("failed to initialize schema");
+        initialize_schema(&conn, None).expect("failed to initialize schema");
 
         // Check that indexes exist
         let indexes: Vec<String> = conn
@@ -225,7 +1176,7 @@ mod tests {
             .unwrap()
             .query_map([], |row| row.get(0))
             .unwrap()
-            .filter_map(|r| r.ok())
+            .filter_map(std::result::Result::ok)
             .collect();
 
         assert!(indexes.iter().any(|n| n.contains("timestamp")));
@@ -3,31 +3,81 @@
 //! This module provides `SQLite`-based persistent storage for captured text,
 //! including deduplication, search, and pruning capabilities.
 
+mod annotations;
+mod backup;
+mod check;
+mod codec;
+mod crypto;
+mod delta;
+mod history;
 pub mod migrations;
+mod pool;
+mod query;
+mod retention;
 pub mod schema;
+mod search;
+mod trash;
 
+use std::collections::{BTreeMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use chrono::{DateTime, Duration, Utc};
-use rusqlite::{params, Connection, OptionalExtension};
+use chrono::{DateTime, Utc};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Params};
+use serde::{Deserialize, Serialize};
 use tracing::{debug, info, warn};
 
 use crate::capture::{Capture, CaptureType};
 use crate::error::{Error, Result};
+use crypto::Keys;
+
+pub use backup::{BackupInfo, RestoreInfo};
+pub use check::{CaptureProblem, CheckReport, Problem, RepairReport};
+pub use codec::DEFAULT_COMPRESSION_THRESHOLD;
+pub use crypto::{EncryptionKey, MIN_KEY_FILE_LEN};
+pub use delta::DEFAULT_KEYFRAME_INTERVAL;
+pub use history::DEFAULT_SESSION_GAP;
+pub use pool::ReadPool;
+pub use query::{CaptureQuery, Cursor, QueryOrder, QueryPage};
+pub use retention::{PrunePlan, PruneReport, RetentionPolicy, RetentionRule};
+pub use search::{SearchHit, HIGHLIGHT_END, HIGHLIGHT_START, SNIPPET_ELLIPSIS};
+pub use trash::TrashedCapture;
 
 /// Storage engine for captured text.
 ///
 /// Provides persistent storage using `SQLite` with support for:
-/// - Capture insertion with deduplication
-/// - Full-text search
-/// - Filtering by app, type, and time range
-/// - Automatic pruning of old entries
+/// - Capture insertion, keeping the versions of each text field draft (see
+///   [`Storage::history`]) and repeated clipboard content as occurrences
+/// - Delta compression of draft versions between periodic keyframes, and
+///   zstd compression of large content
+/// - Optional encryption of content at rest (see [`Storage::open_with_key`])
+/// - Queries combining full-text search with app, type and time range
+///   filters, with keyset pagination (see [`CaptureQuery`])
+/// - Pruning by age, number of captures and database size (see
+///   [`RetentionPolicy`])
+/// - A trash from which deleted captures can be restored (see
+///   [`Storage::delete`])
+/// - Pins that exempt captures from pruning, tags and notes (see
+///   [`Storage::pin`])
+/// - Checking for damaged rows and quarantining them (see
+///   [`Storage::check`])
+/// - Online backups, and restoring them (see [`Storage::backup`])
 #[derive(Debug)]
 pub struct Storage {
     /// Path to the database file.
     path: PathBuf,
     /// Database connection.
     conn: Connection,
+    /// Longest pause between two snapshots of the same draft.
+    session_gap: std::time::Duration,
+    /// Number of draft versions from one keyframe to the next.
+    keyframe_interval: u32,
+    /// Size in bytes above which content is compressed, if compression is
+    /// enabled.
+    compression_threshold: Option<usize>,
+    /// Keys of an encrypted database, shared with its read connections.
+    keys: Option<Arc<Keys>>,
 }
 
 impl Storage {
@@ -38,8 +88,26 @@ impl Storage {
     ///
     /// # Errors
     ///
-    /// Returns an error if the database cannot be opened or schema initialization fails.
+    /// Returns an error if the database cannot be opened or schema initialization fails,
+    /// or [`Error::DatabaseLocked`] if the database is encrypted.
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_key(path, None)
+    }
+
+    /// Open or create a storage database, encrypting content with `key`.
+    ///
+    /// An encrypted database can only be opened with the key it was
+    /// encrypted with. An unencrypted database opened with a key is
+    /// encrypted first, as [`Storage::rekey`] would, and one encrypted
+    /// before values were bound to their capture is encrypted again. Without
+    /// a key this is the same as [`Storage::open`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::DatabaseLocked`] if the database is encrypted and
+    /// `key` is missing or wrong, or an error if the database cannot be
+    /// opened or schema initialization fails.
+    pub fn open_with_key(path: impl AsRef<Path>, key: Option<&EncryptionKey>) -> Result<Self> {
         let path = path.as_ref().to_path_buf();
 
         // Create parent directories if needed
@@ -60,12 +128,88 @@ impl Storage {
 
         // Enable WAL mode for better concurrent read performance
         conn.execute_batch("PRAGMA journal_mode=WAL; PRAGMA synchronous=NORMAL;")?;
+        trash::enable_secure_delete(&conn)?;
+        retention::enable_incremental_vacuum(&conn)?;
 
         // Initialize schema
-        migrations::initialize_schema(&conn)?;
+        migrations::initialize_schema(&conn, Some(&path))?;
+
+        let encrypted = crypto::is_encrypted(&conn)?;
+        let keys = match key {
+            Some(key) if encrypted => Some(Arc::new(crypto::unlock(&conn, key)?)),
+            None if encrypted => {
+                return Err(Error::DatabaseLocked {
+                    message: "the database is encrypted and no key is configured".to_string(),
+                })
+            }
+            _ => None,
+        };
 
         info!("Database opened successfully at {}", path.display());
-        Ok(Self { path, conn })
+        let mut storage = Self {
+            path,
+            conn,
+            session_gap: DEFAULT_SESSION_GAP,
+            keyframe_interval: DEFAULT_KEYFRAME_INTERVAL,
+            compression_threshold: Some(DEFAULT_COMPRESSION_THRESHOLD),
+            keys,
+        };
+        match (key, &storage.keys) {
+            (Some(key), None) => storage.rekey(Some(key), false)?,
+            (_, Some(keys)) if keys.is_legacy_format() => storage.reseal()?,
+            (_, Some(keys)) => annotations::encrypt_plain(&storage.conn, keys)?,
+            (None, None) => {}
+        }
+        Ok(storage)
+    }
+
+    /// Open an existing database read-only, changing nothing in it.
+    ///
+    /// Unlike [`Storage::open_with_key`], this neither migrates the schema
+    /// nor encrypts an unencrypted database, so it is safe while the daemon
+    /// is writing to the database. `key` is only used to unlock an
+    /// encrypted database. Writes through the storage fail.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::DatabaseMigration`] if the database is not a
+    /// flightrecorder database or its schema needs migrating,
+    /// [`Error::SchemaTooNew`] if it was written by a newer release,
+    /// [`Error::DatabaseLocked`] if it is encrypted and `key` is missing or
+    /// wrong, or an error if it cannot be opened.
+    pub fn open_read_only(path: impl AsRef<Path>, key: Option<&EncryptionKey>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        debug!("Opening database at {} read-only", path.display());
+        let conn = pool::connect(&path)?;
+
+        let version = migrations::check_schema_version(&conn)?;
+        if version < migrations::CURRENT_VERSION {
+            return Err(Error::DatabaseMigration {
+                message: format!(
+                    "schema version {version} needs migrating to {}; \
+                     open the database read-write (start the daemon) first",
+                    migrations::CURRENT_VERSION
+                ),
+            });
+        }
+
+        let keys = if crypto::is_encrypted(&conn)? {
+            let key = key.ok_or_else(|| Error::DatabaseLocked {
+                message: "the database is encrypted and no key is configured".to_string(),
+            })?;
+            Some(Arc::new(crypto::unlock(&conn, key)?))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            path,
+            conn,
+            session_gap: DEFAULT_SESSION_GAP,
+            keyframe_interval: DEFAULT_KEYFRAME_INTERVAL,
+            compression_threshold: Some(DEFAULT_COMPRESSION_THRESHOLD),
+            keys,
+        })
     }
 
     /// Create an in-memory storage instance for testing.
@@ -79,11 +223,17 @@ impl Storage {
             source,
         })?;
 
-        migrations::initialize_schema(&conn)?;
+        trash::enable_secure_delete(&conn)?;
+        retention::enable_incremental_vacuum(&conn)?;
+        migrations::initialize_schema(&conn, None)?;
 
         Ok(Self {
             path: PathBuf::from(":memory:"),
             conn,
+            session_gap: DEFAULT_SESSION_GAP,
+            keyframe_interval: DEFAULT_KEYFRAME_INTERVAL,
+            compression_threshold: Some(DEFAULT_COMPRESSION_THRESHOLD),
+            keys: None,
         })
     }
 
@@ -93,57 +243,536 @@ impl Storage {
         &self.path
     }
 
+    /// Set the longest pause between two snapshots of the same draft (see
+    /// [`DEFAULT_SESSION_GAP`]).
+    #[must_use]
+    pub fn with_session_gap(mut self, gap: std::time::Duration) -> Self {
+        self.session_gap = gap;
+        self
+    }
+
+    /// Change the session gap of an open storage.
+    pub fn set_session_gap(&mut self, gap: std::time::Duration) {
+        self.session_gap = gap;
+    }
+
+    /// Set how many versions of a draft there are from one full keyframe to
+    /// the next (see [`DEFAULT_KEYFRAME_INTERVAL`]). The versions in between
+    /// are stored as deltas. An interval of 1 stores every version in full.
+    #[must_use]
+    pub fn with_keyframe_interval(mut self, interval: u32) -> Self {
+        self.keyframe_interval = interval;
+        self
+    }
+
+    /// Set the size in bytes above which content is stored compressed (see
+    /// [`DEFAULT_COMPRESSION_THRESHOLD`]), or `None` to store all content
+    /// uncompressed. Content already stored keeps its encoding.
+    #[must_use]
+    pub fn with_compression_threshold(mut self, threshold: Option<usize>) -> Self {
+        self.compression_threshold = threshold;
+        self
+    }
+
+    /// Open a pool of up to `size` read-only connections to this database,
+    /// which can run queries while this storage writes (see [`ReadPool`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database is in memory, where other
+    /// connections cannot see it, or if the connections cannot be opened.
+    pub fn read_pool(&self, size: u32) -> Result<ReadPool> {
+        if self.path.to_string_lossy() == ":memory:" {
+            return Err(Error::InvalidArgument {
+                message: "an in-memory database cannot be shared with read connections".to_string(),
+            });
+        }
+        ReadPool::open(self, size)
+    }
+
+    /// Back up the database to `path` with the `SQLite` online backup API,
+    /// replacing any file there.
+    ///
+    /// The backup is a consistent snapshot of the database even while
+    /// another connection writes to it, though each write through another
+    /// connection restarts the copy. It can be made from a read-only
+    /// connection (see [`ReadPool`]). An encrypted database stays encrypted
+    /// in the backup, under the same key.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidArgument`] if `path` is the database itself,
+    /// even through a relative path or a symbolic link, or an error if the
+    /// backup cannot be written.
+    pub fn backup(&self, path: impl AsRef<Path>) -> Result<BackupInfo> {
+        let path = path.as_ref();
+        if backup::is_same_file(path, &self.path) {
+            return Err(Error::InvalidArgument {
+                message: "cannot back up the database over itself".to_string(),
+            });
+        }
+        let size_bytes = backup::write(&self.conn, path)?;
+        info!(path = %path.display(), size_bytes, "Backed up the database");
+        Ok(BackupInfo {
+            path: path.to_path_buf(),
+            size_bytes,
+            removed: Vec::new(),
+        })
+    }
+
+    /// Write a scheduled backup into `dir`, named after the database and the
+    /// current time, and remove the oldest scheduled backups there beyond
+    /// `keep` (at least one is kept).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the backup cannot be written or `dir` cannot be
+    /// read.
+    pub fn backup_rotating(&self, dir: impl AsRef<Path>, keep: usize) -> Result<BackupInfo> {
+        backup::write_rotating(&self.conn, &self.path, dir.as_ref(), keep, Utc::now())
+    }
+
+    /// When the newest scheduled backup in `dir` was made, if there is one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `dir` cannot be read.
+    pub fn latest_backup(&self, dir: impl AsRef<Path>) -> Result<Option<DateTime<Utc>>> {
+        backup::latest(&self.path, dir.as_ref())
+    }
+
+    /// Replace the database at `path` with the backup at `backup`.
+    ///
+    /// The backup must be a flightrecorder database of a schema version this
+    /// release can open, pass the `SQLite` integrity check, and, if it is
+    /// encrypted, be unlocked by `key`. An older schema is migrated when the
+    /// database is next opened. The database it replaces is first backed up
+    /// to `<path>.before-restore.bak`. Nothing else may use the database
+    /// while it is restored, so stop the daemon first.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::SchemaTooNew`] if the backup was made by a newer
+    /// release, [`Error::DatabaseLocked`] if `key` does not unlock it, or an
+    /// error if it is not a flightrecorder database, is damaged, or cannot
+    /// be copied.
+    pub fn restore_backup(
+        backup: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+        key: Option<&EncryptionKey>,
+    ) -> Result<RestoreInfo> {
+        backup::restore(backup.as_ref(), path.as_ref(), key)
+    }
+
+    /// Whether content is encrypted at rest.
+    #[must_use]
+    pub fn is_encrypted(&self) -> bool {
+        self.keys.is_some()
+    }
+
+    /// Change the key protecting the database.
+    ///
+    /// With a key, an unencrypted database is encrypted under a new random
+    /// data key, and the data key of an encrypted database is rewrapped with
+    /// the new key, which leaves the captures as they are. Without a key, an
+    /// encrypted database is decrypted. `rotate_data_key` also replaces the
+    /// data key of an encrypted database, re-encrypting every capture, for
+    /// when the old data key may have been exposed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key cannot be read or the database operation
+    /// fails, in which case the database is left as it was.
+    pub fn rekey(&mut self, key: Option<&EncryptionKey>, rotate_data_key: bool) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        let keys = match (key, &self.keys) {
+            (Some(key), Some(keys)) if !rotate_data_key => {
+                crypto::store(&tx, keys, key)?;
+                tx.commit()?;
+                info!("Rewrapped the data key of {}", self.path.display());
+                return Ok(());
+            }
+            (None, None) => return Ok(()),
+            (Some(_), _) => Some(Keys::generate()),
+            (None, Some(_)) => None,
+        };
+
+        let count = self.reencrypt(&tx, keys.as_ref())?;
+        match (key, &keys) {
+            (Some(key), Some(keys)) => crypto::store(&tx, keys, key)?,
+            _ => crypto::remove(&tx)?,
+        }
+        tx.commit()?;
+        self.keys = keys.map(Arc::new);
+        info!(
+            "{} {count} captures in {}",
+            if self.keys.is_some() {
+                "Encrypted"
+            } else {
+                "Decrypted"
+            },
+            self.path.display()
+        );
+        Ok(())
+    }
+
+    /// Encrypt every capture again with the same data key, in the current
+    /// format, binding each value to its capture and column.
+    fn reseal(&mut self) -> Result<()> {
+        let Some(keys) = self.keys.as_deref().map(Keys::current_format) else {
+            return Ok(());
+        };
+        let tx = self.conn.unchecked_transaction()?;
+        let count = self.reencrypt(&tx, Some(&keys))?;
+        tx.commit()?;
+        self.keys = Some(Arc::new(keys));
+        info!(
+            "Encrypted {count} captures in {} again in the current format",
+            self.path.display()
+        );
+        Ok(())
+    }
+
+    /// Store every capture again for `keys` (or unencrypted): its content or
+    /// delta, content hash, index entry, note and tags. Draft sessions forget
+    /// their field. Returns the number of captures.
+    fn reencrypt(&self, conn: &Connection, keys: Option<&Keys>) -> Result<usize> {
+        let old = self.keys.as_deref();
+        conn.execute(
+            "INSERT INTO captures_fts (captures_fts) VALUES ('delete-all')",
+            [],
+        )?;
+        // Newest first: versions are rebuilt from older ones, which must
+        // still be stored the old way.
+        let ids: Vec<i64> = conn
+            .prepare("SELECT id FROM captures ORDER BY id DESC")?
+            .query_map([], |row| row.get(0))?
+            .collect::<std::result::Result<_, _>>()?;
+        let mut select =
+            conn.prepare("SELECT content, codec, delta, encrypted FROM captures WHERE id = ?1")?;
+        let mut update = conn.prepare(
+            "UPDATE captures SET content = ?2, delta = ?3, content_hash = ?4, encrypted = ?5
+             WHERE id = ?1",
+        )?;
+        let mut index =
+            conn.prepare("INSERT INTO captures_fts (rowid, content) VALUES (?1, ?2)")?;
+        for &id in &ids {
+            let text = delta::load_content(conn, old, id)?;
+            let (content, codec, delta, encrypted): (Value, Option<String>, Option<Vec<u8>>, bool) =
+                select.query_row([id], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })?;
+            let decrypt = |column: &str, bytes: Vec<u8>| -> Result<Vec<u8>> {
+                if !encrypted {
+                    return Ok(bytes);
+                }
+                old.and_then(|old| old.decrypt(id, column, &bytes))
+                    .ok_or_else(|| Error::Encryption {
+                        message: format!("capture {id} cannot be decrypted"),
+                    })
+            };
+            let delta = match delta {
+                Some(delta) => Some(Self::encrypt_with(keys, id, decrypt("delta", delta)?)?),
+                None => None,
+            };
+            let content = match content {
+                Value::Text(text) if delta.is_none() => {
+                    let bytes = decrypt("content", text.into_bytes())?;
+                    Self::seal(keys, id, codec.as_deref(), bytes)?
+                }
+                Value::Blob(bytes) if delta.is_none() => {
+                    Self::seal(keys, id, codec.as_deref(), decrypt("content", bytes)?)?
+                }
+                // The content of a version stored as a delta is empty.
+                content => content,
+            };
+            update.execute(params![
+                id,
+                content,
+                delta,
+                Self::hash_with(keys, &text),
+                keys.is_some()
+            ])?;
+            index.execute(params![id, Self::index_text_with(keys, &text)])?;
+        }
+        annotations::reencrypt(conn, old, keys)?;
+        if keys.is_some() {
+            crypto::set_current_format(conn)?;
+        }
+        // Field hashes cannot be recomputed for the new keys, so no later
+        // snapshot continues a session started before; a hash is never empty.
+        conn.execute(
+            "UPDATE sessions SET field_hash = '' WHERE field_hash IS NOT NULL",
+            [],
+        )?;
+        Ok(ids.len())
+    }
+
+    /// Encrypt the delta of capture `id` with `keys`, if given.
+    fn encrypt_with(keys: Option<&Keys>, id: i64, bytes: Vec<u8>) -> Result<Vec<u8>> {
+        match keys {
+            Some(keys) => keys.encrypt(id, "delta", &bytes),
+            None => Ok(bytes),
+        }
+    }
+
+    /// The value stored for the content of capture `id` encoded with
+    /// `codec`, encrypted with `keys` if given.
+    fn seal(keys: Option<&Keys>, id: i64, codec: Option<&str>, bytes: Vec<u8>) -> Result<Value> {
+        Ok(match (keys, codec) {
+            (Some(keys), _) => Value::Blob(keys.encrypt(id, "content", &bytes)?),
+            (None, None) => {
+                Value::Text(String::from_utf8(bytes).map_err(|e| Error::Encryption {
+                    message: format!("decrypted content is not text: {e}"),
+                })?)
+            }
+            (None, Some(_)) => Value::Blob(bytes),
+        })
+    }
+
+    /// The content hash stored for `content`: a keyed MAC when the database
+    /// is encrypted.
+    fn hash_with(keys: Option<&Keys>, content: &str) -> String {
+        keys.map_or_else(|| Capture::compute_hash(content), |keys| keys.mac(content))
+    }
+
+    /// The text indexed for `content`: its blind tokens when the database is
+    /// encrypted.
+    fn index_text_with(keys: Option<&Keys>, content: &str) -> String {
+        keys.map_or_else(|| content.to_string(), |keys| keys.blind(content))
+    }
+
+    /// Encode the content of capture `id` for storage: compressed above the
+    /// compression threshold, then encrypted if the database is. Returns the
+    /// value to store and its codec.
+    fn encode_content(&self, id: i64, content: &str) -> Result<(Value, Option<&'static str>)> {
+        let stored = codec::encode(content, self.compression_threshold);
+        let value = Self::seal(
+            self.keys.as_deref(),
+            id,
+            stored.codec(),
+            stored.as_bytes().to_vec(),
+        )?;
+        Ok((value, stored.codec()))
+    }
+
     /// Insert a capture into storage.
     ///
-    /// Returns the assigned ID, or `None` if the capture was deduplicated
-    /// (i.e., an identical capture already exists).
+    /// A text field or keystroke capture becomes the next version of the
+    /// draft session it continues, or the first version of a new one. A
+    /// clipboard capture whose content was copied before is recorded as
+    /// another occurrence of the earlier capture instead. Versions after the
+    /// first are stored as deltas against the version before, except for
+    /// keyframes, and content above the compression threshold is compressed.
+    /// In an encrypted database, content and deltas are encrypted, and
+    /// duplicates are found by keyed MACs of their content.
+    ///
+    /// Returns the assigned ID, or `None` if nothing new was stored: the
+    /// content recurred, or is the same as the latest version of its draft.
+    /// Timestamps are stored with microsecond precision.
     ///
     /// # Errors
     ///
     /// Returns an error if the database operation fails.
     pub fn insert(&self, capture: &Capture) -> Result<Option<i64>> {
-        // Check for duplicate by hash
-        if self.exists_by_hash(&capture.content_hash)? {
-            debug!(
-                "Skipping duplicate capture with hash {}",
-                &capture.content_hash[..16]
-            );
-            return Ok(None);
-        }
+        let tx = self.conn.unchecked_transaction()?;
+        let id = self.insert_in(&tx, capture)?;
+        tx.commit()?;
+        Ok(id)
+    }
+
+    /// Insert captures in one transaction, as [`Storage::insert`] would one
+    /// at a time. Committing once for the batch is much cheaper than once per
+    /// capture. Returns the ID assigned to each capture, in order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any insert fails, in which case none of the
+    /// captures are stored.
+    pub fn insert_batch(&self, captures: &[Capture]) -> Result<Vec<Option<i64>>> {
+        let tx = self.conn.unchecked_transaction()?;
+        let ids = captures
+            .iter()
+            .map(|capture| self.insert_in(&tx, capture))
+            .collect::<Result<Vec<_>>>()?;
+        tx.commit()?;
+        debug!("Inserted a batch of {} captures", captures.len());
+        Ok(ids)
+    }
+
+    /// Insert a capture within the transaction `tx`.
+    fn insert_in(&self, tx: &Connection, capture: &Capture) -> Result<Option<i64>> {
+        let capture_type = capture.capture_type;
+        let source_app = capture.source_app.as_deref();
+        let timestamp = capture.timestamp.timestamp_micros();
+        let keys = self.keys.as_deref();
+        let content_hash = match keys {
+            Some(keys) => keys.mac(&capture.content),
+            None => capture.content_hash.clone(),
+        };
+        // Fields are told apart by a hash, keyed like the content hash.
+        let field_hash = capture
+            .field_id
+            .as_deref()
+            .map(|field| Self::hash_with(keys, field));
+
+        let (session_id, version, base_id) = if history::is_draft(capture_type) {
+            match history::find_open_session(
+                tx,
+                capture_type,
+                source_app,
+                field_hash.as_deref(),
+                timestamp,
+                self.session_gap,
+            )? {
+                Some(session) => {
+                    history::touch_session(tx, session.id, timestamp)?;
+                    if session.latest_hash == content_hash {
+                        return Ok(None);
+                    }
+                    (
+                        Some(session.id),
+                        Some(session.latest_version + 1),
+                        Some(session.latest_id),
+                    )
+                }
+                None => (
+                    Some(history::start_session(
+                        tx,
+                        capture_type,
+                        source_app,
+                        field_hash.as_deref(),
+                        timestamp,
+                    )?),
+                    Some(1),
+                    None,
+                ),
+            }
+        } else {
+            if let Some(id) = Self::find_by_hash(tx, capture_type, &content_hash)? {
+                tx.execute(
+                    "INSERT INTO capture_occurrences (capture_id, timestamp) VALUES (?1, ?2)",
+                    params![id, timestamp],
+                )?;
+                debug!("Recorded another occurrence of capture {id}");
+                return Ok(None);
+            }
+            (None, None, None)
+        };
 
-        let capture_type = capture.capture_type.to_string();
-        let timestamp = capture.timestamp.to_rfc3339();
+        // Encrypted values are bound to their capture, so the ID is chosen
+        // before they are encoded.
+        let id = Self::next_id(tx)?;
+        let Encoded {
+            content,
+            codec,
+            base_id,
+            delta,
+        } = self.encode_version(tx, id, &capture.content, base_id, version)?;
 
-        self.conn.execute(
+        tx.execute(
             r"
-            INSERT INTO captures (timestamp, source_app, content, content_hash, capture_type)
-            VALUES (?1, ?2, ?3, ?4, ?5)
+            INSERT INTO captures
+                (id, timestamp, source_app, content, content_hash, capture_type, session_id,
+                 version, base_id, delta, codec, content_size, encrypted)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
             ",
             params![
+                id,
                 timestamp,
-                capture.source_app,
-                capture.content,
-                capture.content_hash,
-                capture_type,
+                source_app,
+                content,
+                content_hash,
+                capture_type.to_string(),
+                session_id,
+                version,
+                base_id,
+                delta,
+                codec,
+                i64::try_from(capture.content.len()).unwrap_or(i64::MAX),
+                keys.is_some(),
             ],
         )?;
+        tx.execute(
+            "INSERT INTO captures_fts (rowid, content) VALUES (?1, ?2)",
+            params![id, Self::index_text_with(keys, &capture.content)],
+        )?;
 
-        let id = self.conn.last_insert_rowid();
         debug!("Inserted capture with id {}", id);
         Ok(Some(id))
     }
 
-    /// Check if a capture with the given hash already exists.
-    fn exists_by_hash(&self, hash: &str) -> Result<bool> {
-        let count: i32 = self.conn.query_row(
-            "SELECT COUNT(*) FROM captures WHERE content_hash = ?1",
-            [hash],
+    /// The ID the next capture inserted gets, as `AUTOINCREMENT` chooses
+    /// it: one more than the largest ID ever used.
+    fn next_id(conn: &Connection) -> Result<i64> {
+        Ok(conn.query_row(
+            "SELECT MAX(
+                 COALESCE((SELECT seq FROM sqlite_sequence WHERE name = 'captures'), 0),
+                 COALESCE((SELECT MAX(id) FROM captures), 0)
+             ) + 1",
+            [],
             |row| row.get(0),
-        )?;
-        Ok(count > 0)
+        )?)
     }
 
-    /// Get a capture by its ID.
+    /// Encode the content of a new capture `id`: as a delta against the
+    /// version before it, unless it is a keyframe or the delta would save
+    /// little, or else in full.
+    fn encode_version(
+        &self,
+        conn: &Connection,
+        id: i64,
+        content: &str,
+        base_id: Option<i64>,
+        version: Option<u32>,
+    ) -> Result<Encoded> {
+        let keys = self.keys.as_deref();
+        let delta = match (base_id, version) {
+            (Some(base_id), Some(version))
+                if !delta::is_keyframe(version, self.keyframe_interval) =>
+            {
+                delta::encode(&delta::load_content(conn, keys, base_id)?, content)
+            }
+            _ => None,
+        };
+        if let Some(delta) = delta {
+            let delta = Self::encrypt_with(keys, id, delta)?;
+            return Ok(Encoded {
+                content: Value::Text(String::new()),
+                codec: None,
+                base_id,
+                delta: Some(delta),
+            });
+        }
+        let (content, codec) = self.encode_content(id, content)?;
+        Ok(Encoded {
+            content,
+            codec,
+            base_id: None,
+            delta: None,
+        })
+    }
+
+    /// Find the latest capture of a type with the given content hash, outside
+    /// the trash. Content seen again after it was trashed is stored anew.
+    fn find_by_hash(
+        conn: &Connection,
+        capture_type: CaptureType,
+        hash: &str,
+    ) -> Result<Option<i64>> {
+        let id = conn
+            .query_row(
+                "SELECT id FROM captures
+                 WHERE content_hash = ?1 AND capture_type = ?2 AND trashed_at IS NULL
+                 ORDER BY id DESC LIMIT 1",
+                params![hash, capture_type.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(id)
+    }
+
+    /// Get a capture by its ID, unless it is in the trash.
     ///
     /// # Errors
     ///
@@ -153,284 +782,599 @@ impl Storage {
             .conn
             .query_row(
                 r"
-                SELECT id, timestamp, source_app, content, content_hash, capture_type
-                FROM captures WHERE id = ?1
+                SELECT id, timestamp, source_app, content, content_hash, capture_type,
+                       session_id, version, base_id, codec, encrypted, pinned, note
+                FROM captures WHERE id = ?1 AND trashed_at IS NULL
                 ",
                 [id],
-                Self::row_to_capture,
+                |row| Self::row_to_capture(&self.conn, self.keys.as_deref(), row),
             )
             .optional()?;
         Ok(result)
     }
 
-    /// Get the most recent captures.
+    /// Get every version of the draft a capture belongs to, oldest first.
+    ///
+    /// A capture outside any session (such as a clipboard capture) is its
+    /// own history. Versions in the trash are left out. Returns an empty list
+    /// if the capture does not exist or is in the trash.
     ///
     /// # Errors
     ///
     /// Returns an error if the database operation fails.
-    pub fn get_recent(&self, limit: usize) -> Result<Vec<Capture>> {
+    pub fn history(&self, id: i64) -> Result<Vec<Capture>> {
+        let Some(capture) = self.get(id)? else {
+            return Ok(Vec::new());
+        };
+        let Some(session_id) = capture.session_id else {
+            return Ok(vec![capture]);
+        };
         let mut stmt = self.conn.prepare(
             r"
-            SELECT id, timestamp, source_app, content, content_hash, capture_type
-            FROM captures ORDER BY timestamp DESC LIMIT ?1
+            SELECT id, timestamp, source_app, content, content_hash, capture_type,
+                   session_id, version, base_id, codec, encrypted, pinned, note
+            FROM captures WHERE session_id = ?1 AND trashed_at IS NULL ORDER BY version
             ",
         )?;
-
-        let limit_i64 = i64::try_from(limit).unwrap_or(i64::MAX);
-        let captures = stmt
-            .query_map([limit_i64], Self::row_to_capture)?
+        let versions = stmt
+            .query_map([session_id], |row| {
+                Self::row_to_capture(&self.conn, self.keys.as_deref(), row)
+            })?
             .collect::<std::result::Result<Vec<_>, _>>()?;
-
-        Ok(captures)
+        Ok(versions)
     }
 
-    /// Get captures from a specific application.
+    /// Get the times a capture's content was seen again after it was first
+    /// stored, oldest first.
     ///
     /// # Errors
     ///
     /// Returns an error if the database operation fails.
-    pub fn get_by_app(&self, app: &str, limit: usize) -> Result<Vec<Capture>> {
+    pub fn occurrences(&self, id: i64) -> Result<Vec<DateTime<Utc>>> {
         let mut stmt = self.conn.prepare(
-            r"
-            SELECT id, timestamp, source_app, content, content_hash, capture_type
-            FROM captures WHERE source_app = ?1
-            ORDER BY timestamp DESC LIMIT ?2
-            ",
+            "SELECT timestamp FROM capture_occurrences WHERE capture_id = ?1 ORDER BY timestamp",
         )?;
-
-        let limit_i64 = i64::try_from(limit).unwrap_or(i64::MAX);
-        let captures = stmt
-            .query_map(params![app, limit_i64], Self::row_to_capture)?
-            .collect::<std::result::Result<Vec<_>, _>>()?;
-
-        Ok(captures)
+        let times = stmt
+            .query_map([id], |row| row.get::<_, i64>(0))?
+            .map(|micros| micros_to_datetime(micros?))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(times)
     }
 
-    /// Get captures of a specific type.
+    /// Get the most recent captures.
     ///
     /// # Errors
     ///
     /// Returns an error if the database operation fails.
-    pub fn get_by_type(&self, capture_type: CaptureType, limit: usize) -> Result<Vec<Capture>> {
-        let type_str = capture_type.to_string();
-        let mut stmt = self.conn.prepare(
-            r"
-            SELECT id, timestamp, source_app, content, content_hash, capture_type
-            FROM captures WHERE capture_type = ?1
-            ORDER BY timestamp DESC LIMIT ?2
-            ",
-        )?;
+    pub fn get_recent(&self, limit: usize) -> Result<Vec<Capture>> {
+        let page = self.query(&CaptureQuery::new().with_limit(limit))?;
+        Ok(page.into_captures())
+    }
 
-        let limit_i64 = i64::try_from(limit).unwrap_or(i64::MAX);
-        let captures = stmt
-            .query_map(params![type_str, limit_i64], Self::row_to_capture)?
+    /// Run a query, returning one page of matching captures.
+    ///
+    /// Captures come with snippets when the query has a full-text filter.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidArgument`] if the full-text filter has a `NOT`
+    /// without a term on both sides, or an error if the database operation
+    /// fails.
+    pub fn query(&self, query: &CaptureQuery) -> Result<QueryPage> {
+        let (sql, params) = query.to_sql(self.keys.as_deref())?;
+        let highlighter = query.highlighter();
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt
+            .query_map(params_from_iter(params), |row| {
+                let capture = Self::row_to_capture(&self.conn, self.keys.as_deref(), row)?;
+                let hit = SearchHit {
+                    snippet: highlighter.as_ref().map(|h| h.snippet(&capture.content)),
+                    capture,
+                };
+                let timestamp: i64 = row.get(1)?;
+                let rank: Option<f64> = row.get(13)?;
+                Ok((hit, timestamp, rank))
+            })?
             .collect::<std::result::Result<Vec<_>, _>>()?;
 
-        Ok(captures)
+        let next = match (rows.last(), query.limit()) {
+            (Some((hit, timestamp, rank)), Some(limit)) if rows.len() == limit => hit
+                .capture
+                .id
+                .map(|id| query.cursor_after(*timestamp, id, *rank)),
+            _ => None,
+        };
+        Ok(QueryPage {
+            hits: rows.into_iter().map(|(hit, ..)| hit).collect(),
+            next,
+        })
     }
 
-    /// Search captures by content.
-    ///
-    /// Performs a case-insensitive substring search.
+    /// Count the captures in storage, not counting those in the trash.
     ///
     /// # Errors
     ///
     /// Returns an error if the database operation fails.
-    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<Capture>> {
-        let pattern = format!("%{query}%");
-        let mut stmt = self.conn.prepare(
-            r"
-            SELECT id, timestamp, source_app, content, content_hash, capture_type
-            FROM captures WHERE content LIKE ?1
-            ORDER BY timestamp DESC LIMIT ?2
-            ",
+    pub fn count(&self) -> Result<i64> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM captures WHERE trashed_at IS NULL",
+            [],
+            |row| row.get(0),
         )?;
+        Ok(count)
+    }
 
-        let limit_i64 = i64::try_from(limit).unwrap_or(i64::MAX);
-        let captures = stmt
-            .query_map(params![pattern, limit_i64], Self::row_to_capture)?
-            .collect::<std::result::Result<Vec<_>, _>>()?;
+    /// Delete a capture by ID, moving it to the trash from which it can be
+    /// restored (see [`Storage::restore`]).
+    ///
+    /// Returns `true` if a capture was deleted, `false` if not found or
+    /// already in the trash.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn delete(&self, id: i64) -> Result<bool> {
+        Ok(self.trash(&[id])? > 0)
+    }
 
-        Ok(captures)
+    /// Move the captures with the given IDs to the trash, returning how many
+    /// were moved. IDs of captures that do not exist or are already in the
+    /// trash are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn trash(&self, ids: &[i64]) -> Result<usize> {
+        let moved = trash::trash(&self.conn, ids, Utc::now())?;
+        debug!("Moved {moved} captures to the trash");
+        Ok(moved)
     }
 
-    /// Get captures within a time range.
+    /// Take the captures with the given IDs out of the trash, returning how
+    /// many were restored. IDs of captures not in the trash are ignored.
     ///
     /// # Errors
     ///
     /// Returns an error if the database operation fails.
-    pub fn get_by_time_range(
-        &self,
-        since: DateTime<Utc>,
-        until: DateTime<Utc>,
-        limit: usize,
-    ) -> Result<Vec<Capture>> {
-        let since_str = since.to_rfc3339();
-        let until_str = until.to_rfc3339();
+    pub fn restore(&self, ids: &[i64]) -> Result<usize> {
+        let restored = trash::restore(&self.conn, ids)?;
+        debug!("Restored {restored} captures from the trash");
+        Ok(restored)
+    }
 
+    /// List the captures in the trash, most recently trashed first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn list_trash(&self, limit: usize) -> Result<Vec<TrashedCapture>> {
         let mut stmt = self.conn.prepare(
             r"
-            SELECT id, timestamp, source_app, content, content_hash, capture_type
-            FROM captures WHERE timestamp >= ?1 AND timestamp <= ?2
-            ORDER BY timestamp DESC LIMIT ?3
+            SELECT id, timestamp, source_app, content, content_hash, capture_type,
+                   session_id, version, base_id, codec, encrypted, pinned, note,
+                   trashed_at
+            FROM captures WHERE trashed_at IS NOT NULL
+            ORDER BY trashed_at DESC, id DESC
+            LIMIT ?1
             ",
         )?;
+        let trashed = stmt
+            .query_map([i64::try_from(limit).unwrap_or(i64::MAX)], |row| {
+                let capture = Self::row_to_capture(&self.conn, self.keys.as_deref(), row)?;
+                Ok((capture, row.get::<_, i64>(13)?))
+            })?
+            .map(|row| {
+                let (capture, trashed_at) = row?;
+                Ok(TrashedCapture {
+                    capture,
+                    trashed_at: micros_to_datetime(trashed_at)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(trashed)
+    }
 
-        let limit_i64 = i64::try_from(limit).unwrap_or(i64::MAX);
-        let captures = stmt
-            .query_map(
-                params![since_str, until_str, limit_i64],
-                Self::row_to_capture,
-            )?
-            .collect::<std::result::Result<Vec<_>, _>>()?;
+    /// Pin or unpin a capture. Pinned captures are never pruned, though they
+    /// can still be deleted.
+    ///
+    /// Returns `false` if the capture was not found or is in the trash.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn pin(&self, id: i64, pinned: bool) -> Result<bool> {
+        annotations::pin(&self.conn, id, pinned)
+    }
 
-        Ok(captures)
+    /// Add tags to a capture. Each tag must be a single word.
+    ///
+    /// Returns `false` if the capture was not found or is in the trash.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a tag is empty or contains whitespace, or if the
+    /// database operation fails.
+    pub fn add_tags(&self, id: i64, tags: &[String]) -> Result<bool> {
+        annotations::add_tags(&self.conn, self.keys.as_deref(), id, tags)
     }
 
-    /// Count total captures in storage.
+    /// Remove tags from a capture.
+    ///
+    /// Returns `false` if the capture was not found or is in the trash.
     ///
     /// # Errors
     ///
     /// Returns an error if the database operation fails.
-    pub fn count(&self) -> Result<i64> {
-        let count: i64 = self
-            .conn
-            .query_row("SELECT COUNT(*) FROM captures", [], |row| row.get(0))?;
-        Ok(count)
+    pub fn remove_tags(&self, id: i64, tags: &[String]) -> Result<bool> {
+        annotations::remove_tags(&self.conn, self.keys.as_deref(), id, tags)
     }
 
-    /// Delete a capture by ID.
+    /// Set the note on a capture, or clear it with `None` or empty text.
     ///
-    /// Returns `true` if a capture was deleted, `false` if not found.
+    /// Returns `false` if the capture was not found or is in the trash.
     ///
     /// # Errors
     ///
     /// Returns an error if the database operation fails.
-    pub fn delete(&self, id: i64) -> Result<bool> {
-        let affected = self
-            .conn
-            .execute("DELETE FROM captures WHERE id = ?1", [id])?;
-        Ok(affected > 0)
+    pub fn set_note(&self, id: i64, note: Option<&str>) -> Result<bool> {
+        annotations::set_note(&self.conn, self.keys.as_deref(), id, note)
     }
 
-    /// Prune captures older than the given duration.
-    ///
-    /// Returns the number of captures deleted.
+    /// Delete every capture in the trash for good, returning how many were
+    /// deleted, and reclaim the space they used.
     ///
     /// # Errors
     ///
     /// Returns an error if the database operation fails.
-    pub fn prune_older_than(&self, max_age: Duration) -> Result<usize> {
-        let cutoff = Utc::now() - max_age;
-        let cutoff_str = cutoff.to_rfc3339();
+    pub fn empty_trash(&self) -> Result<usize> {
+        let deleted = self.delete_where("trashed_at IS NOT NULL", [])?;
+        if deleted > 0 {
+            let reclaimed_bytes = self.reclaim_space()?;
+            info!(deleted, reclaimed_bytes, "Emptied the trash");
+        }
+        Ok(deleted)
+    }
 
-        let affected = self
+    /// Plan which captures pruning under `policy` would delete, without
+    /// deleting anything.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn plan_prune(&self, policy: &RetentionPolicy) -> Result<PrunePlan> {
+        let used_bytes = retention::used_bytes(&self.conn)?;
+        let rows: Vec<CandidateRow> = self
             .conn
-            .execute("DELETE FROM captures WHERE timestamp < ?1", [cutoff_str])?;
+            .prepare(
+                r"
+                SELECT id, timestamp, source_app, capture_type,
+                       octet_length(content) + COALESCE(octet_length(delta), 0),
+                       trashed_at, pinned
+                FROM captures
+                ORDER BY timestamp DESC, id DESC
+                ",
+            )?
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                    row.get(6)?,
+                ))
+            })?
+            .collect::<std::result::Result<_, _>>()?;
+        let candidates = rows
+            .into_iter()
+            .map(
+                |(id, timestamp, app, capture_type, stored_bytes, trashed_at, pinned)| {
+                    Ok(retention::Candidate {
+                        id,
+                        timestamp: micros_to_datetime(timestamp)?,
+                        app,
+                        capture_type: parse_capture_type(&capture_type),
+                        stored_bytes: u64::try_from(stored_bytes).unwrap_or(0),
+                        trashed_at: trashed_at.map(micros_to_datetime).transpose()?,
+                        pinned,
+                    })
+                },
+            )
+            .collect::<Result<Vec<_>>>()?;
+        Ok(retention::plan(
+            &candidates,
+            used_bytes,
+            retention::fixed_bytes(&self.conn)?,
+            policy,
+            Utc::now(),
+        ))
+    }
 
-        if affected > 0 {
-            info!("Pruned {} old captures", affected);
-        }
-        Ok(affected)
+    /// Delete the captures with the given IDs for good, in the trash or not,
+    /// returning how many were deleted. IDs of captures that no longer exist
+    /// are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn purge(&self, ids: &[i64]) -> Result<usize> {
+        let ids = serde_json::to_string(ids)?;
+        self.delete_where("id IN (SELECT value FROM json_each(?1))", [ids])
     }
 
-    /// Prune captures to keep only the most recent N entries.
+    /// Return the pages freed by deleted captures to the file system,
+    /// returning how many bytes the database shrank by.
+    ///
+    /// # Errors
     ///
-    /// Returns the number of captures deleted.
+    /// Returns an error if the database operation fails.
+    pub fn reclaim_space(&self) -> Result<u64> {
+        let before = retention::file_bytes(&self.conn)?;
+        // Each step of the pragma frees one page.
+        let mut vacuum = self.conn.prepare("PRAGMA incremental_vacuum")?;
+        let mut steps = vacuum.query([])?;
+        while steps.next()?.is_some() {}
+        drop(steps);
+        drop(vacuum);
+        // Moved pages are written to the write-ahead log first; checkpoint
+        // them so that the database file itself shrinks.
+        self.conn
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        let after = retention::file_bytes(&self.conn)?;
+        Ok(before.saturating_sub(after))
+    }
+
+    /// Move the captures beyond the limits of `policy` to the trash, or
+    /// delete them, delete the captures at the end of their grace period in
+    /// the trash, and reclaim the space they used.
     ///
     /// # Errors
     ///
     /// Returns an error if the database operation fails.
-    pub fn prune_keep_recent(&self, keep_count: usize) -> Result<usize> {
-        let keep_i64 = i64::try_from(keep_count).unwrap_or(i64::MAX);
-        let affected = self.conn.execute(
-            r"
-            DELETE FROM captures WHERE id NOT IN (
-                SELECT id FROM captures ORDER BY timestamp DESC LIMIT ?1
-            )
-            ",
-            [keep_i64],
-        )?;
+    pub fn prune(&self, policy: &RetentionPolicy) -> Result<PruneReport> {
+        let PrunePlan {
+            ids,
+            trash_ids,
+            mut report,
+        } = self.plan_prune(policy)?;
+        self.trash(&trash_ids)?;
+        if !ids.is_empty() {
+            self.purge(&ids)?;
+            report.reclaimed_bytes = self.reclaim_space()?;
+            info!(
+                deleted = ids.len(),
+                reclaimed_bytes = report.reclaimed_bytes,
+                "Pruned captures"
+            );
+        }
+        Ok(report)
+    }
+
+    /// Delete the captures matching an SQL condition, returning how many
+    /// were deleted.
+    ///
+    /// Versions stored as deltas against a deleted capture are stored in
+    /// full first, so that they can still be rebuilt.
+    fn delete_where(&self, condition: &str, params: impl Params) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let deleted = self.delete_in(&tx, condition, params)?;
+        tx.commit()?;
+        Ok(deleted)
+    }
 
-        if affected > 0 {
-            info!("Pruned {} captures to keep {} recent", affected, keep_count);
+    /// Delete the captures matching an SQL condition within the transaction
+    /// `tx`, as [`Storage::delete_where`] would.
+    fn delete_in(&self, tx: &Connection, condition: &str, params: impl Params) -> Result<usize> {
+        let doomed: Vec<i64> = tx
+            .prepare(&format!(
+                "SELECT id FROM captures WHERE {condition} ORDER BY id"
+            ))?
+            .query_map(params, |row| row.get(0))?
+            .collect::<std::result::Result<_, _>>()?;
+        let doomed_ids: HashSet<i64> = doomed.iter().copied().collect();
+
+        let mut dependents = tx.prepare("SELECT id FROM captures WHERE base_id = ?1")?;
+        for &id in &doomed {
+            let ids: Vec<i64> = dependents
+                .query_map([id], |row| row.get(0))?
+                .collect::<std::result::Result<_, _>>()?;
+            for dependent in ids.into_iter().filter(|id| !doomed_ids.contains(id)) {
+                let content = delta::load_content(tx, self.keys.as_deref(), dependent)?;
+                let (value, codec) = self.encode_content(dependent, &content)?;
+                tx.execute(
+                    "UPDATE captures SET content = ?2, codec = ?3, base_id = NULL, delta = NULL
+                     WHERE id = ?1",
+                    params![dependent, value, codec],
+                )?;
+            }
+        }
+        drop(dependents);
+
+        // Newest first, so that no delta outlives its base.
+        let mut delete = tx.prepare("DELETE FROM captures WHERE id = ?1")?;
+        for &id in doomed.iter().rev() {
+            delete.execute([id])?;
+        }
+        if !doomed.is_empty() {
+            trash::scrub_search_index(tx)?;
         }
-        Ok(affected)
+        Ok(doomed.len())
     }
 
-    /// Get database statistics.
+    /// Check the database for damage: run `PRAGMA integrity_check`, read
+    /// back every capture, in the trash or not, against its content hash,
+    /// and look for versions whose delta base or session is missing and for
+    /// orphaned search index entries. Changes nothing.
     ///
     /// # Errors
     ///
     /// Returns an error if the database operation fails.
-    pub fn stats(&self) -> Result<StorageStats> {
-        let total_captures = self.count()?;
+    pub fn check(&self) -> Result<CheckReport> {
+        let integrity_errors = check::integrity_errors(&self.conn)?;
+        let (checked, problems) = check::check_captures(&self.conn, self.keys.as_deref())?;
+        let orphaned_index_entries = check::orphaned_index_entries(&self.conn)?;
+        Ok(CheckReport {
+            checked,
+            integrity_errors,
+            problems,
+            orphaned_index_entries,
+        })
+    }
 
-        let oldest: Option<String> = self
-            .conn
-            .query_row(
-                "SELECT timestamp FROM captures ORDER BY timestamp ASC LIMIT 1",
-                [],
-                |row| row.get(0),
-            )
-            .optional()?;
+    /// Repair the problems a check found, in one transaction.
+    ///
+    /// Damaged captures are copied to the `quarantine` table as stored, with
+    /// the reasons they were quarantined, and then deleted; versions stored
+    /// as deltas against them are stored in full first, as for any delete.
+    /// Versions of a missing session are detached from it, and orphaned
+    /// search index entries are removed. Integrity errors are left alone.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails, in which case
+    /// nothing is changed.
+    pub fn repair(&self, report: &CheckReport) -> Result<RepairReport> {
+        let mut reasons: BTreeMap<i64, Vec<String>> = BTreeMap::new();
+        for CaptureProblem {
+            capture_id,
+            problem,
+        } in &report.problems
+        {
+            if problem.quarantines() {
+                reasons
+                    .entry(*capture_id)
+                    .or_default()
+                    .push(problem.to_string());
+            }
+        }
 
-        let newest: Option<String> = self
-            .conn
-            .query_row(
-                "SELECT timestamp FROM captures ORDER BY timestamp DESC LIMIT 1",
-                [],
-                |row| row.get(0),
-            )
-            .optional()?;
+        let tx = self.conn.unchecked_transaction()?;
+        let now = Utc::now().timestamp_micros();
+        let mut repaired = RepairReport::default();
+        let mut ids = Vec::with_capacity(reasons.len());
+        for (&id, reasons) in &reasons {
+            if check::quarantine(&tx, id, &reasons.join("; "), now)? {
+                ids.push(id);
+            }
+        }
+        if !ids.is_empty() {
+            let ids = serde_json::to_string(&ids)?;
+            repaired.quarantined =
+                self.delete_in(&tx, "id IN (SELECT value FROM json_each(?1))", [ids])?;
+        }
+        for CaptureProblem {
+            capture_id,
+            problem,
+        } in &report.problems
+        {
+            if !problem.quarantines()
+                && !reasons.contains_key(capture_id)
+                && check::detach(&tx, *capture_id)?
+            {
+                repaired.detached += 1;
+            }
+        }
+        for &rowid in &report.orphaned_index_entries {
+            if check::remove_index_entry(&tx, rowid)? {
+                repaired.removed_index_entries += 1;
+            }
+        }
+        tx.commit()?;
+        if repaired.quarantined > 0 {
+            warn!(
+                quarantined = repaired.quarantined,
+                "Quarantined damaged captures"
+            );
+        }
+        Ok(repaired)
+    }
 
-        let oldest_capture = oldest
-            .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
-            .map(|dt| dt.with_timezone(&Utc));
-        let newest_capture = newest
-            .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
-            .map(|dt| dt.with_timezone(&Utc));
+    /// Get database statistics.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database operation fails.
+    pub fn stats(&self) -> Result<StorageStats> {
+        let total_captures = self.count()?;
+        let trashed_captures = trash::count(&self.conn)?;
+
+        let (oldest, newest): (Option<i64>, Option<i64>) = self.conn.query_row(
+            "SELECT MIN(timestamp), MAX(timestamp) FROM captures WHERE trashed_at IS NULL",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let oldest_capture = oldest.map(micros_to_datetime).transpose()?;
+        let newest_capture = newest.map(micros_to_datetime).transpose()?;
+
+        let (content_bytes, stored_content_bytes): (i64, i64) = self.conn.query_row(
+            r"
+            SELECT COALESCE(SUM(content_size), 0),
+                   COALESCE(SUM(octet_length(content) + COALESCE(octet_length(delta), 0)), 0)
+            FROM captures
+            ",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
 
         // Get database file size
         let db_size_bytes = if self.path.to_string_lossy() == ":memory:" {
             0
         } else {
-            std::fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0)
+            std::fs::metadata(&self.path).map_or(0, |m| m.len())
         };
 
         Ok(StorageStats {
             total_captures,
+            trashed_captures,
             oldest_capture,
             newest_capture,
             db_size_bytes,
+            content_bytes: u64::try_from(content_bytes).unwrap_or(0),
+            stored_content_bytes: u64::try_from(stored_content_bytes).unwrap_or(0),
         })
     }
 
-    /// Convert a database row to a Capture struct.
-    fn row_to_capture(row: &rusqlite::Row) -> rusqlite::Result<Capture> {
+    /// Convert a database row to a Capture struct, rebuilding content stored
+    /// as a delta and decrypting encrypted content with `keys`.
+    fn row_to_capture(
+        conn: &Connection,
+        keys: Option<&Keys>,
+        row: &rusqlite::Row,
+    ) -> rusqlite::Result<Capture> {
         let id: i64 = row.get(0)?;
-        let timestamp_str: String = row.get(1)?;
+        let timestamp_micros: i64 = row.get(1)?;
         let source_app: Option<String> = row.get(2)?;
-        let content: String = row.get(3)?;
-        let content_hash: String = row.get(4)?;
         let capture_type_str: String = row.get(5)?;
-
-        let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
-            .map_or_else(|_| Utc::now(), |dt| dt.with_timezone(&Utc));
-
-        let capture_type = match capture_type_str.as_str() {
-            "clipboard" => CaptureType::Clipboard,
-            "text_field" => CaptureType::TextField,
-            "keystroke" => CaptureType::Keystroke,
-            _ => {
-                warn!(
-                    "Unknown capture type: {}, defaulting to clipboard",
-                    capture_type_str
-                );
-                CaptureType::Clipboard
-            }
+        let session_id: Option<i64> = row.get(6)?;
+        let version: Option<u32> = row.get(7)?;
+        let base_id: Option<i64> = row.get(8)?;
+        let codec: Option<String> = row.get(9)?;
+        let encrypted: bool = row.get(10)?;
+        let pinned: bool = row.get(11)?;
+        let note = annotations::unseal_note(row.get_ref(12)?, keys, id, 12)?;
+        let content = if base_id.is_some() {
+            delta::load_content(conn, keys, id)?
+        } else {
+            codec::decode_stored(row.get_ref(3)?, codec.as_deref(), encrypted, keys, id, 3)?
+        };
+        // The MAC stored for encrypted content is no use outside the storage.
+        let content_hash = if encrypted {
+            Capture::compute_hash(&content)
+        } else {
+            row.get(4)?
         };
 
+        let timestamp = DateTime::from_timestamp_micros(timestamp_micros).ok_or_else(|| {
+            rusqlite::Error::FromSqlConversionFailure(
+                1,
+                rusqlite::types::Type::Integer,
+                format!("capture {id} has an out-of-range timestamp: {timestamp_micros}").into(),
+            )
+        })?;
+
+        let capture_type = parse_capture_type(&capture_type_str);
+
         Ok(Capture {
             id: Some(id),
             timestamp,
@@ -438,26 +1382,90 @@ impl Storage {
             content,
             content_hash,
             capture_type,
+            field_id: None,
+            session_id,
+            version,
+            pinned,
+            tags: annotations::load_tags(conn, keys, id)?,
+            note,
         })
     }
 }
 
+/// A capture as read for planning a prune: its ID, timestamp, source
+/// application, type, stored size, when it was trashed and whether it is
+/// pinned.
+type CandidateRow = (i64, i64, Option<String>, String, i64, Option<i64>, bool);
+
+/// The stored form of a capture's content.
+struct Encoded {
+    /// The content, or empty text for a version stored as a delta.
+    content: Value,
+    /// The codec the content is stored with, if any.
+    codec: Option<&'static str>,
+    /// The version the delta applies to.
+    base_id: Option<i64>,
+    /// The delta against the base version.
+    delta: Option<Vec<u8>>,
+}
+
+/// Convert a stored capture type, defaulting to clipboard for unknown types.
+fn parse_capture_type(stored: &str) -> CaptureType {
+    stored_capture_type(stored).unwrap_or_else(|| {
+        warn!("Unknown capture type: {}, defaulting to clipboard", stored);
+        CaptureType::Clipboard
+    })
+}
+
+/// Convert a stored capture type, or `None` for a type this release does
+/// not know.
+fn stored_capture_type(stored: &str) -> Option<CaptureType> {
+    match stored {
+        "clipboard" => Some(CaptureType::Clipboard),
+        "text_field" => Some(CaptureType::TextField),
+        "keystroke" => Some(CaptureType::Keystroke),
+        _ => None,
+    }
+}
+
+/// Convert a stored timestamp to a date and time.
+fn micros_to_datetime(micros: i64) -> Result<DateTime<Utc>> {
+    DateTime::from_timestamp_micros(micros).ok_or_else(|| {
+        Error::DatabaseQuery(rusqlite::Error::FromSqlConversionFailure(
+            0,
+            rusqlite::types::Type::Integer,
+            format!("out-of-range timestamp: {micros}").into(),
+        ))
+    })
+}
+
 /// Statistics about the storage.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct StorageStats {
-    /// Total number of captures stored.
+    /// Total number of captures stored, not counting those in the trash.
     pub total_captures: i64,
+    /// Number of captures in the trash.
+    #[serde(default)]
+    pub trashed_captures: i64,
     /// Timestamp of the oldest capture.
     pub oldest_capture: Option<DateTime<Utc>>,
     /// Timestamp of the newest capture.
     pub newest_capture: Option<DateTime<Utc>>,
     /// Size of the database file in bytes.
     pub db_size_bytes: u64,
+    /// Total size in bytes of the captured content, as captured.
+    #[serde(default)]
+    pub content_bytes: u64,
+    /// Bytes used to store the captured content, after delta encoding and
+    /// compression.
+    #[serde(default)]
+    pub stored_content_bytes: u64,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::Duration;
 
     fn create_test_storage() -> Storage {
         Storage::open_in_memory().expect("failed to create test storage")
@@ -467,6 +1475,15 @@ mod tests {
         Capture::new(content.to_string(), CaptureType::Clipboard, None)
     }
 
+    fn query_captures(storage: &Storage, query: &CaptureQuery) -> Vec<Capture> {
+        storage.query(query).unwrap().into_captures()
+    }
+
+    fn search(storage: &Storage, text: &str) -> Vec<SearchHit> {
+        let query = CaptureQuery::new().with_text(text).with_limit(10);
+        storage.query(&query).unwrap().hits
+    }
+
     #[test]
     fn test_open_in_memory() {
         let storage = Storage::open_in_memory();
@@ -474,172 +1491,1281 @@ mod tests {
     }
 
     #[test]
-    fn test_insert_and_get() {
-        let storage = create_test_storage();
-        let capture = create_test_capture("Hello, world!");
+    fn test_insert_and_get() {
+        let storage = create_test_storage();
+        let capture = create_test_capture("Hello, world!");
+
+        let id = storage.insert(&capture).unwrap();
+        assert!(id.is_some());
+
+        let retrieved = storage.get(id.unwrap()).unwrap();
+        assert!(retrieved.is_some());
+
+        let retrieved = retrieved.unwrap();
+        assert_eq!(retrieved.content, "Hello, world!");
+        assert_eq!(retrieved.capture_type, CaptureType::Clipboard);
+    }
+
+    #[test]
+    fn test_insert_deduplication() {
+        let storage = create_test_storage();
+        let capture = create_test_capture("Duplicate content");
+
+        let id1 = storage.insert(&capture).unwrap();
+        let id2 = storage.insert(&capture).unwrap();
+
+        assert!(id1.is_some());
+        assert!(id2.is_none()); // Deduplicated
+    }
+
+    fn text_field(content: &str, app: &str, timestamp: DateTime<Utc>) -> Capture {
+        let mut capture = Capture::new(
+            content.to_string(),
+            CaptureType::TextField,
+            Some(app.to_string()),
+        );
+        capture.timestamp = timestamp;
+        capture
+    }
+
+    #[test]
+    fn test_text_field_snapshots_are_versions() {
+        let storage = create_test_storage();
+        let start = Utc::now() - Duration::hours(2);
+
+        let first = storage
+            .insert(&text_field("Dear", "Mail", start))
+            .unwrap()
+            .unwrap();
+        storage
+            .insert(&text_field(
+                "Dear Bob",
+                "Mail",
+                start + Duration::seconds(1),
+            ))
+            .unwrap();
+        // An unchanged snapshot adds nothing.
+        let unchanged = storage
+            .insert(&text_field(
+                "Dear Bob",
+                "Mail",
+                start + Duration::seconds(2),
+            ))
+            .unwrap();
+        assert!(unchanged.is_none());
+        // Going back to earlier text is still a new version.
+        let last = storage
+            .insert(&text_field("Dear", "Mail", start + Duration::seconds(3)))
+            .unwrap()
+            .unwrap();
+        // Another application's field is a different draft.
+        storage
+            .insert(&text_field("Dear", "Notes", start + Duration::seconds(4)))
+            .unwrap();
+
+        let history = storage.history(last).unwrap();
+        let versions: Vec<_> = history
+            .iter()
+            .map(|c| (c.content.as_str(), c.version))
+            .collect();
+        assert_eq!(
+            versions,
+            [("Dear", Some(1)), ("Dear Bob", Some(2)), ("Dear", Some(3))]
+        );
+        assert_eq!(storage.history(first).unwrap(), history);
+        assert!(storage.history(99_999).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_fields_of_one_application_are_different_drafts() {
+        let storage = create_test_storage();
+        let start = Utc::now() - Duration::hours(1);
+        let field = |content: &str, field: &str, seconds: i64| {
+            text_field(content, "Mail", start + Duration::seconds(seconds)).with_field_id(field)
+        };
+
+        let to = storage.insert(&field("bob@", "to", 0)).unwrap().unwrap();
+        let body = storage
+            .insert(&field("Hi Bob", "body", 1))
+            .unwrap()
+            .unwrap();
+        let to_again = storage
+            .insert(&field("bob@example.com", "to", 2))
+            .unwrap()
+            .unwrap();
+        storage
+            .insert(&field("Hi Bob, see you", "body", 3))
+            .unwrap();
+
+        let contents = |id| -> Vec<String> {
+            storage
+                .history(id)
+                .unwrap()
+                .into_iter()
+                .map(|c| c.content)
+                .collect()
+        };
+        assert_eq!(contents(to), ["bob@", "bob@example.com"]);
+        assert_eq!(contents(body), ["Hi Bob", "Hi Bob, see you"]);
+        assert_eq!(storage.get(to_again).unwrap().unwrap().version, Some(2));
+    }
+
+    #[test]
+    fn test_trashed_versions_are_not_continued() {
+        let storage = create_test_storage();
+        let start = Utc::now() - Duration::hours(1);
+        let first = storage
+            .insert(&text_field("draft", "Mail", start))
+            .unwrap()
+            .unwrap();
+        let second = storage
+            .insert(&text_field(
+                "draft two",
+                "Mail",
+                start + Duration::seconds(1),
+            ))
+            .unwrap()
+            .unwrap();
+        storage.delete(second).unwrap();
+
+        // The same text as the trashed version is a new version, numbered
+        // after it and stored against the version outside the trash.
+        let third = storage
+            .insert(&text_field(
+                "draft two",
+                "Mail",
+                start + Duration::seconds(2),
+            ))
+            .unwrap()
+            .unwrap();
+        let third = storage.get(third).unwrap().unwrap();
+        assert_eq!(third.version, Some(3));
+        let versions: Vec<_> = storage
+            .history(first)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.version)
+            .collect();
+        assert_eq!(versions, [Some(1), Some(3)]);
+
+        // Once every version is in the trash, the session is closed.
+        storage.delete(first).unwrap();
+        storage.delete(third.id.unwrap()).unwrap();
+        let fourth = storage
+            .insert(&text_field(
+                "draft two",
+                "Mail",
+                start + Duration::seconds(3),
+            ))
+            .unwrap()
+            .unwrap();
+        let fourth = storage.get(fourth).unwrap().unwrap();
+        assert_ne!(fourth.session_id, third.session_id);
+        assert_eq!(fourth.version, Some(1));
+    }
+
+    #[test]
+    fn test_retyped_text_after_a_pause_is_kept() {
+        let storage = create_test_storage().with_session_gap(std::time::Duration::from_secs(60));
+        let yesterday = Utc::now() - Duration::days(1);
+
+        let old = storage
+            .insert(&text_field("thanks!", "Chat", yesterday))
+            .unwrap()
+            .unwrap();
+        let new = storage
+            .insert(&text_field("thanks!", "Chat", Utc::now()))
+            .unwrap()
+            .unwrap();
+
+        assert_ne!(old, new);
+        let old = storage.get(old).unwrap().unwrap();
+        let new = storage.get(new).unwrap().unwrap();
+        assert_ne!(old.session_id, new.session_id);
+        assert_eq!(new.version, Some(1));
+    }
+
+    #[test]
+    fn test_recopied_clipboard_content_is_an_occurrence() {
+        let storage = create_test_storage();
+        let mut capture = create_test_capture("copied twice");
+        capture.timestamp = Utc::now() - Duration::days(1);
+        let id = storage.insert(&capture).unwrap().unwrap();
+
+        let again = Capture::new("copied twice".to_string(), CaptureType::Clipboard, None);
+        assert!(storage.insert(&again).unwrap().is_none());
+        assert_eq!(storage.count().unwrap(), 1);
+
+        let seen = storage.occurrences(id).unwrap();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(
+            seen[0].timestamp_micros(),
+            again.timestamp.timestamp_micros()
+        );
+        assert_eq!(storage.history(id).unwrap().len(), 1);
+        assert_eq!(storage.get(id).unwrap().unwrap().session_id, None);
+
+        // Occurrences go with their capture.
+        storage.purge(&[id]).unwrap();
+        assert!(storage.occurrences(id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_deleting_a_draft_removes_its_session() {
+        let storage = create_test_storage();
+        let id = storage
+            .insert(&text_field("only version", "Mail", Utc::now()))
+            .unwrap()
+            .unwrap();
+        storage.purge(&[id]).unwrap();
+
+        let sessions: i64 = storage
+            .conn
+            .query_row("SELECT COUNT(*) FROM sessions", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(sessions, 0);
+    }
+
+    /// Insert `count` versions of a growing draft, returning their ids.
+    fn insert_draft(storage: &Storage, count: usize) -> Vec<i64> {
+        use std::fmt::Write;
+
+        let start = Utc::now() - Duration::hours(1);
+        let mut draft = "Meeting notes for the quarterly planning review.\n".repeat(3);
+        (1..=count)
+            .map(|n| {
+                writeln!(draft, "Item {n}: follow up with the team.").unwrap();
+                let timestamp = start + Duration::seconds(i64::try_from(n).unwrap());
+                storage
+                    .insert(&text_field(&draft, "Notes", timestamp))
+                    .unwrap()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn delta_count(storage: &Storage) -> i64 {
+        storage
+            .conn
+            .query_row(
+                "SELECT COUNT(*) FROM captures WHERE base_id IS NOT NULL",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_draft_versions_are_stored_as_deltas() {
+        let storage = create_test_storage().with_keyframe_interval(4);
+        let ids = insert_draft(&storage, 10);
+        // Versions 1, 5 and 9 are keyframes.
+        assert_eq!(delta_count(&storage), 7);
+
+        let history = storage.history(ids[9]).unwrap();
+        assert_eq!(history.len(), 10);
+        for (n, version) in history.iter().enumerate() {
+            assert_eq!(
+                version.content_hash,
+                Capture::compute_hash(&version.content)
+            );
+            assert!(version
+                .content
+                .ends_with(&format!("Item {}: follow up with the team.\n", n + 1)));
+        }
+        assert_eq!(storage.get(ids[6]).unwrap().unwrap(), history[6]);
+
+        let hits = search(&storage, "\"item 7\"");
+        assert_eq!(hits.len(), 4);
+        assert!(hits[0].snippet.as_deref().unwrap().contains("[Item] [7]"));
+
+        let full = create_test_storage().with_keyframe_interval(1);
+        insert_draft(&full, 10);
+        assert_eq!(delta_count(&full), 0);
+    }
+
+    #[test]
+    fn test_deleting_a_base_keeps_later_versions() {
+        let storage = create_test_storage();
+        let ids = insert_draft(&storage, 6);
+        let expected = storage.history(ids[0]).unwrap();
+
+        assert_eq!(storage.purge(&[ids[0], ids[3]]).unwrap(), 2);
+        let contents: Vec<_> = storage
+            .history(ids[5])
+            .unwrap()
+            .into_iter()
+            .map(|c| c.content)
+            .collect();
+        assert_eq!(contents.len(), 4);
+        for (content, n) in contents.iter().zip([1, 2, 4, 5]) {
+            assert_eq!(*content, expected[n].content);
+        }
+
+        storage
+            .prune(&RetentionPolicy::new().with_max_captures(1))
+            .unwrap();
+        assert_eq!(storage.get(ids[5]).unwrap().unwrap(), expected[5]);
+        assert_eq!(delta_count(&storage), 0);
+    }
+
+    #[test]
+    fn test_get_nonexistent() {
+        let storage = create_test_storage();
+        let result = storage.get(99999).unwrap();
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_get_recent() {
+        let storage = create_test_storage();
+
+        for i in 0..5 {
+            let capture = create_test_capture(&format!("Capture {i}"));
+            storage.insert(&capture).unwrap();
+        }
+
+        let recent = storage.get_recent(3).unwrap();
+        assert_eq!(recent.len(), 3);
+    }
+
+    #[test]
+    fn test_query_by_app() {
+        let storage = create_test_storage();
+
+        let mut capture1 = create_test_capture("From app A");
+        capture1.source_app = Some("AppA".to_string());
+        storage.insert(&capture1).unwrap();
+
+        let mut capture2 = create_test_capture("From app B");
+        capture2.source_app = Some("AppB".to_string());
+        storage.insert(&capture2).unwrap();
+
+        let app_a = query_captures(
+            &storage,
+            &CaptureQuery::new().with_app("AppA").with_limit(10),
+        );
+        assert_eq!(app_a.len(), 1);
+        assert_eq!(app_a[0].source_app, Some("AppA".to_string()));
+    }
+
+    #[test]
+    fn test_query_by_type() {
+        let storage = create_test_storage();
+
+        let clipboard = Capture::new("Clipboard".to_string(), CaptureType::Clipboard, None);
+        let textfield = Capture::new("TextField".to_string(), CaptureType::TextField, None);
+
+        storage.insert(&clipboard).unwrap();
+        storage.insert(&textfield).unwrap();
+
+        let results = query_captures(
+            &storage,
+            &CaptureQuery::new()
+                .with_type(CaptureType::TextField)
+                .with_limit(10),
+        );
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].capture_type, CaptureType::TextField);
+    }
+
+    #[test]
+    fn test_search() {
+        let storage = create_test_storage();
+
+        storage.insert(&create_test_capture("Hello world")).unwrap();
+        storage
+            .insert(&create_test_capture("Goodbye world"))
+            .unwrap();
+        storage.insert(&create_test_capture("Hello there")).unwrap();
+
+        let results = search(&storage, "Hello");
+        assert_eq!(results.len(), 2);
+
+        let results = search(&storage, "world");
+        assert_eq!(results.len(), 2);
+
+        let results = search(&storage, "nonexistent");
+        assert_eq!(results.len(), 0);
+    }
+
+    fn search_contents(storage: &Storage, query: &str) -> Vec<String> {
+        search(storage, query)
+            .into_iter()
+            .map(|hit| hit.capture.content)
+            .collect()
+    }
+
+    #[test]
+    fn test_search_phrase_prefix_and_boolean() {
+        let storage = create_test_storage();
+        storage
+            .insert(&create_test_capture("the quick brown fox"))
+            .unwrap();
+        storage
+            .insert(&create_test_capture("brown bread and quick oats"))
+            .unwrap();
+        storage
+            .insert(&create_test_capture("a foxglove in the garden"))
+            .unwrap();
+
+        assert_eq!(
+            search_contents(&storage, "\"quick brown\""),
+            ["the quick brown fox"]
+        );
+        assert_eq!(search_contents(&storage, "fox*").len(), 2);
+        assert_eq!(
+            search_contents(&storage, "brown NOT fox"),
+            ["brown bread and quick oats"]
+        );
+        assert_eq!(search_contents(&storage, "garden OR oats").len(), 2);
+        assert_eq!(
+            search_contents(&storage, "(garden OR bread) AND quick"),
+            ["brown bread and quick oats"]
+        );
+        // Punctuation that is FTS5 syntax is searched for literally.
+        assert_eq!(
+            search_contents(&storage, "fox- \"(\" -"),
+            ["the quick brown fox"]
+        );
+    }
+
+    #[test]
+    fn test_search_ranks_and_highlights() {
+        let storage = create_test_storage();
+        storage
+            .insert(&create_test_capture(
+                "one mention of rust among many other words here",
+            ))
+            .unwrap();
+        storage
+            .insert(&create_test_capture("rust rust rust"))
+            .unwrap();
+
+        let hits = search(&storage, "rust");
+        assert_eq!(hits[0].capture.content, "rust rust rust");
+        assert_eq!(hits[0].snippet.as_deref(), Some("[rust] [rust] [rust]"));
+        assert!(hits[1].snippet.as_deref().unwrap().contains("[rust]"));
+    }
+
+    #[test]
+    fn test_search_index_follows_deletes() {
+        let storage = create_test_storage();
+        let id = storage
+            .insert(&create_test_capture("temporary secret"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(search_contents(&storage, "secret").len(), 1);
+
+        storage.delete(id).unwrap();
+        storage.empty_trash().unwrap();
+        assert!(search_contents(&storage, "secret").is_empty());
+    }
+
+    #[test]
+    fn test_purged_words_are_gone_from_the_search_index() {
+        let storage = create_test_storage();
+        storage.insert(&create_test_capture("kept note")).unwrap();
+        let id = storage
+            .insert(&create_test_capture("swordfish"))
+            .unwrap()
+            .unwrap();
+        let indexed = |word: &str| -> i64 {
+            storage
+                .conn
+                .query_row(
+                    "SELECT COUNT(*) FROM captures_fts_data
+                     WHERE instr(block, CAST(?1 AS BLOB)) > 0",
+                    [word],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert!(indexed("swordfish") > 0);
+
+        storage.purge(&[id]).unwrap();
+        assert_eq!(indexed("swordfish"), 0);
+        assert!(indexed("kept") > 0);
+    }
+
+    #[test]
+    fn test_count() {
+        let storage = create_test_storage();
+        assert_eq!(storage.count().unwrap(), 0);
+
+        storage.insert(&create_test_capture("One")).unwrap();
+        storage.insert(&create_test_capture("Two")).unwrap();
+
+        assert_eq!(storage.count().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_delete() {
+        let storage = create_test_storage();
+        let id = storage
+            .insert(&create_test_capture("To delete"))
+            .unwrap()
+            .unwrap();
+
+        assert!(storage.get(id).unwrap().is_some());
+        assert!(storage.delete(id).unwrap());
+        assert!(storage.get(id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_delete_nonexistent() {
+        let storage = create_test_storage();
+        assert!(!storage.delete(99999).unwrap());
+    }
+
+    #[test]
+    fn test_deleted_captures_can_be_restored() {
+        let storage = create_test_storage();
+        let id = storage
+            .insert(&create_test_capture("deleted by mistake"))
+            .unwrap()
+            .unwrap();
+
+        assert!(storage.delete(id).unwrap());
+        assert!(!storage.delete(id).unwrap());
+        assert!(storage.get(id).unwrap().is_none());
+        assert_eq!(storage.count().unwrap(), 0);
+        assert!(search_contents(&storage, "mistake").is_empty());
+        let trashed = storage.list_trash(10).unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].capture.content, "deleted by mistake");
+        assert_eq!(storage.stats().unwrap().trashed_captures, 1);
+
+        assert_eq!(storage.restore(&[id, 99999]).unwrap(), 1);
+        assert_eq!(
+            storage.get(id).unwrap().unwrap().content,
+            "deleted by mistake"
+        );
+        assert_eq!(search_contents(&storage, "mistake").len(), 1);
+        assert!(storage.list_trash(10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_empty_trash() {
+        let storage = create_test_storage();
+        let kept = storage
+            .insert(&create_test_capture("kept"))
+            .unwrap()
+            .unwrap();
+        let gone = storage
+            .insert(&create_test_capture("gone"))
+            .unwrap()
+            .unwrap();
+        storage.delete(gone).unwrap();
+
+        assert_eq!(storage.empty_trash().unwrap(), 1);
+        assert_eq!(storage.restore(&[gone]).unwrap(), 0);
+        assert!(storage.list_trash(10).unwrap().is_empty());
+        assert!(storage.get(kept).unwrap().is_some());
+        // Content seen again after it was trashed is stored anew.
+        assert!(storage
+            .insert(&create_test_capture("gone"))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_pin_tag_and_note() {
+        let storage = create_test_storage();
+        let id = storage
+            .insert(&create_test_capture("half-written incident report"))
+            .unwrap()
+            .unwrap();
+        storage
+            .insert(&create_test_capture("something else"))
+            .unwrap();
+
+        assert!(storage.pin(id, true).unwrap());
+        let tags = ["outage".to_string(), " incident ".to_string()];
+        assert!(storage.add_tags(id, &tags).unwrap());
+        assert!(storage.add_tags(id, &tags[..1]).unwrap());
+        assert!(storage.set_note(id, Some("finish on Monday")).unwrap());
+        assert!(storage.add_tags(id, &["two words".to_string()]).is_err());
+        assert!(!storage.pin(99999, true).unwrap());
+        assert!(!storage.add_tags(99999, &tags).unwrap());
+
+        let capture = storage.get(id).unwrap().unwrap();
+        assert!(capture.pinned);
+        assert_eq!(capture.tags, ["incident", "outage"]);
+        assert_eq!(capture.note.as_deref(), Some("finish on Monday"));
+
+        let tagged = query_captures(&storage, &CaptureQuery::new().with_tag("incident"));
+        assert_eq!(tagged.len(), 1);
+        assert_eq!(tagged[0].id, Some(id));
+        let pinned = query_captures(&storage, &CaptureQuery::new().pinned_only());
+        assert_eq!(pinned.len(), 1);
+
+        assert!(storage.remove_tags(id, &tags[..1]).unwrap());
+        assert!(storage.pin(id, false).unwrap());
+        assert!(storage.set_note(id, Some("  ")).unwrap());
+        let capture = storage.get(id).unwrap().unwrap();
+        assert!(!capture.pinned);
+        assert_eq!(capture.tags, ["incident"]);
+        assert_eq!(capture.note, None);
+
+        // Tags go with their capture.
+        storage.purge(&[id]).unwrap();
+        let tags: i64 = storage
+            .conn
+            .query_row("SELECT COUNT(*) FROM capture_tags", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tags, 0);
+    }
+
+    #[test]
+    fn test_pinned_captures_survive_pruning() {
+        let storage = create_test_storage();
+        let ids: Vec<i64> = (0..5)
+            .map(|i| {
+                let mut capture = create_test_capture(&format!("capture {i}"));
+                capture.timestamp = Utc::now() - Duration::days(10 - i);
+                storage.insert(&capture).unwrap().unwrap()
+            })
+            .collect();
+        storage.pin(ids[0], true).unwrap();
+
+        storage
+            .prune(&RetentionPolicy::new().with_max_age(Duration::days(7)))
+            .unwrap();
+        assert!(storage.get(ids[0]).unwrap().is_some());
+        storage
+            .prune(&RetentionPolicy::new().with_max_captures(1))
+            .unwrap();
+        assert!(storage.get(ids[0]).unwrap().is_some());
+        let report = storage
+            .prune(&RetentionPolicy::new().with_max_captures(0))
+            .unwrap();
+        assert_eq!(report.over_count, 1);
+        assert_eq!(storage.count().unwrap(), 1);
+        assert!(storage.get(ids[0]).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_check_finds_damage_and_repair_quarantines_it() {
+        let storage = create_test_storage();
+        let draft = insert_draft(&storage, 3);
+        let insert = |content| {
+            storage
+                .insert(&create_test_capture(content))
+                .unwrap()
+                .unwrap()
+        };
+        let (rehashed, retyped, fine) = (insert("one"), insert("two"), insert("three"));
+        assert!(storage.check().unwrap().is_healthy());
+
+        // Damage a hash, a type and a delta (which the next version of the
+        // draft is rebuilt from), drop the draft's session and leave an index
+        // entry without a capture.
+        storage
+            .conn
+            .execute_batch(&format!(
+                "PRAGMA foreign_keys = OFF;
+                 UPDATE captures SET content_hash = 'bogus' WHERE id = {rehashed};
+                 UPDATE captures SET capture_type = 'fax' WHERE id = {retyped};
+                 UPDATE captures SET delta = x'ff' WHERE id = {};
+                 DELETE FROM sessions;
+                 INSERT INTO captures_fts (rowid, content) VALUES (999, 'ghost');
+                 PRAGMA foreign_keys = ON;",
+                draft[1]
+            ))
+            .unwrap();
+
+        let report = storage.check().unwrap();
+        assert!(report.integrity_errors.is_empty());
+        assert_eq!(report.checked, 6);
+        let problems = |id| {
+            report
+                .problems
+                .iter()
+                .filter(|p| p.capture_id == id)
+                .map(|p| p.problem.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(problems(rehashed), [Problem::HashMismatch]);
+        assert_eq!(
+            problems(retyped),
+            [Problem::UnknownCaptureType {
+                stored: "fax".to_string()
+            }]
+        );
+        for id in [draft[1], draft[2]] {
+            assert!(matches!(
+                problems(id).as_slice(),
+                [
+                    Problem::UnreadableContent { .. },
+                    Problem::MissingSession { .. }
+                ]
+            ));
+        }
+        assert!(matches!(
+            problems(draft[0]).as_slice(),
+            [Problem::MissingSession { .. }]
+        ));
+        assert!(problems(fine).is_empty());
+        assert_eq!(report.orphaned_index_entries, [999]);
+
+        let repaired = storage.repair(&report).unwrap();
+        assert_eq!(
+            repaired,
+            RepairReport {
+                quarantined: 4,
+                detached: 1,
+                removed_index_entries: 1,
+            }
+        );
+        assert!(storage.check().unwrap().is_healthy());
+        assert_eq!(storage.count().unwrap(), 2);
+        assert_eq!(storage.get(draft[0]).unwrap().unwrap().session_id, None);
+
+        let quarantined: Vec<(i64, String)> = storage
+            .conn
+            .prepare("SELECT capture_id, reason FROM quarantine ORDER BY capture_id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        let ids: Vec<i64> = quarantined.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, [draft[1], draft[2], rehashed, retyped]);
+        assert!(quarantined[0].1.starts_with("unreadable content"));
+        assert_eq!(quarantined[2].1, "content does not match its hash");
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let dir = std::env::temp_dir().join(format!("fr_storage_backup_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let database = dir.join("captures.db");
+        let storage = Storage::open(&database).unwrap();
+        storage
+            .insert(&create_test_capture("before the backup"))
+            .unwrap();
+        let backup = storage.backup(dir.join("copy.db")).unwrap();
+        assert!(backup.size_bytes > 0);
+        let link = dir.join("link.db");
+        std::os::unix::fs::symlink(&database, &link).unwrap();
+        for over_itself in [&link, &dir.join(".").join("captures.db"), &database] {
+            assert!(matches!(
+                storage.backup(over_itself),
+                Err(Error::InvalidArgument { .. })
+            ));
+        }
+        storage
+            .insert(&create_test_capture("after the backup"))
+            .unwrap();
+        drop(storage);
 
-        let id = storage.insert(&capture).unwrap();
-        assert!(id.is_some());
+        let restored = Storage::restore_backup(&backup.path, &database, None).unwrap();
+        assert_eq!(restored.schema_version, migrations::CURRENT_VERSION);
+        let previous = restored.previous.unwrap();
+        assert_eq!(previous, dir.join("captures.db.before-restore.bak"));
+        let storage = Storage::open(&database).unwrap();
+        let contents: Vec<_> = storage
+            .get_recent(10)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.content)
+            .collect();
+        assert_eq!(contents, ["before the backup"]);
+        assert_eq!(Storage::open(&previous).unwrap().count().unwrap(), 2);
+
+        let not_ours = dir.join("notes.txt");
+        std::fs::write(&not_ours, "not a database").unwrap();
+        assert!(Storage::restore_backup(&not_ours, &database, None).is_err());
+        assert_eq!(storage.count().unwrap(), 1);
+        drop(storage);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-        let retrieved = storage.get(id.unwrap()).unwrap();
-        assert!(retrieved.is_some());
+    #[test]
+    fn test_encrypted_backup_needs_its_key() {
+        let dir = std::env::temp_dir().join(format!("fr_storage_locked_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let key = test_key("backup", 4);
+        let storage = Storage::open_with_key(dir.join("captures.db"), Some(&key)).unwrap();
+        storage.insert(&create_test_capture("secret")).unwrap();
+        let backup = storage.backup(dir.join("copy.db")).unwrap();
+        drop(storage);
 
-        let retrieved = retrieved.unwrap();
-        assert_eq!(retrieved.content, "Hello, world!");
-        assert_eq!(retrieved.capture_type, CaptureType::Clipboard);
+        let target = dir.join("restored.db");
+        assert!(matches!(
+            Storage::restore_backup(&backup.path, &target, None),
+            Err(Error::DatabaseLocked { .. })
+        ));
+        let restored = Storage::restore_backup(&backup.path, &target, Some(&key)).unwrap();
+        assert_eq!(restored.previous, None);
+        let storage = Storage::open_with_key(&target, Some(&key)).unwrap();
+        assert_eq!(storage.get_recent(1).unwrap()[0].content, "secret");
+        drop(storage);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_insert_deduplication() {
-        let storage = create_test_storage();
-        let capture = create_test_capture("Duplicate content");
-
-        let id1 = storage.insert(&capture).unwrap();
-        let id2 = storage.insert(&capture).unwrap();
+    fn test_read_only_storage_changes_nothing() {
+        let dir = std::env::temp_dir().join(format!("fr_storage_read_only_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("captures.db");
+        let storage = Storage::open(&path).unwrap();
+        storage.insert(&create_test_capture("plain")).unwrap();
+        drop(storage);
 
-        assert!(id1.is_some());
-        assert!(id2.is_none()); // Deduplicated
+        // A configured key does not encrypt the database.
+        let key = test_key("read_only", 5);
+        let storage = Storage::open_read_only(&path, Some(&key)).unwrap();
+        assert!(storage.check().unwrap().is_healthy());
+        assert!(storage.insert(&create_test_capture("more")).is_err());
+        drop(storage);
+        assert_eq!(Storage::open(&path).unwrap().count().unwrap(), 1);
+
+        // Nor is an older schema migrated.
+        let conn = Connection::open(&path).unwrap();
+        conn.execute(
+            "UPDATE metadata SET value = '1' WHERE key = 'schema_version'",
+            [],
+        )
+        .unwrap();
+        drop(conn);
+        assert!(matches!(
+            Storage::open_read_only(&path, None),
+            Err(Error::DatabaseMigration { .. })
+        ));
+        assert!(Storage::open_read_only(dir.join("missing.db"), None).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_get_nonexistent() {
-        let storage = create_test_storage();
-        let result = storage.get(99999).unwrap();
-        assert!(result.is_none());
+    fn test_check_encrypted_database() {
+        let mut storage = create_test_storage();
+        storage.rekey(Some(&test_key("check", 3)), false).unwrap();
+        insert_draft(&storage, 3);
+        storage.insert(&create_test_capture("secret")).unwrap();
+        let report = storage.check().unwrap();
+        assert!(report.is_healthy(), "{report:?}");
+        assert_eq!(report.checked, 4);
     }
 
     #[test]
-    fn test_get_recent() {
+    fn test_prune_max_captures() {
         let storage = create_test_storage();
 
-        for i in 0..5 {
+        for i in 0..10 {
             let capture = create_test_capture(&format!("Capture {i}"));
             storage.insert(&capture).unwrap();
         }
 
-        let recent = storage.get_recent(3).unwrap();
-        assert_eq!(recent.len(), 3);
+        assert_eq!(storage.count().unwrap(), 10);
+
+        let report = storage
+            .prune(&RetentionPolicy::new().with_max_captures(5))
+            .unwrap();
+        assert_eq!(report.over_count, 5);
+        assert_eq!(storage.count().unwrap(), 5);
     }
 
     #[test]
-    fn test_get_by_app() {
+    fn test_stats_empty() {
         let storage = create_test_storage();
+        let stats = storage.stats().unwrap();
 
-        let mut capture1 = create_test_capture("From app A");
-        capture1.source_app = Some("AppA".to_string());
-        storage.insert(&capture1).unwrap();
+        assert_eq!(stats.total_captures, 0);
+        assert!(stats.oldest_capture.is_none());
+        assert!(stats.newest_capture.is_none());
+    }
 
-        let mut capture2 = create_test_capture("From app B");
-        capture2.source_app = Some("AppB".to_string());
-        storage.insert(&capture2).unwrap();
+    #[test]
+    fn test_stats_with_data() {
+        let storage = create_test_storage();
 
-        let app_a = storage.get_by_app("AppA", 10).unwrap();
-        assert_eq!(app_a.len(), 1);
-        assert_eq!(app_a[0].source_app, Some("AppA".to_string()));
+        storage.insert(&create_test_capture("First")).unwrap();
+        storage.insert(&create_test_capture("Second")).unwrap();
+
+        let stats = storage.stats().unwrap();
+        assert_eq!(stats.total_captures, 2);
+        assert!(stats.oldest_capture.is_some());
+        assert!(stats.newest_capture.is_some());
+        assert_eq!(stats.content_bytes, 11);
+        assert_eq!(stats.stored_content_bytes, 11);
     }
 
     #[test]
-    fn test_get_by_type() {
+    fn test_large_content_is_compressed_transparently() {
+        let log = "2024-01-01 12:00:00 INFO worker: job finished in 12ms\n".repeat(2000);
         let storage = create_test_storage();
+        let id = storage.insert(&create_test_capture(&log)).unwrap().unwrap();
 
-        let clipboard = Capture::new("Clipboard".to_string(), CaptureType::Clipboard, None);
-        let textfield = Capture::new("TextField".to_string(), CaptureType::TextField, None);
+        let codec: Option<String> = storage
+            .conn
+            .query_row("SELECT codec FROM captures WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(codec.as_deref(), Some("zstd"));
+        assert_eq!(storage.get(id).unwrap().unwrap().content, log);
+        assert_eq!(
+            search_contents(&storage, "worker"),
+            std::slice::from_ref(&log)
+        );
 
-        storage.insert(&clipboard).unwrap();
-        storage.insert(&textfield).unwrap();
+        let stats = storage.stats().unwrap();
+        assert_eq!(stats.content_bytes, log.len() as u64);
+        assert!(stats.stored_content_bytes < stats.content_bytes / 20);
 
-        let results = storage.get_by_type(CaptureType::TextField, 10).unwrap();
-        assert_eq!(results.len(), 1);
-        assert_eq!(results[0].capture_type, CaptureType::TextField);
+        let uncompressed = create_test_storage().with_compression_threshold(None);
+        uncompressed.insert(&create_test_capture(&log)).unwrap();
+        let stats = uncompressed.stats().unwrap();
+        assert_eq!(stats.stored_content_bytes, stats.content_bytes);
     }
 
-    #[test]
-    fn test_search() {
-        let storage = create_test_storage();
+    fn test_key(name: &str, byte: u8) -> EncryptionKey {
+        let path =
+            std::env::temp_dir().join(format!("fr_storage_{name}_{}.key", std::process::id()));
+        std::fs::write(&path, [byte; 32]).unwrap();
+        EncryptionKey::key_file(path)
+    }
 
-        storage.insert(&create_test_capture("Hello world")).unwrap();
+    /// Whether any stored content, delta, hash, note, tag or index entry
+    /// contains `text` in the clear.
+    fn stored_in_clear(storage: &Storage, text: &str) -> bool {
+        let hash = Capture::compute_hash(text);
         storage
-            .insert(&create_test_capture("Goodbye world"))
-            .unwrap();
-        storage.insert(&create_test_capture("Hello there")).unwrap();
+            .conn
+            .query_row(
+                "SELECT (SELECT COUNT(*) FROM captures
+                         WHERE instr(CAST(content AS BLOB), CAST(?1 AS BLOB)) > 0
+                            OR instr(COALESCE(delta, x''), CAST(?1 AS BLOB)) > 0
+                            OR instr(CAST(COALESCE(note, '') AS BLOB), CAST(?1 AS BLOB)) > 0
+                            OR content_hash = ?2)
+                      + (SELECT COUNT(*) FROM capture_tags
+                         WHERE instr(CAST(tag AS BLOB), CAST(?1 AS BLOB)) > 0
+                            OR tag_hash = ?2)",
+                params![text, hash],
+                |row| row.get::<_, i64>(0),
+            )
+            .unwrap()
+            > 0
+            || !search(storage, text).is_empty() && !storage.is_encrypted()
+    }
 
-        let results = storage.search("Hello", 10).unwrap();
-        assert_eq!(results.len(), 2);
+    #[test]
+    fn test_encrypted_content_is_not_stored_in_clear() {
+        let mut storage = create_test_storage().with_keyframe_interval(4);
+        storage
+            .rekey(Some(&test_key("encrypted", 1)), false)
+            .unwrap();
+        assert!(storage.is_encrypted());
 
-        let results = storage.search("world", 10).unwrap();
-        assert_eq!(results.len(), 2);
+        let secret = "the launch code is swordfish";
+        let id = storage
+            .insert(&create_test_capture(secret))
+            .unwrap()
+            .unwrap();
+        assert_eq!(storage.insert(&create_test_capture(secret)).unwrap(), None);
+        let ids = insert_draft(&storage, 6);
+        let log = "2024-01-01 INFO worker: job finished\n".repeat(500);
+        let log_id = storage.insert(&create_test_capture(&log)).unwrap().unwrap();
+        assert!(delta_count(&storage) > 0);
+
+        let capture = storage.get(id).unwrap().unwrap();
+        assert_eq!(capture.content, secret);
+        assert_eq!(capture.content_hash, Capture::compute_hash(secret));
+        assert_eq!(storage.get(log_id).unwrap().unwrap().content, log);
+        let history = storage.history(ids[5]).unwrap();
+        assert_eq!(history.len(), 6);
+        assert!(history[5].content.contains("Item 6: follow up"));
+        for text in [secret, "swordfish", "Meeting notes", "Item 6", "worker"] {
+            assert!(
+                !stored_in_clear(&storage, text),
+                "{text} is stored in clear"
+            );
+        }
 
-        let results = storage.search("nonexistent", 10).unwrap();
-        assert_eq!(results.len(), 0);
+        // Searches go through the blind index.
+        let hits = search(&storage, "SWORDFISH");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(
+            hits[0].snippet.as_deref(),
+            Some("the launch code is [swordfish]")
+        );
+        assert_eq!(search(&storage, "\"launch code\"").len(), 1);
+        assert_eq!(search(&storage, "\"code launch\"").len(), 0);
+        assert_eq!(search(&storage, "worker AND job").len(), 1);
+        assert_eq!(search(&storage, "\"item 6\"").len(), 1);
+        // Prefixes only match whole words.
+        assert_eq!(search(&storage, "sword*").len(), 0);
+
+        // Deleting a base re-encodes its dependents encrypted.
+        storage.purge(&[ids[0]]).unwrap();
+        assert_eq!(storage.history(ids[5]).unwrap().len(), 5);
+        assert!(!stored_in_clear(&storage, "Meeting notes"));
     }
 
     #[test]
-    fn test_count() {
-        let storage = create_test_storage();
-        assert_eq!(storage.count().unwrap(), 0);
+    fn test_encrypted_notes_and_tags_are_not_stored_in_clear() {
+        let mut storage = create_test_storage();
+        let id = storage
+            .insert(&create_test_capture("quarterly report"))
+            .unwrap()
+            .unwrap();
+        storage
+            .add_tags(id, &["finance".to_string(), "draft".to_string()])
+            .unwrap();
+        storage.set_note(id, Some("ask about the merger")).unwrap();
+        assert!(stored_in_clear(&storage, "merger"));
+        assert!(stored_in_clear(&storage, "finance"));
 
-        storage.insert(&create_test_capture("One")).unwrap();
-        storage.insert(&create_test_capture("Two")).unwrap();
+        storage
+            .rekey(Some(&test_key("annotations", 1)), false)
+            .unwrap();
+        let later = storage
+            .insert(&create_test_capture("budget"))
+            .unwrap()
+            .unwrap();
+        storage.add_tags(later, &["finance".to_string()]).unwrap();
+        storage.set_note(later, Some("confidential")).unwrap();
+        for text in ["merger", "finance", "draft", "confidential"] {
+            assert!(
+                !stored_in_clear(&storage, text),
+                "{text} is stored in clear"
+            );
+        }
 
-        assert_eq!(storage.count().unwrap(), 2);
+        let capture = storage.get(id).unwrap().unwrap();
+        assert_eq!(capture.tags, ["draft", "finance"]);
+        assert_eq!(capture.note.as_deref(), Some("ask about the merger"));
+        let tagged = query_captures(&storage, &CaptureQuery::new().with_tag("finance"));
+        assert_eq!(tagged.len(), 2);
+        assert!(storage.add_tags(id, &["draft".to_string()]).unwrap());
+        assert!(storage.remove_tags(id, &["draft".to_string()]).unwrap());
+        assert_eq!(storage.get(id).unwrap().unwrap().tags, ["finance"]);
+
+        storage
+            .rekey(Some(&test_key("annotations", 1)), true)
+            .unwrap();
+        let tagged = query_captures(&storage, &CaptureQuery::new().with_tag("finance"));
+        assert_eq!(tagged.len(), 2);
+        assert_eq!(tagged[0].note.as_deref(), Some("confidential"));
+
+        storage.rekey(None, false).unwrap();
+        assert!(stored_in_clear(&storage, "confidential"));
+        assert!(stored_in_clear(&storage, "finance"));
+        let tagged = query_captures(&storage, &CaptureQuery::new().with_tag("finance"));
+        assert_eq!(tagged.len(), 2);
     }
 
     #[test]
-    fn test_delete() {
-        let storage = create_test_storage();
+    fn test_opening_encrypts_notes_and_tags_left_in_clear() {
+        let dir = std::env::temp_dir().join(format!("fr_storage_plain_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("captures.db");
+        let key = test_key("plain", 1);
+
+        let storage = Storage::open_with_key(&path, Some(&key)).unwrap();
         let id = storage
-            .insert(&create_test_capture("To delete"))
+            .insert(&create_test_capture("quarterly report"))
             .unwrap()
             .unwrap();
+        // As stored before notes and tags were encrypted.
+        storage
+            .conn
+            .execute_batch(&format!(
+                "UPDATE captures SET note = 'ask about the merger' WHERE id = {id};
+                 INSERT INTO capture_tags (capture_id, tag_hash, tag)
+                 VALUES ({id}, '{}', 'finance');",
+                Capture::compute_hash("finance")
+            ))
+            .unwrap();
+        assert_eq!(
+            storage.get(id).unwrap().unwrap().note.as_deref(),
+            Some("ask about the merger")
+        );
+        drop(storage);
 
-        assert!(storage.get(id).unwrap().is_some());
-        assert!(storage.delete(id).unwrap());
-        assert!(storage.get(id).unwrap().is_none());
+        let storage = Storage::open_with_key(&path, Some(&key)).unwrap();
+        assert!(!stored_in_clear(&storage, "merger"));
+        assert!(!stored_in_clear(&storage, "finance"));
+        let tagged = query_captures(&storage, &CaptureQuery::new().with_tag("finance"));
+        assert_eq!(tagged.len(), 1);
+        assert_eq!(tagged[0].note.as_deref(), Some("ask about the merger"));
+
+        let _ = std::fs::remove_dir_all(&dir);
     }
 
     #[test]
-    fn test_delete_nonexistent() {
-        let storage = create_test_storage();
-        assert!(!storage.delete(99999).unwrap());
+    fn test_encrypted_values_are_bound_to_their_capture() {
+        let mut storage = create_test_storage();
+        storage.rekey(Some(&test_key("bound", 1)), false).unwrap();
+        let ids: Vec<i64> = ["first", "second", "third"]
+            .into_iter()
+            .map(|text| {
+                let id = storage.insert(&create_test_capture(text)).unwrap().unwrap();
+                storage.set_note(id, Some(text)).unwrap();
+                id
+            })
+            .collect();
+
+        // Content copied from another capture, or a note copied into the
+        // content, does not decrypt.
+        storage
+            .conn
+            .execute_batch(&format!(
+                "UPDATE captures SET content = (SELECT content FROM captures WHERE id = {1})
+                 WHERE id = {0};
+                 UPDATE captures SET content = note WHERE id = {2};",
+                ids[0], ids[1], ids[2]
+            ))
+            .unwrap();
+        assert!(storage.get(ids[0]).is_err());
+        assert_eq!(storage.get(ids[1]).unwrap().unwrap().content, "second");
+        assert!(storage.get(ids[2]).is_err());
+        assert_eq!(storage.check().unwrap().problems.len(), 2);
     }
 
     #[test]
-    fn test_prune_keep_recent() {
-        let storage = create_test_storage();
+    fn test_opening_encrypts_legacy_values_again() {
+        let dir = std::env::temp_dir().join(format!("fr_storage_legacy_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("captures.db");
+        let key = test_key("legacy", 1);
 
-        for i in 0..10 {
-            let capture = create_test_capture(&format!("Capture {i}"));
-            storage.insert(&capture).unwrap();
+        let storage = Storage::open_with_key(&path, Some(&key)).unwrap();
+        let id = storage
+            .insert(&create_test_capture("the launch code"))
+            .unwrap()
+            .unwrap();
+        storage.set_note(id, Some("swordfish")).unwrap();
+        storage.add_tags(id, &["secret".to_string()]).unwrap();
+        let draft = insert_draft(&storage, 3);
+        assert!(delta_count(&storage) > 0);
+
+        // Store every value as it was encrypted before it was bound to its
+        // capture and column.
+        let keys = storage.keys.clone().unwrap();
+        let values: Vec<(i64, String, Vec<u8>)> = storage
+            .conn
+            .prepare(
+                "SELECT id, 'content', content FROM captures WHERE typeof(content) = 'blob'
+                 UNION ALL SELECT id, 'delta', delta FROM captures WHERE delta IS NOT NULL
+                 UNION ALL SELECT id, 'note', note FROM captures WHERE note IS NOT NULL
+                 UNION ALL SELECT capture_id, 'tag', tag FROM capture_tags",
+            )
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        for (id, column, value) in values {
+            let plain = keys.decrypt(id, &column, &value).unwrap();
+            let legacy = keys.encrypt_legacy(&plain).unwrap();
+            let sql = if column == "tag" {
+                "UPDATE capture_tags SET tag = ?2 WHERE capture_id = ?1".to_string()
+            } else {
+                format!("UPDATE captures SET {column} = ?2 WHERE id = ?1")
+            };
+            storage.conn.execute(&sql, params![id, legacy]).unwrap();
         }
+        storage
+            .conn
+            .execute("DELETE FROM metadata WHERE key = 'encryption_format'", [])
+            .unwrap();
+        drop(storage);
 
-        assert_eq!(storage.count().unwrap(), 10);
+        // Legacy values can still be read without changing them.
+        let storage = Storage::open_read_only(&path, Some(&key)).unwrap();
+        assert_eq!(
+            storage.get(id).unwrap().unwrap().note.as_deref(),
+            Some("swordfish")
+        );
+        drop(storage);
 
-        let pruned = storage.prune_keep_recent(5).unwrap();
-        assert_eq!(pruned, 5);
-        assert_eq!(storage.count().unwrap(), 5);
+        let storage = Storage::open_with_key(&path, Some(&key)).unwrap();
+        let capture = storage.get(id).unwrap().unwrap();
+        assert_eq!(capture.content, "the launch code");
+        assert_eq!(capture.note.as_deref(), Some("swordfish"));
+        assert_eq!(capture.tags, ["secret"]);
+        assert_eq!(storage.history(draft[2]).unwrap().len(), 3);
+        let content: Vec<u8> = storage
+            .conn
+            .query_row("SELECT content FROM captures WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert!(keys
+            .current_format()
+            .decrypt(id, "content", &content)
+            .is_some());
+        assert!(storage.check().unwrap().problems.is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
     }
 
     #[test]
-    fn test_stats_empty() {
-        let storage = create_test_storage();
-        let stats = storage.stats().unwrap();
+    fn test_rekey_and_decrypt() {
+        let dir = std::env::temp_dir().join(format!("fr_storage_rekey_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("captures.db");
+        let (first, second) = (test_key("rekey_first", 1), test_key("rekey_second", 2));
 
-        assert_eq!(stats.total_captures, 0);
-        assert!(stats.oldest_capture.is_none());
-        assert!(stats.newest_capture.is_none());
-    }
+        let storage = Storage::open(&path).unwrap();
+        storage
+            .insert(&create_test_capture("written before encryption"))
+            .unwrap();
+        insert_draft(&storage, 3);
+        drop(storage);
 
-    #[test]
-    fn test_stats_with_data() {
-        let storage = create_test_storage();
+        // Opening with a key encrypts what was stored before.
+        let mut storage = Storage::open_with_key(&path, Some(&first)).unwrap();
+        assert!(!stored_in_clear(&storage, "written before encryption"));
+        assert_eq!(search(&storage, "before").len(), 1);
+        drop(storage);
+        assert!(matches!(
+            Storage::open(&path),
+            Err(Error::DatabaseLocked { .. })
+        ));
+        assert!(matches!(
+            Storage::open_with_key(&path, Some(&second)),
+            Err(Error::DatabaseLocked { .. })
+        ));
 
-        storage.insert(&create_test_capture("First")).unwrap();
-        storage.insert(&create_test_capture("Second")).unwrap();
+        // A new key rewraps the data key, and rotation replaces it.
+        storage = Storage::open_with_key(&path, Some(&first)).unwrap();
+        storage.rekey(Some(&second), false).unwrap();
+        drop(storage);
+        assert!(Storage::open_with_key(&path, Some(&first)).is_err());
+        storage = Storage::open_with_key(&path, Some(&second)).unwrap();
+        let hash = |storage: &Storage| -> String {
+            storage
+                .conn
+                .query_row(
+                    "SELECT content_hash FROM captures WHERE id = 1",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        let mac = hash(&storage);
+        storage.rekey(Some(&second), true).unwrap();
+        assert_ne!(hash(&storage), mac);
+        assert_eq!(search(&storage, "before").len(), 1);
 
-        let stats = storage.stats().unwrap();
-        assert_eq!(stats.total_captures, 2);
-        assert!(stats.oldest_capture.is_some());
-        assert!(stats.newest_capture.is_some());
+        // Decrypting restores plain content, hashes and index.
+        storage.rekey(None, false).unwrap();
+        drop(storage);
+        let storage = Storage::open(&path).unwrap();
+        assert!(!storage.is_encrypted());
+        assert_eq!(
+            hash(&storage),
+            Capture::compute_hash("written before encryption")
+        );
+        assert!(stored_in_clear(&storage, "written before encryption"));
+        assert_eq!(search(&storage, "befo*").len(), 1);
+        assert_eq!(storage.history(4).unwrap().len(), 3);
+
+        let _ = std::fs::remove_dir_all(&dir);
     }
 
     #[test]
@@ -661,7 +2787,7 @@ mod tests {
     }
 
     #[test]
-    fn test_get_by_time_range() {
+    fn test_query_by_time_range() {
         let storage = create_test_storage();
 
         // Insert some captures
@@ -671,16 +2797,143 @@ mod tests {
         let since = now - Duration::hours(1);
         let until = now + Duration::hours(1);
 
-        let results = storage.get_by_time_range(since, until, 10).unwrap();
+        let results = query_captures(
+            &storage,
+            &CaptureQuery::new()
+                .with_since(since)
+                .with_until(until)
+                .with_limit(10),
+        );
         assert_eq!(results.len(), 1);
 
         // Query outside range
         let old_since = now - Duration::days(10);
         let old_until = now - Duration::days(9);
-        let results = storage.get_by_time_range(old_since, old_until, 10).unwrap();
+        let results = query_captures(
+            &storage,
+            &CaptureQuery::new()
+                .with_since(old_since)
+                .with_until(old_until)
+                .with_limit(10),
+        );
         assert_eq!(results.len(), 0);
     }
 
+    /// Read every page of a query, checking that no page exceeds the limit.
+    fn all_pages(storage: &Storage, query: &CaptureQuery) -> Vec<Capture> {
+        let mut captures = Vec::new();
+        let mut page = storage.query(query).unwrap();
+        loop {
+            assert!(page.hits.len() <= query.limit().unwrap());
+            let next = page.next.take();
+            captures.extend(page.into_captures());
+            let Some(cursor) = next else {
+                return captures;
+            };
+            page = storage.query(&query.clone().with_after(cursor)).unwrap();
+        }
+    }
+
+    fn contents(captures: &[Capture]) -> Vec<&str> {
+        captures.iter().map(|c| c.content.as_str()).collect()
+    }
+
+    #[test]
+    fn test_query_pages_in_both_orders() {
+        let storage = create_test_storage();
+        let base = Utc::now() - Duration::hours(1);
+        // Pairs of captures share a timestamp, so paging must break ties by id.
+        for i in 0..7 {
+            let mut capture = create_test_capture(&format!("Capture {i}"));
+            capture.timestamp = base + Duration::minutes(i / 2);
+            storage.insert(&capture).unwrap();
+        }
+
+        let newest = all_pages(&storage, &CaptureQuery::new().with_limit(3));
+        assert_eq!(
+            contents(&newest),
+            [
+                "Capture 6",
+                "Capture 5",
+                "Capture 4",
+                "Capture 3",
+                "Capture 2",
+                "Capture 1",
+                "Capture 0"
+            ]
+        );
+
+        let oldest = all_pages(
+            &storage,
+            &CaptureQuery::new()
+                .with_order(QueryOrder::Oldest)
+                .with_limit(2),
+        );
+        assert_eq!(
+            contents(&oldest),
+            [
+                "Capture 0",
+                "Capture 1",
+                "Capture 2",
+                "Capture 3",
+                "Capture 4",
+                "Capture 5",
+                "Capture 6"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_query_pages_by_relevance() {
+        let storage = create_test_storage();
+        for i in 0..6 {
+            let content = format!("deploy {}{i}", "deploy ".repeat(i % 3));
+            storage.insert(&create_test_capture(&content)).unwrap();
+        }
+        storage.insert(&create_test_capture("unrelated")).unwrap();
+
+        let query = CaptureQuery::new().with_text("deploy").with_limit(4);
+        let first = storage.query(&query).unwrap();
+        assert!(first.hits.iter().all(|hit| hit.snippet.is_some()));
+
+        let paged = all_pages(&storage, &query);
+        let unpaged = query_captures(&storage, &query.clone().with_limit(100));
+        assert_eq!(paged.len(), 6);
+        assert_eq!(paged, unpaged);
+        // Captures repeating the term rank first.
+        assert!(paged[0].content.starts_with("deploy deploy deploy"));
+    }
+
+    #[test]
+    fn test_query_combines_filters() {
+        let storage = create_test_storage();
+        let now = Utc::now();
+        let inserts = [
+            ("deploy api", "Terminal", CaptureType::Clipboard, 0),
+            ("deploy web", "Terminal", CaptureType::TextField, 0),
+            ("deploy db", "Browser", CaptureType::Clipboard, 0),
+            ("deploy old", "Terminal", CaptureType::Clipboard, 48),
+            ("build api", "Terminal", CaptureType::Clipboard, 0),
+        ];
+        for (content, app, capture_type, hours_ago) in inserts {
+            let mut capture =
+                Capture::new(content.to_string(), capture_type, Some(app.to_string()));
+            capture.timestamp = now - Duration::hours(hours_ago);
+            storage.insert(&capture).unwrap();
+        }
+
+        let results = query_captures(
+            &storage,
+            &CaptureQuery::new()
+                .with_text("deploy")
+                .with_app("Terminal")
+                .with_type(CaptureType::Clipboard)
+                .with_since(now - Duration::hours(1))
+                .with_limit(10),
+        );
+        assert_eq!(contents(&results), ["deploy api"]);
+    }
+
     #[test]
     fn test_all_capture_types() {
         let storage = create_test_storage();
@@ -787,34 +3040,115 @@ mod tests {
     }
 
     #[test]
-    fn test_prune_older_than() {
+    fn test_prune_max_age() {
         let storage = create_test_storage();
 
         // Insert a capture
         storage.insert(&create_test_capture("Recent")).unwrap();
 
         // Prune with 1 day max age - nothing should be deleted
-        let pruned = storage.prune_older_than(Duration::days(1)).unwrap();
-        assert_eq!(pruned, 0);
+        let report = storage
+            .prune(&RetentionPolicy::new().with_max_age(Duration::days(1)))
+            .unwrap();
+        assert_eq!(report.expired, 0);
         assert_eq!(storage.count().unwrap(), 1);
 
-        // Prune with 0 seconds max age - still should keep the capture (it was just inserted)
-        // Note: This might be flaky in very slow systems, but generally captures are < 1 second old
-        let pruned = storage.prune_older_than(Duration::seconds(0)).unwrap();
-        // The capture should be deleted since it's older than 0 seconds
-        assert_eq!(storage.count().unwrap(), 1 - pruned as i64);
+        // Prune with 0 seconds max age - the capture is older than that
+        let report = storage
+            .prune(&RetentionPolicy::new().with_max_age(Duration::seconds(0)))
+            .unwrap();
+        assert_eq!(report.expired, 1);
+        assert_eq!(storage.count().unwrap(), 0);
     }
 
     #[test]
-    fn test_prune_keep_recent_no_pruning_needed() {
+    fn test_prune_max_captures_no_pruning_needed() {
         let storage = create_test_storage();
 
         storage.insert(&create_test_capture("One")).unwrap();
         storage.insert(&create_test_capture("Two")).unwrap();
 
         // Keep more than we have
-        let pruned = storage.prune_keep_recent(10).unwrap();
-        assert_eq!(pruned, 0);
+        let report = storage
+            .prune(&RetentionPolicy::new().with_max_captures(10))
+            .unwrap();
+        assert_eq!(report.total(), 0);
+        assert_eq!(storage.count().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_prune_to_size_reclaims_space() {
+        let path = std::env::temp_dir().join(format!("fr_storage_prune_{}.db", std::process::id()));
+        let storage = Storage::open(&path)
+            .unwrap()
+            .with_compression_threshold(None);
+        let ids: Vec<i64> = (0..200)
+            .map(|i| {
+                let content = format!("{i} {}", "x".repeat(4000));
+                storage
+                    .insert(&create_test_capture(&content))
+                    .unwrap()
+                    .unwrap()
+            })
+            .collect();
+
+        let used = retention::used_bytes(&storage.conn).unwrap();
+        let policy = RetentionPolicy::new().with_max_size(used / 2);
+        let plan = storage.plan_prune(&policy).unwrap();
+        assert_eq!(plan.report.total(), plan.ids.len());
+        assert!((90..=110).contains(&plan.ids.len()), "{:?}", plan.report);
+        assert_eq!(plan.ids[..], ids[..plan.ids.len()]);
+        assert_eq!(storage.count().unwrap(), 200);
+
+        let report = storage.prune(&policy).unwrap();
+        assert_eq!(report.over_size, plan.ids.len());
+        assert!(report.reclaimed_bytes > 0);
+        let file_bytes = retention::file_bytes(&storage.conn).unwrap();
+        assert!(file_bytes < used);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), file_bytes);
+        assert!(storage.get(ids[199]).unwrap().is_some());
+        // The space used by each capture is estimated, so the result is
+        // only close to the limit.
+        let remaining = retention::used_bytes(&storage.conn).unwrap();
+        assert!(remaining < used / 2 + used / 10, "{remaining} of {used}");
+
+        drop(storage);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[test]
+    fn test_prune_moves_captures_to_the_trash() {
+        let storage = create_test_storage();
+        let ids: Vec<i64> = (0..4)
+            .map(|i| {
+                let mut capture = create_test_capture(&format!("capture {i}"));
+                capture.timestamp = Utc::now() - Duration::minutes(10 - i);
+                storage.insert(&capture).unwrap().unwrap()
+            })
+            .collect();
+        let policy = RetentionPolicy::new()
+            .with_max_captures(2)
+            .with_trash_grace(Duration::days(7));
+
+        let report = storage.prune(&policy).unwrap();
+        assert_eq!((report.trashed, report.purged), (2, 0));
+        assert_eq!(storage.count().unwrap(), 2);
+        let trashed: Vec<i64> = storage
+            .list_trash(10)
+            .unwrap()
+            .into_iter()
+            .filter_map(|t| t.capture.id)
+            .collect();
+        assert_eq!(trashed, [ids[1], ids[0]]);
+
+        // Without a grace period the trash is emptied.
+        let report = storage
+            .prune(&RetentionPolicy::new().with_max_captures(2))
+            .unwrap();
+        assert_eq!(report.purged, 2);
+        assert!(storage.list_trash(10).unwrap().is_empty());
         assert_eq!(storage.count().unwrap(), 2);
     }
 
@@ -844,11 +3178,14 @@ mod tests {
     fn test_storage_stats_debug() {
         let stats = StorageStats {
             total_captures: 10,
+            trashed_captures: 0,
             oldest_capture: Some(Utc::now()),
             newest_capture: Some(Utc::now()),
             db_size_bytes: 1024,
+            content_bytes: 4096,
+            stored_content_bytes: 512,
         };
-        let debug_str = format!("{:?}", stats);
+        let debug_str = format!("{stats:?}");
         assert!(debug_str.contains("total_captures"));
         assert!(debug_str.contains("10"));
     }
@@ -857,9 +3194,12 @@ mod tests {
     fn test_storage_stats_clone() {
         let stats = StorageStats {
             total_captures: 5,
+            trashed_captures: 1,
             oldest_capture: None,
             newest_capture: None,
             db_size_bytes: 512,
+            content_bytes: 0,
+            stored_content_bytes: 0,
         };
         let cloned = stats.clone();
         assert_eq!(stats, cloned);
@@ -874,7 +3214,7 @@ mod tests {
             .unwrap();
 
         // Empty search should match everything
-        let results = storage.search("", 10).unwrap();
+        let results = search(&storage, "");
         assert_eq!(results.len(), 1);
     }
 
@@ -889,25 +3229,35 @@ mod tests {
     }
 
     #[test]
-    fn test_get_by_app_empty_results() {
+    fn test_query_by_app_empty_results() {
         let storage = create_test_storage();
 
         let mut capture = create_test_capture("Test");
         capture.source_app = Some("OtherApp".to_string());
         storage.insert(&capture).unwrap();
 
-        let results = storage.get_by_app("NonExistentApp", 10).unwrap();
+        let results = query_captures(
+            &storage,
+            &CaptureQuery::new()
+                .with_app("NonExistentApp")
+                .with_limit(10),
+        );
         assert_eq!(results.len(), 0);
     }
 
     #[test]
-    fn test_get_by_type_keystroke() {
+    fn test_query_by_type_keystroke() {
         let storage = create_test_storage();
 
         let keystroke = Capture::new("Typed".to_string(), CaptureType::Keystroke, None);
         storage.insert(&keystroke).unwrap();
 
-        let results = storage.get_by_type(CaptureType::Keystroke, 10).unwrap();
+        let results = query_captures(
+            &storage,
+            &CaptureQuery::new()
+                .with_type(CaptureType::Keystroke)
+                .with_limit(10),
+        );
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].capture_type, CaptureType::Keystroke);
     }
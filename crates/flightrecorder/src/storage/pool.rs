@@ -0,0 +1,193 @@
+//! A pool of read-only connections to a database file.
+//!
+//! In WAL mode, a reader sees the last transaction committed when its own
+//! read began and never waits for the writer, nor the writer for it. The
+//! daemon inserts captures through one [`Storage`] and answers searches from
+//! a [`ReadPool`], so a slow search cannot hold up capture.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rusqlite::{Connection, OpenFlags};
+use tracing::debug;
+
+use super::crypto::Keys;
+use super::{
+    Storage, DEFAULT_COMPRESSION_THRESHOLD, DEFAULT_KEYFRAME_INTERVAL, DEFAULT_SESSION_GAP,
+};
+use crate::error::{Error, Result};
+
+/// How long to wait for a free connection before giving up.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Read-only connections to a database, each a [`Storage`] that can run any
+/// query but fails to write.
+///
+/// Cloning a pool is cheap and shares its connections.
+#[derive(Clone)]
+pub struct ReadPool {
+    pool: r2d2::Pool<ReadOnly>,
+    path: PathBuf,
+}
+
+impl std::fmt::Debug for ReadPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.pool.state();
+        f.debug_struct("ReadPool")
+            .field("path", &self.path)
+            .field("connections", &state.connections)
+            .field("idle", &state.idle_connections)
+            .finish()
+    }
+}
+
+impl ReadPool {
+    /// Open `size` read-only connections to the database of `storage`.
+    pub(super) fn open(storage: &Storage, size: u32) -> Result<Self> {
+        let manager = ReadOnly {
+            path: storage.path.clone(),
+            keys: storage.keys.clone(),
+        };
+        let pool = r2d2::Pool::builder()
+            .max_size(size)
+            .connection_timeout(CONNECTION_TIMEOUT)
+            .build(manager)
+            .map_err(|e| Error::Timeout {
+                operation: format!(
+                    "opening read connections to {}: {e}",
+                    storage.path.display()
+                ),
+            })?;
+        debug!(
+            "Opened {size} read connections to {}",
+            storage.path.display()
+        );
+        Ok(Self {
+            pool,
+            path: storage.path.clone(),
+        })
+    }
+
+    /// Take a connection from the pool, waiting for one to be returned if
+    /// they are all in use. The connection goes back to the pool when the
+    /// guard is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Timeout`] if no connection is free in time.
+    pub fn get(&self) -> Result<impl std::ops::Deref<Target = Storage>> {
+        self.pool.get().map_err(|e| Error::Timeout {
+            operation: format!("waiting for a read connection: {e}"),
+        })
+    }
+}
+
+/// Opens the connections of a [`ReadPool`].
+#[derive(Debug)]
+struct ReadOnly {
+    path: PathBuf,
+    keys: Option<Arc<Keys>>,
+}
+
+impl r2d2::ManageConnection for ReadOnly {
+    type Connection = Storage;
+    type Error = Error;
+
+    fn connect(&self) -> Result<Storage> {
+        // The writer has created and migrated the database already.
+        let conn = connect(&self.path)?;
+        Ok(Storage {
+            path: self.path.clone(),
+            conn,
+            session_gap: DEFAULT_SESSION_GAP,
+            keyframe_interval: DEFAULT_KEYFRAME_INTERVAL,
+            compression_threshold: Some(DEFAULT_COMPRESSION_THRESHOLD),
+            keys: self.keys.clone(),
+        })
+    }
+
+    fn is_valid(&self, storage: &mut Storage) -> Result<()> {
+        storage.conn.execute_batch("SELECT 1")?;
+        Ok(())
+    }
+
+    fn has_broken(&self, _storage: &mut Storage) -> bool {
+        false
+    }
+}
+
+/// Open a read-only connection to the database at `path`.
+pub(super) fn connect(path: &Path) -> Result<Connection> {
+    Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_URI
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+    .map_err(|source| Error::DatabaseOpen {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{Capture, CaptureType};
+    use crate::storage::{CaptureQuery, EncryptionKey};
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fr_pool_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("captures.db")
+    }
+
+    fn clipboard(content: &str) -> Capture {
+        Capture::new(content.to_string(), CaptureType::Clipboard, None)
+    }
+
+    #[test]
+    fn test_readers_see_committed_captures() {
+        let path = temp_path("committed");
+        let storage = Storage::open(&path).unwrap();
+        let pool = storage.read_pool(2).unwrap();
+        storage.insert(&clipboard("before")).unwrap();
+
+        let reader = pool.get().unwrap();
+        assert_eq!(reader.count().unwrap(), 1);
+        storage
+            .insert_batch(&[clipboard("first"), clipboard("second")])
+            .unwrap();
+        assert_eq!(reader.count().unwrap(), 3);
+
+        // A read connection cannot write.
+        assert!(reader.insert(&clipboard("rejected")).is_err());
+        drop(reader);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_readers_decrypt_with_the_writers_keys() {
+        let path = temp_path("encrypted");
+        let key = EncryptionKey::passphrase("correct horse battery staple");
+        let storage = Storage::open_with_key(&path, Some(&key)).unwrap();
+        storage.insert(&clipboard("secret meeting notes")).unwrap();
+
+        let pool = storage.read_pool(1).unwrap();
+        let page = pool
+            .get()
+            .unwrap()
+            .query(&CaptureQuery::new().with_text("meeting"))
+            .unwrap();
+        assert_eq!(page.hits.len(), 1);
+        assert_eq!(page.hits[0].capture.content, "secret meeting notes");
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_in_memory_database_has_no_pool() {
+        let storage = Storage::open_in_memory().unwrap();
+        assert!(storage.read_pool(1).is_err());
+    }
+}
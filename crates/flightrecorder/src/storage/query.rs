@@ -0,0 +1,438 @@
+//! Composable capture queries.
+//!
+//! A [`CaptureQuery`] combines any of a full-text query, an application, a
+//! capture type and a time range, and compiles into a single parameterized SQL
+//! statement. Results come back a page at a time: each [`QueryPage`] carries a
+//! [`Cursor`] that continues after its last row (keyset pagination), so later
+//! pages cost the same as the first and are not disturbed by new captures.
+
+use chrono::{DateTime, Utc};
+use rusqlite::types::Value;
+
+use super::annotations;
+use super::crypto::Keys;
+use super::search::{self, Highlighter, SearchHit};
+use super::trash::NOT_TRASHED;
+use crate::capture::{Capture, CaptureType};
+use crate::error::Result;
+
+/// Columns selected for every query, in the order `Storage::row_to_capture`
+/// reads them. Queries add the rank after them.
+const COLUMNS: &str = "c.id, c.timestamp, c.source_app, c.content, c.content_hash, \
+                       c.capture_type, c.session_id, c.version, c.base_id, c.codec, c.encrypted, \
+                       c.pinned, c.note";
+
+/// How query results are ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryOrder {
+    /// Most recent first.
+    Newest,
+    /// Oldest first.
+    Oldest,
+    /// Most relevant to the full-text query first (BM25), then most recent.
+    /// Without a full-text query this is the same as [`QueryOrder::Newest`].
+    Relevance,
+}
+
+/// Where a page of results ends, so that the next page can start after it.
+///
+/// A cursor is only meaningful for a query with the same filters and order
+/// as the one that produced it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    timestamp: i64,
+    id: i64,
+    rank: Option<f64>,
+}
+
+/// One page of query results.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryPage {
+    /// The matching captures, in the query's order.
+    pub hits: Vec<SearchHit>,
+    /// Cursor for the next page, or `None` if this page is the last. The next
+    /// page may be empty if the results ended exactly at the page limit.
+    pub next: Option<Cursor>,
+}
+
+impl QueryPage {
+    /// Get the captures on this page, dropping snippets.
+    #[must_use]
+    pub fn into_captures(self) -> Vec<Capture> {
+        self.hits.into_iter().map(|hit| hit.capture).collect()
+    }
+}
+
+/// A query for captures matching every filter that is set.
+///
+/// # Example
+///
+/// ```
+/// use flightrecorder::capture::CaptureType;
+/// use flightrecorder::storage::{CaptureQuery, Storage};
+///
+/// let storage = Storage::open_in_memory()?;
+/// let query = CaptureQuery::new()
+///     .with_text("deploy*")
+///     .with_app("Terminal")
+///     .with_type(CaptureType::Clipboard)
+///     .with_limit(20);
+/// let page = storage.query(&query)?;
+/// if let Some(cursor) = page.next {
+///     let _more = storage.query(&query.with_after(cursor))?;
+/// }
+/// # Ok::<(), flightrecorder::Error>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CaptureQuery {
+    text: Option<String>,
+    app: Option<String>,
+    capture_type: Option<CaptureType>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    tag: Option<String>,
+    pinned: bool,
+    order: Option<QueryOrder>,
+    limit: Option<usize>,
+    after: Option<Cursor>,
+}
+
+/// A column the results are sorted by.
+struct SortKey {
+    expr: &'static str,
+    descending: bool,
+}
+
+impl CaptureQuery {
+    /// Create a query matching every capture, most recent first.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only match captures whose content matches a full-text query: words,
+    /// `"quoted phrases"`, `prefix*` and the operators `AND`, `OR` and `NOT`
+    /// with parentheses. Anything else is matched literally. A `NOT` needs a
+    /// term on both sides, or running the query fails.
+    ///
+    /// Results then carry snippets and default to [`QueryOrder::Relevance`].
+    /// A query without any terms matches everything.
+    #[must_use]
+    pub fn with_text(mut self, text: impl Into<String>) -> Self {
+        self.text = Some(text.into());
+        self
+    }
+
+    /// Only match captures from this application.
+    #[must_use]
+    pub fn with_app(mut self, app: impl Into<String>) -> Self {
+        self.app = Some(app.into());
+        self
+    }
+
+    /// Only match captures of this type.
+    #[must_use]
+    pub fn with_type(mut self, capture_type: CaptureType) -> Self {
+        self.capture_type = Some(capture_type);
+        self
+    }
+
+    /// Only match captures taken at or after this time.
+    #[must_use]
+    pub fn with_since(mut self, since: DateTime<Utc>) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// Only match captures taken at or before this time.
+    #[must_use]
+    pub fn with_until(mut self, until: DateTime<Utc>) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    /// Only match captures with this tag.
+    #[must_use]
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// Only match pinned captures.
+    #[must_use]
+    pub fn pinned_only(mut self) -> Self {
+        self.pinned = true;
+        self
+    }
+
+    /// Set the order of the results.
+    #[must_use]
+    pub fn with_order(mut self, order: QueryOrder) -> Self {
+        self.order = Some(order);
+        self
+    }
+
+    /// Return at most `limit` captures per page.
+    #[must_use]
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Start after the end of a previous page.
+    #[must_use]
+    pub fn with_after(mut self, cursor: Cursor) -> Self {
+        self.after = Some(cursor);
+        self
+    }
+
+    /// Get the page size, if limited.
+    #[must_use]
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// The FTS5 expression for the text filter, if it has any terms. With
+    /// `keys`, the expression searches the blind index of an encrypted
+    /// database.
+    fn fts_query(&self, keys: Option<&Keys>) -> Result<Option<String>> {
+        let Some(text) = self.text.as_deref() else {
+            return Ok(None);
+        };
+        match keys {
+            Some(keys) => search::blind_fts_query(text, keys),
+            None => search::fts_query(text),
+        }
+    }
+
+    /// The highlighter building snippets for the text filter, if it has any
+    /// terms.
+    pub(crate) fn highlighter(&self) -> Option<Highlighter> {
+        self.text.as_deref().and_then(Highlighter::new)
+    }
+
+    /// The sort keys of the effective order.
+    fn sort_keys(&self, full_text: bool) -> &'static [SortKey] {
+        const NEWEST: &[SortKey] = &[
+            SortKey {
+                expr: "c.timestamp",
+                descending: true,
+            },
+            SortKey {
+                expr: "c.id",
+                descending: true,
+            },
+        ];
+        const OLDEST: &[SortKey] = &[
+            SortKey {
+                expr: "c.timestamp",
+                descending: false,
+            },
+            SortKey {
+                expr: "c.id",
+                descending: false,
+            },
+        ];
+        const RELEVANCE: &[SortKey] = &[
+            SortKey {
+                expr: "captures_fts.rank",
+                descending: false,
+            },
+            SortKey {
+                expr: "c.timestamp",
+                descending: true,
+            },
+            SortKey {
+                expr: "c.id",
+                descending: true,
+            },
+        ];
+
+        let default = if full_text {
+            QueryOrder::Relevance
+        } else {
+            QueryOrder::Newest
+        };
+        match self.order.unwrap_or(default) {
+            QueryOrder::Relevance if full_text => RELEVANCE,
+            QueryOrder::Newest | QueryOrder::Relevance => NEWEST,
+            QueryOrder::Oldest => OLDEST,
+        }
+    }
+
+    /// Compile the query into one SQL statement and its parameters.
+    ///
+    /// Every value from the query is passed as a parameter; the SQL text only
+    /// depends on which filters are set. `keys` are the keys of an
+    /// encrypted database. Fails if the text filter is not a valid query.
+    pub(crate) fn to_sql(&self, keys: Option<&Keys>) -> Result<(String, Vec<Value>)> {
+        let fts_query = self.fts_query(keys)?;
+        let full_text = fts_query.is_some();
+        let mut params = Vec::new();
+        let mut conditions = vec![format!("c.{NOT_TRASHED}")];
+
+        let mut sql = if let Some(fts_query) = fts_query {
+            conditions.push("captures_fts MATCH ?".to_string());
+            params.push(Value::Text(fts_query));
+            format!(
+                "SELECT {COLUMNS}, captures_fts.rank \
+                 FROM captures_fts JOIN captures c ON c.id = captures_fts.rowid"
+            )
+        } else {
+            format!("SELECT {COLUMNS}, NULL FROM captures c")
+        };
+
+        if let Some(app) = &self.app {
+            conditions.push("c.source_app = ?".to_string());
+            params.push(Value::Text(app.clone()));
+        }
+        if let Some(capture_type) = self.capture_type {
+            conditions.push("c.capture_type = ?".to_string());
+            params.push(Value::Text(capture_type.to_string()));
+        }
+        if let Some(since) = self.since {
+            conditions.push("c.timestamp >= ?".to_string());
+            params.push(Value::Integer(since.timestamp_micros()));
+        }
+        if let Some(until) = self.until {
+            conditions.push("c.timestamp <= ?".to_string());
+            params.push(Value::Integer(until.timestamp_micros()));
+        }
+        if let Some(tag) = &self.tag {
+            conditions.push(
+                "EXISTS (SELECT 1 FROM capture_tags t \
+                 WHERE t.capture_id = c.id AND t.tag_hash = ?)"
+                    .to_string(),
+            );
+            params.push(Value::Text(annotations::tag_hash(keys, tag)));
+        }
+        if self.pinned {
+            conditions.push("c.pinned = 1".to_string());
+        }
+
+        let keys = self.sort_keys(full_text);
+        if let Some(cursor) = &self.after {
+            conditions.push(keyset_condition(keys, cursor, &mut params));
+        }
+
+        sql.push_str(" WHERE ");
+        sql.push_str(&conditions.join(" AND "));
+
+        let order: Vec<String> = keys
+            .iter()
+            .map(|key| {
+                let direction = if key.descending { "DESC" } else { "ASC" };
+                format!("{} {direction}", key.expr)
+            })
+            .collect();
+        sql.push_str(" ORDER BY ");
+        sql.push_str(&order.join(", "));
+
+        sql.push_str(" LIMIT ?");
+        params.push(Value::Integer(
+            self.limit
+                .map_or(-1, |limit| i64::try_from(limit).unwrap_or(i64::MAX)),
+        ));
+
+        Ok((sql, params))
+    }
+
+    /// Build the cursor continuing after a row returned by this query.
+    pub(crate) fn cursor_after(&self, timestamp: i64, id: i64, rank: Option<f64>) -> Cursor {
+        Cursor {
+            timestamp,
+            id,
+            rank: rank.filter(|_| self.text.is_some()),
+        }
+    }
+}
+
+/// The condition selecting rows that sort after `cursor`:
+/// `(k1 > v1) OR (k1 = v1 AND k2 > v2) OR ...`, with `<` for descending keys.
+fn keyset_condition(keys: &[SortKey], cursor: &Cursor, params: &mut Vec<Value>) -> String {
+    let value = |expr: &str| match expr {
+        "captures_fts.rank" => cursor.rank.map_or(Value::Null, Value::Real),
+        "c.timestamp" => Value::Integer(cursor.timestamp),
+        _ => Value::Integer(cursor.id),
+    };
+
+    let mut alternatives = Vec::new();
+    for (i, key) in keys.iter().enumerate() {
+        let mut terms = Vec::new();
+        for earlier in &keys[..i] {
+            terms.push(format!("{} = ?", earlier.expr));
+            params.push(value(earlier.expr));
+        }
+        let op = if key.descending { "<" } else { ">" };
+        terms.push(format!("{} {op} ?", key.expr));
+        params.push(value(key.expr));
+        alternatives.push(format!("({})", terms.join(" AND ")));
+    }
+    format!("({})", alternatives.join(" OR "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_query_selects_everything_outside_the_trash() {
+        let (sql, params) = CaptureQuery::new().to_sql(None).unwrap();
+        assert!(sql.contains("WHERE c.trashed_at IS NULL ORDER BY"));
+        assert!(sql.contains("ORDER BY c.timestamp DESC, c.id DESC"));
+        assert_eq!(params, [Value::Integer(-1)]);
+    }
+
+    #[test]
+    fn test_values_are_parameters() {
+        let query = CaptureQuery::new()
+            .with_text("it's")
+            .with_app("Robert'); DROP TABLE captures;--")
+            .with_type(CaptureType::TextField)
+            .with_limit(5);
+        let (sql, params) = query.to_sql(None).unwrap();
+        assert!(!sql.contains("Robert"));
+        assert!(!sql.contains("it's"));
+        assert_eq!(sql.matches('?').count(), params.len());
+        assert!(sql.contains("captures_fts MATCH ?"));
+        assert!(sql.contains("ORDER BY captures_fts.rank ASC"));
+    }
+
+    #[test]
+    fn test_tag_and_pinned_filters() {
+        let (sql, params) = CaptureQuery::new()
+            .with_tag("incident")
+            .pinned_only()
+            .to_sql(None)
+            .unwrap();
+        assert!(sql.contains("t.capture_id = c.id AND t.tag_hash = ?"));
+        assert!(sql.contains("c.pinned = 1"));
+        assert!(!sql.contains("incident"));
+        assert_eq!(sql.matches('?').count(), params.len());
+    }
+
+    #[test]
+    fn test_relevance_without_text_is_newest() {
+        let (sql, _) = CaptureQuery::new()
+            .with_order(QueryOrder::Relevance)
+            .to_sql(None)
+            .unwrap();
+        assert!(sql.contains("ORDER BY c.timestamp DESC, c.id DESC"));
+    }
+
+    #[test]
+    fn test_keyset_condition() {
+        let cursor = Cursor {
+            timestamp: 1_704_067_200_000_000,
+            id: 7,
+            rank: None,
+        };
+        let (sql, params) = CaptureQuery::new()
+            .with_order(QueryOrder::Oldest)
+            .with_after(cursor)
+            .to_sql(None)
+            .unwrap();
+        assert!(sql.contains("((c.timestamp > ?) OR (c.timestamp = ? AND c.id > ?))"));
+        assert_eq!(sql.matches('?').count(), params.len());
+    }
+}
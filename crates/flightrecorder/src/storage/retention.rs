@@ -0,0 +1,671 @@
+//! Retention limits and pruning.
+//!
+//! A [`RetentionPolicy`] limits the age of captures, how many are kept and
+//! the size of the database. Its [`RetentionRule`]s give the captures of some
+//! applications, or of one type, a maximum age of their own; the first rule
+//! matching a capture applies. Pruning is planned before anything is deleted
+//! (see [`Storage::plan_prune`](super::Storage::plan_prune)), so that the
+//! daemon can delete in small batches between captures, and so that
+//! `fliterec prune --dry-run` can report the plan without carrying it out.
+//!
+//! The size of the database is the space used by its pages, not counting
+//! free pages. The space used by one capture cannot be measured, so it is
+//! estimated by sharing the used space, less a page for each table and
+//! index, among captures in proportion to their stored size plus a fixed
+//! allowance per row for the indexes. Captures are
+//! deleted oldest first until their estimated space brings the database
+//! under the limit. The estimate runs a little short, since some of the
+//! space is not freed with the captures; the next prune deletes what is
+//! left over the limit.
+//!
+//! With a trash grace period, pruning moves expired captures and captures
+//! beyond the maximum number to the trash, where they can still be restored,
+//! and deletes them for good once they have been in the trash for the grace
+//! period. Moving a capture to the trash frees no space, so to bring the
+//! database under its maximum size the trash is emptied first, oldest first,
+//! and then the oldest captures are deleted outright.
+//!
+//! Pinned captures are never pruned, and do not count towards the maximum
+//! number of captures. Their space still counts towards the maximum size.
+//!
+//! Databases use incremental auto-vacuum, so the pages freed by pruning can
+//! be returned to the file system without rebuilding the whole file (see
+//! [`Storage::reclaim_space`](super::Storage::reclaim_space)).
+
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::capture::CaptureType;
+use crate::error::Result;
+
+/// Bytes counted for each capture besides its stored content, for its row,
+/// index entries and full-text index entries.
+const ROW_OVERHEAD_BYTES: u64 = 256;
+
+/// Value of `PRAGMA auto_vacuum` for incremental auto-vacuum.
+const AUTO_VACUUM_INCREMENTAL: i64 = 2;
+
+/// Limits on what the storage keeps. No limit is set by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Delete captures older than this, unless a rule matches them.
+    pub max_age: Option<Duration>,
+    /// Maximum ages of the captures matching each rule, in order.
+    pub rules: Vec<RetentionRule>,
+    /// Keep at most this many captures, deleting the oldest.
+    pub max_captures: Option<usize>,
+    /// Keep the database under this many bytes, deleting the oldest
+    /// captures.
+    pub max_size_bytes: Option<u64>,
+    /// Move pruned captures to the trash and keep them there this long
+    /// before deleting them, or delete them straight away if `None`.
+    pub trash_grace: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    /// Create a policy without limits.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delete captures older than `max_age`.
+    #[must_use]
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Add a rule, which applies to the captures it matches that no earlier
+    /// rule matches.
+    #[must_use]
+    pub fn with_rule(mut self, rule: RetentionRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Keep at most `max_captures` captures.
+    #[must_use]
+    pub fn with_max_captures(mut self, max_captures: usize) -> Self {
+        self.max_captures = Some(max_captures);
+        self
+    }
+
+    /// Keep the database under `max_size_bytes` bytes.
+    #[must_use]
+    pub fn with_max_size(mut self, max_size_bytes: u64) -> Self {
+        self.max_size_bytes = Some(max_size_bytes);
+        self
+    }
+
+    /// Keep pruned captures in the trash for `grace` before deleting them.
+    #[must_use]
+    pub fn with_trash_grace(mut self, grace: Duration) -> Self {
+        self.trash_grace = Some(grace);
+        self
+    }
+
+    /// Whether the policy sets no limit at all. Pruning under such a policy
+    /// only empties the trash.
+    #[must_use]
+    pub fn is_unlimited(&self) -> bool {
+        self.max_age.is_none()
+            && self.rules.iter().all(|rule| rule.max_age.is_none())
+            && self.max_captures.is_none()
+            && self.max_size_bytes.is_none()
+    }
+
+    /// The maximum age of a capture from `app` of type `capture_type`.
+    #[must_use]
+    pub fn max_age_for(&self, app: Option<&str>, capture_type: CaptureType) -> Option<Duration> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(app, capture_type))
+            .map_or(self.max_age, |rule| rule.max_age)
+    }
+}
+
+/// A maximum age for the captures of some applications, of one type, or
+/// both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionRule {
+    /// The application name, or a glob in which `*` matches any text and `?`
+    /// any one character, compared ignoring case. `None` matches captures
+    /// from any application, or from none.
+    pub app: Option<String>,
+    /// The type of capture. `None` matches every type.
+    pub capture_type: Option<CaptureType>,
+    /// Delete matching captures older than this, or never if `None`.
+    pub max_age: Option<Duration>,
+}
+
+impl RetentionRule {
+    /// Create a rule matching every capture.
+    #[must_use]
+    pub fn new(max_age: Option<Duration>) -> Self {
+        Self {
+            app: None,
+            capture_type: None,
+            max_age,
+        }
+    }
+
+    /// Only match captures from applications matching `app`.
+    #[must_use]
+    pub fn for_app(mut self, app: impl Into<String>) -> Self {
+        self.app = Some(app.into());
+        self
+    }
+
+    /// Only match captures of type `capture_type`.
+    #[must_use]
+    pub fn for_type(mut self, capture_type: CaptureType) -> Self {
+        self.capture_type = Some(capture_type);
+        self
+    }
+
+    /// Check whether a capture from `app` of type `capture_type` matches.
+    #[must_use]
+    pub fn matches(&self, app: Option<&str>, capture_type: CaptureType) -> bool {
+        self.capture_type.map_or(true, |t| t == capture_type)
+            && self.app.as_deref().map_or(true, |pattern| {
+                app.is_some_and(|app| glob_matches(pattern, app))
+            })
+    }
+}
+
+impl fmt::Display for RetentionRule {
+    /// Describes the captures the rule matches, such as `clipboard captures
+    /// from "*Terminal*"`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.capture_type {
+            Some(capture_type) => write!(f, "{capture_type} captures")?,
+            None => f.write_str("captures")?,
+        }
+        match &self.app {
+            Some(app) => write!(f, " from \"{app}\""),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Match `text` against a glob in which `*` matches any text and `?` any one
+/// character, ignoring ASCII case.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let text: Vec<char> = text.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and how much text it has matched up to.
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // Let the last `*` match one more character.
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// What pruning deleted, or would delete.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PruneReport {
+    /// Captures older than the maximum age.
+    pub expired: usize,
+    /// Captures beyond the maximum number of captures.
+    pub over_count: usize,
+    /// Captures deleted to bring the database under its maximum size.
+    pub over_size: usize,
+    /// Of the expired captures and those beyond the maximum number, how many
+    /// were moved to the trash rather than deleted.
+    #[serde(default)]
+    pub trashed: usize,
+    /// Captures in the trash deleted for good, at the end of their grace
+    /// period or to bring the database under its maximum size.
+    #[serde(default)]
+    pub purged: usize,
+    /// Estimated space freed by the captures deleted for good, in bytes.
+    pub estimated_bytes: u64,
+    /// Space used by the database before pruning, in bytes.
+    pub used_bytes: u64,
+    /// Bytes returned to the file system after deleting. Zero for a dry
+    /// run.
+    #[serde(default)]
+    pub reclaimed_bytes: u64,
+}
+
+impl PruneReport {
+    /// Total number of captures deleted or moved to the trash.
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.expired + self.over_count + self.over_size + self.purged
+    }
+}
+
+/// The captures a prune would delete.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrunePlan {
+    /// IDs of the captures to delete for good, oldest first.
+    pub ids: Vec<i64>,
+    /// IDs of the captures to move to the trash, oldest first.
+    pub trash_ids: Vec<i64>,
+    /// What deleting them would do.
+    pub report: PruneReport,
+}
+
+/// A capture considered for pruning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Candidate {
+    pub(crate) id: i64,
+    pub(crate) timestamp: DateTime<Utc>,
+    pub(crate) app: Option<String>,
+    pub(crate) capture_type: CaptureType,
+    /// Bytes used by the capture's stored content and delta.
+    pub(crate) stored_bytes: u64,
+    /// When the capture was moved to the trash, if it is there.
+    pub(crate) trashed_at: Option<DateTime<Utc>>,
+    /// Whether the capture is pinned.
+    pub(crate) pinned: bool,
+}
+
+/// Plan which of `candidates`, given newest first, to delete under `policy`
+/// at time `now`, in a database using `used_bytes`, of which `fixed_bytes`
+/// would be used without any captures.
+pub(crate) fn plan(
+    candidates: &[Candidate],
+    used_bytes: u64,
+    fixed_bytes: u64,
+    policy: &RetentionPolicy,
+    now: DateTime<Utc>,
+) -> PrunePlan {
+    let weight = |c: &Candidate| c.stored_bytes + ROW_OVERHEAD_BYTES;
+    let total_weight: u64 = candidates.iter().map(weight).sum();
+    let shared_bytes = used_bytes.saturating_sub(fixed_bytes);
+    let estimate = |c: &Candidate| {
+        let share = u128::from(weight(c)) * u128::from(shared_bytes) / u128::from(total_weight);
+        u64::try_from(share).unwrap_or(u64::MAX)
+    };
+
+    let mut report = PruneReport {
+        used_bytes,
+        ..PruneReport::default()
+    };
+    let mut trashed = Vec::new();
+    let mut doomed = Vec::new();
+    let mut kept = Vec::new();
+    for candidate in candidates {
+        if let Some(trashed_at) = candidate.trashed_at {
+            trashed.push((trashed_at, candidate));
+            continue;
+        }
+        if candidate.pinned {
+            continue;
+        }
+        let max_age = policy.max_age_for(candidate.app.as_deref(), candidate.capture_type);
+        if max_age.is_some_and(|age| candidate.timestamp < now - age) {
+            report.expired += 1;
+        } else if policy.max_captures.is_some_and(|max| kept.len() >= max) {
+            report.over_count += 1;
+        } else {
+            kept.push(candidate);
+            continue;
+        }
+        doomed.push(candidate);
+    }
+
+    let mut purge = Vec::new();
+    let mut freed = 0;
+    let mut to_trash = Vec::new();
+    if policy.trash_grace.is_some() {
+        to_trash = doomed;
+    } else {
+        for candidate in doomed {
+            freed += estimate(candidate);
+            purge.push(candidate.id);
+        }
+    }
+
+    // Oldest in the trash first.
+    trashed.sort_by_key(|(trashed_at, _)| *trashed_at);
+    let mut in_trash = Vec::new();
+    for (trashed_at, candidate) in trashed {
+        if policy
+            .trash_grace
+            .map_or(true, |grace| trashed_at < now - grace)
+        {
+            report.purged += 1;
+            freed += estimate(candidate);
+            purge.push(candidate.id);
+        } else {
+            in_trash.push(candidate);
+        }
+    }
+
+    if let Some(max_size) = policy.max_size_bytes {
+        let over = |freed: u64| used_bytes.saturating_sub(freed) > max_size;
+        for candidate in in_trash {
+            if !over(freed) {
+                break;
+            }
+            report.purged += 1;
+            freed += estimate(candidate);
+            purge.push(candidate.id);
+        }
+        // Captures on their way to the trash, oldest first.
+        while over(freed) {
+            let Some(candidate) = to_trash.pop() else {
+                break;
+            };
+            freed += estimate(candidate);
+            purge.push(candidate.id);
+        }
+        for candidate in kept.iter().rev() {
+            if !over(freed) {
+                break;
+            }
+            report.over_size += 1;
+            freed += estimate(candidate);
+            purge.push(candidate.id);
+        }
+    }
+
+    report.estimated_bytes = freed;
+    report.trashed = to_trash.len();
+    let mut trash_ids: Vec<i64> = to_trash.iter().map(|c| c.id).collect();
+    purge.sort_unstable();
+    trash_ids.sort_unstable();
+    PrunePlan {
+        ids: purge,
+        trash_ids,
+        report,
+    }
+}
+
+/// Bytes used by the pages of the database, not counting free pages.
+pub(crate) fn used_bytes(conn: &Connection) -> Result<u64> {
+    let (pages, free, page_size): (i64, i64, i64) = conn.query_row(
+        "SELECT * FROM pragma_page_count, pragma_freelist_count, pragma_page_size",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    Ok(u64::try_from((pages - free) * page_size).unwrap_or(0))
+}
+
+/// Bytes used by the first page of each table and index, which an empty
+/// database uses too.
+pub(crate) fn fixed_bytes(conn: &Connection) -> Result<u64> {
+    let (roots, page_size): (i64, i64) = conn.query_row(
+        "SELECT COUNT(*), (SELECT page_size FROM pragma_page_size)
+         FROM sqlite_master WHERE rootpage > 0",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    Ok(u64::try_from(roots * page_size).unwrap_or(0))
+}
+
+/// Bytes taken by the database's pages, free or not.
+pub(crate) fn file_bytes(conn: &Connection) -> Result<u64> {
+    let (pages, page_size): (i64, i64) = conn.query_row(
+        "SELECT * FROM pragma_page_count, pragma_page_size",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    Ok(u64::try_from(pages * page_size).unwrap_or(0))
+}
+
+/// Switch the database to incremental auto-vacuum.
+///
+/// An empty database switches straight away. Any other is rebuilt with
+/// `VACUUM`, once, which may take a while for a large database.
+pub(crate) fn enable_incremental_vacuum(conn: &Connection) -> Result<()> {
+    let mode = |conn: &Connection| -> Result<i64> {
+        Ok(conn.query_row("PRAGMA auto_vacuum", [], |row| row.get(0))?)
+    };
+    if mode(conn)? == AUTO_VACUUM_INCREMENTAL {
+        return Ok(());
+    }
+    conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL")?;
+    if mode(conn)? != AUTO_VACUUM_INCREMENTAL {
+        info!("Rebuilding the database for incremental vacuum");
+        conn.execute_batch("VACUUM")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(now: DateTime<Utc>, count: i64, stored_bytes: u64) -> Vec<Candidate> {
+        (0..count)
+            .rev()
+            .map(|id| Candidate {
+                id,
+                timestamp: now - Duration::days(count - id),
+                app: None,
+                capture_type: CaptureType::Clipboard,
+                stored_bytes,
+                trashed_at: None,
+                pinned: false,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_unlimited_policy_keeps_everything() {
+        let now = Utc::now();
+        let plan = plan(
+            &candidates(now, 10, 100),
+            4096,
+            1024,
+            &RetentionPolicy::new(),
+            now,
+        );
+        assert!(plan.ids.is_empty());
+        assert_eq!(plan.report.total(), 0);
+        assert_eq!(plan.report.used_bytes, 4096);
+    }
+
+    #[test]
+    fn test_age_and_count_limits() {
+        let now = Utc::now();
+        // Captures 0..10 are 10..1 days old.
+        let policy = RetentionPolicy::new()
+            .with_max_age(Duration::hours(7 * 24 + 12))
+            .with_max_captures(5);
+        let plan = plan(&candidates(now, 10, 744), 12_000, 2000, &policy, now);
+        assert_eq!(plan.ids, vec![0, 1, 2, 3, 4]);
+        assert_eq!(plan.report.expired, 3);
+        assert_eq!(plan.report.over_count, 2);
+        assert_eq!(plan.report.over_size, 0);
+        assert_eq!(plan.report.estimated_bytes, 5000);
+    }
+
+    #[test]
+    fn test_size_limit_deletes_oldest_first() {
+        let now = Utc::now();
+        // Each capture is estimated at a tenth of the used space.
+        let policy = RetentionPolicy::new().with_max_size(7000);
+        let plan = plan(&candidates(now, 10, 744), 10_000, 0, &policy, now);
+        assert_eq!(plan.ids, vec![0, 1, 2]);
+        assert_eq!(plan.report.over_size, 3);
+        assert_eq!(plan.report.estimated_bytes, 3000);
+
+        let policy = RetentionPolicy::new().with_max_size(10_000);
+        assert!(
+            super::plan(&candidates(now, 10, 744), 10_000, 0, &policy, now)
+                .ids
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_size_limit_counts_captures_already_pruned() {
+        let now = Utc::now();
+        let policy = RetentionPolicy::new()
+            .with_max_captures(8)
+            .with_max_size(7000);
+        let plan = plan(&candidates(now, 10, 744), 10_000, 0, &policy, now);
+        assert_eq!(plan.ids, vec![0, 1, 2]);
+        assert_eq!(plan.report.over_count, 2);
+        assert_eq!(plan.report.over_size, 1);
+    }
+
+    #[test]
+    fn test_trash_grace_period() {
+        let now = Utc::now();
+        let mut candidates = candidates(now, 10, 744);
+        // Captures 9 and 8 were trashed 2 and 10 days ago.
+        candidates[0].trashed_at = Some(now - Duration::days(2));
+        candidates[1].trashed_at = Some(now - Duration::days(10));
+        let policy = RetentionPolicy::new()
+            .with_max_captures(5)
+            .with_trash_grace(Duration::days(7));
+
+        let plan = plan(&candidates, 10_000, 0, &policy, now);
+        assert_eq!(plan.trash_ids, vec![0, 1, 2]);
+        assert_eq!(plan.ids, vec![8]);
+        assert_eq!(plan.report.over_count, 3);
+        assert_eq!(plan.report.trashed, 3);
+        assert_eq!(plan.report.purged, 1);
+        assert_eq!(plan.report.estimated_bytes, 1000);
+    }
+
+    #[test]
+    fn test_size_limit_empties_the_trash_first() {
+        let now = Utc::now();
+        let mut candidates = candidates(now, 10, 744);
+        candidates[0].trashed_at = Some(now - Duration::days(1));
+        let policy = RetentionPolicy::new()
+            .with_max_captures(8)
+            .with_max_size(6000)
+            .with_trash_grace(Duration::days(7));
+
+        // Trashing frees nothing: the trash is emptied, then the captures on
+        // their way to it and the oldest kept captures are deleted outright.
+        let plan = plan(&candidates, 10_000, 0, &policy, now);
+        assert!(plan.trash_ids.is_empty());
+        assert_eq!(plan.ids, vec![0, 1, 2, 9]);
+        assert_eq!(plan.report.purged, 1);
+        assert_eq!(plan.report.over_count, 1);
+        assert_eq!(plan.report.over_size, 2);
+        assert_eq!(plan.report.trashed, 0);
+    }
+
+    #[test]
+    fn test_pinned_captures_are_never_pruned() {
+        let now = Utc::now();
+        let mut candidates = candidates(now, 10, 744);
+        // The two oldest captures are pinned.
+        candidates[8].pinned = true;
+        candidates[9].pinned = true;
+        let policy = RetentionPolicy::new()
+            .with_max_age(Duration::hours(7 * 24 + 12))
+            .with_max_captures(3)
+            .with_max_size(1000);
+
+        let plan = plan(&candidates, 10_000, 0, &policy, now);
+        assert_eq!(plan.ids, vec![2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(plan.report.expired, 1);
+        assert_eq!(plan.report.over_count, 4);
+        assert_eq!(plan.report.over_size, 3);
+    }
+
+    #[test]
+    fn test_rules_override_the_maximum_age() {
+        let now = Utc::now();
+        let policy = RetentionPolicy::new()
+            .with_max_age(Duration::days(30))
+            .with_rule(
+                RetentionRule::new(Some(Duration::days(2)))
+                    .for_app("*terminal*")
+                    .for_type(CaptureType::Clipboard),
+            )
+            .with_rule(
+                RetentionRule::new(Some(Duration::days(90))).for_type(CaptureType::TextField),
+            )
+            .with_rule(RetentionRule::new(None).for_app("Notes"));
+        let candidate = |id, days, app: Option<&str>, capture_type| Candidate {
+            id,
+            timestamp: now - Duration::days(days),
+            app: app.map(str::to_string),
+            capture_type,
+            stored_bytes: 0,
+            trashed_at: None,
+            pinned: false,
+        };
+        let candidates = [
+            candidate(1, 3, Some("GNOME Terminal"), CaptureType::Clipboard),
+            candidate(2, 3, Some("GNOME Terminal"), CaptureType::TextField),
+            candidate(3, 60, Some("Editor"), CaptureType::TextField),
+            candidate(4, 100, Some("Editor"), CaptureType::TextField),
+            candidate(5, 60, Some("Editor"), CaptureType::Clipboard),
+            candidate(6, 400, Some("Notes"), CaptureType::Clipboard),
+            candidate(7, 3, None, CaptureType::Clipboard),
+        ];
+
+        let plan = plan(&candidates, 4096, 0, &policy, now);
+        assert_eq!(plan.ids, vec![1, 4, 5]);
+        assert_eq!(plan.report.expired, 3);
+    }
+
+    #[test]
+    fn test_rule_matching() {
+        let rule = RetentionRule::new(None).for_app("Term*");
+        assert!(rule.matches(Some("Terminal"), CaptureType::Keystroke));
+        assert!(rule.matches(Some("term"), CaptureType::Clipboard));
+        assert!(!rule.matches(Some("xterm"), CaptureType::Clipboard));
+        assert!(!rule.matches(None, CaptureType::Clipboard));
+        assert!(RetentionRule::new(None).matches(None, CaptureType::Clipboard));
+
+        assert_eq!(rule.to_string(), "captures from \"Term*\"");
+        assert_eq!(
+            RetentionRule::new(None)
+                .for_type(CaptureType::TextField)
+                .to_string(),
+            "text_field captures"
+        );
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("firefox", "Firefox"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(glob_matches("a?c", "abc"));
+        assert!(!glob_matches("a?c", "ac"));
+        assert!(!glob_matches("a*b", "aXbY"));
+        assert!(glob_matches("*.app", "Code.app"));
+        assert!(!glob_matches("", "x"));
+    }
+
+    #[test]
+    fn test_enable_incremental_vacuum() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("PRAGMA auto_vacuum = NONE; CREATE TABLE t (x)")
+            .unwrap();
+        enable_incremental_vacuum(&conn).unwrap();
+        let mode: i64 = conn
+            .query_row("PRAGMA auto_vacuum", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(mode, AUTO_VACUUM_INCREMENTAL);
+    }
+}
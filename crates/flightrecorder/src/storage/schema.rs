@@ -3,8 +3,9 @@
 //! This module contains the SQL statements for creating and managing
 //! the database schema.
 
-/// SQL statement to create the captures table.
-pub const CREATE_CAPTURES_TABLE: &str = r"
+/// SQL statement to create the captures table as of schema version 1, with
+/// RFC 3339 text timestamps.
+pub const CREATE_CAPTURES_TABLE_V1: &str = r"
 CREATE TABLE IF NOT EXISTS captures (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     timestamp TEXT NOT NULL,
@@ -16,11 +17,17 @@ CREATE TABLE IF NOT EXISTS captures (
 )
 ";
 
-/// SQL statement to create an index on timestamp for efficient queries.
-pub const CREATE_TIMESTAMP_INDEX: &str = r"
+/// SQL statement to create the timestamp index as of schema version 1.
+pub const CREATE_TIMESTAMP_INDEX_V1: &str = r"
 CREATE INDEX IF NOT EXISTS idx_captures_timestamp ON captures(timestamp DESC)
 ";
 
+/// SQL statement to create an index on timestamp for time range queries and
+/// ordering. Including the id lets keyset pagination use the index too.
+pub const CREATE_TIMESTAMP_INDEX: &str = r"
+CREATE INDEX IF NOT EXISTS idx_captures_timestamp ON captures(timestamp, id)
+";
+
 /// SQL statement to create an index on `content_hash` for deduplication.
 pub const CREATE_HASH_INDEX: &str = r"
 CREATE INDEX IF NOT EXISTS idx_captures_hash ON captures(content_hash)
@@ -44,35 +51,350 @@ CREATE TABLE IF NOT EXISTS metadata (
 )
 ";
 
-/// All schema creation statements in order.
-pub const SCHEMA_STATEMENTS: &[&str] = &[
-    CREATE_CAPTURES_TABLE,
-    CREATE_TIMESTAMP_INDEX,
-    CREATE_HASH_INDEX,
-    CREATE_APP_INDEX,
-    CREATE_TYPE_INDEX,
-    CREATE_METADATA_TABLE,
+/// SQL statement to create the full-text index over capture content as of
+/// schema version 2.
+///
+/// The index is an external-content FTS5 table: it stores only the index, and
+/// reads content (for `snippet()`) from the `captures` row with the same id.
+pub const CREATE_CAPTURES_FTS_TABLE_V2: &str = r"
+CREATE VIRTUAL TABLE IF NOT EXISTS captures_fts USING fts5(
+    content,
+    content = 'captures',
+    content_rowid = 'id',
+    tokenize = 'unicode61 remove_diacritics 2'
+)
+";
+
+/// SQL statements creating the triggers that keep the external-content
+/// `captures_fts` of schema version 2 in sync with `captures`.
+pub const CREATE_CAPTURES_FTS_TRIGGERS_V2: &[&str] = &[
+    r"
+CREATE TRIGGER IF NOT EXISTS captures_fts_insert AFTER INSERT ON captures BEGIN
+    INSERT INTO captures_fts (rowid, content) VALUES (new.id, new.content);
+END
+",
+    r"
+CREATE TRIGGER IF NOT EXISTS captures_fts_delete AFTER DELETE ON captures BEGIN
+    INSERT INTO captures_fts (captures_fts, rowid, content)
+    VALUES ('delete', old.id, old.content);
+END
+",
+    r"
+CREATE TRIGGER IF NOT EXISTS captures_fts_update AFTER UPDATE OF content ON captures BEGIN
+    INSERT INTO captures_fts (captures_fts, rowid, content)
+    VALUES ('delete', old.id, old.content);
+    INSERT INTO captures_fts (rowid, content) VALUES (new.id, new.content);
+END
+",
+];
+
+/// SQL statement to rebuild the external-content `captures_fts` of schema
+/// version 2 from the current `captures` rows.
+pub const REBUILD_CAPTURES_FTS_V2: &str = r"
+INSERT INTO captures_fts (captures_fts) VALUES ('rebuild')
+";
+
+/// SQL statement to create the captures table of schema version 3 under a
+/// temporary name. Timestamps are microseconds since the Unix epoch, and
+/// anything else is rejected.
+pub const CREATE_CAPTURES_V3_TABLE: &str = r"
+CREATE TABLE captures_v3 (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp INTEGER NOT NULL CHECK (typeof(timestamp) = 'integer'),
+    source_app TEXT,
+    content TEXT NOT NULL,
+    content_hash TEXT NOT NULL,
+    capture_type TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+)
+";
+
+/// SQL replacing the captures table with `captures_v3`. Dropping the old
+/// table also drops its indexes and triggers.
+pub const REPLACE_CAPTURES_WITH_V3: &str = r"
+DROP TABLE captures;
+ALTER TABLE captures_v3 RENAME TO captures;
+";
+
+/// SQL statement to create the table holding rows that could not be read.
+///
+/// The copied capture columns have no declared type, so values are kept
+/// exactly as they were found.
+pub const CREATE_QUARANTINE_TABLE: &str = r"
+CREATE TABLE IF NOT EXISTS quarantine (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    capture_id INTEGER,
+    timestamp,
+    source_app,
+    content,
+    content_hash,
+    capture_type,
+    reason TEXT NOT NULL,
+    quarantined_at INTEGER NOT NULL
+)
+";
+
+/// SQL statement to create the sessions table: one row per draft whose
+/// snapshots are stored as versions.
+pub const CREATE_SESSIONS_TABLE: &str = r"
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    capture_type TEXT NOT NULL,
+    source_app TEXT,
+    started_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+)
+";
+
+/// SQL statement to create the index used to find a draft's open session.
+pub const CREATE_SESSIONS_INDEX: &str = r"
+CREATE INDEX IF NOT EXISTS idx_sessions_open ON sessions(capture_type, source_app, updated_at)
+";
+
+/// SQL adding the session columns to the captures table.
+pub const ADD_CAPTURE_SESSION_COLUMNS: &str = r"
+ALTER TABLE captures ADD COLUMN session_id INTEGER REFERENCES sessions(id);
+ALTER TABLE captures ADD COLUMN version INTEGER;
+";
+
+/// SQL statement to create an index on a capture's session and version.
+pub const CREATE_SESSION_VERSION_INDEX: &str = r"
+CREATE INDEX IF NOT EXISTS idx_captures_session ON captures(session_id, version)
+";
+
+/// SQL statement to create the table of repeated sightings of a capture's
+/// content.
+pub const CREATE_OCCURRENCES_TABLE: &str = r"
+CREATE TABLE IF NOT EXISTS capture_occurrences (
+    id INTEGER PRIMARY KEY,
+    capture_id INTEGER NOT NULL REFERENCES captures(id),
+    timestamp INTEGER NOT NULL
+)
+";
+
+/// SQL statement to create an index on the capture of an occurrence.
+pub const CREATE_OCCURRENCES_INDEX: &str = r"
+CREATE INDEX IF NOT EXISTS idx_occurrences_capture ON capture_occurrences(capture_id, timestamp)
+";
+
+/// SQL statements creating the triggers that delete occurrences and empty
+/// sessions along with their captures.
+pub const CREATE_HISTORY_TRIGGERS: &[&str] = &[
+    r"
+CREATE TRIGGER IF NOT EXISTS captures_occurrences_delete AFTER DELETE ON captures BEGIN
+    DELETE FROM capture_occurrences WHERE capture_id = old.id;
+END
+",
+    r"
+CREATE TRIGGER IF NOT EXISTS captures_sessions_delete AFTER DELETE ON captures
+WHEN old.session_id IS NOT NULL
+    AND NOT EXISTS (SELECT 1 FROM captures WHERE session_id = old.session_id)
+BEGIN
+    DELETE FROM sessions WHERE id = old.session_id;
+END
+",
 ];
 
+/// SQL adding the delta columns to the captures table. A version stored as a
+/// delta has empty content, and is rebuilt by applying `delta` to the
+/// content of capture `base_id`.
+pub const ADD_CAPTURE_DELTA_COLUMNS: &str = r"
+ALTER TABLE captures ADD COLUMN base_id INTEGER REFERENCES captures(id);
+ALTER TABLE captures ADD COLUMN delta BLOB;
+";
+
+/// SQL statement to create an index on the base of each delta, used to find
+/// the versions that depend on a capture before it is deleted.
+pub const CREATE_BASE_INDEX: &str = r"
+CREATE INDEX IF NOT EXISTS idx_captures_base ON captures(base_id) WHERE base_id IS NOT NULL
+";
+
+/// SQL replacing the external-content `captures_fts` with a contentless
+/// index, which needs no content to delete a row by id. Captures are added to
+/// it as they are inserted, since their content is not always stored in
+/// full.
+pub const REPLACE_CAPTURES_FTS: &str = r"
+DROP TRIGGER IF EXISTS captures_fts_insert;
+DROP TRIGGER IF EXISTS captures_fts_delete;
+DROP TRIGGER IF EXISTS captures_fts_update;
+DROP TABLE IF EXISTS captures_fts;
+CREATE VIRTUAL TABLE captures_fts USING fts5(
+    content,
+    content = '',
+    contentless_delete = 1,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+";
+
+/// SQL statement to create the trigger removing deleted captures from the
+/// contentless `captures_fts`.
+pub const CREATE_CAPTURES_FTS_DELETE_TRIGGER: &str = r"
+CREATE TRIGGER IF NOT EXISTS captures_fts_delete AFTER DELETE ON captures BEGIN
+    DELETE FROM captures_fts WHERE rowid = old.id;
+END
+";
+
+/// SQL adding the compression columns to the captures table: the codec the
+/// content is stored with, if any (see [`codec`](super::codec)), and the size
+/// in bytes of the content as captured.
+pub const ADD_CAPTURE_CODEC_COLUMNS: &str = r"
+ALTER TABLE captures ADD COLUMN codec TEXT;
+ALTER TABLE captures ADD COLUMN content_size INTEGER NOT NULL DEFAULT 0;
+";
+
+/// SQL adding the encryption flag to the captures table: whether the content
+/// and delta are encrypted with the database's data key (see
+/// [`crypto`](super::crypto)).
+pub const ADD_CAPTURE_ENCRYPTED_COLUMN: &str = r"
+ALTER TABLE captures ADD COLUMN encrypted INTEGER NOT NULL DEFAULT 0
+";
+
+/// SQL adding the trash column to the captures table: when the capture was
+/// moved to the trash, or `NULL` for a capture that is not in the trash.
+pub const ADD_CAPTURE_TRASHED_COLUMN: &str = r"
+ALTER TABLE captures ADD COLUMN trashed_at INTEGER
+";
+
+/// SQL statement to create an index on trashed captures, used to list and
+/// empty the trash.
+pub const CREATE_TRASH_INDEX: &str = r"
+CREATE INDEX IF NOT EXISTS idx_captures_trashed ON captures(trashed_at)
+WHERE trashed_at IS NOT NULL
+";
+
+/// SQL adding the annotation columns to the captures table: whether the
+/// capture is pinned, which exempts it from pruning, and the user's note on
+/// it.
+pub const ADD_CAPTURE_ANNOTATION_COLUMNS: &str = r"
+ALTER TABLE captures ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE captures ADD COLUMN note TEXT;
+";
+
+/// SQL statement to create the tags table, with one row per tag on a
+/// capture.
+pub const CREATE_TAGS_TABLE: &str = r"
+CREATE TABLE IF NOT EXISTS capture_tags (
+    capture_id INTEGER NOT NULL REFERENCES captures(id),
+    tag TEXT NOT NULL,
+    PRIMARY KEY (capture_id, tag)
+) WITHOUT ROWID
+";
+
+/// SQL statement to create an index on tags, used to filter by tag.
+pub const CREATE_TAGS_INDEX: &str = r"
+CREATE INDEX IF NOT EXISTS idx_capture_tags_tag ON capture_tags(tag)
+";
+
+/// SQL statement to create the trigger deleting tags along with their
+/// capture.
+pub const CREATE_TAGS_DELETE_TRIGGER: &str = r"
+CREATE TRIGGER IF NOT EXISTS captures_tags_delete AFTER DELETE ON captures BEGIN
+    DELETE FROM capture_tags WHERE capture_id = old.id;
+END
+";
+
+/// SQL adding the stored form of a capture to the quarantine table: its
+/// codec, delta, delta base and encryption flag, so that the content of a
+/// capture quarantined by a repair can still be decoded by hand.
+pub const ADD_QUARANTINE_STORAGE_COLUMNS: &str = r"
+ALTER TABLE quarantine ADD COLUMN codec;
+ALTER TABLE quarantine ADD COLUMN delta;
+ALTER TABLE quarantine ADD COLUMN base_id;
+ALTER TABLE quarantine ADD COLUMN encrypted;
+";
+
+/// SQL adding a hash of the identity of the text field a draft session was
+/// captured from, so that drafts in different fields of one application are
+/// kept apart.
+pub const ADD_SESSION_FIELD_COLUMN: &str = r"
+ALTER TABLE sessions ADD COLUMN field_hash TEXT
+";
+
+/// SQL turning on secure delete in `captures_fts`, then merging the index so
+/// that the words of captures deleted before it are dropped (see
+/// [`trash::scrub_search_index`](super::trash::scrub_search_index)).
+pub const ENABLE_CAPTURES_FTS_SECURE_DELETE: &str = r"
+INSERT INTO captures_fts (captures_fts, rank) VALUES ('secure-delete', 1);
+INSERT INTO captures_fts (captures_fts) VALUES ('optimize');
+";
+
+/// SQL statement to create the tags table looked up by the hash of each tag,
+/// so that tags can be stored encrypted. A tag is text, or a blob when it is
+/// encrypted.
+pub const CREATE_TAGS_V13_TABLE: &str = r"
+CREATE TABLE capture_tags_v13 (
+    capture_id INTEGER NOT NULL REFERENCES captures(id),
+    tag_hash TEXT NOT NULL,
+    tag NOT NULL,
+    PRIMARY KEY (capture_id, tag_hash)
+) WITHOUT ROWID
+";
+
+/// SQL replacing the tags table with `capture_tags_v13`. Dropping the old
+/// table also drops its index; the trigger deleting tags is dropped first,
+/// since it refers to the table by name.
+pub const REPLACE_TAGS_WITH_V13: &str = r"
+DROP TRIGGER IF EXISTS captures_tags_delete;
+DROP TABLE capture_tags;
+ALTER TABLE capture_tags_v13 RENAME TO capture_tags;
+";
+
+/// SQL statement to create an index on tag hashes, used to filter by tag.
+pub const CREATE_TAG_HASH_INDEX: &str = r"
+CREATE INDEX IF NOT EXISTS idx_capture_tags_hash ON capture_tags(tag_hash)
+";
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn test_schema_statements_not_empty() {
-        assert!(!SCHEMA_STATEMENTS.is_empty());
-        for stmt in SCHEMA_STATEMENTS {
-            assert!(!stmt.is_empty());
+    fn test_create_captures_table_contains_required_columns() {
+        for table in [CREATE_CAPTURES_TABLE_V1, CREATE_CAPTURES_V3_TABLE] {
+            assert!(table.contains("id INTEGER PRIMARY KEY"));
+            assert!(table.contains("content TEXT NOT NULL"));
+            assert!(table.contains("content_hash TEXT NOT NULL"));
+            assert!(table.contains("capture_type TEXT NOT NULL"));
+        }
+        assert!(CREATE_CAPTURES_TABLE_V1.contains("timestamp TEXT NOT NULL"));
+        assert!(CREATE_CAPTURES_V3_TABLE.contains("timestamp INTEGER NOT NULL"));
+    }
+
+    #[test]
+    fn test_fts_triggers_cover_every_change() {
+        let triggers = CREATE_CAPTURES_FTS_TRIGGERS_V2.join("\n");
+        assert!(triggers.contains("AFTER INSERT ON captures"));
+        assert!(triggers.contains("AFTER DELETE ON captures"));
+        assert!(triggers.contains("AFTER UPDATE OF content ON captures"));
+    }
+
+    #[test]
+    fn test_contentless_fts_replaces_every_v2_trigger() {
+        for name in [
+            "captures_fts_insert",
+            "captures_fts_delete",
+            "captures_fts_update",
+        ] {
+            assert!(CREATE_CAPTURES_FTS_TRIGGERS_V2
+                .iter()
+                .any(|trigger| trigger.contains(name)));
+            assert!(REPLACE_CAPTURES_FTS.contains(&format!("DROP TRIGGER IF EXISTS {name};")));
         }
+        assert!(REPLACE_CAPTURES_FTS.contains("contentless_delete = 1"));
     }
 
     #[test]
-    fn test_create_captures_table_contains_required_columns() {
-        assert!(CREATE_CAPTURES_TABLE.contains("id INTEGER PRIMARY KEY"));
-        assert!(CREATE_CAPTURES_TABLE.contains("timestamp TEXT NOT NULL"));
-        assert!(CREATE_CAPTURES_TABLE.contains("content TEXT NOT NULL"));
-        assert!(CREATE_CAPTURES_TABLE.contains("content_hash TEXT NOT NULL"));
-        assert!(CREATE_CAPTURES_TABLE.contains("capture_type TEXT NOT NULL"));
+    fn test_history_triggers_cover_occurrences_and_sessions() {
+        let triggers = CREATE_HISTORY_TRIGGERS.join("\n");
+        assert!(triggers.contains("DELETE FROM capture_occurrences"));
+        assert!(triggers.contains("DELETE FROM sessions"));
+    }
+
+    #[test]
+    fn test_tags_go_with_their_capture() {
+        assert!(CREATE_TAGS_TABLE.contains("PRIMARY KEY (capture_id, tag)"));
+        assert!(CREATE_TAGS_V13_TABLE.contains("PRIMARY KEY (capture_id, tag_hash)"));
+        assert!(CREATE_TAGS_DELETE_TRIGGER.contains("DELETE FROM capture_tags"));
     }
 
     #[test]
@@ -0,0 +1,457 @@
+//! Full-text search over captured text.
+//!
+//! Searches run against the `captures_fts` FTS5 index. The index does not
+//! keep a copy of the content, since stored drafts may be deltas (see
+//! [`delta`](super::delta)): captures are indexed as they are inserted, and a
+//! trigger removes them when they are deleted. Snippets are built from the
+//! rebuilt content by [`Highlighter`]. User queries are translated into FTS5
+//! syntax by [`fts_query`], which supports:
+//!
+//! - bare words, matched anywhere in the content (`hello world` finds
+//!   captures containing both words)
+//! - phrases in double quotes (`"hello world"`)
+//! - prefixes with a trailing `*` (`hel*`)
+//! - the boolean operators `AND`, `OR` and `NOT` (upper case), with
+//!   parentheses for grouping
+//!
+//! Everything else is treated as literal text, so no query can cause an FTS5
+//! syntax error. Stray `AND`s, `OR`s and parentheses are dropped, but a `NOT`
+//! without a term on both sides is an error: dropping it would match exactly
+//! what the query meant to exclude.
+
+use serde::{Deserialize, Serialize};
+
+use super::crypto::Keys;
+use crate::capture::Capture;
+use crate::error::{Error, Result};
+
+/// Marks the start of a matched term in [`SearchHit::snippet`].
+pub const HIGHLIGHT_START: &str = "[";
+
+/// Marks the end of a matched term in [`SearchHit::snippet`].
+pub const HIGHLIGHT_END: &str = "]";
+
+/// Marks text left out of [`SearchHit::snippet`].
+pub const SNIPPET_ELLIPSIS: &str = "…";
+
+/// Maximum number of tokens in a snippet.
+pub(crate) const SNIPPET_TOKENS: usize = 16;
+
+/// A capture found by a full-text search.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchHit {
+    /// The matching capture.
+    pub capture: Capture,
+    /// The part of the content around the best match, with matched terms
+    /// between [`HIGHLIGHT_START`] and [`HIGHLIGHT_END`]. `None` when the
+    /// search had no terms to match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+}
+
+/// One element of a user query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// A phrase, and whether its last word is a prefix.
+    Term(String, bool),
+    /// `AND`, `OR` or `NOT`.
+    Operator(&'static str),
+    Open,
+    Close,
+}
+
+/// Translate a user query into an FTS5 match expression.
+///
+/// Returns `None` if the query contains no terms, or
+/// [`Error::InvalidArgument`] if it has a `NOT` without a term on both sides.
+pub(crate) fn fts_query(input: &str) -> Result<Option<String>> {
+    Ok(to_fts(&normalize(tokenize(input))?))
+}
+
+/// Translate a user query into an FTS5 match expression over a blind index
+/// (see [`crypto`](super::crypto)), in which every word is replaced by its
+/// token. Prefixes only match whole words.
+///
+/// Returns `None` if the query contains no words, or
+/// [`Error::InvalidArgument`] if it has a `NOT` without a word on both sides.
+pub(crate) fn blind_fts_query(input: &str, keys: &Keys) -> Result<Option<String>> {
+    let tokens = tokenize(input)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Term(phrase, _) => {
+                let blind = keys.blind(&phrase);
+                (!blind.is_empty()).then_some(Token::Term(blind, false))
+            }
+            token => Some(token),
+        })
+        .collect();
+    Ok(to_fts(&normalize(tokens)?))
+}
+
+fn to_fts(tokens: &[Token]) -> Option<String> {
+    if tokens.is_empty() {
+        return None;
+    }
+    let parts: Vec<String> = tokens
+        .iter()
+        .map(|token| match token {
+            Token::Term(phrase, prefix) => quote(phrase, *prefix),
+            Token::Operator(op) => (*op).to_string(),
+            Token::Open => "(".to_string(),
+            Token::Close => ")".to_string(),
+        })
+        .collect();
+    Some(parts.join(" "))
+}
+
+/// Split a query into terms, operators and parentheses.
+fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                let phrase: String = chars.by_ref().take_while(|&c| c != '"').collect();
+                let prefix = chars.next_if_eq(&'*').is_some();
+                if !phrase.trim().is_empty() {
+                    tokens.push(Token::Term(phrase, prefix));
+                }
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && !"()\"".contains(c)) {
+                    word.push(c);
+                }
+                tokens.push(match word.as_str() {
+                    "AND" => Token::Operator("AND"),
+                    "OR" => Token::Operator("OR"),
+                    "NOT" => Token::Operator("NOT"),
+                    _ => {
+                        let stem = word.trim_end_matches('*');
+                        if stem.is_empty() {
+                            continue;
+                        }
+                        Token::Term(stem.to_string(), stem.len() < word.len())
+                    }
+                });
+            }
+        }
+    }
+    tokens
+}
+
+/// Quote text as an FTS5 string, so that it is matched literally.
+fn quote(text: &str, prefix: bool) -> String {
+    let star = if prefix { "*" } else { "" };
+    format!("\"{}\"{star}", text.replace('"', "\"\""))
+}
+
+/// Highlights the words of a query in the content of the captures it found.
+///
+/// Words match case-insensitively, and the last word of a `prefix*` term
+/// matches any word it starts.
+#[derive(Debug)]
+pub(crate) struct Highlighter {
+    /// Lower-cased words to highlight, and whether each is a prefix.
+    words: Vec<(String, bool)>,
+}
+
+impl Highlighter {
+    /// Create the highlighter for a user query. Returns `None` if the query
+    /// contains no terms or is invalid.
+    pub(crate) fn new(input: &str) -> Option<Self> {
+        let mut words = Vec::new();
+        for token in normalize(tokenize(input)).ok()? {
+            if let Token::Term(phrase, prefix) = token {
+                let phrase = phrase.to_lowercase();
+                let spans = word_spans(&phrase);
+                let last = spans.len().saturating_sub(1);
+                for (i, (start, end)) in spans.into_iter().enumerate() {
+                    words.push((phrase[start..end].to_string(), prefix && i == last));
+                }
+            }
+        }
+        (!words.is_empty()).then_some(Self { words })
+    }
+
+    fn matches(&self, word: &str) -> bool {
+        let word = word.to_lowercase();
+        self.words.iter().any(|(term, prefix)| {
+            if *prefix {
+                word.starts_with(term.as_str())
+            } else {
+                word == *term
+            }
+        })
+    }
+
+    /// Build the snippet for `content`: the run of at most
+    /// [`SNIPPET_TOKENS`] words with the most matches, centred on them, with
+    /// matched words marked and left-out text replaced by
+    /// [`SNIPPET_ELLIPSIS`].
+    pub(crate) fn snippet(&self, content: &str) -> String {
+        let words: Vec<(usize, usize, bool)> = word_spans(content)
+            .into_iter()
+            .map(|(start, end)| (start, end, self.matches(&content[start..end])))
+            .collect();
+        let window = SNIPPET_TOKENS;
+
+        let mut first = 0;
+        let mut best = 0;
+        for start in 0..=words.len().saturating_sub(window) {
+            let count = words[start..words.len().min(start + window)]
+                .iter()
+                .filter(|(.., matched)| *matched)
+                .count();
+            if count > best {
+                (first, best) = (start, count);
+            }
+        }
+        // Centre the matches of the best run in the window.
+        let matched: Vec<usize> = (first..words.len().min(first + window))
+            .filter(|&i| words[i].2)
+            .collect();
+        if let (Some(&start), Some(&end)) = (matched.first(), matched.last()) {
+            first = start
+                .saturating_sub((window - (end - start + 1)) / 2)
+                .min(words.len().saturating_sub(window));
+        }
+        let last = words.len().min(first + window);
+
+        let from = if first == 0 { 0 } else { words[first].0 };
+        let to = if last == words.len() {
+            content.len()
+        } else {
+            words[last - 1].1
+        };
+        let mut out = String::new();
+        if from > 0 {
+            out.push_str(SNIPPET_ELLIPSIS);
+        }
+        let mut pos = from;
+        for &(start, end, matched) in &words[first..last] {
+            if matched {
+                out.push_str(&content[pos..start]);
+                out.push_str(HIGHLIGHT_START);
+                out.push_str(&content[start..end]);
+                out.push_str(HIGHLIGHT_END);
+                pos = end;
+            }
+        }
+        out.push_str(&content[pos..to]);
+        if to < content.len() {
+            out.push_str(SNIPPET_ELLIPSIS);
+        }
+        out
+    }
+}
+
+/// Byte ranges of the words in `text`: runs of letters and digits, as the
+/// index tokenizes them.
+pub(crate) fn word_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                spans.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        spans.push((s, text.len()));
+    }
+    spans
+}
+
+/// Drop operators and parentheses that would make the expression invalid:
+/// `AND` and `OR` without an operand on both sides, empty groups and
+/// unmatched parentheses. A `NOT` without an operand on both sides is an
+/// error instead, since dropping it would invert the query.
+fn normalize(tokens: Vec<Token>) -> Result<Vec<Token>> {
+    let mut out: Vec<Token> = Vec::new();
+    let mut depth = 0;
+    for token in tokens {
+        match token {
+            Token::Term(..) | Token::Open => {
+                if matches!(token, Token::Open) {
+                    depth += 1;
+                }
+                out.push(token);
+            }
+            Token::Operator(op) => {
+                if matches!(out.last(), Some(Token::Term(..) | Token::Close)) {
+                    out.push(token);
+                } else if op == "NOT" {
+                    return Err(dangling_not());
+                }
+            }
+            Token::Close => {
+                if depth == 0 {
+                    continue;
+                }
+                depth -= 1;
+                close_group(&mut out)?;
+            }
+        }
+    }
+    while depth > 0 {
+        depth -= 1;
+        close_group(&mut out)?;
+    }
+    drop_trailing_operators(&mut out)?;
+    Ok(out)
+}
+
+/// Close the innermost open group, dropping it if it would be empty.
+fn close_group(out: &mut Vec<Token>) -> Result<()> {
+    drop_trailing_operators(out)?;
+    if matches!(out.last(), Some(Token::Open)) {
+        out.pop();
+        // The operator before an empty group has lost its right operand.
+        drop_trailing_operators(out)?;
+    } else {
+        out.push(Token::Close);
+    }
+    Ok(())
+}
+
+/// Drop the operators at the end of `out`, which have no right operand.
+fn drop_trailing_operators(out: &mut Vec<Token>) -> Result<()> {
+    while let Some(Token::Operator(op)) = out.last() {
+        if *op == "NOT" {
+            return Err(dangling_not());
+        }
+        out.pop();
+    }
+    Ok(())
+}
+
+/// The error for a `NOT` without an operand on both sides.
+fn dangling_not() -> Error {
+    Error::InvalidArgument {
+        message: "NOT needs a term on both sides, as in `cat NOT dog`".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_words_are_quoted() {
+        assert_eq!(
+            fts_query("hello world").unwrap().as_deref(),
+            Some("\"hello\" \"world\"")
+        );
+        assert_eq!(
+            fts_query("don't foo-bar col:x").unwrap().as_deref(),
+            Some("\"don't\" \"foo-bar\" \"col:x\"")
+        );
+    }
+
+    #[test]
+    fn test_phrases_and_prefixes() {
+        assert_eq!(
+            fts_query("\"hello world\" hel*").unwrap().as_deref(),
+            Some("\"hello world\" \"hel\"*")
+        );
+        assert_eq!(
+            fts_query("\"hello wor\"*").unwrap().as_deref(),
+            Some("\"hello wor\"*")
+        );
+        assert_eq!(
+            fts_query("\"unterminated").unwrap().as_deref(),
+            Some("\"unterminated\"")
+        );
+    }
+
+    #[test]
+    fn test_boolean_operators() {
+        assert_eq!(
+            fts_query("(cat OR dog) NOT fish").unwrap().as_deref(),
+            Some("( \"cat\" OR \"dog\" ) NOT \"fish\"")
+        );
+        // Lower-case operators are ordinary words.
+        assert_eq!(
+            fts_query("cats and dogs").unwrap().as_deref(),
+            Some("\"cats\" \"and\" \"dogs\"")
+        );
+    }
+
+    #[test]
+    fn test_invalid_structure_is_repaired() {
+        assert_eq!(fts_query("AND cat OR").unwrap().as_deref(), Some("\"cat\""));
+        assert_eq!(
+            fts_query("cat AND OR dog").unwrap().as_deref(),
+            Some("\"cat\" AND \"dog\"")
+        );
+        assert_eq!(fts_query("(cat").unwrap().as_deref(), Some("( \"cat\" )"));
+        assert_eq!(
+            fts_query("cat) dog").unwrap().as_deref(),
+            Some("\"cat\" \"dog\"")
+        );
+        assert_eq!(fts_query("cat OR ()").unwrap().as_deref(), Some("\"cat\""));
+    }
+
+    #[test]
+    fn test_not_needs_both_operands() {
+        // Dropping the NOT would find exactly what was meant to be left out.
+        for query in [
+            "NOT cat",
+            "cat OR NOT dog",
+            "(NOT cat)",
+            "cat NOT",
+            "cat NOT ()",
+        ] {
+            assert!(
+                matches!(fts_query(query), Err(Error::InvalidArgument { .. })),
+                "{query}"
+            );
+        }
+        assert!(Highlighter::new("NOT cat").is_none());
+        assert_eq!(
+            fts_query("cat NOT (dog OR fish)").unwrap().as_deref(),
+            Some("\"cat\" NOT ( \"dog\" OR \"fish\" )")
+        );
+    }
+
+    #[test]
+    fn test_snippets_highlight_matches() {
+        let highlighter = Highlighter::new("\"Quick brown\" jump*").unwrap();
+        assert_eq!(
+            highlighter.snippet("The quick brown fox jumped."),
+            "The [quick] [brown] fox [jumped]."
+        );
+
+        let content = format!("{}rust is here{}", "word ".repeat(30), " word".repeat(30));
+        let snippet = Highlighter::new("rust").unwrap().snippet(&content);
+        assert!(snippet.starts_with(SNIPPET_ELLIPSIS));
+        assert!(snippet.ends_with(SNIPPET_ELLIPSIS));
+        assert!(snippet.contains("[rust] is here"));
+        assert_eq!(word_spans(&snippet).len(), SNIPPET_TOKENS);
+
+        assert!(Highlighter::new("AND ( )").is_none());
+    }
+
+    #[test]
+    fn test_queries_without_terms() {
+        assert_eq!(fts_query("").unwrap(), None);
+        assert_eq!(fts_query("  AND ( ) * \"\" ").unwrap(), None);
+    }
+}
@@ -0,0 +1,83 @@
+//! The trash.
+//!
+//! Deleting a capture moves it to the trash rather than removing it: the row
+//! stays as it was, with the time it was trashed in `trashed_at`, and every
+//! query, search and history leaves it out. A trashed capture can be
+//! restored until the trash is emptied, or until pruning deletes it at the
+//! end of its grace period (see
+//! [`RetentionPolicy::trash_grace`](super::RetentionPolicy::trash_grace)).
+//!
+//! Captures deleted for good are overwritten on disk, since every connection
+//! runs with `secure_delete` on, and their words are merged out of the
+//! search index (see [`scrub_search_index`]).
+
+use chrono::{DateTime, Utc};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::capture::Capture;
+use crate::error::Result;
+
+/// The condition selecting captures that are not in the trash.
+pub(crate) const NOT_TRASHED: &str = "trashed_at IS NULL";
+
+/// A capture in the trash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrashedCapture {
+    /// The capture as it was when it was trashed.
+    pub capture: Capture,
+    /// When the capture was moved to the trash.
+    pub trashed_at: DateTime<Utc>,
+}
+
+/// Turn on overwriting of deleted content.
+pub(crate) fn enable_secure_delete(conn: &Connection) -> Result<()> {
+    conn.execute_batch("PRAGMA secure_delete = ON")?;
+    Ok(())
+}
+
+/// Drop the words of deleted captures from the search index.
+///
+/// The index is contentless, so deleting a capture only records that its
+/// row is gone, and its words stay in the index, readable in an unencrypted
+/// database, until the segments holding them are merged. Merging every
+/// segment rewrites the index without them, and secure delete overwrites
+/// the pages they were on.
+pub(crate) fn scrub_search_index(conn: &Connection) -> Result<()> {
+    conn.execute_batch("INSERT INTO captures_fts (captures_fts) VALUES ('optimize')")?;
+    Ok(())
+}
+
+/// Move the captures with the given IDs to the trash at `now`, returning how
+/// many were moved. Captures already in the trash keep their time.
+pub(crate) fn trash(conn: &Connection, ids: &[i64], now: DateTime<Utc>) -> Result<usize> {
+    let ids = serde_json::to_string(ids)?;
+    let moved = conn.execute(
+        "UPDATE captures SET trashed_at = ?2
+         WHERE id IN (SELECT value FROM json_each(?1)) AND trashed_at IS NULL",
+        rusqlite::params![ids, now.timestamp_micros()],
+    )?;
+    Ok(moved)
+}
+
+/// Take the captures with the given IDs out of the trash, returning how many
+/// were restored.
+pub(crate) fn restore(conn: &Connection, ids: &[i64]) -> Result<usize> {
+    let ids = serde_json::to_string(ids)?;
+    let restored = conn.execute(
+        "UPDATE captures SET trashed_at = NULL
+         WHERE id IN (SELECT value FROM json_each(?1)) AND trashed_at IS NOT NULL",
+        [ids],
+    )?;
+    Ok(restored)
+}
+
+/// Count the captures in the trash.
+pub(crate) fn count(conn: &Connection) -> Result<i64> {
+    let count = conn.query_row(
+        "SELECT COUNT(*) FROM captures WHERE trashed_at IS NOT NULL",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(count)
+}